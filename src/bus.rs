@@ -0,0 +1,369 @@
+//! Fan-out of samples from inputs to outputs.
+//!
+//! Every output subscribed to the bus gets its own bounded queue, which is drained on the thread
+//! pool. A slow output doesn't hold up the others, and what happens when its queue is full is
+//! decided by the `on_full` policy.
+
+use futures_cpupool::CpuPool;
+use plugin::{ConfiguredOutput, Sample};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use telemetry::Telemetry;
+
+/// Number of samples queued for each output, unless configured otherwise.
+pub const DEFAULT_BUFFER_SIZE: usize = 10000;
+
+/// What to do with samples published while the queue of an output is full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnFull {
+    /// Wait for the output to catch up, which holds up the poll which published them.
+    Block,
+    /// Drop the oldest queued samples to make room.
+    DropOldest,
+    /// Drop the published samples which don't fit.
+    DropNewest,
+}
+
+struct Subscriber {
+    output: Arc<ConfiguredOutput>,
+    queue: Mutex<VecDeque<Sample>>,
+    /// Signalled when the queue has been drained, or the bus is closed.
+    space: Condvar,
+    /// If a task draining the queue is scheduled or running.
+    draining: AtomicBool,
+}
+
+impl Subscriber {
+    /// Feed queued samples to the output until the queue is empty.
+    fn drain(&self) {
+        loop {
+            let samples: Vec<Sample> = match self.queue.lock() {
+                Ok(mut queue) => queue.drain(..).collect(),
+                Err(e) => {
+                    error!("{}: sample queue poisoned: {}", self.output.options.name, e);
+                    Vec::new()
+                }
+            };
+
+            self.space.notify_all();
+
+            for sample in samples.iter() {
+                if let Err(e) = self.output.feed(sample) {
+                    error!("{}: feed failed: {}", self.output.options.name, e);
+                }
+            }
+
+            self.draining.store(false, Ordering::SeqCst);
+
+            // samples published after the queue was taken would otherwise wait for the next
+            // publish.
+            let pending = self.queue.lock().map(|q| !q.is_empty()).unwrap_or(false);
+
+            if !pending || self.draining.swap(true, Ordering::SeqCst) {
+                return;
+            }
+        }
+    }
+}
+
+/// Delivers every published sample to all subscribed outputs.
+pub struct SampleBus {
+    subscribers: Vec<Arc<Subscriber>>,
+    pool: Arc<CpuPool>,
+    buffer_size: usize,
+    on_full: OnFull,
+    telemetry: Arc<Telemetry>,
+    /// Set on shutdown, so that publishers blocked on a stalled output give up.
+    closed: AtomicBool,
+}
+
+impl SampleBus {
+    pub fn new(
+        pool: Arc<CpuPool>,
+        buffer_size: usize,
+        on_full: OnFull,
+        telemetry: Arc<Telemetry>,
+    ) -> SampleBus {
+        SampleBus {
+            subscribers: Vec::new(),
+            pool: pool,
+            buffer_size: buffer_size,
+            on_full: on_full,
+            telemetry: telemetry,
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn on_full(&self) -> OnFull {
+        self.on_full
+    }
+
+    /// Deliver published samples to the given output.
+    pub fn subscribe(&mut self, output: Arc<ConfiguredOutput>) {
+        self.subscribers.push(Arc::new(Subscriber {
+            output: output,
+            queue: Mutex::new(VecDeque::new()),
+            space: Condvar::new(),
+            draining: AtomicBool::new(false),
+        }));
+    }
+
+    /// Queue samples for every subscribed output.
+    ///
+    /// With `OnFull::Block` outputs are fed by the publishing thread, which waits for outputs to
+    /// make room, so it must not be called on the reactor.
+    pub fn publish(&self, samples: &[Sample]) {
+        self.publish_to(None, samples)
+    }
+
+    /// Queue samples for the outputs with the given names, or every output if `route` is `None`.
+    pub fn publish_to(&self, route: Option<&[String]>, samples: &[Sample]) {
+        for subscriber in self.subscribers.iter() {
+            if let Some(route) = route {
+                if !route.contains(&subscriber.output.options.name) {
+                    continue;
+                }
+            }
+
+            let samples: Vec<&Sample> = match subscriber.output.options.filter {
+                Some(ref filter) => {
+                    samples.iter().filter(|s| filter.matches(&s.metric_id)).collect()
+                }
+                None => samples.iter().collect(),
+            };
+
+            let dropped = match self.enqueue(subscriber, samples) {
+                Some(dropped) => dropped,
+                None => continue,
+            };
+
+            if dropped > 0 {
+                let name = &subscriber.output.options.name;
+                warn!("{}: output is falling behind, dropped {} sample(s)", name, dropped);
+                self.telemetry.output_samples_dropped(name, dropped);
+            }
+
+            match self.on_full {
+                OnFull::Block => {
+                    if !subscriber.draining.swap(true, Ordering::SeqCst) {
+                        subscriber.drain();
+                    }
+                }
+                _ => self.schedule_drain(subscriber),
+            }
+        }
+    }
+
+    /// Queue samples for a single output, returning how many were dropped.
+    fn enqueue(&self, subscriber: &Arc<Subscriber>, samples: Vec<&Sample>) -> Option<usize> {
+        let mut queue = match subscriber.queue.lock() {
+            Ok(queue) => queue,
+            Err(e) => {
+                error!("{}: sample queue poisoned: {}", subscriber.output.options.name, e);
+                return None;
+            }
+        };
+
+        let mut dropped = 0;
+
+        for sample in samples {
+            while queue.len() >= self.buffer_size {
+                match self.on_full {
+                    OnFull::DropOldest => {
+                        queue.pop_front();
+                        dropped += 1;
+                    }
+                    OnFull::DropNewest => break,
+                    OnFull::Block if self.closed.load(Ordering::SeqCst) => break,
+                    OnFull::Block => {
+                        // a drain queued behind blocked publishers might never run, so drain
+                        // here unless another thread is already doing so.
+                        let result = if subscriber.draining.swap(true, Ordering::SeqCst) {
+                            subscriber.space.wait(queue)
+                        } else {
+                            drop(queue);
+                            subscriber.drain();
+                            subscriber.queue.lock()
+                        };
+
+                        queue = match result {
+                            Ok(queue) => queue,
+                            Err(e) => {
+                                let name = &subscriber.output.options.name;
+                                error!("{}: sample queue poisoned: {}", name, e);
+                                return None;
+                            }
+                        };
+                    }
+                }
+            }
+
+            if queue.len() >= self.buffer_size {
+                dropped += 1;
+                continue;
+            }
+
+            queue.push_back(sample.clone());
+        }
+
+        Some(dropped)
+    }
+
+    /// Drain the queue of the output on the pool, unless that is already happening.
+    fn schedule_drain(&self, subscriber: &Arc<Subscriber>) {
+        if subscriber.draining.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let subscriber = subscriber.clone();
+
+        // outputs might block while delivering, so keep it off the reactor.
+        self.pool
+            .spawn_fn(move || -> Result<(), ()> {
+                subscriber.drain();
+                Ok(())
+            })
+            .forget();
+    }
+
+    /// Feed every queued sample to the outputs before returning.
+    ///
+    /// Drains which are in progress on the pool are waited for.
+    pub fn drain_all(&self) {
+        for subscriber in self.subscribers.iter() {
+            while subscriber.draining.swap(true, Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(10));
+            }
+
+            subscriber.drain();
+        }
+    }
+
+    /// Stop blocking on full queues, samples which don't fit are dropped from then on.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+
+        for subscriber in self.subscribers.iter() {
+            // holding the lock makes sure that no publisher is between checking and waiting.
+            let _queue = subscriber.queue.lock();
+            subscriber.space.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use errors::*;
+    use metric::key;
+    use plugin::{InstanceOptions, OutputInstance};
+
+    struct Collect(Arc<Mutex<Vec<f64>>>);
+
+    impl OutputInstance for Collect {
+        fn feed(&self, sample: &Sample) -> Result<()> {
+            self.0.lock()?.push(sample.value);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_publish() {
+        let mut bus = SampleBus::new(
+            Arc::new(CpuPool::new(2)),
+            DEFAULT_BUFFER_SIZE,
+            OnFull::DropOldest,
+            Arc::new(Telemetry::new()),
+        );
+        let collected: Vec<_> = (0..2).map(|_| Arc::new(Mutex::new(Vec::new()))).collect();
+
+        for c in collected.iter() {
+            let output = ConfiguredOutput::new(
+                "out".to_owned(),
+                InstanceOptions::default(),
+                Box::new(Collect(c.clone())),
+                Arc::new(Telemetry::new()),
+            );
+
+            bus.subscribe(Arc::new(output));
+        }
+
+        let id = Arc::new(key("test").build());
+        bus.publish(&[Sample::new(id.clone(), 1f64), Sample::new(id.clone(), 2f64)]);
+
+        for c in collected.iter() {
+            for _ in 0..100 {
+                if c.lock().unwrap().len() == 2 {
+                    break;
+                }
+
+                thread::sleep(Duration::from_millis(10));
+            }
+
+            assert_eq!(vec![1f64, 2f64], *c.lock().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_publish_to() {
+        let mut bus = SampleBus::new(
+            Arc::new(CpuPool::new(1)),
+            DEFAULT_BUFFER_SIZE,
+            OnFull::Block,
+            Arc::new(Telemetry::new()),
+        );
+        let collected: Vec<_> = (0..2).map(|_| Arc::new(Mutex::new(Vec::new()))).collect();
+
+        for (name, c) in ["stdout", "influxdb"].iter().zip(collected.iter()) {
+            let mut options = InstanceOptions::default();
+            options.name = name.to_string();
+
+            let output = ConfiguredOutput::new(
+                name.to_string(),
+                options,
+                Box::new(Collect(c.clone())),
+                Arc::new(Telemetry::new()),
+            );
+
+            bus.subscribe(Arc::new(output));
+        }
+
+        let id = Arc::new(key("test").build());
+        let route = vec!["stdout".to_owned()];
+        bus.publish_to(Some(&route), &[Sample::new(id.clone(), 1f64)]);
+        bus.publish_to(None, &[Sample::new(id.clone(), 2f64)]);
+
+        assert_eq!(vec![1f64, 2f64], *collected[0].lock().unwrap());
+        assert_eq!(vec![2f64], *collected[1].lock().unwrap());
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        let telemetry = Arc::new(Telemetry::new());
+        let bus = SampleBus::new(Arc::new(CpuPool::new(1)), 2, OnFull::DropNewest, telemetry);
+        let collected = Arc::new(Mutex::new(Vec::new()));
+
+        let subscriber = Arc::new(Subscriber {
+            output: Arc::new(ConfiguredOutput::new(
+                "out".to_owned(),
+                InstanceOptions::default(),
+                Box::new(Collect(collected.clone())),
+                Arc::new(Telemetry::new()),
+            )),
+            queue: Mutex::new(VecDeque::new()),
+            space: Condvar::new(),
+            draining: AtomicBool::new(false),
+        });
+
+        let id = Arc::new(key("test").build());
+        let samples: Vec<Sample> = (0..3).map(|v| Sample::new(id.clone(), v as f64)).collect();
+
+        assert_eq!(Some(1), bus.enqueue(&subscriber, samples.iter().collect()));
+
+        let queued: Vec<f64> = subscriber.queue.lock().unwrap().iter().map(|s| s.value).collect();
+        assert_eq!(vec![0f64, 1f64], queued);
+    }
+}
@@ -1,26 +1,107 @@
 use serde;
+use bus::{OnFull, DEFAULT_BUFFER_SIZE};
 use plugin::*;
 use errors::*;
-use std::sync::Arc;
+use dry_run::DryRunOutputInstance;
+use health::*;
+use logger;
+use parsers::duration::*;
+use scheduler::Overrun;
+use poller::OnNonFinite;
+use interpolate::interpolate_table;
+use yaml::parse_yaml;
+use rename::{decode_rename, RenameRule};
+use filter::decode_filter;
+use histograms::{decode_histograms, HistogramRule};
+use host::{self, DEFAULT_HOST_TAG};
+use spool::{spool_path, Spool};
 use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::fs;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 use toml;
 use num_cpus;
 use futures_cpupool::*;
-use tokio_core;
+use telemetry::{Telemetry, SELF_INSTANCE};
+use tokio_core::reactor::Handle;
+use tokio_timer::Timer;
+use std::cmp;
+use std::thread;
 use std::time::Duration;
 
+const DEFAULT_SETUP_RETRY_DELAY_SECS: u64 = 1;
+const MAX_SETUP_RETRY_DELAY_SECS: u64 = 60;
+
+#[derive(Clone)]
 pub struct Config {
-    /// Number of threads to configure in thread pool.
-    threads: usize,
+    /// Number of threads to configure in thread pool, defaults to the number of cpus, or a single
+    /// thread with `Reactor::Single`.
+    threads: Option<usize>,
     /// If the threads option is per cpu or not.
     threads_per_cpu: bool,
     /// Interval at which to perform updates.
     pub update_interval: Duration,
     /// Interval at which to perform polling.
     pub poll_interval: Duration,
+    /// How long a single poll may take before it is abandoned.
+    pub poll_timeout: Duration,
+    /// Tags added to every sample, unless the instance or the sample itself sets them.
+    pub tags: Vec<(String, String)>,
+    /// Tag the hostname is added as, unless `[tags]` sets it. Disabled if not set.
+    pub host_tag: Option<String>,
+    /// Never add the hostname as a tag, regardless of `host_tag`.
+    pub no_host_tag: bool,
+    /// Rules which rename samples before they are fed to outputs, in order.
+    pub rename: Vec<RenameRule>,
+    /// Bucket boundaries of histograms, by metric.
+    pub histograms: Vec<HistogramRule>,
+    /// Clock used by plugins when computing rates.
+    pub clock: Clock,
+    /// Where polls and updates of input instances run.
+    pub reactor: Reactor,
+    /// Maximum number of instances polled at the same time, defaults to the number of threads.
+    max_concurrency: Option<usize>,
+    /// Number of tasks queued onto the thread pool beyond which it is saturated, defaults to four
+    /// times the number of threads.
+    max_queued_tasks: Option<usize>,
+    /// Largest number of samples per second fed to outputs, over all instances.
+    pub max_samples_per_second: Option<u64>,
+    /// Log and skip plugins which fail to set up, instead of failing.
+    pub skip_failed: bool,
+    /// Log the samples outputs would have sent, instead of sending them.
+    pub dry_run: bool,
+    /// Ignore keys in plugin sections which are not recognized, instead of failing.
+    pub allow_unknown_keys: bool,
+    /// Fail instead of warning about configurations which are likely mistakes.
+    pub strict: bool,
+    /// Sections to set up, referred to by id, name, or plugin type. Sections of a kind which none
+    /// of the names refer to are all set up.
+    pub only_plugins: Vec<String>,
+    /// Sections to skip, referred to by id, name, or plugin type.
+    pub disabled_plugins: Vec<String>,
+    /// Largest random offset of the first poll, as a fraction of the poll interval.
+    pub jitter: f64,
+    /// Seed used to make the jitter deterministic.
+    pub jitter_seed: Option<u64>,
+    /// Consecutive failures after which a critical instance is unhealthy.
+    pub health_threshold: u32,
+    /// Report metrics about sysmon itself through an input instance named `self`.
+    pub internal_metrics: bool,
+    /// What to do about ticks missed because a run took longer than its interval.
+    pub on_overrun: Overrun,
+    /// Interval at which a `sysmon_alive` sample is fed to outputs, disabled if not set.
+    pub heartbeat_interval: Option<Duration>,
+    /// Interval at which the health of every instance is logged, disabled if not set.
+    pub status_interval: Option<Duration>,
+    /// Path of the unix socket to accept control commands on, disabled if not set.
+    pub control_socket: Option<String>,
+    /// Number of samples queued for every output.
+    pub buffer_size: usize,
+    /// What to do with samples published while the queue of an output is full.
+    pub on_full: OnFull,
+    /// What to do with samples whose value is NaN or infinite.
+    pub on_non_finite: OnNonFinite,
 }
 
 /// Model used to parse configuration file.
@@ -28,72 +109,773 @@ pub struct Config {
 #[derive(Deserialize, Debug)]
 pub struct ConfigIn {
     threads: Option<usize>,
+    max_concurrency: Option<usize>,
+    max_queued_tasks: Option<usize>,
+    max_samples_per_second: Option<u64>,
     threads_per_cpu: Option<bool>,
-    update_interval: Option<Duration>,
-    poll_interval: Option<Duration>,
+    jitter: Option<f64>,
+    jitter_seed: Option<u64>,
+    health_threshold: Option<u32>,
+    internal_metrics: Option<bool>,
+    control_socket: Option<String>,
+    buffer_size: Option<usize>,
+    host_tag: Option<String>,
 }
 
 pub struct PartialPluginContext {
     cpupool: Arc<CpuPool>,
-    core: Rc<RefCell<tokio_core::reactor::Core>>,
+    handle: Handle,
+    clock: Clock,
+    reactor: Reactor,
+    health: Arc<Health>,
+    timer: Arc<Timer>,
+    telemetry: Arc<Telemetry>,
+    allow_unknown_keys: bool,
+    dry_run: bool,
+    /// Retry failing setups as configured through `setup_retries`.
+    retry_setup: bool,
 }
 
 impl PartialPluginContext {
     pub fn new(
         cpupool: Arc<CpuPool>,
-        core: Rc<RefCell<tokio_core::reactor::Core>>,
+        handle: Handle,
+        clock: Clock,
+        reactor: Reactor,
+        health: Arc<Health>,
+        timer: Arc<Timer>,
+        telemetry: Arc<Telemetry>,
+        allow_unknown_keys: bool,
+        dry_run: bool,
     ) -> PartialPluginContext {
         PartialPluginContext {
             cpupool: cpupool,
-            core: core,
+            handle: handle,
+            clock: clock,
+            reactor: reactor,
+            health: health,
+            timer: timer,
+            telemetry: telemetry,
+            allow_unknown_keys: allow_unknown_keys,
+            dry_run: dry_run,
+            retry_setup: true,
         }
     }
 
-    fn build<'a>(&self, id: &'a String, config: &'a toml::Table) -> PluginContext<'a> {
+    /// Don't retry failing setups.
+    ///
+    /// Retries wait on the thread setting up, which is the reactor thread once sysmon is running.
+    pub fn without_retries(mut self) -> PartialPluginContext {
+        self.retry_setup = false;
+        self
+    }
+
+    fn build<'a>(
+        &self,
+        id: &'a String,
+        options: &'a InstanceOptions,
+        config: &'a toml::Table,
+        base_dir: &'a Path,
+        keys: &'a RefCell<ConfigKeys>,
+    ) -> PluginContext<'a> {
         PluginContext {
             id: id,
+            name: &options.name,
             config: config,
+            base_dir: base_dir,
             cpupool: self.cpupool.clone(),
-            core: self.core.clone(),
+            handle: self.handle.clone(),
+            clock: self.clock,
+            reactor: self.reactor,
+            health: self.health.clone(),
+            timer: self.timer.clone(),
+            telemetry: self.telemetry.clone(),
+            keys: keys,
         }
     }
 }
 
-pub type PluginSetup = Fn(&Config, &PluginRegistry, &PartialPluginContext)
-                          -> Result<(Vec<Arc<Box<InputInstance>>>, Vec<Box<OutputInstance>>)>;
-
 impl Config {
     pub fn new() -> Config {
         // defaults
         Config {
-            threads: 4,
+            threads: None,
             threads_per_cpu: false,
             update_interval: Duration::new(1, 0),
             poll_interval: Duration::new(10, 0),
+            poll_timeout: Duration::new(30, 0),
+            tags: Vec::new(),
+            host_tag: Some(DEFAULT_HOST_TAG.to_owned()),
+            no_host_tag: false,
+            rename: Vec::new(),
+            histograms: Vec::new(),
+            clock: Clock::Wall,
+            reactor: Reactor::Pool,
+            max_concurrency: None,
+            max_queued_tasks: None,
+            max_samples_per_second: None,
+            skip_failed: false,
+            dry_run: false,
+            allow_unknown_keys: false,
+            strict: false,
+            only_plugins: Vec::new(),
+            disabled_plugins: Vec::new(),
+            jitter: 0f64,
+            jitter_seed: None,
+            health_threshold: DEFAULT_FAILURE_THRESHOLD,
+            internal_metrics: false,
+            on_overrun: Overrun::Skip,
+            heartbeat_interval: None,
+            status_interval: Some(Duration::new(60, 0)),
+            control_socket: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            on_full: OnFull::DropOldest,
+            on_non_finite: OnNonFinite::Drop,
+        }
+    }
+
+    /// Add the hostname as a tag to every sample, unless disabled or already set by `[tags]`.
+    ///
+    /// A hostname which can't be detected is logged, and the tag is left out.
+    pub fn add_host_tag(&mut self) {
+        if self.no_host_tag {
+            return;
+        }
+
+        let tag = match self.host_tag {
+            Some(ref tag) => tag.clone(),
+            None => return,
+        };
+
+        if self.tags.iter().any(|&(ref k, _)| *k == tag) {
+            return;
+        }
+
+        match host::hostname() {
+            Ok(hostname) => self.tags.push((tag, hostname)),
+            Err(e) => warn!("not adding the {} tag: {}", tag, e),
+        }
+    }
+
+    /// Disable the sections which are left out by `only_plugins`, or listed in `disabled_plugins`.
+    ///
+    /// Names which don't refer to any section are logged.
+    pub fn select_sections(&self, sections: &mut [PluginSection]) {
+        let lists = [("--only", &self.only_plugins), ("--disable", &self.disabled_plugins)];
+
+        for &(option, names) in lists.iter() {
+            for name in names.iter() {
+                if !sections.iter().any(|s| s.refers_to(name)) {
+                    warn!("{}: no plugin section is named {}", option, name);
+                }
+            }
+        }
+
+        let only = |section: &PluginSection| {
+            let refers_to_kind = self.only_plugins.iter().any(|name| {
+                sections.iter().any(|s| s.kind == section.kind && s.refers_to(name))
+            });
+
+            !refers_to_kind || self.only_plugins.iter().any(|name| section.refers_to(name))
+        };
+
+        let selected: Vec<bool> = sections
+            .iter()
+            .map(|s| only(s) && !self.disabled_plugins.iter().any(|name| s.refers_to(name)))
+            .collect();
+
+        for (section, selected) in sections.iter_mut().zip(selected) {
+            if !selected && section.is_enabled() {
+                debug!("{}: disabled from the command line", section.id);
+                section.table.insert("enabled".to_owned(), toml::Value::Boolean(false));
+            }
         }
     }
 
     pub fn threads(&self) -> usize {
-        if self.threads_per_cpu {
-            return num_cpus::get() * self.threads;
+        match self.threads {
+            Some(threads) if self.threads_per_cpu => num_cpus::get() * threads,
+            Some(threads) => threads,
+            // only instances which block use the pool.
+            None if self.reactor == Reactor::Single => 1,
+            None => num_cpus::get(),
+        }
+    }
+
+    /// Maximum number of instances polled at the same time.
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency.unwrap_or_else(|| self.threads())
+    }
+
+    pub fn max_queued_tasks(&self) -> usize {
+        self.max_queued_tasks.unwrap_or_else(|| self.threads() * 4)
+    }
+
+    /// Set an explicit number of threads, overriding any configured value.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = Some(threads);
+        self.threads_per_cpu = false;
+    }
+
+    /// Poll interval to use for the given instance.
+    pub fn poll_interval_for(&self, options: &InstanceOptions) -> Duration {
+        options.interval.unwrap_or(self.poll_interval)
+    }
+
+    /// Update interval to use for the given instance.
+    pub fn update_interval_for(&self, options: &InstanceOptions) -> Duration {
+        options.update_interval.unwrap_or(self.update_interval)
+    }
+}
+
+/// Parse a human-readable duration like `500ms` or `1m30s`.
+pub fn parse_duration_str(input: &str) -> Result<Duration> {
+    let parts = duration_parts(input.as_bytes()).to_full_result().map_err(|_| {
+        ErrorKind::Message(format!("not a valid duration: {:?}", input))
+    })?;
+
+    for &(_, unit) in parts.iter() {
+        if duration_unit(unit).is_none() {
+            let unit = String::from_utf8_lossy(unit).into_owned();
+            return Err(ErrorKind::UnknownDurationUnit(unit).into());
+        }
+    }
+
+    let duration = parse_duration(input.as_bytes()).to_full_result().map_err(
+        Into::<Error>::into,
+    )?;
+
+    // zero intervals would make timers fire continuously.
+    if duration == Duration::new(0, 0) {
+        return Err(ErrorKind::Message(format!("duration must not be zero: {:?}", input)).into());
+    }
+
+    Ok(duration)
+}
+
+/// Read an optional duration from a table.
+///
+/// Durations are either a number of seconds, or a human-readable string like `"1m30s"`.
+pub fn decode_duration(table: &toml::Table, key: &str) -> Result<Option<Duration>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(&toml::Value::Integer(secs)) if secs > 0 => Ok(Some(Duration::new(secs as u64, 0))),
+        Some(&toml::Value::String(ref value)) => {
+            parse_duration_str(value).map(Some).chain_err(|| {
+                ErrorKind::ConfigField(key.to_owned(), "invalid duration".to_owned())
+            })
+        }
+        Some(_) => Err(
+            ErrorKind::ConfigField(key.to_owned(), "must be a positive number".to_owned()).into(),
+        ),
+    }
+}
+
+/// Read an optional positive number from a table.
+fn decode_positive(table: &toml::Table, key: &str) -> Result<Option<usize>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(&toml::Value::Integer(value)) if value > 0 => Ok(Some(value as usize)),
+        Some(_) => Err(
+            ErrorKind::ConfigField(key.to_owned(), "must be a positive number".to_owned()).into(),
+        ),
+    }
+}
+
+/// Read an optional path from a table, resolved against the directory of the configuration file.
+fn decode_path(table: &toml::Table, key: &str, base_dir: &Path) -> Result<Option<PathBuf>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(&toml::Value::String(ref value)) if !value.is_empty() => {
+            Ok(Some(base_dir.join(value)))
+        }
+        Some(_) => Err(
+            ErrorKind::ConfigField(key.to_owned(), "must be a non-empty string".to_owned()).into(),
+        ),
+    }
+}
+
+fn decode_bool(table: &toml::Table, key: &str) -> Result<Option<bool>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(&toml::Value::Boolean(value)) => Ok(Some(value)),
+        Some(_) => Err(
+            ErrorKind::ConfigField(key.to_owned(), "must be a boolean".to_owned()).into(),
+        ),
+    }
+}
+
+/// Decode a table of tags, dropping tags with empty values.
+fn decode_tags(table: &toml::Table, key: &str) -> Result<Vec<(String, String)>> {
+    let tags = match table.get(key) {
+        None => return Ok(Vec::new()),
+        Some(&toml::Value::Table(ref tags)) => tags,
+        Some(_) => {
+            return Err(
+                ErrorKind::ConfigField(key.to_owned(), "must be a table".to_owned()).into(),
+            );
+        }
+    };
+
+    let mut out = Vec::new();
+
+    for (name, value) in tags {
+        match *value {
+            toml::Value::String(ref value) if value.is_empty() => {}
+            toml::Value::String(ref value) => out.push((name.clone(), value.clone())),
+            _ => {
+                return Err(
+                    ErrorKind::ConfigField(
+                        format!("{}.{}", key, name),
+                        "must be a string".to_owned(),
+                    ).into(),
+                );
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Decode the names of the outputs an input is routed to, which is a name or an array of names.
+fn decode_route(table: &toml::Table) -> Result<Option<Vec<String>>> {
+    let invalid = || {
+        ErrorKind::ConfigField(
+            "route".to_owned(),
+            "must be a string or an array of strings".to_owned(),
+        )
+    };
+
+    let values = match table.get("route") {
+        None => return Ok(None),
+        Some(&toml::Value::String(ref value)) => return Ok(Some(vec![value.clone()])),
+        Some(&toml::Value::Array(ref values)) => values,
+        Some(_) => return Err(invalid().into()),
+    };
+
+    let mut route = Vec::new();
+
+    for value in values {
+        match *value {
+            toml::Value::String(ref value) => route.push(value.clone()),
+            _ => return Err(invalid().into()),
+        }
+    }
+
+    Ok(Some(route))
+}
+
+/// Check that inputs are only routed to outputs which are configured.
+///
+/// Outputs are referred to by their name, which defaults to their id. Routing to a disabled output
+/// is allowed, so that outputs can be disabled without changing the inputs routed to them.
+pub fn check_routes(sections: &[PluginSection]) -> Result<()> {
+    let outputs: Vec<String> = sections
+        .iter()
+        .filter(|s| s.kind == PluginKind::Output)
+        .map(section_name)
+        .collect();
+
+    let inputs = sections.iter().filter(|s| s.kind == PluginKind::Input && s.is_enabled());
+
+    for section in inputs {
+        let route = decode_route(&section.table).chain_err(|| {
+            ErrorKind::ConfigSection(section.id.clone())
+        })?;
+
+        for name in route.iter().flat_map(|route| route.iter()) {
+            if !outputs.contains(name) {
+                let e: Error = ErrorKind::ConfigField(
+                    "route".to_owned(),
+                    format!("no such output: {}", name),
+                ).into();
+
+                return Err(e).chain_err(|| ErrorKind::ConfigSection(section.id.clone()));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Name of the instance of a section, which defaults to its id.
+fn section_name(section: &PluginSection) -> String {
+    match section.table.get("name") {
+        Some(&toml::Value::String(ref name)) => name.clone(),
+        _ => section.id.clone(),
+    }
+}
+
+/// Decode the framework options that are common to all plugin sections.
+fn decode_options(id: &String, table: &toml::Table, base_dir: &Path) -> Result<InstanceOptions> {
+    let name = match table.get("name") {
+        None => id.clone(),
+        Some(&toml::Value::String(ref name)) => name.clone(),
+        Some(_) => {
+            return Err(
+                ErrorKind::ConfigField("name".to_owned(), "must be a string".to_owned()).into(),
+            );
+        }
+    };
+
+    Ok(InstanceOptions {
+        name: name,
+        interval: decode_duration(table, "interval")?,
+        update_interval: decode_duration(table, "update_interval")?,
+        poll_timeout: decode_duration(table, "poll_timeout")?,
+        batch_size: decode_positive(table, "batch_size")?,
+        flush_interval: decode_duration(table, "flush_interval")?,
+        initial_backoff: decode_duration(table, "initial_backoff")?,
+        max_backoff: decode_duration(table, "max_backoff")?,
+        backoff_buffer_size: decode_positive(table, "backoff_buffer_size")?,
+        spool_dir: decode_path(table, "spool_dir", base_dir)?,
+        max_spool_bytes: decode_positive(table, "max_spool_bytes")?.map(|b| b as u64),
+        align: decode_bool(table, "align")?.unwrap_or(false),
+        critical: decode_bool(table, "critical")?.unwrap_or(false),
+        max_samples_per_poll: decode_positive(table, "max_samples_per_poll")?,
+        filter: decode_filter(table)?,
+        route: decode_route(table)?,
+        dedup: decode_bool(table, "dedup")?.unwrap_or(false),
+        dedup_heartbeat: decode_positive(table, "dedup_heartbeat")?,
+        setup_retries: decode_positive(table, "setup_retries")?,
+        setup_retry_delay: decode_duration(table, "setup_retry_delay")?,
+    })
+}
+
+/// Kind of plugin that a section configures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PluginKind {
+    Input,
+    Output,
+}
+
+impl PluginKind {
+    /// Name of the top-level table holding sections of this kind.
+    pub fn table(&self) -> &'static str {
+        match *self {
+            PluginKind::Input => "in",
+            PluginKind::Output => "out",
         }
+    }
+}
+
+/// Configuration of a single plugin instance, as read from a configuration file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginSection {
+    pub kind: PluginKind,
+    pub id: String,
+    pub table: toml::Table,
+    /// Directory of the configuration file the section was read from, which relative paths in the
+    /// section are resolved against.
+    pub base_dir: PathBuf,
+}
 
-        self.threads
+#[cfg(test)]
+impl PluginSection {
+    /// Section of the given kind and id, parsed from `content`.
+    pub fn parse(kind: PluginKind, id: &str, content: &str) -> PluginSection {
+        PluginSection {
+            kind: kind,
+            id: id.to_owned(),
+            table: toml::Parser::new(content).parse().unwrap(),
+            base_dir: PathBuf::new(),
+        }
     }
 }
 
-fn load_instance<Entry, Instance, Load, Plugin, Setup>(
-    id: &String,
-    plugin_section: toml::Value,
+/// Keys of a plugin section which are read by the framework, rather than by the plugin.
+const FRAMEWORK_KEYS: &'static [&'static str] = &[
+    "type",
+    "enabled",
+    "name",
+    "tags",
+    "interval",
+    "update_interval",
+    "poll_timeout",
+    "batch_size",
+    "flush_interval",
+    "initial_backoff",
+    "max_backoff",
+    "backoff_buffer_size",
+    "spool_dir",
+    "max_spool_bytes",
+    "align",
+    "critical",
+    "priority",
+    "depends_on",
+    "max_samples_per_poll",
+    "include",
+    "exclude",
+    "include_tags",
+    "exclude_tags",
+    "route",
+    "dedup",
+    "dedup_heartbeat",
+    "setup_retries",
+    "setup_retry_delay",
+];
+
+impl PluginSection {
+    /// If the section is referred to by the given name, which is its id, name, or plugin type.
+    ///
+    /// Any of them can be prefixed by the table of the section, like `in.cpu`, to only refer to
+    /// sections of that kind.
+    pub fn refers_to(&self, name: &str) -> bool {
+        let table = self.kind.table();
+
+        let name = if name.starts_with(table) && name[table.len()..].starts_with('.') {
+            &name[table.len() + 1..]
+        } else {
+            name
+        };
+
+        if self.id == name {
+            return true;
+        }
+
+        ["name", "type"].iter().any(|key| match self.table.get(*key) {
+            Some(&toml::Value::String(ref value)) => value == name,
+            _ => false,
+        })
+    }
+
+    /// If the section is set up, sections which are explicitly disabled are not.
+    pub fn is_enabled(&self) -> bool {
+        match self.table.get("enabled") {
+            Some(&toml::Value::Boolean(false)) => false,
+            _ => true,
+        }
+    }
+}
+
+/// Check that the sections configure both inputs and outputs.
+///
+/// Without outputs all samples are thrown away, and without inputs there are no samples, so either
+/// is almost always a mistake. This is an error if `strict` is set, otherwise a warning.
+pub fn check_sections(sections: &[PluginSection], strict: bool) -> Result<()> {
+    // the instance reporting internal metrics would share its health and telemetry.
+    for section in sections.iter().filter(|s| s.is_enabled()) {
+        if section_name(section) == SELF_INSTANCE {
+            let e: Error = ErrorKind::ConfigField(
+                "name".to_owned(),
+                format!("{:?} is reserved for internal metrics", SELF_INSTANCE),
+            ).into();
+
+            return Err(e).chain_err(|| ErrorKind::ConfigSection(section.id.clone()));
+        }
+    }
+
+    for kind in [PluginKind::Input, PluginKind::Output].iter() {
+        if sections.iter().any(|s| s.kind == *kind && s.is_enabled()) {
+            continue;
+        }
+
+        if strict {
+            return Err(ErrorKind::NoInstances(kind.table().to_owned()).into());
+        }
+
+        warn!("no [{}] sections are configured", kind.table());
+    }
+
+    Ok(())
+}
+
+/// Shortest plausible `flush_interval`, writing a batch is unlikely to take less time than this.
+const MIN_FLUSH_INTERVAL_MS: u64 = 100;
+
+/// Timing of the section which is inconsistent, as the offending field and why.
+///
+/// Fields which can't be decoded are left to be reported when the section is set up.
+fn timing_problem(
+    config: &Config,
+    section: &PluginSection,
+    default_timeout: bool,
+) -> Option<(String, String)> {
+    let table = &section.table;
+    let duration = |key: &str| decode_duration(table, key).unwrap_or(None);
+
+    match section.kind {
+        PluginKind::Input => {
+            // the default timeout is longer than most intervals, but nobody asked for it.
+            let timeout = match duration("poll_timeout") {
+                Some(timeout) => timeout,
+                None if default_timeout => return None,
+                None => config.poll_timeout,
+            };
+
+            let interval = duration("interval").unwrap_or(config.poll_interval);
+
+            if timeout >= interval {
+                return Some((
+                    "poll_timeout".to_owned(),
+                    format!(
+                        "{:?} is not shorter than the interval of {:?}, so polls can pile up",
+                        timeout,
+                        interval
+                    ),
+                ));
+            }
+        }
+        PluginKind::Output => {
+            if let Some(flush_interval) = duration("flush_interval") {
+                if flush_interval < Duration::from_millis(MIN_FLUSH_INTERVAL_MS) {
+                    return Some((
+                        "flush_interval".to_owned(),
+                        format!(
+                            "{:?} is shorter than writing a batch is likely to take",
+                            flush_interval
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Check that the timings of the sections are plausible.
+///
+/// Polls which are allowed to run for longer than the interval of their input pile up, and
+/// batches which are flushed more often than they can be written keep the output permanently
+/// behind. This is an error if `strict` is set, otherwise a warning.
+pub fn check_timings(config: &Config, sections: &[PluginSection]) -> Result<()> {
+    let default_timeout = config.poll_timeout == Config::new().poll_timeout;
+
+    for section in sections.iter().filter(|s| s.is_enabled()) {
+        let (field, reason) = match timing_problem(config, section, default_timeout) {
+            Some(problem) => problem,
+            None => continue,
+        };
+
+        if config.strict {
+            return Err(Error::from(ErrorKind::ConfigField(field, reason)))
+                .chain_err(|| ErrorKind::ConfigSection(section.id.clone()));
+        }
+
+        warn!("{}: {}: {}", section.id, field, reason);
+    }
+
+    Ok(())
+}
+
+/// Read all plugin sections of the given kind from the top-level table.
+fn load_sections(
+    kind: PluginKind,
+    section: &toml::Value,
+    base_dir: &Path,
+) -> Result<Vec<PluginSection>> {
+    let table: toml::Table = toml::decode(section.clone()).ok_or(ErrorKind::TomlDecode)?;
+
+    let mut sections = Vec::new();
+
+    for (id, plugin_section) in table {
+        let mut values = Vec::new();
+
+        match plugin_section {
+            // array of tables, one instance per element.
+            toml::Value::Array(elements) => {
+                for (index, element) in elements.into_iter().enumerate() {
+                    values.push((format!("{}[{}]", id, index), element));
+                }
+            }
+            plugin_section => values.push((id, plugin_section)),
+        }
+
+        for (id, value) in values {
+            let table: toml::Table = toml::decode(value)
+                .ok_or(ErrorKind::TomlDecode)
+                .chain_err(|| ErrorKind::ConfigSection(id.clone()))?;
+
+            sections.push(PluginSection {
+                kind: kind,
+                id: id,
+                table: table,
+                base_dir: base_dir.to_owned(),
+            });
+        }
+    }
+
+    Ok(sections)
+}
+
+/// Run the setup of a section, retrying it as configured through `setup_retries`.
+///
+/// Only failures which aren't caused by the configuration are retried, since those won't go away
+/// by trying again. Retries block the loading of the configuration while they wait, so they are
+/// only made if `retry` is set.
+fn with_retries<T, F>(id: &str, options: &InstanceOptions, retry: bool, mut setup: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let retries = match retry {
+        true => options.setup_retries.unwrap_or(0),
+        false => 0,
+    };
+
+    let mut delay = options.setup_retry_delay.unwrap_or_else(|| {
+        Duration::new(DEFAULT_SETUP_RETRY_DELAY_SECS, 0)
+    });
+
+    let mut attempt = 0;
+
+    loop {
+        let e = match setup() {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+
+        if attempt >= retries || exit_code(&e) != 1 {
+            return Err(e);
+        }
+
+        attempt += 1;
+
+        warn!(
+            "{}: setup failed: {}, retrying in {:?} ({}/{})",
+            id,
+            e,
+            delay,
+            attempt,
+            retries
+        );
+
+        thread::sleep(delay);
+        delay = cmp::min(delay * 2, Duration::new(MAX_SETUP_RETRY_DELAY_SECS, 0));
+    }
+}
+
+/// Set up a single plugin section.
+///
+/// Returns `None` if the section is disabled.
+fn setup_section<Entry, Instance, Load, Plugin, Setup>(
+    section: &PluginSection,
+    partial_context: &PartialPluginContext,
     load: Load,
     setup: Setup,
-) -> Result<Instance>
+) -> Result<Option<Instance>>
 where
     Entry: Fn() -> Result<Plugin>,
     Load: Fn(&String) -> Option<Entry>,
-    Setup: Fn(Plugin, &String, &toml::Table) -> Result<Instance>,
+    Setup: Fn(Plugin, &String, InstanceOptions, &toml::Table, &RefCell<ConfigKeys>)
+        -> Result<Instance>,
 {
-    let plugin_table: toml::Table = toml::decode(plugin_section).ok_or(ErrorKind::TomlDecode)?;
+    let id = &section.id;
+    let plugin_table = &section.table;
+
+    match plugin_table.get("enabled") {
+        None | Some(&toml::Value::Boolean(true)) => {}
+        Some(&toml::Value::Boolean(false)) => {
+            debug!("{}: disabled, skipping", id);
+            return Ok(None);
+        }
+        Some(_) => {
+            return Err(
+                ErrorKind::ConfigField("enabled".to_owned(), "must be a boolean".to_owned())
+                    .into(),
+            );
+        }
+    }
 
     let plugin_type: String = plugin_table
         .get("type")
@@ -105,31 +887,159 @@ where
         ErrorKind::MissingPlugin(plugin_type),
     )?;
 
-    let plugin = entry()?;
+    let options = decode_options(id, plugin_table, &section.base_dir)?;
+    let name = options.name.clone();
+
+    let (instance, keys) = with_retries(id, &options, partial_context.retry_setup, || {
+        let plugin = entry()?;
+
+        let keys = RefCell::new(ConfigKeys::default());
+
+        for key in FRAMEWORK_KEYS {
+            keys.borrow_mut().use_key(key);
+        }
+
+        let instance = logger::with_plugin(&name, || {
+            setup(plugin, id, options.clone(), plugin_table, &keys)
+        })?;
 
-    setup(plugin, id, &plugin_table)
+        Ok((instance, keys))
+    })?;
+
+    let unknown = keys.borrow().unknown(plugin_table);
+
+    if !unknown.is_empty() {
+        if !partial_context.allow_unknown_keys {
+            return Err(ErrorKind::UnknownConfigKey(id.clone(), unknown).into());
+        }
+
+        warn!("{}: ignoring unknown key(s): {}", id, unknown.join(", "));
+    }
+
+    Ok(Some(instance))
 }
 
-fn load_section<Entry, Instance, Load, Plugin, Setup>(
-    section: &toml::Value,
-    load: Load,
-    setup: Setup,
-) -> Result<Vec<Instance>>
-where
-    Entry: Fn() -> Result<Plugin>,
-    Load: Fn(&String) -> Option<Entry>,
-    Setup: Fn(Plugin, &String, &toml::Table) -> Result<Instance>,
-{
-    let mut values: Vec<Instance> = Vec::new();
+/// Set up an input plugin section.
+pub fn setup_input(
+    section: &PluginSection,
+    plugins: &PluginRegistry,
+    partial_context: &PartialPluginContext,
+) -> Result<Option<Arc<ConfiguredInput>>> {
+    let load = |plugin_type: &String| plugins.get_input(plugin_type);
 
-    let table: toml::Table = toml::decode(section.clone()).ok_or(ErrorKind::TomlDecode)?;
+    setup_section(section, partial_context, load, |plugin, id, options, config, keys| {
+        let base_dir = &section.base_dir;
+        let instance = plugin.setup(partial_context.build(id, &options, config, base_dir, keys))?;
 
-    for (id, plugin_section) in table {
-        values.push(load_instance(&id, plugin_section.clone(), &load, &setup)
-            .chain_err(|| ErrorKind::ConfigSection(id))?);
+        let mut tags = decode_tags(config, "tags")?;
+
+        if !tags.iter().any(|&(ref k, _)| k == "plugin") {
+            tags.push(("plugin".to_owned(), options.name.clone()));
+        }
+
+        Ok(Arc::new(ConfiguredInput {
+            id: id.clone(),
+            options: options,
+            tags: tags,
+            instance: instance,
+        }))
+    }).chain_err(|| ErrorKind::ConfigSection(section.id.clone()))
+}
+
+/// Set up an output plugin section.
+///
+/// The spool is not opened if it is the spool of `previous`, the running output being replaced,
+/// which hands it over through `ConfiguredOutput::hand_over_spool` instead.
+pub fn setup_output(
+    section: &PluginSection,
+    plugins: &PluginRegistry,
+    partial_context: &PartialPluginContext,
+    previous: Option<&ConfiguredOutput>,
+) -> Result<Option<Arc<ConfiguredOutput>>> {
+    let load = |plugin_type: &String| plugins.get_output(plugin_type);
+
+    setup_section(section, partial_context, load, |plugin, id, options, config, keys| {
+        let base_dir = &section.base_dir;
+        let ctx = partial_context.build(id, &options, config, base_dir, keys);
+        let mut instance = plugin.setup(ctx)?;
+
+        if partial_context.dry_run {
+            instance = Box::new(DryRunOutputInstance::new(options.name.clone(), instance));
+        }
+
+        let handed_over = match (&options.spool_dir, previous.and_then(|p| p.spool_path())) {
+            (&Some(ref dir), Some(path)) => spool_path(dir, id) == path,
+            _ => false,
+        };
+
+        let spool = match options.spool_dir {
+            Some(ref dir) if !handed_over => {
+                Some(Spool::open(dir, id, options.max_spool_bytes).chain_err(|| {
+                    ErrorKind::ConfigField("spool_dir".to_owned(), "could not be opened".to_owned())
+                })?)
+            }
+            _ => None,
+        };
+
+        let telemetry = partial_context.telemetry.clone();
+        let output = ConfiguredOutput::new(id.clone(), options, instance, telemetry);
+
+        Ok(Arc::new(match spool {
+            Some(spool) => output.with_spool(spool),
+            None => output,
+        }))
+    }).chain_err(|| ErrorKind::ConfigSection(section.id.clone()))
+}
+
+/// Format of a configuration file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Format of the given file, based on its extension.
+    ///
+    /// Returns `None` for files which are not configuration files. Files given explicitly are
+    /// loaded as TOML in that case.
+    pub fn from_path(path: &Path) -> Option<ConfigFormat> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Some(ConfigFormat::Toml),
+            Some("yaml") | Some("yml") => Some(ConfigFormat::Yaml),
+            _ => None,
+        }
+    }
+}
+
+/// Find all `*.toml`, `*.yaml`, and `*.yml` files in the given directory, sorted by path.
+///
+/// Subdirectories are only searched if `recursive` is set.
+pub fn find_config_files(dir: &Path, recursive: bool) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    find_config_files_into(dir, recursive, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn find_config_files_into(dir: &Path, recursive: bool, files: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            if recursive {
+                find_config_files_into(&path, recursive, files)?;
+            }
+
+            continue;
+        }
+
+        if ConfigFormat::from_path(&path).is_some() {
+            files.push(path.to_string_lossy().into_owned());
+        }
     }
 
-    Ok(values)
+    Ok(())
 }
 
 /// Read optional fields from input configuration.
@@ -143,19 +1053,164 @@ macro_rules! read_config {
     };
 }
 
-pub fn load_config(config: &mut Config, path: &String) -> Result<Box<PluginSetup>> {
-    let mut file = fs::File::open(path)?;
+/// Load a configuration file, in the format given by its extension.
+pub fn load_config_file(config: &mut Config, path: &String) -> Result<Vec<PluginSection>> {
+    let root = parse_config_file(path)?;
+    apply_config(config, path, root)
+}
+
+/// Load configuration from the given source, like a file or stdin.
+pub fn load_config<R: Read>(
+    config: &mut Config,
+    path: &str,
+    source: R,
+    format: ConfigFormat,
+) -> Result<Vec<PluginSection>> {
+    let root = parse_config(path, source, format)?;
+    let root = resolve_includes(path, root)?;
+    apply_config(config, path, root)
+}
+
+/// Directory of a configuration file, which relative paths in it are resolved against.
+///
+/// Paths in configuration read from stdin are relative to the working directory.
+pub fn config_dir(path: &str) -> PathBuf {
+    Path::new(path).parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+/// Read and parse a configuration file, in the format given by its extension.
+pub fn parse_config_file(path: &String) -> Result<toml::Table> {
+    let file = fs::File::open(path)?;
+    let format = ConfigFormat::from_path(Path::new(path)).unwrap_or(ConfigFormat::Toml);
+    parse_config(path, file, format)
+}
 
+/// Read and parse configuration, without applying it.
+///
+/// Parsing doesn't depend on other files, so several files can be parsed in parallel and then
+/// applied in order. Syntax errors are reported at a line and column of `path`.
+pub fn parse_config<R: Read>(
+    path: &str,
+    mut source: R,
+    format: ConfigFormat,
+) -> Result<toml::Table> {
     let mut content = String::new();
-    file.read_to_string(&mut content)?;
+    source.read_to_string(&mut content)?;
+
+    let mut table = match format {
+        ConfigFormat::Yaml => parse_yaml(&content)?,
+        ConfigFormat::Toml => {
+            let mut parser = toml::Parser::new(&content);
+
+            match parser.parse() {
+                Some(value) => value,
+                None => {
+                    let errors = SyntaxError::from_parser(&parser);
+                    return Err(ErrorKind::TomlParse(path.to_owned(), errors).into());
+                }
+            }
+        }
+    };
+
+    // expanded after parsing, so that syntax errors point at what is in the file.
+    interpolate_table(&mut table)?;
+    Ok(table)
+}
 
-    let mut parser = toml::Parser::new(&mut content);
+/// Merge `overlay` into `base`.
+///
+/// Tables are merged key by key, any other value of `overlay`, including arrays, replaces the value
+/// of `base`.
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        let value = match (base.get_mut(&key), value) {
+            (Some(&mut toml::Value::Table(ref mut existing)), toml::Value::Table(value)) => {
+                merge_tables(existing, value);
+                continue;
+            }
+            (_, value) => value,
+        };
 
-    let root = match parser.parse() {
-        Some(value) => value,
-        None => return Err(ErrorKind::TomlParse(parser.errors).into()),
+        base.insert(key, value);
+    }
+}
+
+/// Load the files listed in the `include` key of a parsed configuration file, and merge the file
+/// on top of them.
+///
+/// Included paths are relative to the directory of the including file, and are merged in the
+/// order they are listed, so later ones and the including file itself override earlier ones.
+pub fn resolve_includes(path: &str, root: toml::Table) -> Result<toml::Table> {
+    resolve_includes_in(path, root, &mut Vec::new())
+}
+
+fn resolve_includes_in(
+    path: &str,
+    mut root: toml::Table,
+    including: &mut Vec<(PathBuf, String)>,
+) -> Result<toml::Table> {
+    let includes = match root.remove("include") {
+        None => return Ok(root),
+        Some(toml::Value::Array(includes)) => includes,
+        Some(_) => {
+            return Err(
+                ErrorKind::ConfigField("include".to_owned(), "must be an array".to_owned()).into(),
+            );
+        }
     };
 
+    let dir = config_dir(path);
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    including.push((canonical, path.to_owned()));
+
+    let mut merged = toml::Table::new();
+
+    for include in includes {
+        let include = match include {
+            toml::Value::String(include) => dir.join(include),
+            _ => {
+                return Err(
+                    ErrorKind::ConfigField(
+                        "include".to_owned(),
+                        "must be an array of strings".to_owned(),
+                    ).into(),
+                );
+            }
+        };
+
+        let display = include.to_string_lossy().into_owned();
+
+        let canonical = fs::canonicalize(&include).chain_err(|| {
+            ErrorKind::Config(display.clone())
+        })?;
+
+        if let Some(index) = including.iter().position(|&(ref p, _)| *p == canonical) {
+            let mut cycle: Vec<String> = including[index..].iter().map(|i| i.1.clone()).collect();
+            cycle.push(display);
+            return Err(ErrorKind::IncludeCycle(cycle).into());
+        }
+
+        let included = parse_config_file(&display)
+            .and_then(|included| resolve_includes_in(&display, included, including))
+            .chain_err(|| ErrorKind::Config(display.clone()))?;
+
+        merge_tables(&mut merged, included);
+    }
+
+    including.pop();
+    merge_tables(&mut merged, root);
+    Ok(merged)
+}
+
+/// Apply a parsed configuration file on top of the configuration loaded so far.
+///
+/// Returns the plugin sections of the file, whose relative paths are resolved against the
+/// directory of `path`.
+pub fn apply_config(
+    config: &mut Config,
+    path: &str,
+    root: toml::Table,
+) -> Result<Vec<PluginSection>> {
     let config_in: ConfigIn = {
         let mut decoder = toml::Decoder::new(toml::Value::Table(root.clone()));
         serde::Deserialize::deserialize(&mut decoder)
@@ -171,52 +1226,559 @@ pub fn load_config(config: &mut Config, path: &String) -> Result<Box<PluginSetup
             );
         }
 
-        config.threads = threads;
+        config.threads = Some(threads);
+    }
+
+    if let Some(max_concurrency) = config_in.max_concurrency {
+        if max_concurrency <= 0 {
+            return Err(
+                ErrorKind::ConfigField(
+                    "max_concurrency".to_owned(),
+                    "must be a positive number".to_owned(),
+                ).into(),
+            );
+        }
+
+        config.max_concurrency = Some(max_concurrency);
+    }
+
+    if let Some(max_queued_tasks) = config_in.max_queued_tasks {
+        if max_queued_tasks <= 0 {
+            return Err(
+                ErrorKind::ConfigField(
+                    "max_queued_tasks".to_owned(),
+                    "must be a positive number".to_owned(),
+                ).into(),
+            );
+        }
+
+        config.max_queued_tasks = Some(max_queued_tasks);
+    }
+
+    if let Some(max_samples_per_second) = config_in.max_samples_per_second {
+        if max_samples_per_second == 0 {
+            return Err(
+                ErrorKind::ConfigField(
+                    "max_samples_per_second".to_owned(),
+                    "must be a positive number".to_owned(),
+                ).into(),
+            );
+        }
+
+        config.max_samples_per_second = Some(max_samples_per_second);
+    }
+
+    if let Some(jitter) = config_in.jitter {
+        if jitter < 0f64 || jitter > 1f64 {
+            return Err(
+                ErrorKind::ConfigField(
+                    "jitter".to_owned(),
+                    "must be a number between 0 and 1".to_owned(),
+                ).into(),
+            );
+        }
+
+        config.jitter = jitter;
+    }
+
+    if let Some(jitter_seed) = config_in.jitter_seed {
+        config.jitter_seed = Some(jitter_seed);
+    }
+
+    if let Some(buffer_size) = config_in.buffer_size {
+        if buffer_size == 0 {
+            return Err(
+                ErrorKind::ConfigField(
+                    "buffer_size".to_owned(),
+                    "must be a positive number".to_owned(),
+                ).into(),
+            );
+        }
+
+        config.buffer_size = buffer_size;
+    }
+
+    // an empty tag disables the hostname tag.
+    if let Some(host_tag) = config_in.host_tag {
+        config.host_tag = if host_tag.is_empty() {
+            None
+        } else {
+            Some(host_tag)
+        };
+    }
+
+    if let Some(control_socket) = config_in.control_socket {
+        config.control_socket = Some(control_socket);
     }
 
-    read_config!(
-        config,
-        config_in,
-        [threads_per_cpu, update_interval, poll_interval]
-    );
+    if let Some(health_threshold) = config_in.health_threshold {
+        if health_threshold == 0 {
+            return Err(
+                ErrorKind::ConfigField(
+                    "health_threshold".to_owned(),
+                    "must be a positive number".to_owned(),
+                ).into(),
+            );
+        }
+
+        config.health_threshold = health_threshold;
+    }
 
-    let mut input_configs = Vec::new();
-    let mut output_configs = Vec::new();
+    read_config!(config, config_in, [threads_per_cpu, internal_metrics]);
 
-    if let Some(i) = root.get("in") {
-        input_configs.push(i.clone());
+    if let Some(update_interval) = decode_duration(&root, "update_interval")? {
+        config.update_interval = update_interval;
     }
 
-    if let Some(o) = root.get("out") {
-        output_configs.push(o.clone());
+    if let Some(poll_interval) = decode_duration(&root, "poll_interval")? {
+        config.poll_interval = poll_interval;
     }
 
-    Ok(Box::new(move |_config, plugins, partial_context| {
-        let mut inputs: Vec<Arc<Box<InputInstance>>> = Vec::new();
-        let mut outputs: Vec<Box<OutputInstance>> = Vec::new();
+    if let Some(poll_timeout) = decode_duration(&root, "poll_timeout")? {
+        config.poll_timeout = poll_timeout;
+    }
 
-        for i in input_configs.iter() {
-            let loaded = load_section(&i, |plugin_type| plugins.get_input(plugin_type), |plugin,
-             id,
-             config| {
-                plugin.setup(partial_context.build(id, config)).map(
-                    Arc::new,
-                )
-            }).chain_err(|| ErrorKind::ConfigSection("in".to_owned()))?;
+    if let Some(heartbeat_interval) = decode_duration(&root, "heartbeat_interval")? {
+        config.heartbeat_interval = Some(heartbeat_interval);
+    }
 
-            inputs.extend(loaded);
+    // durations are never zero, so a plain `0` is left to disable the status summary.
+    match root.get("status_interval") {
+        Some(&toml::Value::Integer(0)) => config.status_interval = None,
+        _ => {
+            if let Some(status_interval) = decode_duration(&root, "status_interval")? {
+                config.status_interval = Some(status_interval);
+            }
         }
+    }
 
-        for o in output_configs.iter() {
-            let loaded = load_section(&o, |plugin_type| plugins.get_output(plugin_type), |plugin,
-             id,
-             config| {
-                plugin.setup(partial_context.build(id, config))
-            }).chain_err(|| ErrorKind::ConfigSection("out".to_owned()))?;
+    // rules of later files are tried after the rules of earlier ones.
+    config.rename.extend(decode_rename(&root, "rename")?);
+    config.histograms.extend(decode_histograms(&root, "histogram")?);
 
-            outputs.extend(loaded);
+    // tags from later files override tags with the same name from earlier ones.
+    for (name, value) in decode_tags(&root, "tags")? {
+        config.tags.retain(|&(ref k, _)| *k != name);
+        config.tags.push((name, value));
+    }
+
+    match root.get("clock") {
+        None => {}
+        Some(&toml::Value::String(ref clock)) if clock == "wall" => config.clock = Clock::Wall,
+        Some(&toml::Value::String(ref clock)) if clock == "monotonic" => {
+            config.clock = Clock::Monotonic
+        }
+        Some(_) => {
+            return Err(
+                ErrorKind::ConfigField(
+                    "clock".to_owned(),
+                    "must be \"wall\" or \"monotonic\"".to_owned(),
+                ).into(),
+            );
         }
+    }
 
-        Ok((inputs, outputs))
-    }))
+    match root.get("reactor") {
+        None => {}
+        Some(&toml::Value::String(ref value)) if value == "pool" => config.reactor = Reactor::Pool,
+        Some(&toml::Value::String(ref value)) if value == "single" => {
+            config.reactor = Reactor::Single
+        }
+        Some(_) => {
+            return Err(
+                ErrorKind::ConfigField(
+                    "reactor".to_owned(),
+                    "must be \"pool\" or \"single\"".to_owned(),
+                ).into(),
+            );
+        }
+    }
+
+    match root.get("on_overrun") {
+        None => {}
+        Some(&toml::Value::String(ref value)) if value == "skip" => {
+            config.on_overrun = Overrun::Skip
+        }
+        Some(&toml::Value::String(ref value)) if value == "catch_up" => {
+            config.on_overrun = Overrun::CatchUp
+        }
+        Some(_) => {
+            return Err(
+                ErrorKind::ConfigField(
+                    "on_overrun".to_owned(),
+                    "must be \"skip\" or \"catch_up\"".to_owned(),
+                ).into(),
+            );
+        }
+    }
+
+    match root.get("on_full") {
+        None => {}
+        Some(&toml::Value::String(ref value)) if value == "block" => config.on_full = OnFull::Block,
+        Some(&toml::Value::String(ref value)) if value == "drop_oldest" => {
+            config.on_full = OnFull::DropOldest
+        }
+        Some(&toml::Value::String(ref value)) if value == "drop_newest" => {
+            config.on_full = OnFull::DropNewest
+        }
+        Some(_) => {
+            return Err(
+                ErrorKind::ConfigField(
+                    "on_full".to_owned(),
+                    "must be \"block\", \"drop_oldest\" or \"drop_newest\"".to_owned(),
+                ).into(),
+            );
+        }
+    }
+
+    match root.get("on_non_finite") {
+        None => {}
+        Some(&toml::Value::String(ref value)) if value == "drop" => {
+            config.on_non_finite = OnNonFinite::Drop
+        }
+        Some(&toml::Value::String(ref value)) if value == "clamp" => {
+            config.on_non_finite = OnNonFinite::Clamp
+        }
+        Some(_) => {
+            return Err(
+                ErrorKind::ConfigField(
+                    "on_non_finite".to_owned(),
+                    "must be \"drop\" or \"clamp\"".to_owned(),
+                ).into(),
+            );
+        }
+    }
+
+    let mut sections = Vec::new();
+    let base_dir = config_dir(path);
+
+    for kind in vec![PluginKind::Input, PluginKind::Output] {
+        if let Some(section) = root.get(kind.table()) {
+            sections.extend(load_sections(kind, section, &base_dir).chain_err(|| {
+                ErrorKind::ConfigSection(kind.table().to_owned())
+            })?);
+        }
+    }
+
+    Ok(sections)
+}
+
+/// Global options, as they appear at the top of a generated configuration.
+const GLOBAL_EXAMPLE: &'static str = "\
+# threads = 4
+# threads_per_cpu = false
+# max_concurrency = 4
+# max_queued_tasks = 16
+# max_samples_per_second = 10000
+# poll_interval = \"10s\"
+# update_interval = \"1s\"
+# poll_timeout = \"30s\"
+# heartbeat_interval = \"30s\"
+# status_interval = \"60s\"
+# jitter = 0.1
+# health_threshold = 3
+# internal_metrics = false
+# clock = \"wall\"
+# reactor = \"pool\"
+# on_overrun = \"skip\"
+# control_socket = \"/run/sysmon.sock\"
+# host_tag = \"host\"
+# buffer_size = 10000
+# on_full = \"drop_oldest\"
+# on_non_finite = \"drop\"
+#
+# [tags]
+# host = \"web-1\"
+#
+# [[rename]]
+# pattern = \"^app\\\\.(.*)$\"
+# replace = \"$1\"
+# prefix = \"prod.\"
+#
+# [[histogram]]
+# pattern = \"^tcp_check$\"
+# buckets = [1.0, 5.0, 10.0, 50.0, 100.0, 500.0]
+";
+
+/// Generate a commented configuration skeleton showing every registered plugin.
+pub fn generate_config(plugins: &PluginRegistry) -> Result<String> {
+    let mut out = String::new();
+
+    out.push_str("# Generated by sysmon --generate-config.\n");
+    out.push_str("# Uncomment the sections and options you want to use.\n\n");
+    out.push_str(GLOBAL_EXAMPLE);
+
+    for description in plugins.describe() {
+        let plugin_type = &description.plugin_type;
+
+        let (table, example) = match description.kind {
+            "input" => {
+                let entry = plugins.get_input(plugin_type).ok_or_else(|| {
+                    ErrorKind::MissingPlugin(plugin_type.clone())
+                })?;
+
+                ("in", entry()?.example_config())
+            }
+            _ => {
+                let entry = plugins.get_output(plugin_type).ok_or_else(|| {
+                    ErrorKind::MissingPlugin(plugin_type.clone())
+                })?;
+
+                ("out", entry()?.example_config())
+            }
+        };
+
+        out.push_str(&format!("\n# [{}.{}]\n", table, plugin_type));
+        out.push_str(&format!("# type = \"{}\"\n", plugin_type));
+
+        for line in example.unwrap_or("").lines() {
+            out.push_str("# ");
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::io::Write;
+
+    fn parse(content: &str) -> toml::Table {
+        toml::Parser::new(content).parse().unwrap()
+    }
+
+    /// Write the given files into an empty directory of its own, returning the directory.
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("sysmon-config-test-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        for &(file, content) in files {
+            let mut f = fs::File::create(dir.join(file)).unwrap();
+            f.write_all(content.as_bytes()).unwrap();
+        }
+
+        dir
+    }
+
+    fn load(path: &Path) -> Result<toml::Table> {
+        let path = path.to_string_lossy().into_owned();
+        let root = parse_config_file(&path)?;
+        resolve_includes(&path, root)
+    }
+
+    /// Ids of the sections which are left enabled by the given `--only` and `--disable` lists.
+    fn selected(only: &[&str], disable: &[&str]) -> Vec<String> {
+        let mut config = Config::new();
+        config.only_plugins = only.iter().map(|s| s.to_string()).collect();
+        config.disabled_plugins = disable.iter().map(|s| s.to_string()).collect();
+
+        let mut sections = vec![
+            PluginSection::parse(PluginKind::Input, "cpu", "type = \"cpu\""),
+            PluginSection::parse(PluginKind::Input, "load", "type = \"load\"\nname = \"system\""),
+            PluginSection::parse(PluginKind::Output, "cpu", "type = \"stdout\""),
+            PluginSection::parse(PluginKind::Output, "influxdb", "type = \"influxdb\""),
+        ];
+
+        config.select_sections(&mut sections);
+
+        sections
+            .iter()
+            .filter(|s| s.is_enabled())
+            .map(|s| format!("{}.{}", s.kind.table(), s.id))
+            .collect()
+    }
+
+    #[test]
+    fn test_select_sections() {
+        let all = vec!["in.cpu", "in.load", "out.cpu", "out.influxdb"];
+
+        assert_eq!(all, selected(&[], &[]));
+        // names refer to the id, the name, or the type of a section.
+        assert_eq!(vec!["in.load", "out.cpu", "out.influxdb"], selected(&["system"], &[]));
+        assert_eq!(vec!["in.cpu", "in.load", "out.influxdb"], selected(&["influxdb"], &[]));
+        assert_eq!(vec!["in.cpu", "in.load", "out.cpu"], selected(&[], &["influxdb"]));
+        // unknown names only warn.
+        assert_eq!(all, selected(&["nothing"], &["nothing"]));
+    }
+
+    #[test]
+    fn test_select_sections_kind() {
+        // a bare name refers to sections of every kind.
+        assert_eq!(vec!["in.cpu", "out.cpu"], selected(&["cpu"], &[]));
+        assert_eq!(vec!["in.load", "out.influxdb"], selected(&[], &["cpu"]));
+
+        // a name prefixed by its table only refers to sections of that kind.
+        assert_eq!(vec!["in.cpu", "out.cpu", "out.influxdb"], selected(&["in.cpu"], &[]));
+        assert_eq!(vec!["in.cpu", "in.load", "out.influxdb"], selected(&["out.influxdb"], &[]));
+        assert_eq!(vec!["in.load", "out.cpu", "out.influxdb"], selected(&[], &["in.cpu"]));
+        assert_eq!(vec!["in.cpu", "in.load", "out.influxdb"], selected(&[], &["out.stdout"]));
+    }
+
+    #[test]
+    fn test_select_sections_conflict() {
+        // sections which are both listed and disabled are disabled.
+        assert_eq!(vec!["out.cpu"], selected(&["cpu"], &["in.cpu"]));
+        assert_eq!(vec!["out.cpu", "out.influxdb"], selected(&["in.cpu"], &["in.cpu"]));
+    }
+
+    #[test]
+    fn test_merge_tables() {
+        let mut base = parse("a = 1\nlist = [1, 2]\n[t]\nx = 1\ny = 1\n[t.nested]\nz = 1");
+        let overlay = parse("b = 2\nlist = [3]\n[t]\ny = 2\n[t.nested]\nw = 2");
+
+        merge_tables(&mut base, overlay);
+
+        let expected = parse(
+            "a = 1\nb = 2\nlist = [3]\n[t]\nx = 1\ny = 2\n[t.nested]\nz = 1\nw = 2",
+        );
+
+        assert_eq!(expected, base);
+    }
+
+    #[test]
+    fn test_resolve_includes() {
+        let dir = write_files(
+            "includes",
+            &[
+                ("base.toml", "[tags]\nenv = \"base\"\nregion = \"eu\"\n"),
+                (
+                    "common.toml",
+                    "include = [\"base.toml\"]\n[tags]\nenv = \"common\"\n\
+                     [out.stdout]\ntype = \"stdout\"\n",
+                ),
+                (
+                    "sysmon.toml",
+                    "include = [\"common.toml\"]\n[tags]\nenv = \"prod\"\n\
+                     [in.cpu]\ntype = \"cpu\"\n",
+                ),
+            ],
+        );
+
+        let expected = parse(
+            "[tags]\nenv = \"prod\"\nregion = \"eu\"\n\
+             [out.stdout]\ntype = \"stdout\"\n\
+             [in.cpu]\ntype = \"cpu\"\n",
+        );
+
+        assert_eq!(expected, load(&dir.join("sysmon.toml")).unwrap());
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let dir = write_files(
+            "include-cycle",
+            &[
+                ("a.toml", "include = [\"b.toml\"]\n"),
+                ("b.toml", "include = [\"a.toml\"]\n"),
+            ],
+        );
+
+        let e = load(&dir.join("a.toml")).unwrap_err();
+
+        // the cycle is reported within the error of the file which closes it.
+        let mut next = Some(&e);
+        let mut cycle = None;
+
+        while let Some(e) = next {
+            if let ErrorKind::IncludeCycle(ref paths) = *e.kind() {
+                cycle = Some(paths.clone());
+            }
+
+            next = e.1.next_error.as_ref().and_then(|e| e.downcast_ref::<Error>());
+        }
+
+        let cycle = cycle.expect("expected an include cycle");
+        let names: Vec<_> = cycle.iter().map(|p| p.rsplit('/').next().unwrap()).collect();
+        assert_eq!(vec!["a.toml", "b.toml", "a.toml"], names);
+    }
+
+    #[test]
+    fn test_timing_problem() {
+        let config = Config::new();
+        let problem = |content: &str, default_timeout: bool| {
+            let section = PluginSection::parse(PluginKind::Input, "cpu", content);
+            timing_problem(&config, &section, default_timeout).map(|(field, _)| field)
+        };
+
+        // only a timeout somebody asked for is checked.
+        assert_eq!(None, problem("interval = \"1s\"", true));
+        assert_eq!(None, problem("poll_timeout = \"5s\"", true));
+        assert_eq!(Some("poll_timeout".to_owned()), problem("poll_timeout = \"10s\"", true));
+        assert_eq!(
+            Some("poll_timeout".to_owned()),
+            problem("interval = \"5s\"\npoll_timeout = \"5s\"", true)
+        );
+        assert_eq!(Some("poll_timeout".to_owned()), problem("interval = \"1s\"", false));
+    }
+
+    #[test]
+    fn test_check_sections_reserved() {
+        let sections = vec![
+            PluginSection::parse(PluginKind::Input, "cpu", "type = \"cpu\""),
+            PluginSection::parse(PluginKind::Output, "stdout", "type = \"stdout\""),
+        ];
+
+        assert!(check_sections(&sections, true).is_ok());
+
+        let reserved = [
+            ("self", "type = \"cpu\""),
+            ("cpu2", "type = \"cpu\"\nname = \"self\""),
+        ];
+
+        for &(id, content) in reserved.iter() {
+            let mut sections = sections.clone();
+            sections.push(PluginSection::parse(PluginKind::Input, id, content));
+
+            let e = check_sections(&sections, true).unwrap_err();
+
+            match *e.kind() {
+                ErrorKind::ConfigSection(ref section) => assert_eq!(id, section),
+                ref kind => panic!("unexpected error: {}", kind),
+            }
+        }
+
+        // disabled sections are never set up.
+        let mut disabled = sections.clone();
+        let content = "type = \"cpu\"\nenabled = false";
+        disabled.push(PluginSection::parse(PluginKind::Input, "self", content));
+        assert!(check_sections(&disabled, true).is_ok());
+    }
+
+    #[test]
+    fn test_parse_duration_str() {
+        assert_eq!(Duration::new(90, 0), parse_duration_str("1m30s").unwrap());
+        assert!(parse_duration_str("5x").is_err());
+        assert!(parse_duration_str("0s").is_err());
+        assert!(parse_duration_str("0ms").is_err());
+        assert!(parse_duration_str("0m0s").is_err());
+    }
+
+    #[test]
+    fn test_status_interval() {
+        let status_interval = |content: &str| {
+            let mut config = Config::new();
+            apply_config(&mut config, "sysmon.toml", parse(content)).unwrap();
+            config.status_interval
+        };
+
+        assert_eq!(Some(Duration::new(60, 0)), status_interval(""));
+        assert_eq!(Some(Duration::new(30, 0)), status_interval("status_interval = \"30s\""));
+        assert_eq!(None, status_interval("status_interval = 0"));
+    }
+
+    #[test]
+    fn test_decode_duration() {
+        let table = toml::Parser::new("a = \"10s\"\nb = 5\nc = \"0s\"\nd = 0").parse().unwrap();
+
+        assert_eq!(Some(Duration::new(10, 0)), decode_duration(&table, "a").unwrap());
+        assert_eq!(Some(Duration::new(5, 0)), decode_duration(&table, "b").unwrap());
+        assert!(decode_duration(&table, "c").is_err());
+        assert!(decode_duration(&table, "d").is_err());
+        assert_eq!(None, decode_duration(&table, "missing").unwrap());
+    }
 }
@@ -0,0 +1,193 @@
+//! Command interface of a running sysmon, over a unix domain socket.
+//!
+//! Clients write one command per line, and get one JSON object per line back. Successful commands
+//! respond with `{"ok": true, "result": ...}`, failed ones with `{"ok": false, "error": "..."}`.
+
+use errors::*;
+use futures::{Future, IntoFuture};
+use futures::stream::Stream;
+use serde_json;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufReader};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use tokio_core::reactor::Handle;
+use tokio_io::AsyncRead;
+use tokio_io::io as async_io;
+use tokio_uds::UnixListener;
+
+/// A command sent to the control socket.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Reload the configuration, like `SIGHUP`.
+    Reload,
+    /// List the running plugin instances.
+    ListPlugins,
+    /// Health of the named input instance.
+    PluginStatus(String),
+    /// Poll the named input instance immediately, or every input instance if no name is given.
+    PollNow(Option<String>),
+}
+
+/// Parse a single line sent to the control socket.
+pub fn parse_command(line: &str) -> Result<Command> {
+    let mut parts = line.split_whitespace();
+
+    let command = match parts.next() {
+        Some(command) => command,
+        None => return Err(ErrorKind::Message("empty command".to_owned()).into()),
+    };
+
+    let argument = parts.next().map(ToOwned::to_owned);
+
+    if parts.next().is_some() {
+        return Err(ErrorKind::Message(format!("{}: too many arguments", command)).into());
+    }
+
+    let command = match (command, argument) {
+        ("reload", None) => Command::Reload,
+        ("list-plugins", None) => Command::ListPlugins,
+        ("plugin-status", Some(name)) => Command::PluginStatus(name),
+        ("poll-now", name) => Command::PollNow(name),
+        ("plugin-status", None) => {
+            return Err(ErrorKind::Message(format!("{}: expected a name", command)).into());
+        }
+        ("reload", Some(_)) | ("list-plugins", Some(_)) => {
+            return Err(ErrorKind::Message(format!("{}: takes no arguments", command)).into());
+        }
+        (command, _) => {
+            return Err(ErrorKind::Message(format!("unknown command: {}", command)).into());
+        }
+    };
+
+    Ok(command)
+}
+
+pub type Handler = Rc<Fn(Command) -> Result<serde_json::Value>>;
+
+#[derive(Serialize, Debug)]
+struct Response {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Respond to a single line.
+fn respond(handler: &Handler, line: &str) -> Result<String> {
+    let response = match parse_command(line).and_then(|command| handler(command)) {
+        Ok(result) => Response {
+            ok: true,
+            result: Some(result),
+            error: None,
+        },
+        Err(e) => Response {
+            ok: false,
+            result: None,
+            error: Some(e.to_string()),
+        },
+    };
+
+    let mut response = serde_json::to_string(&response)?;
+    response.push('\n');
+    Ok(response)
+}
+
+fn report_and_discard<E: fmt::Display>(e: E) -> () {
+    info!("control: an error occured: {}", e);
+}
+
+/// The bound control socket, which is removed when dropped.
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("could not remove control socket {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Bind the control socket at the given path, and answer every command using `handler`.
+///
+/// A socket left behind by a previous run is replaced, any other kind of file is not.
+pub fn serve(handle: &Handle, path: &Path, handler: Handler) -> Result<ControlSocket> {
+    match fs::symlink_metadata(path) {
+        Ok(ref m) if m.file_type().is_socket() => {
+            fs::remove_file(path).chain_err(|| {
+                format!("could not remove stale control socket {}", path.display())
+            })?
+        }
+        Ok(_) => {
+            return Err(
+                ErrorKind::Message(format!("{}: exists, and is not a socket", path.display()))
+                    .into(),
+            );
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let listener = UnixListener::bind(path, handle).chain_err(|| {
+        format!("could not bind control socket {}", path.display())
+    })?;
+
+    let conn_handle = handle.clone();
+
+    let accept = listener.incoming().map_err(report_and_discard).for_each(
+        move |(socket, _addr)| {
+            let handler = handler.clone();
+            let (reader, writer) = socket.split();
+
+            // commands of a connection are answered in order, until the client disconnects.
+            let conn = async_io::lines(BufReader::new(reader))
+                .map_err(Into::<Error>::into)
+                .fold(writer, move |writer, line| {
+                    respond(&handler, &line).into_future().and_then(|response| {
+                        async_io::write_all(writer, response.into_bytes())
+                            .map(|(writer, _)| writer)
+                            .map_err(Into::into)
+                    })
+                })
+                .map(|_| ())
+                .map_err(report_and_discard);
+
+            conn_handle.spawn(conn);
+            Ok(())
+        },
+    );
+
+    handle.spawn(accept);
+    Ok(ControlSocket { path: path.to_owned() })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(Command::Reload, parse_command("reload").unwrap());
+        assert_eq!(Command::ListPlugins, parse_command(" list-plugins ").unwrap());
+        assert_eq!(
+            Command::PluginStatus("cpu".to_owned()),
+            parse_command("plugin-status cpu").unwrap()
+        );
+        assert_eq!(
+            Command::PollNow(Some("cpu".to_owned())),
+            parse_command("poll-now cpu").unwrap()
+        );
+        assert_eq!(Command::PollNow(None), parse_command("poll-now").unwrap());
+
+        assert!(parse_command("").is_err());
+        assert!(parse_command("plugin-status").is_err());
+        assert!(parse_command("reload now").is_err());
+        assert!(parse_command("poll-now cpu disk").is_err());
+        assert!(parse_command("restart").is_err());
+    }
+}
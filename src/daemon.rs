@@ -0,0 +1,108 @@
+//! Running sysmon in the background, for systems without a service manager.
+
+use errors::*;
+use libc;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+fn check(result: libc::c_int, what: &str) -> Result<libc::c_int> {
+    if result < 0 {
+        return Err(io::Error::last_os_error()).chain_err(|| format!("{} failed", what));
+    }
+
+    Ok(result)
+}
+
+/// Point a standard file descriptor at the given file.
+fn redirect(fd: libc::c_int, path: &Path, append: bool) -> Result<()> {
+    let c_path = CString::new(path.to_string_lossy().into_owned()).map_err(|e| {
+        ErrorKind::Message(e.to_string())
+    })?;
+
+    let flags = if append {
+        libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND
+    } else {
+        libc::O_RDWR
+    };
+
+    let file = check(
+        unsafe { libc::open(c_path.as_ptr(), flags, 0o644) },
+        &format!("open {}", path.display()),
+    )?;
+
+    check(unsafe { libc::dup2(file, fd) }, "dup2")?;
+
+    if file != fd {
+        unsafe { libc::close(file) };
+    }
+
+    Ok(())
+}
+
+/// Fork into the background and detach from the controlling terminal.
+///
+/// Only the final child returns, the parents exit. Stdin is redirected to `/dev/null`, and so are
+/// stdout and stderr unless `output` is given, in which case they are appended to it.
+///
+/// This has to be called before any threads are started, since only the calling thread survives
+/// the fork. Signal handlers have to be installed after it, in the child.
+pub fn daemonize(output: Option<&Path>) -> Result<()> {
+    // the first child is not a process group leader, so it can start a new session.
+    if check(unsafe { libc::fork() }, "fork")? > 0 {
+        unsafe { libc::_exit(0) };
+    }
+
+    check(unsafe { libc::setsid() }, "setsid")?;
+
+    // the second child is no session leader, so it can never acquire a controlling terminal.
+    if check(unsafe { libc::fork() }, "fork")? > 0 {
+        unsafe { libc::_exit(0) };
+    }
+
+    // the working directory is kept, since configuration and log files are opened again by path
+    // on reload and rotation.
+
+    let null = Path::new("/dev/null");
+    redirect(libc::STDIN_FILENO, null, false)?;
+
+    match output {
+        Some(output) => {
+            redirect(libc::STDOUT_FILENO, output, true)?;
+            redirect(libc::STDERR_FILENO, output, true)?;
+        }
+        None => {
+            redirect(libc::STDOUT_FILENO, null, false)?;
+            redirect(libc::STDERR_FILENO, null, false)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A file holding the pid of the running process, which is removed when dropped.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the pid of the current process to the given path.
+    pub fn create(path: &Path) -> Result<PidFile> {
+        let pid = unsafe { libc::getpid() };
+
+        File::create(path)
+            .and_then(|mut f| writeln!(f, "{}", pid))
+            .chain_err(|| format!("could not write pid file {}", path.display()))?;
+
+        Ok(PidFile { path: path.to_owned() })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("could not remove pid file {}: {}", self.path.display(), e);
+        }
+    }
+}
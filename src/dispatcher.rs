@@ -0,0 +1,594 @@
+use errors::*;
+use futures::*;
+use futures_cpupool::CpuPool;
+use metric::MetricId;
+use plugin::{ConfiguredOutput, Sample, SampleKind};
+use rand;
+use scheduler::Runnable;
+use spool::Spool;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::Entry;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use telemetry::Telemetry;
+
+const DEFAULT_INITIAL_BACKOFF_MS: u64 = 1000;
+const DEFAULT_MAX_BACKOFF_SECS: u64 = 300;
+const DEFAULT_BACKOFF_BUFFER_SIZE: usize = 10000;
+const DEFAULT_DEDUP_HEARTBEAT: usize = 10;
+/// Gauges which are not seen for this long are forgotten by `dedup`.
+const DEDUP_EXPIRY_SECS: u64 = 600;
+
+struct Backoff {
+    /// Samples which could not be delivered, oldest first.
+    pending: VecDeque<Sample>,
+    /// Current delay between attempts, `None` if the last delivery succeeded.
+    delay: Option<Duration>,
+    /// No delivery is attempted before this time.
+    retry_at: Option<Instant>,
+    /// Samples which didn't fit in the buffer, which are all older than the pending ones.
+    spool: Option<Spool>,
+    /// Set while samples are fed to the output, during which newer samples are retained.
+    in_flight: bool,
+}
+
+impl Backoff {
+    /// Retain samples for a later attempt.
+    ///
+    /// If the buffer is full the oldest samples are moved to the spool, or dropped without one.
+    fn retain(&mut self, output: &ConfiguredOutput, samples: Vec<Sample>) {
+        let cap = output.options.backoff_buffer_size.unwrap_or(
+            DEFAULT_BACKOFF_BUFFER_SIZE,
+        );
+
+        self.pending.extend(samples);
+        self.enforce_cap(output, cap);
+    }
+
+    /// Retain samples of a failed delivery, which are older than the ones retained meanwhile.
+    fn requeue(&mut self, output: &ConfiguredOutput, samples: Vec<Sample>) {
+        let cap = output.options.backoff_buffer_size.unwrap_or(
+            DEFAULT_BACKOFF_BUFFER_SIZE,
+        );
+
+        for sample in samples.into_iter().rev() {
+            self.pending.push_front(sample);
+        }
+
+        self.enforce_cap(output, cap);
+    }
+
+    /// Move the oldest pending samples beyond `cap` to the spool, or drop them without one.
+    fn enforce_cap(&mut self, output: &ConfiguredOutput, cap: usize) {
+        if self.pending.len() <= cap {
+            return;
+        }
+
+        let excess = self.pending.len() - cap;
+        let overflow: Vec<Sample> = self.pending.drain(..excess).collect();
+
+        let dropped = match self.spool {
+            Some(ref mut spool) => Self::spill(output, spool, &overflow),
+            None => overflow.len(),
+        };
+
+        if dropped > 0 {
+            warn!("{}: backoff buffer is full, dropped {} sample(s)", output.options.name, dropped);
+        }
+    }
+
+    /// Append samples to the spool, returning how many of them were dropped.
+    fn spill(output: &ConfiguredOutput, spool: &mut Spool, samples: &[Sample]) -> usize {
+        match spool.push(samples) {
+            Ok(dropped) => dropped,
+            Err(e) => {
+                error!("{}: failed to write to spool: {}", output.options.name, e);
+                samples.len()
+            }
+        }
+    }
+}
+
+/// Last delivered value of a gauge.
+struct Delivered {
+    value: f64,
+    /// Number of deliveries in a row the gauge was suppressed in since.
+    suppressed: usize,
+    /// Delivery the gauge was last seen in.
+    window: u64,
+    /// When the gauge was last seen.
+    seen: Instant,
+}
+
+/// Suppression of gauges whose value didn't change since they were last delivered.
+struct Dedup {
+    last: HashMap<Arc<MetricId>, Delivered>,
+    /// Number of deliveries so far.
+    window: u64,
+    /// When gauges which were not seen for a while were last forgotten.
+    pruned: Instant,
+}
+
+impl Dedup {
+    fn new() -> Dedup {
+        Dedup {
+            last: HashMap::new(),
+            window: 0,
+            pruned: Instant::now(),
+        }
+    }
+
+    /// Remove unchanged gauges from the samples of a delivery, returning how many were removed.
+    ///
+    /// An unchanged gauge is still delivered every `heartbeat` deliveries it is part of, so that
+    /// outputs which consider series without samples stale keep seeing it. A batch which contains
+    /// the same gauge several times only counts once.
+    fn apply(&mut self, samples: &mut Vec<Sample>, heartbeat: usize, now: Instant) -> usize {
+        self.window += 1;
+        self.prune(now);
+
+        let before = samples.len();
+        let window = self.window;
+        let last = &mut self.last;
+
+        samples.retain(|sample| {
+            if sample.kind != SampleKind::Gauge {
+                return true;
+            }
+
+            let delivered = Delivered {
+                value: sample.value,
+                suppressed: 0,
+                window: window,
+                seen: now,
+            };
+
+            match last.entry(sample.metric_id.clone()) {
+                Entry::Occupied(mut entry) => {
+                    let previous = entry.get_mut();
+                    previous.seen = now;
+
+                    if previous.value == sample.value {
+                        if previous.window == window {
+                            return false;
+                        }
+
+                        previous.window = window;
+
+                        if previous.suppressed + 1 < heartbeat {
+                            previous.suppressed += 1;
+                            return false;
+                        }
+                    }
+
+                    *previous = delivered;
+                    true
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(delivered);
+                    true
+                }
+            }
+        });
+
+        before - samples.len()
+    }
+
+    /// Forget gauges which were not seen since they were last pruned, at most once every
+    /// `DEDUP_EXPIRY_SECS`, so that series which went away don't pile up.
+    fn prune(&mut self, now: Instant) {
+        if now < self.pruned + Duration::new(DEDUP_EXPIRY_SECS, 0) {
+            return;
+        }
+
+        let pruned = self.pruned;
+        self.last.retain(|_, delivered| delivered.seen >= pruned);
+        self.pruned = now;
+    }
+}
+
+/// Randomize a delay to somewhere between half of it, and all of it.
+fn jitter(delay: Duration) -> Duration {
+    let nanos = delay.as_secs() as f64 * 1e9 + delay.subsec_nanos() as f64;
+    let nanos = (nanos * (0.5 + rand::random::<f64>() * 0.5)) as u64;
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+/// Batching and backoff state of a single output.
+pub struct Delivery {
+    /// Samples waiting to be delivered, if batching is enabled.
+    batch: Mutex<Vec<Sample>>,
+    backoff: Mutex<Backoff>,
+    dedup: Mutex<Dedup>,
+    telemetry: Arc<Telemetry>,
+}
+
+impl Delivery {
+    pub fn new(telemetry: Arc<Telemetry>) -> Delivery {
+        Delivery {
+            batch: Mutex::new(Vec::new()),
+            backoff: Mutex::new(Backoff {
+                pending: VecDeque::new(),
+                delay: None,
+                retry_at: None,
+                spool: None,
+                in_flight: false,
+            }),
+            dedup: Mutex::new(Dedup::new()),
+            telemetry: telemetry,
+        }
+    }
+
+    /// Spill samples which don't fit in the backoff buffer to the given spool.
+    pub fn with_spool(self, spool: Spool) -> Delivery {
+        if let Ok(mut backoff) = self.backoff.lock() {
+            backoff.spool = Some(spool);
+        }
+
+        self
+    }
+
+    pub fn has_spool(&self) -> bool {
+        self.backoff.lock().map(|backoff| backoff.spool.is_some()).unwrap_or(false)
+    }
+
+    /// Take the spool, so that it can be handed over to a replacement of the output.
+    pub fn take_spool(&self) -> Option<Spool> {
+        self.backoff.lock().ok().and_then(|mut backoff| backoff.spool.take())
+    }
+
+    /// Spill to the given spool from now on, unless a spool is already set.
+    ///
+    /// Returns the spool if it wasn't used.
+    pub fn set_spool(&self, spool: Spool) -> Option<Spool> {
+        let mut backoff = match self.backoff.lock() {
+            Ok(backoff) => backoff,
+            Err(_) => return Some(spool),
+        };
+
+        if backoff.spool.is_some() {
+            return Some(spool);
+        }
+
+        backoff.spool = Some(spool);
+        None
+    }
+
+    pub fn feed(&self, output: &ConfiguredOutput, sample: &Sample) -> Result<()> {
+        if !output.options.is_batched() {
+            return self.send(output, vec![sample.clone()], false);
+        }
+
+        let full = {
+            let mut batch = self.batch.lock()?;
+            batch.push(sample.clone());
+
+            match output.options.batch_size {
+                Some(batch_size) if batch.len() >= batch_size => {
+                    Some(mem::replace(&mut *batch, Vec::new()))
+                }
+                _ => None,
+            }
+        };
+
+        match full {
+            Some(samples) => self.send(output, samples, false),
+            None => Ok(()),
+        }
+    }
+
+    /// Deliver buffered samples.
+    ///
+    /// If `force` is set, delivery is attempted even if the output is backing off.
+    pub fn deliver(&self, output: &ConfiguredOutput, force: bool) -> Result<()> {
+        let samples = mem::replace(&mut *self.batch.lock()?, Vec::new());
+        self.send(output, samples, force)
+    }
+
+    fn send(&self, output: &ConfiguredOutput, mut samples: Vec<Sample>, force: bool) -> Result<()> {
+        // retained samples already went through this.
+        if output.options.dedup {
+            let heartbeat = output.options.dedup_heartbeat.unwrap_or(DEFAULT_DEDUP_HEARTBEAT);
+            let suppressed = self.dedup.lock()?.apply(&mut samples, heartbeat, Instant::now());
+
+            if suppressed > 0 {
+                self.telemetry.output_samples_deduplicated(&output.options.name, suppressed);
+            }
+        }
+
+        let (samples, spooled) = {
+            let mut backoff = self.backoff.lock()?;
+
+            // a delivery which is still in progress is not overtaken, which keeps samples in order.
+            if backoff.in_flight {
+                backoff.retain(output, samples);
+                return Ok(());
+            }
+
+            if let Some(retry_at) = backoff.retry_at {
+                if !force && Instant::now() < retry_at {
+                    backoff.retain(output, samples);
+                    return Ok(());
+                }
+            }
+
+            let mut samples = if backoff.pending.is_empty() {
+                samples
+            } else {
+                let mut retried: Vec<Sample> = backoff.pending.drain(..).collect();
+                retried.extend(samples);
+                retried
+            };
+
+            // the spool is drained first, so everything newer is queued behind it.
+            let spooled = match backoff.spool {
+                Some(ref mut spool) if !spool.is_empty() => {
+                    let newer = mem::replace(&mut samples, Vec::new());
+                    let dropped = Backoff::spill(output, spool, &newer);
+
+                    if dropped > 0 {
+                        warn!(
+                            "{}: spool is full, dropped {} sample(s)",
+                            output.options.name,
+                            dropped
+                        );
+                    }
+
+                    let cap = output.options.backoff_buffer_size.unwrap_or(
+                        DEFAULT_BACKOFF_BUFFER_SIZE,
+                    );
+
+                    let (oldest, read) = spool.read(cap)?;
+
+                    // nothing but invalid records were read.
+                    if oldest.is_empty() {
+                        spool.consume(read)?;
+                    }
+
+                    samples = oldest;
+                    Some(read)
+                }
+                _ => None,
+            };
+
+            if samples.is_empty() {
+                return Ok(());
+            }
+
+            backoff.in_flight = true;
+            (samples, spooled)
+        };
+
+        // the lock is not held while feeding, so that samples can be retained meanwhile.
+        let result = match samples.len() {
+            1 if !output.options.is_batched() => output.instance.feed(&samples[0]),
+            _ => output.instance.feed_batch(&samples),
+        };
+
+        let mut backoff = self.backoff.lock()?;
+        backoff.in_flight = false;
+
+        match result {
+            Ok(()) => {
+                if let Some(read) = spooled {
+                    if let Some(ref mut spool) = backoff.spool {
+                        spool.consume(read)?;
+                    }
+                }
+
+                if backoff.delay.take().is_some() {
+                    info!("{}: delivery recovered", output.options.name);
+                }
+
+                backoff.retry_at = None;
+                Ok(())
+            }
+            Err(e) => {
+                let initial = output.options.initial_backoff.unwrap_or(
+                    Duration::from_millis(DEFAULT_INITIAL_BACKOFF_MS),
+                );
+
+                let max = output.options.max_backoff.unwrap_or(
+                    Duration::new(DEFAULT_MAX_BACKOFF_SECS, 0),
+                );
+
+                let delay = match backoff.delay {
+                    Some(delay) => cmp::min(delay * 2, max),
+                    None => cmp::min(initial, max),
+                };
+
+                let wait = jitter(delay);
+                warn!("{}: delivery failed, retrying in {:?}", output.options.name, wait);
+
+                backoff.delay = Some(delay);
+                backoff.retry_at = Some(Instant::now() + wait);
+
+                // samples read from the spool are still in it, others go before the ones which
+                // were retained while feeding.
+                if spooled.is_none() {
+                    backoff.requeue(output, samples);
+                }
+
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Periodically delivers batched samples to outputs.
+pub struct Dispatcher {
+    output: Vec<Arc<ConfiguredOutput>>,
+    pool: Arc<CpuPool>,
+}
+
+impl Dispatcher {
+    pub fn new(output: Vec<Arc<ConfiguredOutput>>, pool: Arc<CpuPool>) -> Dispatcher {
+        Dispatcher {
+            output: output,
+            pool: pool,
+        }
+    }
+}
+
+impl Runnable for Dispatcher {
+    fn run(&self) -> Box<Future<Item = (), Error = Error>> {
+        let output = self.output.clone();
+
+        // outputs might block while writing a batch, so keep it off the reactor.
+        Box::new(self.pool.spawn_fn(move || {
+            for output in output.iter() {
+                if let Err(e) = output.deliver() {
+                    error!("{}: failed to deliver batch: {}", output.options.name, e);
+                }
+            }
+
+            Ok(())
+        }))
+    }
+}
+
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        info!("Dropping Dispatcher");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metric::key;
+    use plugin::{InstanceOptions, OutputInstance};
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::thread;
+
+    /// Output which signals every feed, then waits for it to be released.
+    struct Blocking {
+        started: Mutex<Sender<f64>>,
+        release: Mutex<Receiver<()>>,
+        fed: Arc<Mutex<Vec<f64>>>,
+    }
+
+    impl OutputInstance for Blocking {
+        fn feed(&self, sample: &Sample) -> Result<()> {
+            self.started.lock()?.send(sample.value).unwrap();
+            self.release.lock()?.recv().unwrap();
+            self.fed.lock()?.push(sample.value);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_retain_while_feeding() {
+        let (started_tx, started) = mpsc::channel();
+        let (release, release_rx) = mpsc::channel();
+        let fed = Arc::new(Mutex::new(Vec::new()));
+
+        let output = Arc::new(ConfiguredOutput::new(
+            "out".to_owned(),
+            InstanceOptions::default(),
+            Box::new(Blocking {
+                started: Mutex::new(started_tx),
+                release: Mutex::new(release_rx),
+                fed: fed.clone(),
+            }),
+            Arc::new(Telemetry::new()),
+        ));
+
+        let id = Arc::new(key("test").build());
+        let feed = |value: f64| {
+            let output = output.clone();
+            let sample = Sample::new(id.clone(), value);
+            thread::spawn(move || output.feed(&sample).unwrap())
+        };
+
+        let first = feed(1f64);
+        assert_eq!(1f64, started.recv().unwrap());
+
+        // returns without waiting for the first feed, since the sample is retained.
+        let (done, retained) = mpsc::channel();
+        let second = feed(2f64);
+        thread::spawn(move || done.send(second.join().is_ok()).unwrap());
+        assert!(retained.recv_timeout(Duration::new(5, 0)).unwrap());
+
+        release.send(()).unwrap();
+        first.join().unwrap();
+
+        // the retained sample is delivered before the next one.
+        let third = feed(3f64);
+
+        for _ in 0..2 {
+            started.recv().unwrap();
+            release.send(()).unwrap();
+        }
+
+        third.join().unwrap();
+        assert_eq!(vec![1f64, 2f64, 3f64], *fed.lock().unwrap());
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut dedup = Dedup::new();
+        let now = dedup.pruned;
+        let gauge = Arc::new(key("system").tag("what", "cpu-used").build());
+        let counter = Arc::new(key("uptime").build());
+
+        let samples = |value: f64| {
+            vec![
+                Sample::new(gauge.clone(), value),
+                Sample::new(counter.clone(), 10f64).with_kind(SampleKind::Counter),
+            ]
+        };
+
+        let values = |samples: &Vec<Sample>| samples.iter().map(|s| s.value).collect::<Vec<_>>();
+
+        let mut first = samples(1f64);
+        assert_eq!(0, dedup.apply(&mut first, 3, now));
+        assert_eq!(vec![1f64, 10f64], values(&first));
+
+        // unchanged gauges are suppressed, counters never are.
+        let mut second = samples(1f64);
+        assert_eq!(1, dedup.apply(&mut second, 3, now));
+        assert_eq!(vec![10f64], values(&second));
+
+        assert_eq!(1, dedup.apply(&mut samples(1f64), 3, now));
+
+        // delivered anyway the third time it is seen unchanged.
+        assert_eq!(0, dedup.apply(&mut samples(1f64), 3, now));
+
+        let mut changed = samples(2f64);
+        assert_eq!(0, dedup.apply(&mut changed, 3, now));
+        assert_eq!(vec![2f64, 10f64], values(&changed));
+    }
+
+    #[test]
+    fn test_dedup_window() {
+        let mut dedup = Dedup::new();
+        let now = dedup.pruned;
+        let gauge = Arc::new(key("system").tag("what", "cpu-used").build());
+        let batch = || vec![Sample::new(gauge.clone(), 1f64), Sample::new(gauge.clone(), 1f64)];
+
+        assert_eq!(1, dedup.apply(&mut batch(), 2, now));
+        // the heartbeat counts deliveries, not samples.
+        assert_eq!(2, dedup.apply(&mut batch(), 2, now));
+        assert_eq!(1, dedup.apply(&mut batch(), 2, now));
+    }
+
+    #[test]
+    fn test_dedup_prune() {
+        let mut dedup = Dedup::new();
+        let now = dedup.pruned;
+        let expiry = Duration::new(DEDUP_EXPIRY_SECS, 0);
+        let gauge = |what: &str| Arc::new(key("system").tag("what", what).build());
+
+        dedup.apply(&mut vec![Sample::new(gauge("a"), 1f64)], 10, now);
+        dedup.apply(&mut vec![Sample::new(gauge("b"), 1f64)], 10, now + expiry);
+        assert_eq!(2, dedup.last.len());
+
+        // only gauges which were not seen since the previous prune are forgotten.
+        dedup.apply(&mut Vec::new(), 10, now + expiry * 2);
+        assert_eq!(1, dedup.last.len());
+        assert!(dedup.last.contains_key(&gauge("b")));
+    }
+}
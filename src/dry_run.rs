@@ -0,0 +1,53 @@
+//! Support for `--dry-run`, where outputs log the samples they would have sent.
+
+use errors::*;
+use metric::*;
+use plugin::*;
+use plugins::stdout::Format;
+
+/// Wraps a set up output instance, logging samples instead of feeding them to it.
+///
+/// The wrapped instance is still set up as usual, so that a dry run validates its configuration.
+/// Flushes and teardown are forwarded to it, since it never receives any samples to send.
+pub struct DryRunOutputInstance {
+    name: String,
+    instance: Box<OutputInstance>,
+}
+
+impl DryRunOutputInstance {
+    pub fn new(name: String, instance: Box<OutputInstance>) -> DryRunOutputInstance {
+        DryRunOutputInstance {
+            name: name,
+            instance: instance,
+        }
+    }
+}
+
+impl OutputInstance for DryRunOutputInstance {
+    fn feed(&self, sample: &Sample) -> Result<()> {
+        info!("{}: would send: {}", self.name, Format::Line.format(sample)?);
+        Ok(())
+    }
+
+    fn feed_batch(&self, samples: &[Sample]) -> Result<()> {
+        info!("{}: would send a batch of {} sample(s)", self.name, samples.len());
+
+        for sample in samples {
+            info!("{}: would send: {}", self.name, Format::Line.format(sample)?);
+        }
+
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.instance.flush()
+    }
+
+    fn teardown(&self) -> Result<()> {
+        self.instance.teardown()
+    }
+
+    fn exclusive(&self) -> bool {
+        self.instance.exclusive()
+    }
+}
@@ -1,9 +1,11 @@
 use tokio_timer::TimerError;
 
 use getopts;
+use std::any::Any;
 use log;
 use nom;
 use std::cell;
+use std::fmt;
 use std::io;
 use std::sync;
 use toml;
@@ -30,9 +32,17 @@ error_chain! {
             display("poison error: {}", msg)
         }
 
-        TomlParse(errors: Vec<toml::ParserError>) {
+        TomlParse(path: String, errors: Vec<SyntaxError>) {
             description("parse error")
-            display("parse error: {:?}", errors)
+            display("parse error: {}", errors.iter()
+                .map(|e| format!("{}:{}", path, e))
+                .collect::<Vec<_>>()
+                .join("; "))
+        }
+
+        YamlParse(message: String) {
+            description("yaml parse error")
+            display("parse error: {}", message)
         }
 
         TomlDecode {
@@ -55,11 +65,34 @@ error_chain! {
             display("error in section: {}", section)
         }
 
+        Sections(errors: Vec<SectionError>) {
+            description("sections failed to set up")
+            display("{} sections failed to set up: {}", errors.len(), errors.iter()
+                .map(|e| e.message.clone())
+                .collect::<Vec<_>>()
+                .join("; "))
+        }
+
         ConfigField(field: String, reason: String) {
             description("error in field")
             display("error in field: {}: {}", field, reason)
         }
 
+        InvalidPluginConfig(field: String, reason: String) {
+            description("invalid plugin configuration")
+            display("invalid plugin configuration: {}: {}", field, reason)
+        }
+
+        UnknownDurationUnit(unit: String) {
+            description("unknown duration unit")
+            display("unknown duration unit: {:?} (expected one of: ms, s, m, h)", unit)
+        }
+
+        MissingEnvVar(name: String) {
+            description("missing environment variable")
+            display("missing environment variable: {}", name)
+        }
+
         MissingPlugin(key: String) {
             description("no such plugin")
             display("no such plugin: {}", key)
@@ -70,6 +103,26 @@ error_chain! {
             display("missing field: {}", name)
         }
 
+        DependencyCycle(sections: Vec<String>) {
+            description("sections depend on each other")
+            display("sections depend on each other: {}", sections.join(" -> "))
+        }
+
+        IncludeCycle(paths: Vec<String>) {
+            description("configuration files include each other")
+            display("configuration files include each other: {}", paths.join(" -> "))
+        }
+
+        NoInstances(table: String) {
+            description("no plugin instances configured")
+            display("no [{}] sections are configured", table)
+        }
+
+        UnknownConfigKey(section: String, keys: Vec<String>) {
+            description("unknown keys in configuration")
+            display("{}: unknown key(s): {}", section, keys.join(", "))
+        }
+
         Nom(info: String) {
             description("nom error")
             display("nom error: {}", info)
@@ -91,6 +144,126 @@ error_chain! {
     }
 }
 
+impl ErrorKind {
+    /// Exit code to use when sysmon fails with this kind of error.
+    ///
+    /// Configuration errors exit with `2`, missing plugins with `3`, and everything else with `1`.
+    /// Kinds which only tell where an error happened, like the file or section, and plain messages
+    /// have no exit code of their own.
+    pub fn exit_code(&self) -> Option<i32> {
+        let code = match *self {
+            ErrorKind::Config(_) |
+            ErrorKind::ConfigSection(_) |
+            ErrorKind::Message(_) |
+            ErrorKind::Msg(_) => return None,
+            ErrorKind::Getopts(_) |
+            ErrorKind::TomlParse(..) |
+            ErrorKind::TomlDecode |
+            ErrorKind::YamlParse(_) |
+            ErrorKind::TomlKey(_) |
+            ErrorKind::ConfigField(_, _) |
+            ErrorKind::InvalidPluginConfig(_, _) |
+            ErrorKind::UnknownDurationUnit(_) |
+            ErrorKind::MissingEnvVar(_) |
+            ErrorKind::MissingField(_) |
+            ErrorKind::UnknownConfigKey(..) |
+            ErrorKind::NoInstances(_) |
+            ErrorKind::IncludeCycle(_) |
+            ErrorKind::DependencyCycle(_) => 2,
+            ErrorKind::MissingPlugin(_) => 3,
+            ErrorKind::Sections(ref errors) => {
+                errors.iter().map(|e| e.exit_code).max().unwrap_or(1)
+            }
+            _ => 1,
+        };
+
+        Some(code)
+    }
+}
+
+/// Exit code for an error, taken from the innermost error in its chain which has one.
+///
+/// Setting up a section reports what went wrong within the section it happened in, so a missing
+/// plugin still exits with `3`, and failing to read a file with `1`.
+pub fn exit_code(e: &Error) -> i32 {
+    let mut code = e.kind().exit_code();
+    let mut next = e.1.next_error.as_ref().and_then(|e| e.downcast_ref::<Error>());
+
+    while let Some(e) = next {
+        if let Some(inner) = e.kind().exit_code() {
+            code = Some(inner);
+        }
+
+        next = e.1.next_error.as_ref().and_then(|e| e.downcast_ref::<Error>());
+    }
+
+    code.unwrap_or(1)
+}
+
+/// A syntax error in parsed TOML, at a 1-based line and column of the content.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub line: usize,
+    pub column: usize,
+    pub description: String,
+}
+
+impl SyntaxError {
+    /// Locate the errors of a failed parse, the parser only reports byte offsets.
+    pub fn from_parser(parser: &toml::Parser) -> Vec<SyntaxError> {
+        parser
+            .errors
+            .iter()
+            .map(|e| {
+                let (line, column) = parser.to_linecol(e.lo);
+
+                SyntaxError {
+                    line: line + 1,
+                    column: column + 1,
+                    description: e.desc.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.description)
+    }
+}
+
+/// A section which failed to set up, as part of an error about several sections.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionError {
+    /// The error and everything that caused it, on a single line.
+    pub message: String,
+    pub exit_code: i32,
+}
+
+impl SectionError {
+    pub fn from_error(e: &Error) -> SectionError {
+        SectionError {
+            message: e.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(": "),
+            exit_code: exit_code(e),
+        }
+    }
+}
+
+/// Combine the errors of every section which failed to set up.
+///
+/// A single error is returned as is, several are reported together so that they can all be fixed
+/// at once. The exit code is the highest exit code of the sections.
+pub fn combine_errors(mut errors: Vec<Error>) -> Result<()> {
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(
+            ErrorKind::Sections(errors.iter().map(SectionError::from_error).collect()).into(),
+        ),
+    }
+}
+
 impl<T> From<sync::PoisonError<T>> for Error {
     fn from(err: sync::PoisonError<T>) -> Error {
         ErrorKind::Poison(err.to_string()).into()
@@ -115,3 +288,106 @@ impl From<toml::DecodeError> for Error {
         }
     }
 }
+
+/// Extract a printable message from the payload of a caught panic.
+pub fn panic_message(payload: &Box<Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+
+    if let Some(message) = payload.downcast_ref::<String>() {
+        return message.clone();
+    }
+
+    "unknown panic".to_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_exit_code() {
+        let missing: Result<()> = Err(ErrorKind::MissingPlugin("foo".to_owned()).into());
+        let e = missing
+            .chain_err(|| ErrorKind::ConfigSection("in.foo".to_owned()))
+            .chain_err(|| ErrorKind::Config("sysmon.toml".to_owned()))
+            .unwrap_err();
+
+        assert_eq!(3, exit_code(&e));
+
+        let unreadable: Result<()> = Err(io::Error::new(io::ErrorKind::Other, "denied").into());
+        let e = unreadable
+            .chain_err(|| ErrorKind::Config("sysmon.toml".to_owned()))
+            .unwrap_err();
+
+        assert_eq!(1, exit_code(&e));
+
+        let unbound: Result<()> = Err(io::Error::new(io::ErrorKind::Other, "bind failed").into());
+        let e = unbound
+            .chain_err(|| ErrorKind::ConfigSection("out.prometheus".to_owned()))
+            .chain_err(|| ErrorKind::Config("sysmon.toml".to_owned()))
+            .unwrap_err();
+
+        assert_eq!(1, exit_code(&e));
+
+        let invalid: Result<()> = Err(
+            ErrorKind::InvalidPluginConfig("url".to_owned(), "is required".to_owned()).into(),
+        );
+        let e = invalid
+            .chain_err(|| ErrorKind::ConfigSection("out.influxdb".to_owned()))
+            .unwrap_err();
+
+        assert_eq!(2, exit_code(&e));
+
+        let e: Error = io::Error::new(io::ErrorKind::Other, "bind failed").into();
+        assert_eq!(1, exit_code(&e));
+
+        let zero: Result<()> = Err(ErrorKind::Message("must not be zero".to_owned()).into());
+        let e = zero
+            .chain_err(|| ErrorKind::ConfigField("interval".to_owned(), "invalid".to_owned()))
+            .chain_err(|| ErrorKind::ConfigSection("in.cpu".to_owned()))
+            .unwrap_err();
+
+        assert_eq!(2, exit_code(&e));
+    }
+
+    #[test]
+    fn test_combine_errors() {
+        assert!(combine_errors(Vec::new()).is_ok());
+
+        let invalid = || -> Error {
+            let e: Result<()> = Err(
+                ErrorKind::InvalidPluginConfig("url".to_owned(), "is required".to_owned()).into(),
+            );
+
+            e.chain_err(|| ErrorKind::ConfigSection("influxdb".to_owned())).unwrap_err()
+        };
+
+        let missing = || -> Error { ErrorKind::MissingPlugin("foo".to_owned()).into() };
+
+        let e = combine_errors(vec![invalid()]).unwrap_err();
+        assert_eq!(invalid().to_string(), e.to_string());
+
+        let e = combine_errors(vec![invalid(), missing()]).unwrap_err();
+        assert_eq!(3, exit_code(&e));
+        assert_eq!(
+            "2 sections failed to set up: error in section: influxdb: invalid plugin \
+             configuration: url: is required; no such plugin: foo",
+            e.to_string()
+        );
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let mut parser = toml::Parser::new("[in.cpu]\ntype = \"cpu\"\ninterval = \n");
+        assert!(parser.parse().is_none());
+
+        let errors = SyntaxError::from_parser(&parser);
+        assert_eq!(3, errors[0].line);
+
+        let e: Error = ErrorKind::TomlParse("sysmon.toml".to_owned(), errors).into();
+        assert!(e.to_string().starts_with("parse error: sysmon.toml:3:"));
+    }
+}
@@ -0,0 +1,222 @@
+//! Filters deciding which samples an output receives.
+//!
+//! Patterns are globs where `*` matches any number of characters and `?` a single character,
+//! unless they are written as `/<regex>/`. Patterns are compiled once when the section is set up.
+
+use errors::*;
+use metric::MetricId;
+use regex::{self, Regex};
+use toml;
+
+/// A compiled name or tag value pattern.
+#[derive(Debug, Clone)]
+struct Pattern(Regex);
+
+impl Pattern {
+    /// Compile a pattern, errors refer to the given configuration field.
+    fn parse(field: &str, pattern: &str) -> Result<Pattern> {
+        let expression = if pattern.len() >= 2 && pattern.starts_with('/') &&
+            pattern.ends_with('/')
+        {
+            pattern[1..pattern.len() - 1].to_owned()
+        } else {
+            glob_to_regex(pattern)
+        };
+
+        let regex = Regex::new(&expression).map_err(|e| {
+            ErrorKind::ConfigField(
+                field.to_owned(),
+                format!("invalid pattern {:?}: {}", pattern, e),
+            )
+        })?;
+
+        Ok(Pattern(regex))
+    }
+
+    fn is_match(&self, value: &str) -> bool {
+        self.0.is_match(value)
+    }
+}
+
+/// Translate a glob into an anchored regular expression.
+fn glob_to_regex(glob: &str) -> String {
+    let mut out = String::from("^");
+
+    for c in glob.chars() {
+        match c {
+            '*' => out.push_str(".*"),
+            '?' => out.push('.'),
+            c => out.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    out.push('$');
+    out
+}
+
+/// Which samples an output receives, based on their key and tags.
+#[derive(Debug, Clone, Default)]
+pub struct SampleFilter {
+    /// The key has to match one of these, unless empty.
+    include: Vec<Pattern>,
+    /// Samples whose key matches any of these are excluded.
+    exclude: Vec<Pattern>,
+    /// Every one of these tags has to be present and match.
+    include_tags: Vec<(String, Pattern)>,
+    /// Samples with any of these tags matching are excluded.
+    exclude_tags: Vec<(String, Pattern)>,
+}
+
+fn tag<'a>(metric_id: &'a MetricId, name: &str) -> Option<&'a str> {
+    metric_id
+        .tags()
+        .iter()
+        .find(|&&(ref k, _)| k == name)
+        .map(|&(_, ref v)| v.as_str())
+}
+
+impl SampleFilter {
+    /// If a sample with the given id passes the filter.
+    pub fn matches(&self, metric_id: &MetricId) -> bool {
+        let key = metric_id.key().unwrap_or("");
+
+        if !self.include.is_empty() && !self.include.iter().any(|p| p.is_match(key)) {
+            return false;
+        }
+
+        if self.exclude.iter().any(|p| p.is_match(key)) {
+            return false;
+        }
+
+        let included = self.include_tags.iter().all(|&(ref name, ref pattern)| {
+            tag(metric_id, name).map(|v| pattern.is_match(v)).unwrap_or(false)
+        });
+
+        if !included {
+            return false;
+        }
+
+        !self.exclude_tags.iter().any(|&(ref name, ref pattern)| {
+            tag(metric_id, name).map(|v| pattern.is_match(v)).unwrap_or(false)
+        })
+    }
+}
+
+fn decode_patterns(table: &toml::Table, key: &str) -> Result<Vec<Pattern>> {
+    let values = match table.get(key) {
+        None => return Ok(Vec::new()),
+        Some(&toml::Value::String(ref value)) => return Ok(vec![Pattern::parse(key, value)?]),
+        Some(&toml::Value::Array(ref values)) => values,
+        Some(_) => {
+            return Err(
+                ErrorKind::ConfigField(
+                    key.to_owned(),
+                    "must be a string or an array of strings".to_owned(),
+                ).into(),
+            );
+        }
+    };
+
+    let mut out = Vec::new();
+
+    for (index, value) in values.iter().enumerate() {
+        match *value {
+            toml::Value::String(ref value) => {
+                out.push(Pattern::parse(&format!("{}[{}]", key, index), value)?)
+            }
+            _ => {
+                return Err(
+                    ErrorKind::ConfigField(
+                        format!("{}[{}]", key, index),
+                        "must be a string".to_owned(),
+                    ).into(),
+                );
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+fn decode_tag_patterns(table: &toml::Table, key: &str) -> Result<Vec<(String, Pattern)>> {
+    let tags = match table.get(key) {
+        None => return Ok(Vec::new()),
+        Some(&toml::Value::Table(ref tags)) => tags,
+        Some(_) => {
+            return Err(
+                ErrorKind::ConfigField(key.to_owned(), "must be a table".to_owned()).into(),
+            );
+        }
+    };
+
+    let mut out = Vec::new();
+
+    for (name, value) in tags {
+        let field = format!("{}.{}", key, name);
+
+        match *value {
+            toml::Value::String(ref value) => {
+                out.push((name.clone(), Pattern::parse(&field, value)?));
+            }
+            _ => {
+                return Err(ErrorKind::ConfigField(field, "must be a string".to_owned()).into());
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Decode the filter of a section, returns `None` if the section doesn't filter samples.
+pub fn decode_filter(table: &toml::Table) -> Result<Option<SampleFilter>> {
+    let filter = SampleFilter {
+        include: decode_patterns(table, "include")?,
+        exclude: decode_patterns(table, "exclude")?,
+        include_tags: decode_tag_patterns(table, "include_tags")?,
+        exclude_tags: decode_tag_patterns(table, "exclude_tags")?,
+    };
+
+    if filter.include.is_empty() && filter.exclude.is_empty() && filter.include_tags.is_empty() &&
+        filter.exclude_tags.is_empty()
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(filter))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metric::key;
+
+    fn filter(content: &str) -> Result<Option<SampleFilter>> {
+        decode_filter(&toml::Parser::new(content).parse().unwrap())
+    }
+
+    #[test]
+    fn test_filter() {
+        let f = filter(
+            "include = [\"system\", \"/^app\\\\./\"]\n\
+             exclude_tags = { what = \"debug-*\" }",
+        ).unwrap()
+            .unwrap();
+
+        assert!(f.matches(&key("system").tag("what", "cpu-used").build()));
+        assert!(f.matches(&key("app.requests").build()));
+        assert!(!f.matches(&key("apps").build()));
+        assert!(!f.matches(&key("system").tag("what", "debug-queue").build()));
+
+        let f = filter("include_tags = { what = \"cpu-?sed\" }").unwrap().unwrap();
+        assert!(f.matches(&key("system").tag("what", "cpu-used").build()));
+        assert!(!f.matches(&key("system").build()));
+    }
+
+    #[test]
+    fn test_decode_filter() {
+        assert!(filter("").unwrap().is_none());
+        assert!(filter("include = \"/(/\"").is_err());
+        assert!(filter("exclude = [1]").is_err());
+        assert!(filter("include_tags = { what = 1 }").is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Health of input instances, as reported by the poller and the updater.
+
+use errors::*;
+use futures::{future, Future};
+use plugin::ConfiguredInput;
+use scheduler::Runnable;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of consecutive failures after which an instance is considered unhealthy.
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+/// Health of a single input instance.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct InstanceHealth {
+    /// If the instance has to be healthy for sysmon to be healthy.
+    pub critical: bool,
+    /// Time of the last successful poll, in milliseconds since the unix epoch.
+    pub last_success: Option<u64>,
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+}
+
+struct State {
+    threshold: u32,
+    instances: BTreeMap<String, InstanceHealth>,
+}
+
+/// Health of all running input instances, keyed by instance name.
+///
+/// Shared between the runtime, which keeps the set of instances up to date, and the plugins which
+/// expose it.
+pub struct Health {
+    state: Mutex<State>,
+}
+
+impl Health {
+    pub fn new() -> Health {
+        Health {
+            state: Mutex::new(State {
+                threshold: DEFAULT_FAILURE_THRESHOLD,
+                instances: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Track the given instances, forgetting about instances which are no longer running.
+    ///
+    /// Instances which keep running through a reload keep their health.
+    pub fn track(&self, inputs: &Vec<Arc<ConfiguredInput>>, threshold: u32) -> Result<()> {
+        let mut state = self.state.lock()?;
+        state.threshold = threshold;
+
+        let mut instances = BTreeMap::new();
+
+        for input in inputs {
+            let name = &input.options.name;
+            let mut health = state.instances.remove(name).unwrap_or_default();
+            health.critical = input.options.critical;
+            instances.insert(name.clone(), health);
+        }
+
+        state.instances = instances;
+        Ok(())
+    }
+
+    /// Record a successful poll of the named instance.
+    pub fn success(&self, name: &str) {
+        self.update(name, |health| {
+            health.last_success = Some(now_millis());
+            health.consecutive_failures = 0;
+        });
+    }
+
+    /// Record a failed poll or update of the named instance.
+    pub fn failure(&self, name: &str, error: &Error) {
+        self.update(name, |health| {
+            health.last_error = Some(error.to_string());
+            health.consecutive_failures += 1;
+        });
+    }
+
+    fn update<F>(&self, name: &str, f: F)
+    where
+        F: FnOnce(&mut InstanceHealth),
+    {
+        match self.state.lock() {
+            // instances which are not tracked have been removed through a reload.
+            Ok(mut state) => {
+                if let Some(health) = state.instances.get_mut(name) {
+                    f(health);
+                }
+            }
+            Err(e) => error!("failed to record health of {}: {}", name, e),
+        }
+    }
+
+    /// Health of every tracked instance.
+    pub fn status(&self) -> Result<BTreeMap<String, InstanceHealth>> {
+        Ok(self.state.lock()?.instances.clone())
+    }
+
+    /// Names of critical instances which have failed at least the threshold number of times in a
+    /// row.
+    pub fn failing(&self) -> Result<Vec<String>> {
+        let state = self.state.lock()?;
+        Ok(failing(&state.instances, state.threshold))
+    }
+}
+
+fn failing(instances: &BTreeMap<String, InstanceHealth>, threshold: u32) -> Vec<String> {
+    instances
+        .iter()
+        .filter(|&(_, health)| {
+            health.critical && health.consecutive_failures >= threshold
+        })
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Describe the last outcome of every instance, and how often it failed in a row.
+pub fn summarize(instances: &BTreeMap<String, InstanceHealth>, now: u64) -> String {
+    let parts: Vec<String> = instances
+        .iter()
+        .map(|(name, health)| {
+            let last_success = match health.last_success {
+                Some(last_success) => {
+                    format!("last success {}s ago", now.saturating_sub(last_success) / 1000)
+                }
+                None => "never succeeded".to_owned(),
+            };
+
+            match (health.consecutive_failures, health.last_error.as_ref()) {
+                (0, _) | (_, None) => format!("{}: ok, {}", name, last_success),
+                (failures, Some(error)) => {
+                    format!(
+                        "{}: failing ({} in a row: {}), {}",
+                        name,
+                        failures,
+                        error,
+                        last_success
+                    )
+                }
+            }
+        })
+        .collect();
+
+    parts.join("; ")
+}
+
+/// Periodically logs the health of every instance.
+pub struct StatusLog {
+    health: Arc<Health>,
+}
+
+impl StatusLog {
+    pub fn new(health: Arc<Health>) -> StatusLog {
+        StatusLog { health: health }
+    }
+}
+
+impl Runnable for StatusLog {
+    fn run(&self) -> Box<Future<Item = (), Error = Error>> {
+        let state = match self.health.state.lock() {
+            Ok(state) => state,
+            Err(e) => return Box::new(future::err(e.into())),
+        };
+
+        if state.instances.is_empty() {
+            return Box::new(future::ok(()));
+        }
+
+        let failing = failing(&state.instances, state.threshold);
+        let summary = summarize(&state.instances, now_millis());
+
+        if failing.is_empty() {
+            info!("status: {}", summary);
+        } else {
+            warn!("status ({} unhealthy): {}", failing.len(), summary);
+        }
+
+        Box::new(future::ok(()))
+    }
+}
+
+fn now_millis() -> u64 {
+    let since = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    since.as_secs() * 1000 + since.subsec_nanos() as u64 / 1_000_000
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_failing() {
+        let mut instances = BTreeMap::new();
+
+        let health = |critical, consecutive_failures| {
+            InstanceHealth {
+                critical: critical,
+                consecutive_failures: consecutive_failures,
+                ..InstanceHealth::default()
+            }
+        };
+
+        instances.insert("cpu".to_owned(), health(true, 3));
+        instances.insert("disk".to_owned(), health(false, 5));
+        instances.insert("load".to_owned(), health(true, 2));
+
+        assert_eq!(vec!["cpu".to_owned()], failing(&instances, 3));
+        assert_eq!(vec!["cpu".to_owned(), "load".to_owned()], failing(&instances, 2));
+    }
+
+    #[test]
+    fn test_summarize() {
+        let mut instances = BTreeMap::new();
+
+        instances.insert(
+            "cpu".to_owned(),
+            InstanceHealth {
+                last_success: Some(50_000),
+                ..InstanceHealth::default()
+            },
+        );
+
+        instances.insert(
+            "disk".to_owned(),
+            InstanceHealth {
+                last_error: Some("timed out".to_owned()),
+                consecutive_failures: 2,
+                ..InstanceHealth::default()
+            },
+        );
+
+        assert_eq!(
+            "cpu: ok, last success 10s ago; disk: failing (2 in a row: timed out), never succeeded",
+            summarize(&instances, 60_000)
+        );
+    }
+}
@@ -0,0 +1,46 @@
+//! Periodic heartbeat sample, which lets downstream systems detect hosts that stopped reporting.
+
+use bus::SampleBus;
+use errors::*;
+use futures::*;
+use futures_cpupool::CpuPool;
+use metric::{key, MetricId};
+use plugin::{Sample, SampleMeta};
+use scheduler::Runnable;
+use std::sync::Arc;
+
+/// Key of the heartbeat sample.
+pub const HEARTBEAT_KEY: &'static str = "sysmon_alive";
+
+/// Publishes a `sysmon_alive` sample with the value `1` every time it runs.
+pub struct Heartbeat {
+    bus: Arc<SampleBus>,
+    cpupool: Arc<CpuPool>,
+    metric_id: Arc<MetricId>,
+    meta: Arc<SampleMeta>,
+}
+
+impl Heartbeat {
+    /// Create a heartbeat, which carries the given global tags.
+    pub fn new(bus: Arc<SampleBus>, cpupool: Arc<CpuPool>, tags: &[(String, String)]) -> Heartbeat {
+        Heartbeat {
+            bus: bus,
+            cpupool: cpupool,
+            metric_id: Arc::new(key(HEARTBEAT_KEY).build().with_tags(tags)),
+            meta: Arc::new(SampleMeta::new().description("Always 1 while sysmon is running")),
+        }
+    }
+}
+
+impl Runnable for Heartbeat {
+    fn run(&self) -> Box<Future<Item = (), Error = Error>> {
+        let bus = self.bus.clone();
+        let sample = Sample::new(self.metric_id.clone(), 1f64).with_meta(self.meta.clone());
+
+        // publishing might wait for outputs which are falling behind.
+        Box::new(self.cpupool.spawn_fn(move || {
+            bus.publish(&[sample]);
+            Ok(())
+        }))
+    }
+}
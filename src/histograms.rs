@@ -0,0 +1,322 @@
+//! Aggregation of observations into cumulative histograms.
+//!
+//! Plugins report observations, like the duration of a request, as samples of the `Observation`
+//! kind. After every poll, the observations of an instance are counted into the histogram of their
+//! metric, and replaced by its `<key>_bucket`, `<key>_sum`, and `<key>_count` series. Bucket
+//! boundaries are configured per metric through `[[histogram]]` tables.
+
+use errors::*;
+use metric::MetricId;
+use plugin::{Sample, SampleKind, Samples};
+use regex::Regex;
+use serde;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use toml;
+
+/// Bucket boundaries of metrics which no rule matches.
+pub const DEFAULT_BUCKETS: &'static [f64] = &[
+    0.005,
+    0.01,
+    0.025,
+    0.05,
+    0.1,
+    0.25,
+    0.5,
+    1.0,
+    2.5,
+    5.0,
+    10.0,
+];
+
+#[derive(Deserialize, Debug)]
+struct HistogramRuleIn {
+    /// Regular expression the key has to match, every key matches if left out.
+    pattern: Option<String>,
+    /// Upper bounds of the buckets, in increasing order.
+    buckets: Vec<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HistogramRule {
+    pattern: Option<Regex>,
+    buckets: Vec<f64>,
+}
+
+fn decode_rule(value: &toml::Value) -> Result<HistogramRule> {
+    let mut decoder = toml::Decoder::new(value.clone());
+    let rule: HistogramRuleIn = serde::Deserialize::deserialize(&mut decoder)?;
+
+    if let Some(toml::Value::Table(leftover)) = decoder.toml {
+        if !leftover.is_empty() {
+            let keys: Vec<String> = leftover.keys().cloned().collect();
+            return Err(
+                ErrorKind::Message(format!("unknown key(s): {}", keys.join(", "))).into(),
+            );
+        }
+    }
+
+    let pattern = match rule.pattern {
+        Some(pattern) => {
+            Some(Regex::new(&pattern).map_err(|e| {
+                ErrorKind::ConfigField("pattern".to_owned(), e.to_string())
+            })?)
+        }
+        None => None,
+    };
+
+    if rule.buckets.is_empty() {
+        return Err(
+            ErrorKind::ConfigField("buckets".to_owned(), "must not be empty".to_owned()).into(),
+        );
+    }
+
+    let increasing = rule.buckets.iter().all(|b| b.is_finite()) &&
+        rule.buckets.windows(2).all(|w| w[0] < w[1]);
+
+    if !increasing {
+        return Err(
+            ErrorKind::ConfigField(
+                "buckets".to_owned(),
+                "must be finite numbers in increasing order".to_owned(),
+            ).into(),
+        );
+    }
+
+    Ok(HistogramRule {
+        pattern: pattern,
+        buckets: rule.buckets,
+    })
+}
+
+/// Decode the `[[histogram]]` rules of a configuration file.
+pub fn decode_histograms(table: &toml::Table, key: &str) -> Result<Vec<HistogramRule>> {
+    let values = match table.get(key) {
+        None => return Ok(Vec::new()),
+        Some(&toml::Value::Array(ref values)) => values,
+        Some(_) => {
+            return Err(
+                ErrorKind::ConfigField(key.to_owned(), "must be an array of tables".to_owned())
+                    .into(),
+            );
+        }
+    };
+
+    let mut rules = Vec::new();
+
+    for (index, value) in values.iter().enumerate() {
+        rules.push(decode_rule(value).chain_err(|| {
+            ErrorKind::ConfigSection(format!("{}[{}]", key, index))
+        })?);
+    }
+
+    Ok(rules)
+}
+
+/// Buckets of the first rule matching the key, or the default buckets.
+fn buckets_for<'a>(rules: &'a [HistogramRule], key: &str) -> &'a [f64] {
+    rules
+        .iter()
+        .find(|rule| rule.pattern.as_ref().map(|p| p.is_match(key)).unwrap_or(true))
+        .map(|rule| rule.buckets.as_slice())
+        .unwrap_or(DEFAULT_BUCKETS)
+}
+
+/// Cumulative counts of a single histogram.
+struct Histogram {
+    buckets: Vec<f64>,
+    /// Observations less than or equal to the bucket at the same index.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &[f64]) -> Histogram {
+        Histogram {
+            buckets: buckets.to_vec(),
+            counts: vec![0; buckets.len()],
+            sum: 0f64,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.buckets.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Series of the histogram, stamped like the given observation.
+    fn series(&self, observation: &Sample) -> Samples {
+        let id = &observation.metric_id;
+        let key = id.key().unwrap_or("sysmon");
+
+        let series = |metric_id: MetricId, value: f64| {
+            let mut sample = observation.clone();
+            sample.metric_id = Arc::new(metric_id);
+            sample.value = value;
+            sample.kind = SampleKind::Histogram;
+            sample
+        };
+
+        let bucket_key = format!("{}_bucket", key);
+        let le = |bound: String| vec![("le".to_owned(), bound)];
+
+        let mut samples: Samples = self.buckets
+            .iter()
+            .zip(self.counts.iter())
+            .map(|(bound, count)| {
+                let metric_id = id.with_key(&bucket_key).with_tags(&le(bound.to_string()));
+                series(metric_id, *count as f64)
+            })
+            .collect();
+
+        let inf = id.with_key(&bucket_key).with_tags(&le("+Inf".to_owned()));
+        samples.push(series(inf, self.count as f64));
+        samples.push(series(id.with_key(&format!("{}_sum", key)), self.sum));
+        samples.push(series(id.with_key(&format!("{}_count", key)), self.count as f64));
+        samples
+    }
+}
+
+/// Histograms of observations, kept by the runtime like derivatives so that they survive
+/// reloads.
+pub struct Histograms {
+    /// Histogram by instance name and metric.
+    state: Mutex<HashMap<(String, Arc<MetricId>), Histogram>>,
+}
+
+impl Histograms {
+    pub fn new() -> Histograms {
+        Histograms { state: Mutex::new(HashMap::new()) }
+    }
+
+    /// Replace observations of the named instance with the series of their histograms.
+    ///
+    /// Series of a histogram are reported after every poll that observed it. A histogram whose
+    /// buckets were changed through a reload starts over.
+    pub fn apply(&self, name: &str, samples: Samples, rules: &[HistogramRule]) -> Samples {
+        if samples.iter().all(|s| s.kind != SampleKind::Observation) {
+            return samples;
+        }
+
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return samples,
+        };
+
+        let mut out = Vec::with_capacity(samples.len());
+        // last observation of every histogram observed by this poll, in order of appearance.
+        let mut observed: Vec<((String, Arc<MetricId>), Sample)> = Vec::new();
+
+        for sample in samples {
+            if sample.kind != SampleKind::Observation {
+                out.push(sample);
+                continue;
+            }
+
+            if !sample.value.is_finite() {
+                continue;
+            }
+
+            let key = (name.to_owned(), sample.metric_id.clone());
+            let buckets = buckets_for(rules, sample.metric_id.key().unwrap_or(""));
+
+            let reset = state.get(&key).map(|h| h.buckets != buckets).unwrap_or(true);
+
+            if reset {
+                state.insert(key.clone(), Histogram::new(buckets));
+            }
+
+            if let Some(histogram) = state.get_mut(&key) {
+                histogram.observe(sample.value);
+            }
+
+            match observed.iter().position(|&(ref k, _)| *k == key) {
+                Some(index) => observed[index].1 = sample,
+                None => observed.push((key, sample)),
+            }
+        }
+
+        for (key, observation) in observed {
+            if let Some(histogram) = state.get(&key) {
+                out.extend(histogram.series(&observation));
+            }
+        }
+
+        out
+    }
+
+    /// Forget about instances which are no longer running.
+    pub fn retain(&self, instances: &[String]) {
+        if let Ok(mut state) = self.state.lock() {
+            state.retain(|&(ref name, _), _| instances.contains(name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metric::key;
+
+    fn rules(content: &str) -> Result<Vec<HistogramRule>> {
+        let table = toml::Parser::new(content).parse().unwrap();
+        decode_histograms(&table, "histogram")
+    }
+
+    #[test]
+    fn test_histograms() {
+        let rules = rules("[[histogram]]\npattern = \"^tcp\"\nbuckets = [10.0, 100.0]").unwrap();
+        let histograms = Histograms::new();
+        let id = Arc::new(key("tcp").tag("what", "latency").build());
+        let gauge = Arc::new(key("tcp").tag("what", "up").build());
+
+        let observe = |value: f64| {
+            Sample::new(id.clone(), value).with_kind(SampleKind::Observation)
+        };
+
+        histograms.apply("tcp", vec![observe(5f64), observe(50f64)], &rules);
+
+        let samples = histograms.apply(
+            "tcp",
+            vec![Sample::new(gauge.clone(), 1f64), observe(500f64)],
+            &rules,
+        );
+
+        let bucket = |le: &str| {
+            key("tcp_bucket").tag("what", "latency").tag("le", le).build()
+        };
+
+        let series: Vec<(MetricId, f64)> =
+            samples.iter().map(|s| ((*s.metric_id).clone(), s.value)).collect();
+
+        assert_eq!(
+            vec![
+                ((*gauge).clone(), 1f64),
+                (bucket("10"), 1f64),
+                (bucket("100"), 2f64),
+                (bucket("+Inf"), 3f64),
+                (key("tcp_sum").tag("what", "latency").build(), 555f64),
+                (key("tcp_count").tag("what", "latency").build(), 3f64),
+            ],
+            series
+        );
+
+        assert!(samples[1..].iter().all(|s| s.kind == SampleKind::Histogram));
+    }
+
+    #[test]
+    fn test_decode_histograms_errors() {
+        assert!(rules("[[histogram]]\nbuckets = []").is_err());
+        assert!(rules("[[histogram]]\nbuckets = [2.0, 1.0]").is_err());
+        assert!(rules("[[histogram]]\npattern = \"(\"\nbuckets = [1.0]").is_err());
+        assert!(rules("[[histogram]]\nbuckets = [1.0]\nbucket = 2").is_err());
+    }
+}
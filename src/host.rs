@@ -0,0 +1,54 @@
+//! Detection of the name of the host, which is added as a tag to every sample.
+
+use errors::*;
+use libc;
+use std::io;
+
+/// Tag the hostname is added as, unless configured otherwise.
+pub const DEFAULT_HOST_TAG: &'static str = "host";
+
+/// Largest hostname accepted, which is larger than what any system allows.
+const MAX_HOSTNAME: usize = 256;
+
+/// Decode a hostname from a buffer filled by `gethostname`, which might not be terminated.
+fn decode_hostname(buffer: &[u8]) -> Result<String> {
+    let end = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+
+    let hostname = String::from_utf8(buffer[..end].to_vec()).map_err(|_| {
+        ErrorKind::Message("hostname is not valid UTF-8".to_owned())
+    })?;
+
+    if hostname.is_empty() {
+        return Err(ErrorKind::Message("hostname is empty".to_owned()).into());
+    }
+
+    Ok(hostname)
+}
+
+/// Name of the host, as reported by `gethostname`.
+pub fn hostname() -> Result<String> {
+    let mut buffer = [0u8; MAX_HOSTNAME];
+
+    let result = unsafe {
+        libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len())
+    };
+
+    if result < 0 {
+        return Err(io::Error::last_os_error()).chain_err(|| "gethostname failed");
+    }
+
+    decode_hostname(&buffer)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_hostname() {
+        assert_eq!("web-1", decode_hostname(b"web-1\0\0\0").unwrap());
+        assert_eq!("web-1", decode_hostname(b"web-1").unwrap());
+        assert!(decode_hostname(b"\0web-1").is_err());
+        assert!(decode_hostname(b"\xff\0").is_err());
+    }
+}
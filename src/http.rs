@@ -0,0 +1,260 @@
+//! Minimal HTTP server used by plugins which expose data over HTTP.
+
+use errors::*;
+
+use futures::{Async, Future, Poll};
+use futures::future::{self, Loop};
+use futures::stream::{self, Stream};
+use futures::sync::oneshot;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::str;
+use std::sync::{Arc, Mutex};
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::Handle;
+use tokio_io::io as async_io;
+
+/// Largest request head that will be accepted.
+const MAX_HEAD: usize = 8 * 1024;
+
+/// A parsed HTTP request.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+}
+
+/// A complete HTTP response.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Response {
+        Response {
+            status: status,
+            content_type: content_type.to_owned(),
+            body: body,
+        }
+    }
+
+    pub fn text(status: u16, body: &str) -> Response {
+        Response::new(status, "text/plain; charset=utf-8", body.as_bytes().to_vec())
+    }
+
+    pub fn not_found() -> Response {
+        Response::text(404, "not found\n")
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            500 => "Internal Server Error",
+            503 => "Service Unavailable",
+            _ => "Unknown",
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let mut out = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.body.len()
+        ).into_bytes();
+
+        out.extend(self.body);
+        out
+    }
+}
+
+pub type Handler = Rc<Fn(&Request) -> Response>;
+
+fn report_and_discard<E: fmt::Display>(e: E) -> () {
+    info!("http: an error occured: {}", e);
+}
+
+fn head_complete(head: &[u8]) -> bool {
+    head.windows(4).any(|w| w == b"\r\n\r\n")
+}
+
+/// Read the request head (request line and headers) from the socket.
+fn read_head(socket: TcpStream) -> Box<Future<Item = (TcpStream, Vec<u8>), Error = io::Error>> {
+    Box::new(future::loop_fn((socket, Vec::new()), |(socket, mut head)| {
+        async_io::read(socket, vec![0u8; 1024]).and_then(move |(socket, buf, n)| {
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed",
+                ));
+            }
+
+            head.extend_from_slice(&buf[..n]);
+
+            if head_complete(&head) {
+                return Ok(Loop::Break((socket, head)));
+            }
+
+            if head.len() > MAX_HEAD {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "request head too large",
+                ));
+            }
+
+            Ok(Loop::Continue((socket, head)))
+        })
+    }))
+}
+
+/// Parse the request line from a request head.
+pub fn parse_request(head: &[u8]) -> Option<Request> {
+    let head = str::from_utf8(head).ok()?;
+    let line = head.lines().next()?;
+    let mut parts = line.split_whitespace();
+
+    let method = parts.next()?;
+    let path = parts.next()?;
+
+    // strip any query string.
+    let path = path.split('?').next().unwrap_or(path);
+
+    Some(Request {
+        method: method.to_owned(),
+        path: path.to_owned(),
+    })
+}
+
+/// A running server, which stops accepting connections once it is stopped or dropped.
+pub struct Server {
+    /// Shared with the accept loop, so that stopping closes the socket right away instead of once
+    /// the reactor gets around to it.
+    listener: Arc<Mutex<Option<TcpListener>>>,
+    stop: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+impl Server {
+    /// Stop accepting connections, and close the listening socket.
+    ///
+    /// The port can be bound again as soon as this returns, even from another thread than the one
+    /// running the reactor. Connections which have already been accepted are answered.
+    pub fn stop(&self) {
+        if let Ok(mut listener) = self.listener.lock() {
+            listener.take();
+        }
+
+        if let Ok(mut stop) = self.stop.lock() {
+            if let Some(stop) = stop.take() {
+                let _ = stop.send(());
+            }
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Connections accepted by a server, until it is stopped.
+pub type Incoming = Box<Stream<Item = (TcpStream, SocketAddr), Error = io::Error>>;
+
+/// Bind the given address, returning the server and the stream of connections it accepts.
+pub fn listen(handle: &Handle, addr: &SocketAddr) -> Result<(Server, Incoming)> {
+    let listener = Arc::new(Mutex::new(Some(TcpListener::bind(addr, handle)?)));
+    let (stop, mut stopped) = oneshot::channel::<()>();
+    let accept_listener = listener.clone();
+
+    let incoming = stream::poll_fn(move || -> Poll<Option<(TcpStream, SocketAddr)>, io::Error> {
+        // the sender is dropped together with the server, which stops it as well.
+        match stopped.poll() {
+            Ok(Async::NotReady) => {}
+            _ => return Ok(Async::Ready(None)),
+        }
+
+        let mut listener = match accept_listener.lock() {
+            Ok(listener) => listener,
+            Err(_) => return Ok(Async::Ready(None)),
+        };
+
+        let listener = match *listener {
+            Some(ref mut listener) => listener,
+            None => return Ok(Async::Ready(None)),
+        };
+
+        match listener.accept() {
+            Ok(accepted) => Ok(Async::Ready(Some(accepted))),
+            // the listener wakes the task once another connection can be accepted.
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(e) => Err(e),
+        }
+    });
+
+    let server = Server {
+        listener: listener,
+        stop: Mutex::new(Some(stop)),
+    };
+
+    Ok((server, Box::new(incoming)))
+}
+
+/// Bind the given address and answer every request using `handler`.
+pub fn serve(handle: &Handle, addr: &SocketAddr, handler: Handler) -> Result<Server> {
+    let (server, incoming) = listen(handle, addr)?;
+    let conn_handle = handle.clone();
+
+    let accept = incoming.map_err(report_and_discard).for_each(
+        move |(socket, _addr)| {
+            let handler = handler.clone();
+
+            let conn = read_head(socket)
+                .and_then(move |(socket, head)| {
+                    let response = match parse_request(&head) {
+                        Some(request) => handler(&request),
+                        None => Response::text(400, "bad request\n"),
+                    };
+
+                    async_io::write_all(socket, response.into_bytes())
+                })
+                .map(|_| ())
+                .map_err(report_and_discard);
+
+            conn_handle.spawn(conn);
+            Ok(())
+        },
+    );
+
+    handle.spawn(accept);
+    Ok(server)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let request = parse_request(b"GET /metrics?foo=bar HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+
+        assert_eq!("GET", request.method);
+        assert_eq!("/metrics", request.path);
+        assert!(parse_request(b"\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn test_head_complete() {
+        assert!(head_complete(b"GET / HTTP/1.1\r\n\r\n"));
+        assert!(!head_complete(b"GET / HTTP/1.1\r\n"));
+    }
+}
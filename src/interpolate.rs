@@ -0,0 +1,232 @@
+//! Expansion of environment variables in configuration files.
+//!
+//! References are expanded in string values once the file has been parsed, so that keys and
+//! comments are left alone, and expanded values can't change the structure of the document.
+
+use errors::*;
+use std::env;
+use toml;
+
+fn is_name_start(c: char) -> bool {
+    match c {
+        'a'...'z' | 'A'...'Z' | '_' => true,
+        _ => false,
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start(c) || c.is_digit(10)
+}
+
+/// Expand `${VAR}` and `$VAR` references in the given input using `lookup`.
+///
+/// `$$` expands to a literal `$`. Expanded values are not expanded again.
+pub fn interpolate_with<F>(input: &str, lookup: F) -> Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut out = String::with_capacity(input.len());
+    let mut it = input.char_indices().peekable();
+
+    while let Some((_, c)) = it.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+
+        let name = match it.peek().cloned() {
+            Some((_, '$')) => {
+                it.next();
+                out.push('$');
+                continue;
+            }
+            Some((start, '{')) => {
+                it.next();
+
+                let mut end = None;
+
+                while let Some((i, c)) = it.next() {
+                    if c == '}' {
+                        end = Some(i);
+                        break;
+                    }
+                }
+
+                match end {
+                    Some(end) => &input[start + 1..end],
+                    None => {
+                        return Err(
+                            ErrorKind::Message(
+                                format!("unterminated variable reference: {}", &input[start..]),
+                            ).into(),
+                        );
+                    }
+                }
+            }
+            Some((start, c)) if is_name_start(c) => {
+                let mut end = input.len();
+
+                while let Some(&(i, c)) = it.peek() {
+                    if !is_name_char(c) {
+                        end = i;
+                        break;
+                    }
+
+                    it.next();
+                }
+
+                &input[start..end]
+            }
+            _ => {
+                out.push('$');
+                continue;
+            }
+        };
+
+        match lookup(name) {
+            Some(value) => out.push_str(&value),
+            None => return Err(ErrorKind::MissingEnvVar(name.to_owned()).into()),
+        }
+    }
+
+    Ok(out)
+}
+
+fn interpolate_value<F>(value: &mut toml::Value, lookup: &F) -> Result<()>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match *value {
+        toml::Value::String(ref mut value) => {
+            let expanded = interpolate_with(value, lookup)?;
+            *value = expanded;
+        }
+        toml::Value::Array(ref mut values) => {
+            for value in values.iter_mut() {
+                interpolate_value(value, lookup)?;
+            }
+        }
+        toml::Value::Table(ref mut table) => interpolate_table_with(table, lookup)?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Expand references in every string value of a parsed table, including strings in arrays and
+/// nested tables, using `lookup`.
+pub fn interpolate_table_with<F>(table: &mut toml::Table, lookup: &F) -> Result<()>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    for (key, value) in table.iter_mut() {
+        interpolate_value(value, lookup).chain_err(|| {
+            ErrorKind::ConfigField(key.clone(), "could not expand variables".to_owned())
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Expand references in every string value of a parsed table against the process environment.
+pub fn interpolate_table(table: &mut toml::Table) -> Result<()> {
+    interpolate_table_with(table, &|name: &str| env::var(name).ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOST" => Some("localhost".to_owned()),
+            "PORT" => Some("8086".to_owned()),
+            "NESTED" => Some("$HOST".to_owned()),
+            _ => None,
+        }
+    }
+
+    fn expand(input: &str) -> Option<String> {
+        interpolate_with(input, lookup).ok()
+    }
+
+    #[test]
+    fn test_plain() {
+        assert_eq!(Some("no variables".to_owned()), expand("no variables"));
+    }
+
+    #[test]
+    fn test_references() {
+        assert_eq!(Some("localhost".to_owned()), expand("$HOST"));
+        assert_eq!(Some("localhost".to_owned()), expand("${HOST}"));
+        assert_eq!(Some("localhost.".to_owned()), expand("$HOST."));
+    }
+
+    #[test]
+    fn test_multiple_references() {
+        assert_eq!(
+            Some("http://localhost:8086/write".to_owned()),
+            expand("http://${HOST}:$PORT/write")
+        );
+        assert_eq!(Some("localhost8086".to_owned()), expand("${HOST}${PORT}"));
+    }
+
+    #[test]
+    fn test_values_are_not_expanded_again() {
+        assert_eq!(Some("$HOST".to_owned()), expand("${NESTED}"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(Some("$HOST".to_owned()), expand("$$HOST"));
+        assert_eq!(Some("cost: $5".to_owned()), expand("cost: $5"));
+        assert_eq!(Some("$".to_owned()), expand("$"));
+    }
+
+    #[test]
+    fn test_missing() {
+        match interpolate_with("${MISSING}", lookup) {
+            Err(Error(ErrorKind::MissingEnvVar(ref name), _)) => assert_eq!("MISSING", name),
+            _ => panic!("expected missing variable"),
+        }
+
+        assert!(expand("${HOST").is_none());
+    }
+
+    #[test]
+    fn test_table() {
+        let content = "# ${MISSING} in a comment\n\
+                       url = \"http://${HOST}:$PORT\"\n\
+                       [\"$HOST\"]\n\
+                       tags = [\"$HOST\", 1]\n";
+
+        let mut table = toml::Parser::new(content).parse().unwrap();
+        interpolate_table_with(&mut table, &lookup).unwrap();
+
+        assert_eq!(Some("http://localhost:8086"), table["url"].as_str());
+
+        // keys are not expanded.
+        let section = table["$HOST"].as_table().unwrap();
+        let tags = section["tags"].as_slice().unwrap();
+        assert_eq!(Some("localhost"), tags[0].as_str());
+        assert_eq!(Some(1), tags[1].as_integer());
+    }
+
+    #[test]
+    fn test_table_values_stay_strings() {
+        // a value which looks like TOML can't add keys.
+        let quoting = |name: &str| match name {
+            "EVIL" => Some("a\"\ninjected = \"b".to_owned()),
+            _ => None,
+        };
+
+        let mut table = toml::Parser::new("name = \"$EVIL\"").parse().unwrap();
+        interpolate_table_with(&mut table, &quoting).unwrap();
+
+        assert_eq!(Some("a\"\ninjected = \"b"), table["name"].as_str());
+        assert!(!table.contains_key("injected"));
+
+        let mut table = toml::Parser::new("[in.cpu]\nname = \"$MISSING\"").parse().unwrap();
+        assert!(interpolate_table_with(&mut table, &lookup).is_err());
+    }
+}
@@ -19,7 +19,18 @@ extern crate futures_cpupool;
 extern crate tokio_io;
 extern crate tokio_timer;
 extern crate tokio_core;
+extern crate tokio_uds;
 extern crate num_cpus;
+extern crate libc;
+extern crate rand;
+extern crate yaml_rust;
+extern crate regex;
+#[cfg(feature = "http")]
+extern crate reqwest;
+#[cfg(feature = "watch")]
+extern crate notify;
+#[cfg(feature = "kafka")]
+extern crate kafka;
 
 #[macro_use]
 extern crate serde_derive;
@@ -33,5 +44,27 @@ pub mod metric;
 pub mod scheduler;
 pub mod errors;
 pub mod poller;
+pub mod pool;
 pub mod updater;
+pub mod bus;
+pub mod dispatcher;
+pub mod heartbeat;
+pub mod dry_run;
+pub mod health;
+pub mod telemetry;
+pub mod runtime;
 pub mod config;
+pub mod control;
+pub mod daemon;
+pub mod ordering;
+pub mod spool;
+pub mod rename;
+pub mod filter;
+pub mod histograms;
+pub mod host;
+pub mod logger;
+pub mod interpolate;
+pub mod yaml;
+pub mod http;
+#[cfg(feature = "watch")]
+pub mod watch;
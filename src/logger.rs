@@ -0,0 +1,575 @@
+use errors::*;
+use futures::{Future, Poll};
+use log::{self, Level, LevelFilter, Log, Metadata, Record};
+use serde_json;
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use time;
+use libc;
+
+thread_local!(static PLUGIN: RefCell<Option<String>> = RefCell::new(None));
+
+/// Format of emitted log records.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Human-readable, one record per line.
+    Plain,
+    /// One JSON object per line.
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(value: &str) -> Result<LogFormat> {
+        match value {
+            "plain" => Ok(LogFormat::Plain),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(
+                ErrorKind::Message(format!("unsupported log format: {:?}", value)).into(),
+            ),
+        }
+    }
+}
+
+/// Where log records are written.
+#[derive(Debug, Clone)]
+pub enum LogTarget {
+    Stderr,
+    /// Append to a file, optionally rotating it once it grows beyond `max_size` bytes.
+    ///
+    /// At most `keep` rotated files are kept around, named `<path>.1` (newest) to `<path>.<keep>`.
+    File {
+        path: PathBuf,
+        max_size: Option<u64>,
+        keep: usize,
+    },
+    /// Send records to the local syslog daemon.
+    Syslog { ident: String, facility: u8 },
+}
+
+/// Syslog facilities which can be logged to.
+const FACILITIES: &[(&str, u8)] = &[
+    ("user", 1),
+    ("daemon", 3),
+    ("local0", 16),
+    ("local1", 17),
+    ("local2", 18),
+    ("local3", 19),
+    ("local4", 20),
+    ("local5", 21),
+    ("local6", 22),
+    ("local7", 23),
+];
+
+/// Sockets the local syslog daemon is commonly listening on.
+const SYSLOG_SOCKETS: &[&str] = &["/dev/log", "/var/run/syslog"];
+
+/// Look up a syslog facility by name.
+pub fn parse_facility(name: &str) -> Result<u8> {
+    FACILITIES
+        .iter()
+        .find(|&&(n, _)| n == name)
+        .map(|&(_, code)| code)
+        .ok_or_else(|| {
+            ErrorKind::Message(format!("unsupported syslog facility: {:?}", name)).into()
+        })
+}
+
+/// Log levels per target, in the style of `RUST_LOG`.
+///
+/// A filter like `warn,sysmon::poller=debug` logs everything at `warn` or above, except for
+/// `sysmon::poller` and its submodules which log at `debug` or above.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFilter {
+    default: LevelFilter,
+    /// Target prefixes and their levels, longest prefix first.
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    /// Filter which logs every target at the given level.
+    pub fn new(default: LevelFilter) -> LogFilter {
+        LogFilter {
+            default: default,
+            directives: Vec::new(),
+        }
+    }
+
+    /// Parse a comma-separated list of `<target>=<level>` or `<level>` directives.
+    ///
+    /// The default level is used for targets which no directive matches.
+    pub fn parse(spec: &str, default: LevelFilter) -> Result<LogFilter> {
+        let mut filter = LogFilter::new(default);
+
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            let first = parts.next().unwrap_or("");
+
+            match parts.next() {
+                Some(level) => {
+                    filter.directives.push((first.to_owned(), parse_level(level)?));
+                }
+                None => filter.default = parse_level(first)?,
+            }
+        }
+
+        filter.directives.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        Ok(filter)
+    }
+
+    /// Level at which the given target logs.
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        for &(ref prefix, level) in self.directives.iter() {
+            if target == prefix || target.starts_with(&format!("{}::", prefix)) {
+                return level;
+            }
+        }
+
+        self.default
+    }
+
+    /// Most verbose level of any target.
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives.iter().map(|&(_, level)| level).fold(
+            self.default,
+            ::std::cmp::max,
+        )
+    }
+}
+
+/// Parse a level, like `debug` or `warn`.
+pub fn parse_level(level: &str) -> Result<LevelFilter> {
+    level.parse::<LevelFilter>().map_err(|_| {
+        ErrorKind::Message(format!("unsupported log level: {:?}", level)).into()
+    })
+}
+
+/// Configuration of the global logger.
+#[derive(Debug, Clone)]
+pub struct LoggerConfig {
+    pub filter: LogFilter,
+    pub format: LogFormat,
+    pub target: LogTarget,
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    timestamp: &'a str,
+    level: String,
+    module: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plugin: Option<String>,
+}
+
+/// Log file which is rotated once it grows too large.
+struct RotatingFile {
+    path: PathBuf,
+    file: fs::File,
+    size: u64,
+    max_size: Option<u64>,
+    keep: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: Option<u64>, keep: usize) -> io::Result<RotatingFile> {
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            path: path,
+            file: file,
+            size: size,
+            max_size: max_size,
+            keep: keep,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+
+        if let Some(max_size) = self.max_size {
+            if self.size > 0 && self.size + len > max_size {
+                self.rotate()?;
+            }
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..self.keep).rev() {
+            let from = rotated_path(&self.path, index);
+
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+
+        if self.keep > 0 {
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+/// Connection to the local syslog daemon.
+struct Syslog {
+    socket: UnixDatagram,
+    ident: String,
+    facility: u8,
+    pid: i32,
+}
+
+impl Syslog {
+    fn connect(ident: String, facility: u8) -> io::Result<Syslog> {
+        let socket = UnixDatagram::unbound()?;
+
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no syslog socket found");
+
+        for path in SYSLOG_SOCKETS {
+            match socket.connect(path) {
+                Ok(()) => {
+                    return Ok(Syslog {
+                        socket: socket,
+                        ident: ident,
+                        facility: facility,
+                        pid: unsafe { libc::getpid() },
+                    })
+                }
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    fn send(&self, level: Level, message: &str) -> io::Result<()> {
+        let priority = self.facility as u32 * 8 + severity(level) as u32;
+        let packet = format!("<{}>{}[{}]: {}", priority, self.ident, self.pid, message);
+        self.socket.send(packet.as_bytes()).map(|_| ())
+    }
+}
+
+/// Map a log level to a syslog severity.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+enum Output {
+    Stderr,
+    File(Mutex<RotatingFile>),
+    Syslog(Syslog),
+}
+
+struct Logger {
+    filter: LogFilter,
+    format: LogFormat,
+    output: Output,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let timestamp = time::now_utc().rfc3339().to_string();
+        let plugin = current_plugin();
+        let line = format_record(self.format, &timestamp, plugin, record);
+
+        match self.output {
+            Output::Syslog(ref syslog) => {
+                // syslog adds its own timestamp, so the plain format only carries the message.
+                let message = match self.format {
+                    LogFormat::Plain => {
+                        let module = record.module_path().unwrap_or(record.target());
+                        format!("{}: {}", module, record.args())
+                    }
+                    LogFormat::Json => line,
+                };
+
+                if let Err(e) = syslog.send(record.level(), &message) {
+                    let stderr = io::stderr();
+                    let _ = writeln!(stderr.lock(), "failed to write to syslog: {}: {}", e, message);
+                }
+            }
+            Output::Stderr => {
+                let stderr = io::stderr();
+                let _ = writeln!(stderr.lock(), "{}", line);
+            }
+            Output::File(ref file) => {
+                let mut file = file.lock().unwrap_or_else(|p| p.into_inner());
+
+                if let Err(e) = file.write_line(&line) {
+                    let stderr = io::stderr();
+                    let _ = writeln!(stderr.lock(), "failed to write log file: {}: {}", e, line);
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        match self.output {
+            Output::Stderr => {
+                let _ = io::stderr().flush();
+            }
+            Output::File(ref file) => {
+                let _ = file.lock().unwrap_or_else(|p| p.into_inner()).file.flush();
+            }
+            Output::Syslog(..) => {}
+        }
+    }
+}
+
+/// Guard which restores the previous plugin when dropped, also when unwinding.
+struct PluginGuard {
+    previous: Option<String>,
+}
+
+impl Drop for PluginGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        PLUGIN.with(|p| *p.borrow_mut() = previous);
+    }
+}
+
+fn current_plugin() -> Option<String> {
+    PLUGIN.with(|p| p.borrow().clone())
+}
+
+/// Run the given closure with log records attributed to the named plugin.
+pub fn with_plugin<F, T>(name: &str, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let previous = PLUGIN.with(|p| mem::replace(&mut *p.borrow_mut(), Some(name.to_owned())));
+    let _guard = PluginGuard { previous: previous };
+    f()
+}
+
+/// Future which attributes log records to the named plugin while it is being polled.
+pub struct Scoped<F> {
+    name: String,
+    future: F,
+}
+
+impl<F: Future> Future for Scoped<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let future = &mut self.future;
+        with_plugin(&self.name, || future.poll())
+    }
+}
+
+/// Attribute log records emitted while polling the given future to the named plugin.
+pub fn scoped<F: Future>(name: String, future: F) -> Scoped<F> {
+    Scoped {
+        name: name,
+        future: future,
+    }
+}
+
+fn format_record(
+    format: LogFormat,
+    timestamp: &str,
+    plugin: Option<String>,
+    record: &Record,
+) -> String {
+    let module = record.module_path().unwrap_or(record.target());
+
+    match format {
+        LogFormat::Plain => {
+            format!("{} {:5} {}: {}", timestamp, record.level(), module, record.args())
+        }
+        LogFormat::Json => {
+            let json = JsonRecord {
+                timestamp: timestamp,
+                level: record.level().to_string(),
+                module: module,
+                message: record.args().to_string(),
+                plugin: plugin,
+            };
+
+            serde_json::to_string(&json).unwrap_or_else(|e| {
+                format!("{{\"level\":\"{}\",\"message\":{:?}}}", Level::Error, e.to_string())
+            })
+        }
+    }
+}
+
+/// Install the global logger.
+///
+/// If the configured log file or syslog can't be opened, logging falls back to stderr so that the returned
+/// error can be reported.
+pub fn init(config: LoggerConfig) -> Result<()> {
+    let (output, result) = match config.target {
+        LogTarget::Stderr => (Output::Stderr, Ok(())),
+        LogTarget::File {
+            path,
+            max_size,
+            keep,
+        } => {
+            match RotatingFile::open(path.clone(), max_size, keep) {
+                Ok(file) => (Output::File(Mutex::new(file)), Ok(())),
+                Err(e) => {
+                    let path = path.display();
+                    let message = format!("failed to open log file {}: {}", path, e);
+                    (Output::Stderr, Err(ErrorKind::Message(message).into()))
+                }
+            }
+        }
+        LogTarget::Syslog { ident, facility } => {
+            match Syslog::connect(ident, facility) {
+                Ok(syslog) => (Output::Syslog(syslog), Ok(())),
+                Err(e) => {
+                    let message = format!("failed to connect to syslog: {}", e);
+                    (Output::Stderr, Err(ErrorKind::Message(message).into()))
+                }
+            }
+        }
+    };
+
+    log::set_boxed_logger(Box::new(Logger {
+        filter: config.filter.clone(),
+        format: config.format,
+        output: output,
+    }))?;
+
+    log::set_max_level(config.filter.max_level());
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record<F, T>(message: &str, f: F) -> T
+    where
+        F: FnOnce(&Record) -> T,
+    {
+        f(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(Level::Info)
+                .target("sysmon::poller")
+                .module_path(Some("sysmon::poller"))
+                .build(),
+        )
+    }
+
+    #[test]
+    fn test_plain() {
+        let line = record("hello", |r| {
+            format_record(LogFormat::Plain, "2017-01-01T00:00:00Z", None, r)
+        });
+
+        assert_eq!("2017-01-01T00:00:00Z INFO  sysmon::poller: hello", line);
+    }
+
+    #[test]
+    fn test_json() {
+        let line = record("say \"hi\"", |r| {
+            format_record(LogFormat::Json, "2017-01-01T00:00:00Z", Some("cpu".to_owned()), r)
+        });
+
+        assert_eq!(
+            "{\"timestamp\":\"2017-01-01T00:00:00Z\",\"level\":\"INFO\",\
+             \"module\":\"sysmon::poller\",\"message\":\"say \\\"hi\\\"\",\"plugin\":\"cpu\"}",
+            line
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = LogFilter::parse("warn, sysmon=info,sysmon::poller=debug", LevelFilter::Info)
+            .unwrap();
+
+        assert_eq!(LevelFilter::Debug, filter.level_for("sysmon::poller"));
+        assert_eq!(LevelFilter::Debug, filter.level_for("sysmon::poller::inner"));
+        assert_eq!(LevelFilter::Info, filter.level_for("sysmon::pollers"));
+        assert_eq!(LevelFilter::Info, filter.level_for("sysmon::updater"));
+        assert_eq!(LevelFilter::Warn, filter.level_for("tokio_core"));
+        assert_eq!(LevelFilter::Debug, filter.max_level());
+
+        assert!(LogFilter::parse("sysmon=loud", LevelFilter::Info).is_err());
+    }
+
+    #[test]
+    fn test_facility() {
+        assert_eq!(3, parse_facility("daemon").unwrap());
+        assert_eq!(23, parse_facility("local7").unwrap());
+        assert!(parse_facility("kern").is_err());
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = ::std::env::temp_dir().join("sysmon-logger-test-rotate");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("sysmon.log");
+        let mut file = RotatingFile::open(path.clone(), Some(10), 2).unwrap();
+
+        for line in &["first", "second", "third", "fourth"] {
+            file.write_line(line).unwrap();
+        }
+
+        let read = |p: &Path| {
+            let mut content = String::new();
+            io::Read::read_to_string(&mut fs::File::open(p).unwrap(), &mut content).unwrap();
+            content
+        };
+
+        assert_eq!("fourth\n", read(&path));
+        assert_eq!("third\n", read(&rotated_path(&path, 1)));
+        assert_eq!("second\n", read(&rotated_path(&path, 2)));
+        assert!(!rotated_path(&path, 3).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_with_plugin() {
+        assert_eq!(None, current_plugin());
+
+        with_plugin("outer", || {
+            with_plugin("inner", || assert_eq!(Some("inner".to_owned()), current_plugin()));
+            assert_eq!(Some("outer".to_owned()), current_plugin());
+        });
+
+        assert_eq!(None, current_plugin());
+    }
+}
@@ -9,26 +9,30 @@ extern crate tokio_core;
 extern crate tokio_signal;
 #[macro_use]
 extern crate log;
-#[cfg(features = "watch")]
-extern crate notify;
 extern crate serde;
-extern crate env_logger;
+extern crate serde_json;
 
 use sysmon::config::*;
+use sysmon::control::{self, Command};
+use sysmon::daemon::{daemonize, PidFile};
 use sysmon::errors::*;
+use sysmon::logger::{parse_facility, parse_level, LogFilter, LogFormat, LogTarget, LoggerConfig};
 use sysmon::plugin::*;
-use sysmon::poller::Poller;
-use sysmon::scheduler::*;
-use sysmon::updater::Updater;
+use sysmon::runtime::{teardown_inputs, teardown_outputs, Runtime};
 
 use futures::*;
+use futures::future::Either;
 use futures::stream::Stream;
 use futures_cpupool::CpuPool;
 use std::cell::RefCell;
 use std::env;
+use std::io::{self, Read};
+use std::path::Path;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio_core::reactor::*;
+use tokio_signal::unix::{Signal, SIGHUP, SIGTERM, SIGUSR1};
 
 fn print_usage(program: &str, plugins: &PluginRegistry, opts: getopts::Options) {
     let brief = format!("Usage: {} [options]", program);
@@ -45,50 +49,471 @@ fn print_usage(program: &str, plugins: &PluginRegistry, opts: getopts::Options)
     }
 }
 
-fn load_configs(paths: Vec<String>) -> Result<(Config, Vec<Box<PluginSetup>>)> {
-    let mut setups = Vec::new();
+fn print_version() {
+    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
 
-    let mut config = Config::new();
+    let mut features = Vec::new();
 
-    for path in paths.iter() {
+    if cfg!(feature = "http") {
+        features.push("http");
+    }
+
+    if cfg!(feature = "watch") {
+        features.push("watch");
+    }
+
+    if cfg!(feature = "kafka") {
+        features.push("kafka");
+    }
+
+    println!("features: {}", features.join(", "));
+}
+
+/// Path given to `--config` to read configuration from stdin.
+const STDIN: &'static str = "-";
+
+/// Where configuration is loaded from.
+struct ConfigSource {
+    files: Vec<String>,
+    dirs: Vec<String>,
+    recursive: bool,
+    /// Configuration read from stdin, which has to be kept around since it can only be read once.
+    stdin: Option<String>,
+}
+
+impl ConfigSource {
+    /// All configuration files to load.
+    ///
+    /// Directories are searched again every time, so that added files are picked up on reload.
+    fn paths(&self) -> Result<Vec<String>> {
+        let mut paths = self.files.clone();
+
+        for dir in self.dirs.iter() {
+            let found = find_config_files(Path::new(dir), self.recursive).chain_err(
+                || ErrorKind::Config(dir.clone()),
+            )?;
+            paths.extend(found);
+        }
+
+        Ok(paths)
+    }
+}
+
+/// Load all configuration files.
+///
+/// Files are read and parsed in parallel on the given pool, but applied in the order they are
+/// listed, so the result doesn't depend on which file finished parsing first. Command line
+/// overrides are applied before the result is checked.
+fn load_configs(
+    mut config: Config,
+    overrides: &Overrides,
+    source: &ConfigSource,
+    pool: &CpuPool,
+) -> Result<(Config, Vec<PluginSection>)> {
+    let paths = source.paths()?;
+
+    let parsing: Vec<_> = paths
+        .iter()
+        .map(|path| {
+            let path = path.clone();
+            let stdin = if path == STDIN {
+                source.stdin.clone()
+            } else {
+                None
+            };
+
+            pool.spawn_fn(move || -> Result<Result<toml::Table>> {
+                let parsed = match (path.as_str(), stdin) {
+                    (STDIN, Some(content)) => {
+                        parse_config("<stdin>", content.as_bytes(), ConfigFormat::Toml)
+                    }
+                    _ => parse_config_file(&path),
+                };
+
+                let parsed = parsed.and_then(|root| resolve_includes(&path, root));
+
+                // every file is parsed, so that the first failing file in order is reported.
+                Ok(parsed.chain_err(|| ErrorKind::Config(path.clone())))
+            })
+        })
+        .collect();
+
+    let parsed = future::join_all(parsing).wait()?;
+    let mut sections = Vec::new();
+
+    for (path, root) in paths.iter().zip(parsed) {
         info!("loading: {}", path);
-        setups.push(load_config(&mut config, path)?);
+
+        let loaded = root.and_then(|root| {
+            apply_config(&mut config, path, root).chain_err(|| ErrorKind::Config(path.clone()))
+        })?;
+
+        sections.extend(loaded);
+    }
+
+    overrides.apply(&mut config);
+    config.add_host_tag();
+    config.select_sections(&mut sections);
+    check_sections(&sections, config.strict)?;
+    check_timings(&config, &sections)?;
+    check_routes(&sections)?;
+    Ok((config, sections))
+}
+
+/// Read all of stdin, for `--config -`.
+fn read_stdin() -> Result<String> {
+    let mut content = String::new();
+    io::stdin().read_to_string(&mut content).chain_err(|| {
+        ErrorKind::Config(STDIN.to_owned())
+    })?;
+    Ok(content)
+}
+
+/// Parse the value of `--threads`.
+fn parse_threads(value: &str) -> Result<usize> {
+    match value.parse::<usize>() {
+        Ok(threads) if threads >= 1 => Ok(threads),
+        _ => Err(
+            ErrorKind::Message(format!("--threads: expected a number >= 1, got {:?}", value))
+                .into(),
+        ),
+    }
+}
+
+/// Parse the value of `--reactor`.
+fn parse_reactor(value: &str) -> Result<Reactor> {
+    Reactor::parse(value).ok_or_else(|| {
+        ErrorKind::Message(format!("--reactor: expected pool or single, got {:?}", value)).into()
+    })
+}
+
+/// Parse the value of an interval given on the command line, like `--poll-interval`.
+fn parse_interval(option: &str, value: &str) -> Result<Duration> {
+    parse_duration_str(value).chain_err(|| format!("--{}", option))
+}
+
+/// Names given to a plugin selection option like `--only`, which can be repeated or separated by
+/// commas.
+fn plugin_list(matches: &getopts::Matches, option: &str) -> Vec<String> {
+    matches
+        .opt_strs(option)
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Options given on the command line, which override the global options of the configuration
+/// every time it is loaded.
+///
+/// Intervals configured for a specific instance still take precedence.
+#[derive(Default)]
+struct Overrides {
+    poll_interval: Option<Duration>,
+    update_interval: Option<Duration>,
+    reactor: Option<Reactor>,
+}
+
+impl Overrides {
+    fn from_matches(matches: &getopts::Matches) -> Result<Overrides> {
+        let mut overrides = Overrides::default();
+
+        if let Some(value) = matches.opt_str("poll-interval") {
+            overrides.poll_interval = Some(parse_interval("poll-interval", &value)?);
+        }
+
+        if let Some(value) = matches.opt_str("update-interval") {
+            overrides.update_interval = Some(parse_interval("update-interval", &value)?);
+        }
+
+        if let Some(value) = matches.opt_str("reactor") {
+            overrides.reactor = Some(parse_reactor(&value)?);
+        }
+
+        Ok(overrides)
+    }
+
+    fn apply(&self, config: &mut Config) {
+        if let Some(poll_interval) = self.poll_interval {
+            config.poll_interval = poll_interval;
+        }
+
+        if let Some(update_interval) = self.update_interval {
+            config.update_interval = update_interval;
+        }
+
+        if let Some(reactor) = self.reactor {
+            config.reactor = reactor;
+        }
     }
+}
+
+/// Reloads configuration into a running runtime.
+struct Reloader {
+    runtime: Rc<RefCell<Runtime>>,
+    base_config: Config,
+    overrides: Overrides,
+    source: ConfigSource,
+    /// Pool to parse configuration files on.
+    cpupool: Arc<CpuPool>,
+}
 
-    Ok((config, setups))
+impl Reloader {
+    /// A configuration which fails to load or set up leaves the running instances in place.
+    fn reload(&self) -> Result<()> {
+        info!("Reloading configuration");
+
+        let loaded = load_configs(
+            self.base_config.clone(),
+            &self.overrides,
+            &self.source,
+            &self.cpupool,
+        );
+
+        let result = loaded.and_then(|(config, sections)| {
+            self.runtime.borrow_mut().reload(&config, sections)
+        });
+
+        if let Err(ref e) = result {
+            report_error(e);
+            warn!("Reload failed, keeping previous configuration");
+        }
+
+        result
+    }
+}
+
+/// Execute commands received through the control socket.
+fn control_handler(reloader: Rc<Reloader>) -> control::Handler {
+    Rc::new(move |command| {
+        let runtime = &reloader.runtime;
+
+        match command {
+            Command::Reload => {
+                reloader.reload()?;
+                Ok(serde_json::Value::Null)
+            }
+            Command::ListPlugins => Ok(serde_json::to_value(&runtime.borrow().describe())),
+            Command::PluginStatus(name) => {
+                match runtime.borrow().status(&name)? {
+                    Some(status) => Ok(serde_json::to_value(&status)),
+                    None => Err(
+                        ErrorKind::Message(format!("no such input instance: {}", name)).into(),
+                    ),
+                }
+            }
+            Command::PollNow(name) => {
+                runtime.borrow().poll_now(name.as_ref().map(String::as_str))?;
+                Ok(serde_json::Value::Null)
+            }
+        }
+    })
 }
 
-fn setup_plugins(
-    setups: Vec<Box<PluginSetup>>,
-    config: &Config,
-    plugins: &PluginRegistry,
-    partial_context: &PartialPluginContext,
-) -> Result<(Arc<Vec<Arc<Box<InputInstance>>>>, Arc<Vec<Box<OutputInstance>>>)> {
-    let mut inputs: Vec<Arc<Box<InputInstance>>> = Vec::new();
-    let mut outputs: Vec<Box<OutputInstance>> = Vec::new();
+/// Give every output a chance to flush buffered state, and to shut down, before exiting.
+fn flush_outputs(outputs: &Vec<Arc<ConfiguredOutput>>) -> Result<()> {
+    for output in outputs.iter() {
+        if let Err(e) = output.flush() {
+            error!("{}: failed to flush output: {}", output.options.name, e);
+        }
+
+        if let Err(e) = output.instance.shutdown() {
+            error!("{}: failed to shut down output: {}", output.options.name, e);
+        }
+    }
+
+    Ok(())
+}
 
-    for setup in setups {
-        let (input, output) = setup(&config, plugins, partial_context)?;
+/// Log an error together with its chain of causes.
+fn report_error(e: &Error) {
+    error!("{}", e);
 
-        inputs.extend(input);
-        outputs.extend(output);
+    for e in e.iter().skip(1) {
+        error!("  caused by: {}", e);
     }
 
-    Ok((Arc::new(inputs), Arc::new(outputs)))
+    if let Some(backtrace) = e.backtrace() {
+        error!("  backtrace: {:?}", backtrace);
+    }
 }
 
 fn setup_opts() -> getopts::Options {
     let mut opts = getopts::Options::new();
 
     opts.optflag("h", "help", "print this help");
-    opts.optflag("", "debug", "enable debug logging");
-    opts.optmulti("", "config", "load configuration file", "<file>");
+    opts.optflag("V", "version", "print version information");
+    opts.optflag(
+        "",
+        "list-plugins",
+        "print available plugins as JSON, then exit",
+    );
+    opts.optflag(
+        "",
+        "generate-config",
+        "print an example configuration for all available plugins, then exit",
+    );
+    opts.optflag("", "debug", "enable debug logging, same as --log-level debug");
+    opts.optopt(
+        "",
+        "log-level",
+        "level to log at, one of trace, debug, info, warn, or error (default: info)",
+        "<level>",
+    );
+    opts.optopt(
+        "",
+        "log-filter",
+        "per-module log levels, like sysmon::poller=debug,sysmon::updater=info",
+        "<filter>",
+    );
+    opts.optopt(
+        "",
+        "log-format",
+        "format of log records, defaults to plain",
+        "<plain|json>",
+    );
+    opts.optopt(
+        "",
+        "log-target",
+        "where to write logs, defaults to file if --log-file is given, otherwise stderr",
+        "<stderr|file|syslog>",
+    );
+    opts.optopt("", "log-file", "write logs to the given file instead of stderr", "<path>");
+    opts.optopt(
+        "",
+        "log-max-size",
+        "rotate the log file once it grows beyond this many bytes",
+        "<bytes>",
+    );
+    opts.optopt(
+        "",
+        "log-keep",
+        "number of rotated log files to keep, defaults to 5",
+        "<n>",
+    );
+    opts.optopt(
+        "",
+        "syslog-ident",
+        "identity to log to syslog as, defaults to sysmon",
+        "<ident>",
+    );
+    opts.optopt(
+        "",
+        "syslog-facility",
+        "syslog facility to log to, defaults to daemon",
+        "<user|daemon|local0..local7>",
+    );
+    opts.optmulti(
+        "",
+        "config",
+        "load configuration file, or - to read TOML from stdin",
+        "<file>",
+    );
+    opts.optopt(
+        "",
+        "threads",
+        "number of worker threads, defaults to the number of cpus",
+        "<n>",
+    );
+    opts.optopt(
+        "",
+        "reactor",
+        "where inputs are polled and updated, pool (default) or single",
+        "<model>",
+    );
+    opts.optopt(
+        "",
+        "poll-interval",
+        "default poll interval, overrides the configured one",
+        "<duration>",
+    );
+    opts.optopt(
+        "",
+        "update-interval",
+        "default update interval, overrides the configured one",
+        "<duration>",
+    );
+    opts.optmulti(
+        "",
+        "config-dir",
+        "load all *.toml, *.yaml and *.yml files in directory",
+        "<dir>",
+    );
+    opts.optflag(
+        "",
+        "recursive",
+        "also load configuration from subdirectories of --config-dir",
+    );
+    opts.optflag(
+        "",
+        "skip-failed",
+        "log and skip plugins which fail to set up",
+    );
+    opts.optflag(
+        "",
+        "dry-run",
+        "set up outputs, but log the samples they would have sent instead of sending them",
+    );
+    opts.optflag(
+        "",
+        "allow-unknown-keys",
+        "warn about unknown keys in plugin sections, instead of failing",
+    );
+    opts.optflag(
+        "",
+        "strict",
+        "fail if the configuration has no inputs or no outputs, instead of warning",
+    );
+    opts.optflag(
+        "",
+        "no-host-tag",
+        "don't add the hostname as a tag to every sample",
+    );
+    opts.optmulti(
+        "",
+        "only",
+        "only set up the plugin sections with the given ids, names, or types",
+        "<name>,...",
+    );
+    opts.optmulti(
+        "",
+        "disable",
+        "don't set up the plugin sections with the given ids, names, or types",
+        "<name>,...",
+    );
+    opts.optflag(
+        "",
+        "daemon",
+        "fork into the background, requires logging to a file or to syslog",
+    );
+    opts.optopt(
+        "",
+        "pid-file",
+        "write the pid to the given file, which is removed on shutdown",
+        "<path>",
+    );
+    opts.optflag(
+        "",
+        "once",
+        "update and poll every input once, deliver the samples, then exit",
+    );
+    opts.optflag(
+        "",
+        "check",
+        "validate configuration and plugin setup, then exit",
+    );
+    opts.optflag(
+        "",
+        "self-test",
+        "poll every input once at startup, and warn about inputs which fail or report nothing",
+    );
 
     #[cfg(feature = "watch")]
     opts.optflag(
         "w",
         "watch",
-        "enable watching of the configuration directory",
+        "reload when configuration files change",
     );
 
     opts
@@ -96,15 +521,107 @@ fn setup_opts() -> getopts::Options {
 
 /// Configure logging
 ///
-/// If debug (--debug) is specified, logging should be configured with LogLevelFilter::Debug.
+/// The level is taken from `--log-level`, `--debug` is a shorthand for `--log-level debug`.
 fn setup_logger(matches: &getopts::Matches) -> Result<()> {
-    let level: log::LevelFilter = match matches.opt_present("debug") {
-        true => log::LevelFilter::Debug,
-        false => log::LevelFilter::Info,
+    let level = match (matches.opt_str("log-level"), matches.opt_present("debug")) {
+        (Some(_), true) => {
+            return Err(
+                ErrorKind::Message("--debug: can't be combined with --log-level".to_owned())
+                    .into(),
+            );
+        }
+        (Some(level), false) => parse_level(&level).chain_err(|| "--log-level")?,
+        (None, true) => log::LevelFilter::Debug,
+        (None, false) => log::LevelFilter::Info,
     };
 
-    env_logger::init();
-    Ok(())
+    let format = match matches.opt_str("log-format") {
+        Some(format) => LogFormat::parse(&format)?,
+        None => LogFormat::Plain,
+    };
+
+    let log_file = matches.opt_str("log-file");
+
+    let default_target = match log_file {
+        Some(_) => "file",
+        None => "stderr",
+    };
+
+    let target = match matches.opt_str("log-target") {
+        Some(target) => target,
+        None => default_target.to_owned(),
+    };
+
+    let target = match target.as_str() {
+        "stderr" if matches.opt_present("daemon") => {
+            return Err(
+                ErrorKind::Message(
+                    "--daemon: stderr is detached, use --log-file or --log-target syslog"
+                        .to_owned(),
+                ).into(),
+            );
+        }
+        "stderr" => LogTarget::Stderr,
+        "file" => {
+            let path = log_file.ok_or_else(|| {
+                ErrorKind::Message("--log-target file requires --log-file".to_owned())
+            })?;
+
+            let max_size = match matches.opt_str("log-max-size") {
+                Some(max_size) => Some(parse_number("--log-max-size", &max_size)?),
+                None => None,
+            };
+
+            let keep = match matches.opt_str("log-keep") {
+                Some(keep) => parse_number("--log-keep", &keep)? as usize,
+                None => 5,
+            };
+
+            LogTarget::File {
+                path: path.into(),
+                max_size: max_size,
+                keep: keep,
+            }
+        }
+        "syslog" => {
+            let ident = matches.opt_str("syslog-ident").unwrap_or_else(
+                || "sysmon".to_owned(),
+            );
+
+            let facility = match matches.opt_str("syslog-facility") {
+                Some(facility) => parse_facility(&facility)?,
+                None => parse_facility("daemon")?,
+            };
+
+            LogTarget::Syslog {
+                ident: ident,
+                facility: facility,
+            }
+        }
+        target => {
+            return Err(
+                ErrorKind::Message(format!("unsupported log target: {:?}", target)).into(),
+            );
+        }
+    };
+
+    let filter = match matches.opt_str("log-filter") {
+        Some(spec) => LogFilter::parse(&spec, level)?,
+        None => LogFilter::new(level),
+    };
+
+    sysmon::logger::init(LoggerConfig {
+        filter: filter,
+        format: format,
+        target: target,
+    })
+}
+
+/// Parse a non-negative number given as the value of a command line option.
+fn parse_number(option: &str, value: &str) -> Result<u64> {
+    value.parse::<u64>().map_err(|_| {
+        ErrorKind::Message(format!("{}: expected a number, got {:?}", option, value)).into()
+    })
 }
 
 /// Actual main
@@ -129,60 +646,231 @@ fn run() -> Result<()> {
         return Ok(());
     }
 
+    if matches.opt_present("version") {
+        print_version();
+        return Ok(());
+    }
+
+    if matches.opt_present("generate-config") {
+        print!("{}", generate_config(&plugins)?);
+        return Ok(());
+    }
+
+    if matches.opt_present("list-plugins") {
+        println!("{}", serde_json::to_string(&plugins.describe())?);
+        return Ok(());
+    }
+
     setup_logger(&matches)?;
 
-    let (config, setups) = load_configs(matches.opt_strs("config"))?;
+    let files = matches.opt_strs("config");
 
-    let cpupool = Arc::new(CpuPool::new(config.threads()));
-    let core = Rc::new(RefCell::new(Core::new()?));
+    let stdin = match files.iter().filter(|f| *f == STDIN).count() {
+        0 => None,
+        1 => Some(read_stdin()?),
+        _ => {
+            return Err(
+                ErrorKind::Message("--config -: stdin can only be used once".to_owned()).into(),
+            )
+        }
+    };
+
+    let overrides = Overrides::from_matches(&matches)?;
 
-    let (input, output) = {
-        let partial_context = PartialPluginContext::new(cpupool.clone(), core.clone());
-        setup_plugins(setups, &config, &plugins, &partial_context)?
+    // no threads may be running when forking, and signal handlers are installed after it.
+    if matches.opt_present("daemon") && !matches.opt_present("check") {
+        let output = match matches.opt_str("log-target") {
+            Some(ref target) if target != "file" => None,
+            _ => matches.opt_str("log-file"),
+        };
+
+        daemonize(output.as_ref().map(Path::new))?;
+        info!("running in the background");
+    }
+
+    let _pid_file = match matches.opt_str("pid-file") {
+        Some(ref path) if !matches.opt_present("check") => Some(PidFile::create(Path::new(path))?),
+        _ => None,
     };
 
-    let poller = Poller::new(input.clone(), output.clone());
-    let updater = Updater::new(input.clone(), cpupool.clone());
+    let source = ConfigSource {
+        files: files,
+        dirs: matches.opt_strs("config-dir"),
+        recursive: matches.opt_present("recursive"),
+        stdin: stdin,
+    };
 
-    let ref mut core = core.try_borrow_mut()?;
+    let mut base_config = Config::new();
+    base_config.skip_failed = matches.opt_present("skip-failed");
+    base_config.dry_run = matches.opt_present("dry-run");
+    base_config.allow_unknown_keys = matches.opt_present("allow-unknown-keys");
+    base_config.strict = matches.opt_present("strict");
+    base_config.no_host_tag = matches.opt_present("no-host-tag");
+    base_config.only_plugins = plugin_list(&matches, "only");
+    base_config.disabled_plugins = plugin_list(&matches, "disable");
+
+    // the pool used for polling is sized by the configuration, so it can't be used to load it.
+    let (mut config, sections) = {
+        let loader = CpuPool::new_num_cpus();
+        load_configs(base_config.clone(), &overrides, &source, &loader)?
+    };
 
+    if let Some(threads) = matches.opt_str("threads") {
+        config.set_threads(parse_threads(&threads)?);
+    }
+
+    debug!("using {} worker thread(s), reactor: {:?}", config.threads(), config.reactor);
+
+    let cpupool = Arc::new(CpuPool::new(config.threads()));
+    let mut core = Core::new()?;
     let handle = core.handle();
 
-    let update_interval = Interval::new(config.update_interval, &handle)?.map_err(
-        Into::into,
-    );
-    let poll_interval = Interval::new(config.poll_interval, &handle)?.map_err(
-        Into::into,
-    );
+    let mut runtime = Runtime::new(handle.clone(), cpupool.clone(), plugins);
+
+    if matches.opt_present("check") {
+        let instances = runtime.prepare(&config, sections)?;
 
-    let update = update_interval.and_then(move |_| updater.run());
-    let poll = poll_interval.and_then(move |_| poller.run());
+        println!(
+            "configuration ok: {} input(s) and {} output(s) validated",
+            instances.inputs().len(),
+            instances.outputs().len()
+        );
+
+        return Ok(());
+    }
+
+    if matches.opt_present("once") {
+        let instances = runtime.prepare(&config, sections)?;
+        let once = runtime.run_once(&config, instances)?;
+        core.run(once)?;
+        teardown_inputs(runtime.take_instances().inputs());
+        info!("Polled all inputs once, shutting down");
+        return Ok(());
+    }
+
+    if matches.opt_present("self-test") {
+        let instances = runtime.prepare(&config, sections)?;
+        let self_test = runtime.self_test(&config, &instances);
+        core.run(self_test)?;
+        runtime.start(&config, instances)?;
+    } else {
+        runtime.reload(&config, sections)?;
+    }
+
+    let runtime = Rc::new(RefCell::new(runtime));
 
     let ctrl_c = core.run(::tokio_signal::ctrl_c(&handle))?;
+    let sigterm = core.run(Signal::new(SIGTERM, &handle))?;
+    let sighup = core.run(Signal::new(SIGHUP, &handle))?;
+    let sigusr1 = core.run(Signal::new(SIGUSR1, &handle))?;
+
+    #[cfg(feature = "watch")]
+    let watch = if matches.opt_present("watch") {
+        let files: Vec<String> = source.files.iter().filter(|f| *f != STDIN).cloned().collect();
+
+        Some(sysmon::watch::watch_config(
+            &files,
+            &source.dirs,
+            source.recursive,
+        )?)
+    } else {
+        None
+    };
+
+    let reloader = Rc::new(Reloader {
+        runtime: runtime.clone(),
+        base_config: base_config,
+        overrides: overrides,
+        source: source,
+        cpupool: cpupool.clone(),
+    });
+
+    let sighup_reloader = reloader.clone();
+
+    let reload = sighup.map_err(Into::<Error>::into).for_each(move |_| {
+        let _ = sighup_reloader.reload();
+        Ok(())
+    });
+
+    handle.spawn(reload.map_err(|e| error!("reload handler failed: {}", e)));
+
+    let poll_runtime = runtime.clone();
+
+    let poll_now = sigusr1.map_err(Into::<Error>::into).for_each(move |_| {
+        if let Err(e) = poll_runtime.borrow().poll_now(None) {
+            report_error(&e);
+        }
 
-    let shutdown: Box<Future<Item = (), Error = Error>> =
-        Box::new(ctrl_c.map_err(Into::into).for_each(|_| {
-            info!("Interrupted");
-            Err(ErrorKind::Shutdown.into())
-        }));
+        Ok(())
+    });
 
-    let mut futures: Vec<Box<Future<Item = (), Error = Error>>> = Vec::new();
-    futures.push(Box::new(update.for_each(|_| Ok(()))));
-    futures.push(Box::new(poll.for_each(|_| Ok(()))));
+    handle.spawn(poll_now.map_err(|e| error!("poll handler failed: {}", e)));
 
-    let tasks: Box<Future<Item = (), Error = Error>> =
-        Box::new(future::join_all(futures).map(|_| ()));
-    let combo = future::select_all(vec![tasks, shutdown]);
+    #[cfg(feature = "watch")]
+    {
+        if let Some(watch) = watch {
+            let watch_reloader = reloader.clone();
+
+            let reload = watch.for_each(move |_| {
+                info!("Configuration changed");
+                let _ = watch_reloader.reload();
+                Ok(())
+            });
+
+            handle.spawn(reload.map_err(|e| error!("watch failed: {}", e)));
+        }
+    }
+
+    // bound once at startup, changing it only takes effect on restart.
+    let _control_socket = match config.control_socket {
+        Some(ref path) => {
+            let handler = control_handler(reloader.clone());
+            Some(control::serve(&handle, Path::new(path), handler)?)
+        }
+        None => None,
+    };
+
+    // every SIGINT or SIGTERM shows up as one item in this stream.
+    let signals = ctrl_c.select(sigterm.map(|_| ())).map_err(
+        Into::<Error>::into,
+    );
+
+    let shutdown = signals.into_future().map(|(_, rest)| rest).map_err(
+        |(e, _)| e,
+    );
 
     info!("Started!");
 
-    match core.run(combo) {
-        Err((Error(ErrorKind::Shutdown, ..), ..)) => {}
-        Ok(..) => {}
-        Err(e) => {
-            let (error, _size, _futures) = e;
-            return Err(error);
+    let signals = core.run(shutdown)?;
+
+    // stops both the pollers and the updaters.
+    runtime.borrow_mut().shutdown();
+
+    info!("Interrupted, flushing outputs (signal again to force exit)");
+
+    // the runtime lets go of the instances, so that inputs can be torn down once their polls have
+    // completed.
+    let (flush_output, teardown_input) = {
+        let instances = runtime.borrow_mut().take_instances();
+        (instances.outputs(), instances.inputs())
+    };
+
+    let flush = cpupool.spawn_fn(move || {
+        flush_outputs(&flush_output)?;
+        teardown_outputs(flush_output);
+        teardown_inputs(teardown_input);
+        Ok::<(), Error>(())
+    });
+    let forced = signals.into_future().map_err(|(e, _)| e);
+
+    match core.run(flush.select2(forced)) {
+        Ok(Either::A(..)) => {}
+        Ok(Either::B(..)) => {
+            warn!("Interrupted again, forcing exit");
+            ::std::process::exit(1);
         }
+        Err(Either::A((e, _))) => return Err(e),
+        Err(Either::B((e, _))) => return Err(e),
     }
 
     info!("Shutting down!");
@@ -193,17 +881,8 @@ fn run() -> Result<()> {
 fn main() {
     match run() {
         Err(e) => {
-            error!("{}", e);
-
-            for e in e.iter().skip(1) {
-                error!("  caused by: {}", e);
-            }
-
-            if let Some(backtrace) = e.backtrace() {
-                error!("  backtrace: {:?}", backtrace);
-            }
-
-            ::std::process::exit(1);
+            report_error(&e);
+            ::std::process::exit(exit_code(&e));
         }
         _ => {}
     };
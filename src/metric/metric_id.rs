@@ -18,6 +18,14 @@ impl MetricIdBuilder {
         self
     }
 
+    pub fn tags(mut self, tags: &[(&str, &str)]) -> MetricIdBuilder {
+        for &(key, value) in tags {
+            self.tags.push((key.to_owned(), value.to_owned()));
+        }
+
+        self
+    }
+
     pub fn resource(mut self, key: &str, value: &str) -> MetricIdBuilder {
         self.resource.push((key.to_owned(), value.to_owned()));
         self
@@ -55,6 +63,38 @@ impl MetricId {
             resource: Vec::new(),
         }
     }
+
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_ref().map(String::as_str)
+    }
+
+    pub fn tags(&self) -> &[(String, String)] {
+        &self.tags
+    }
+
+    pub fn resource(&self) -> &[(String, String)] {
+        &self.resource
+    }
+
+    /// Build a copy of this id, with a different key.
+    pub fn with_key(&self, key: &str) -> MetricId {
+        let mut id = self.clone();
+        id.key = Some(key.to_owned());
+        id
+    }
+
+    /// Build a copy of this id, with the given tags added unless already present.
+    pub fn with_tags(&self, tags: &[(String, String)]) -> MetricId {
+        let mut id = self.clone();
+
+        for &(ref key, ref value) in tags {
+            if !id.tags.iter().any(|&(ref k, _)| k == key) {
+                id.tags.push((key.clone(), value.clone()));
+            }
+        }
+
+        id
+    }
 }
 
 impl fmt::Display for MetricId {
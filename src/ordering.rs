@@ -0,0 +1,242 @@
+//! Ordering of the setup of plugin sections.
+//!
+//! Sections can declare a `priority`, where lower priorities are set up first, and the sections
+//! that they depend on through `depends_on`. A dependency is referred to by its table and id, like
+//! `out.influxdb`, which includes every element of an array of tables.
+
+use config::PluginSection;
+use errors::*;
+use toml;
+
+/// Ordering constraints of a single section.
+#[derive(Debug)]
+struct Node {
+    name: String,
+    priority: i64,
+    /// Indexes of the sections that have to be set up first.
+    depends_on: Vec<usize>,
+}
+
+/// Qualified name of a section, like `in.cpu` or `in.frontend[0]`.
+fn qualified_name(section: &PluginSection) -> String {
+    format!("{}.{}", section.kind.table(), section.id)
+}
+
+/// If a dependency refers to the section with the given qualified name.
+fn refers_to(reference: &str, name: &str) -> bool {
+    name == reference || (name.starts_with(reference) && name[reference.len()..].starts_with('['))
+}
+
+fn decode_priority(table: &toml::Table) -> Result<i64> {
+    match table.get("priority") {
+        None => Ok(0),
+        Some(&toml::Value::Integer(priority)) => Ok(priority),
+        Some(_) => Err(
+            ErrorKind::ConfigField("priority".to_owned(), "must be a number".to_owned()).into(),
+        ),
+    }
+}
+
+fn decode_depends_on(table: &toml::Table) -> Result<Vec<String>> {
+    let values = match table.get("depends_on") {
+        None => return Ok(Vec::new()),
+        Some(&toml::Value::String(ref value)) => return Ok(vec![value.clone()]),
+        Some(&toml::Value::Array(ref values)) => values,
+        Some(_) => {
+            return Err(
+                ErrorKind::ConfigField(
+                    "depends_on".to_owned(),
+                    "must be a string or an array of strings".to_owned(),
+                ).into(),
+            );
+        }
+    };
+
+    let mut out = Vec::new();
+
+    for value in values {
+        match *value {
+            toml::Value::String(ref value) => out.push(value.clone()),
+            _ => {
+                return Err(
+                    ErrorKind::ConfigField(
+                        "depends_on".to_owned(),
+                        "must be a string or an array of strings".to_owned(),
+                    ).into(),
+                );
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+fn nodes(sections: &[PluginSection]) -> Result<Vec<Node>> {
+    let names: Vec<String> = sections.iter().map(qualified_name).collect();
+    let mut nodes = Vec::new();
+
+    for (section, name) in sections.iter().zip(names.iter()) {
+        let mut depends_on = Vec::new();
+
+        let references = decode_depends_on(&section.table).chain_err(|| {
+            ErrorKind::ConfigSection(section.id.clone())
+        })?;
+
+        for reference in references {
+            let before = depends_on.len();
+
+            depends_on.extend(
+                names
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, name)| refers_to(&reference, name))
+                    .map(|(index, _)| index),
+            );
+
+            if depends_on.len() == before {
+                let e: Error = ErrorKind::ConfigField(
+                    "depends_on".to_owned(),
+                    format!("no such section: {}", reference),
+                ).into();
+
+                return Err(e).chain_err(|| ErrorKind::ConfigSection(section.id.clone()));
+            }
+        }
+
+        nodes.push(Node {
+            name: name.clone(),
+            priority: decode_priority(&section.table).chain_err(|| {
+                ErrorKind::ConfigSection(section.id.clone())
+            })?,
+            depends_on: depends_on,
+        });
+    }
+
+    Ok(nodes)
+}
+
+/// Find a cycle among the sections which haven't been ordered, as qualified names.
+///
+/// Every remaining section depends on another remaining section, otherwise it could have been
+/// ordered, so following dependencies eventually visits a section twice.
+fn find_cycle(nodes: &[Node], placed: &[bool]) -> Vec<String> {
+    let mut path: Vec<usize> = Vec::new();
+
+    let mut current = match placed.iter().position(|placed| !placed) {
+        Some(current) => current,
+        None => return Vec::new(),
+    };
+
+    loop {
+        if let Some(start) = path.iter().position(|index| *index == current) {
+            let mut cycle: Vec<String> = path[start..]
+                .iter()
+                .map(|index| nodes[*index].name.clone())
+                .collect();
+
+            cycle.push(nodes[current].name.clone());
+            return cycle;
+        }
+
+        path.push(current);
+
+        current = match nodes[current].depends_on.iter().find(|index| !placed[**index]) {
+            Some(next) => *next,
+            None => return Vec::new(),
+        };
+    }
+}
+
+/// Order sections so that every section is set up after the sections it depends on.
+///
+/// Among sections whose dependencies have been set up, the lowest priority goes first, and
+/// sections with the same priority keep the order they were loaded in.
+pub fn order_sections(sections: Vec<PluginSection>) -> Result<Vec<PluginSection>> {
+    let nodes = nodes(&sections)?;
+    let mut placed = vec![false; nodes.len()];
+    let mut order = Vec::new();
+
+    while order.len() < nodes.len() {
+        let next = nodes
+            .iter()
+            .enumerate()
+            .filter(|&(index, node)| {
+                !placed[index] && node.depends_on.iter().all(|d| placed[*d])
+            })
+            .min_by_key(|&(index, node)| (node.priority, index))
+            .map(|(index, _)| index);
+
+        match next {
+            Some(index) => {
+                placed[index] = true;
+                order.push(index);
+            }
+            None => return Err(ErrorKind::DependencyCycle(find_cycle(&nodes, &placed)).into()),
+        }
+    }
+
+    let mut sections: Vec<Option<PluginSection>> = sections.into_iter().map(Some).collect();
+    Ok(order.into_iter().filter_map(|index| sections[index].take()).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use config::PluginKind;
+
+    fn ids(sections: &[PluginSection]) -> Vec<String> {
+        sections.iter().map(qualified_name).collect()
+    }
+
+    #[test]
+    fn test_order_sections() {
+        let sections = vec![
+            PluginSection::parse(PluginKind::Input, "cpu", "depends_on = \"out.influxdb\""),
+            PluginSection::parse(PluginKind::Input, "memory", ""),
+            PluginSection::parse(PluginKind::Output, "influxdb", "priority = 1"),
+            PluginSection::parse(PluginKind::Output, "stdout", "priority = -1"),
+        ];
+
+        assert_eq!(
+            vec!["out.stdout", "in.memory", "out.influxdb", "in.cpu"],
+            ids(&order_sections(sections).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_order_sections_array() {
+        let sections = vec![
+            PluginSection::parse(PluginKind::Input, "cpu", "depends_on = [\"out.graphite\"]"),
+            PluginSection::parse(PluginKind::Output, "graphite[0]", ""),
+            PluginSection::parse(PluginKind::Output, "graphite[1]", ""),
+        ];
+
+        assert_eq!(
+            vec!["out.graphite[0]", "out.graphite[1]", "in.cpu"],
+            ids(&order_sections(sections).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_order_sections_cycle() {
+        let sections = vec![
+            PluginSection::parse(PluginKind::Input, "cpu", ""),
+            PluginSection::parse(PluginKind::Input, "a", "depends_on = \"in.b\""),
+            PluginSection::parse(PluginKind::Input, "b", "depends_on = \"in.a\""),
+        ];
+
+        match *order_sections(sections).unwrap_err().kind() {
+            ErrorKind::DependencyCycle(ref cycle) => {
+                assert_eq!(vec!["in.a", "in.b", "in.a"], *cycle);
+            }
+            ref kind => panic!("unexpected error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_order_sections_missing() {
+        let sections =
+            vec![PluginSection::parse(PluginKind::Input, "cpu", "depends_on = \"out.missing\"")];
+        assert!(order_sections(sections).is_err());
+    }
+}
@@ -0,0 +1,70 @@
+/// I/O counters of a single block device in /proc/diskstats.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DiskStat {
+    pub device: String,
+    /// Completed reads.
+    pub reads: u64,
+    /// Sectors read, which are always 512 bytes.
+    pub read_sectors: u64,
+    /// Completed writes.
+    pub writes: u64,
+    /// Sectors written.
+    pub written_sectors: u64,
+}
+
+fn parse_line(line: &str) -> Option<DiskStat> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+
+    // major, minor, and the name of the device, followed by at least eleven counters.
+    if parts.len() < 14 {
+        return None;
+    }
+
+    let values = parts[3..].iter().map(|v| v.parse()).collect::<Result<Vec<u64>, _>>();
+
+    let values = match values {
+        Ok(values) => values,
+        Err(_) => return None,
+    };
+
+    Some(DiskStat {
+        device: parts[2].to_owned(),
+        reads: values[0],
+        read_sectors: values[2],
+        writes: values[4],
+        written_sectors: values[6],
+    })
+}
+
+/// Parse the contents of /proc/diskstats into counters per device.
+///
+/// Lines which can't be parsed are ignored.
+pub fn parse_diskstats(input: &str) -> Vec<DiskStat> {
+    input.lines().filter_map(parse_line).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_diskstats() {
+        let text = "   8       0 sda 1000 10 50000 300 2000 20 80000 600 0 900 900\n   \
+                    8       1 sda1 900 5 40000 250 1500 10 60000 500 0 700 750 0 0 0 0\n   \
+                    7       0 loop0 bad 0 0 0 0 0 0 0 0 0 0\n";
+
+        let stats = parse_diskstats(text);
+
+        assert_eq!(2, stats.len());
+        assert_eq!(
+            DiskStat {
+                device: "sda1".to_owned(),
+                reads: 900,
+                read_sectors: 40000,
+                writes: 1500,
+                written_sectors: 60000,
+            },
+            stats[1]
+        );
+    }
+}
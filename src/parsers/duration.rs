@@ -0,0 +1,90 @@
+use nom::{alpha, digit};
+use std::str::{self, FromStr};
+use std::time::Duration;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Number of nanoseconds in the given duration unit.
+pub fn duration_unit(unit: &[u8]) -> Option<u64> {
+    match unit {
+        b"ms" => Some(1_000_000),
+        b"s" => Some(NANOS_PER_SEC),
+        b"m" => Some(60 * NANOS_PER_SEC),
+        b"h" => Some(60 * 60 * NANOS_PER_SEC),
+        _ => None,
+    }
+}
+
+fn to_duration(parts: Vec<(f64, u64)>) -> Duration {
+    let nanos = parts.into_iter().fold(0u64, |total, (value, unit)| {
+        total + (value * unit as f64).round() as u64
+    });
+
+    Duration::new(nanos / NANOS_PER_SEC, (nanos % NANOS_PER_SEC) as u32)
+}
+
+named!(number<f64>,
+       map_res!(map_res!(recognize!(pair!(digit, opt!(complete!(pair!(tag!("."), digit))))),
+                         str::from_utf8),
+                FromStr::from_str));
+
+named!(duration_part<(f64, &[u8])>,
+       do_parse!(value: number >> unit: alpha >> (value, unit)));
+
+/// Split a duration into its numeric parts and their (unchecked) units.
+named!(pub duration_parts<Vec<(f64, &[u8])> >,
+       many1!(complete!(duration_part)));
+
+/// Parse a human-readable duration, like `500ms`, `5s`, `1.5h` or `1m30s`.
+named!(pub parse_duration<Duration>,
+       map!(many1!(complete!(do_parse!(
+           value: number >>
+           unit: map_opt!(alpha, duration_unit) >>
+           (value, unit)))),
+            to_duration));
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(input: &str) -> Option<Duration> {
+        parse_duration(input.as_bytes()).to_full_result().ok()
+    }
+
+    #[test]
+    fn test_simple_durations() {
+        assert_eq!(Some(Duration::from_millis(500)), parse("500ms"));
+        assert_eq!(Some(Duration::new(5, 0)), parse("5s"));
+        assert_eq!(Some(Duration::new(120, 0)), parse("2m"));
+        assert_eq!(Some(Duration::new(3600, 0)), parse("1h"));
+    }
+
+    #[test]
+    fn test_fractional_durations() {
+        assert_eq!(Some(Duration::from_millis(1500)), parse("1.5s"));
+        assert_eq!(Some(Duration::new(30, 0)), parse("0.5m"));
+        assert_eq!(Some(Duration::new(0, 250_000)), parse("0.25ms"));
+    }
+
+    #[test]
+    fn test_compound_durations() {
+        assert_eq!(Some(Duration::new(90, 0)), parse("1m30s"));
+        assert_eq!(Some(Duration::new(5415, 0)), parse("1h30m15s"));
+        assert_eq!(Some(Duration::from_millis(1250)), parse("1s250ms"));
+    }
+
+    #[test]
+    fn test_invalid_durations() {
+        assert_eq!(None, parse(""));
+        assert_eq!(None, parse("5"));
+        assert_eq!(None, parse("5x"));
+        assert_eq!(None, parse("s"));
+        assert_eq!(None, parse("1m 30s"));
+    }
+
+    #[test]
+    fn test_duration_parts() {
+        let parts = duration_parts(b"1m5x").to_full_result().unwrap();
+        assert_eq!(vec![(1f64, &b"m"[..]), (5f64, &b"x"[..])], parts);
+    }
+}
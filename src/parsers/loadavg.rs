@@ -0,0 +1,80 @@
+use errors::*;
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq)]
+pub struct LoadAvg {
+    /// load average over the last minute
+    pub load1: f64,
+    /// load average over the last five minutes
+    pub load5: f64,
+    /// load average over the last fifteen minutes
+    pub load15: f64,
+    /// currently runnable scheduling entities
+    pub running: u64,
+    /// scheduling entities that currently exist
+    pub total: u64,
+}
+
+fn field<T: FromStr>(value: Option<&str>, name: &str) -> Result<T> {
+    value.and_then(|v| v.parse::<T>().ok()).ok_or_else(|| {
+        ErrorKind::Message(format!("malformed loadavg: bad {} field", name)).into()
+    })
+}
+
+/// Parse the contents of /proc/loadavg, like `0.20 0.18 0.12 1/80 11206`.
+pub fn parse_loadavg(input: &str) -> Result<LoadAvg> {
+    let mut parts = input.split_whitespace();
+
+    let load1 = field(parts.next(), "1m load")?;
+    let load5 = field(parts.next(), "5m load")?;
+    let load15 = field(parts.next(), "15m load")?;
+
+    let mut entities = parts.next().unwrap_or("").splitn(2, '/');
+    let running = field(entities.next(), "running")?;
+    let total = field(entities.next(), "total")?;
+
+    // the last pid is not reported, but has to be present for the line to be well-formed.
+    field::<u64>(parts.next(), "last pid")?;
+
+    if parts.next().is_some() {
+        return Err(ErrorKind::Message("malformed loadavg: trailing fields".to_owned()).into());
+    }
+
+    Ok(LoadAvg {
+        load1: load1,
+        load5: load5,
+        load15: load15,
+        running: running,
+        total: total,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_loadavg() {
+        let loadavg = parse_loadavg("0.20 0.18 0.12 1/80 11206\n").unwrap();
+
+        assert_eq!(
+            LoadAvg {
+                load1: 0.20,
+                load5: 0.18,
+                load15: 0.12,
+                running: 1,
+                total: 80,
+            },
+            loadavg
+        );
+    }
+
+    #[test]
+    fn test_parse_loadavg_malformed() {
+        assert!(parse_loadavg("").is_err());
+        assert!(parse_loadavg("0.20 0.18 0.12 1/80").is_err());
+        assert!(parse_loadavg("0.20 0.18 0.12 80 11206").is_err());
+        assert!(parse_loadavg("0.20 x 0.12 1/80 11206").is_err());
+        assert!(parse_loadavg("0.20 0.18 0.12 1/80 11206 1").is_err());
+    }
+}
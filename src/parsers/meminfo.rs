@@ -0,0 +1,50 @@
+use nom::space;
+use std::collections::HashMap;
+use std::str;
+
+use parsers::common::*;
+
+named!(meminfo_line<(&str, u64)>,
+       do_parse!(
+           key: map_res!(take_until_and_consume!(":"), str::from_utf8) >>
+           opt!(space) >>
+           value: type_u64 >>
+           kb: opt!(complete!(preceded!(space, tag!("kB")))) >>
+           (key, if kb.is_some() { value * 1024 } else { value })));
+
+/// Parse the contents of /proc/meminfo into a map of field to value.
+///
+/// Values in kB are converted into bytes. Lines which can't be parsed are ignored.
+pub fn parse_meminfo(input: &str) -> HashMap<String, u64> {
+    let mut fields = HashMap::new();
+
+    for line in input.lines() {
+        if let Ok((key, value)) = meminfo_line(line.trim_right().as_bytes()).to_full_result() {
+            fields.insert(key.to_owned(), value);
+        }
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_meminfo() {
+        let text = "MemTotal:       16314832 kB\n\
+                    MemFree:         1226176 kB\n\
+                    this is not a field\n\
+                    HugePages_Total:       0\n\
+                    MemAvailable:    9483764 kB\n";
+
+        let fields = parse_meminfo(text);
+
+        assert_eq!(Some(&(16314832 * 1024)), fields.get("MemTotal"));
+        assert_eq!(Some(&(1226176 * 1024)), fields.get("MemFree"));
+        assert_eq!(Some(&(9483764 * 1024)), fields.get("MemAvailable"));
+        assert_eq!(Some(&0), fields.get("HugePages_Total"));
+        assert_eq!(4, fields.len());
+    }
+}
@@ -1,3 +1,11 @@
 pub mod stat;
 pub mod mounts;
 pub mod common;
+pub mod duration;
+pub mod meminfo;
+pub mod loadavg;
+pub mod netdev;
+pub mod diskstats;
+pub mod pidstat;
+pub mod uptime;
+pub mod vmstat;
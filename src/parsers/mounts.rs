@@ -1,6 +1,4 @@
 use std::u8;
-use std::str::{self, FromStr};
-use nom::digit;
 
 fn decode_path(input: &str) -> Result<String, ()> {
     let mut it = input.chars();
@@ -34,15 +32,34 @@ fn decode_path(input: &str) -> Result<String, ()> {
     Ok(out)
 }
 
-named!(pub type_u64<u64>,
-       map_res!(map_res!(digit, str::from_utf8), FromStr::from_str));
-
 #[derive(Debug, Default, PartialEq)]
 pub struct Mount {
     /// Device
     pub device: String,
     /// Mountpoint
     pub mountpoint: String,
+    /// Filesystem type
+    pub fstype: String,
+}
+
+/// Parse a single line of /proc/mounts.
+pub fn parse_mount(line: &str) -> Option<Mount> {
+    let mut parts = line.split_whitespace();
+
+    let device = parts.next().and_then(|p| decode_path(p).ok())?;
+    let mountpoint = parts.next().and_then(|p| decode_path(p).ok())?;
+    let fstype = parts.next()?.to_owned();
+
+    Some(Mount {
+        device: device,
+        mountpoint: mountpoint,
+        fstype: fstype,
+    })
+}
+
+/// Parse the contents of /proc/mounts, skipping lines which can't be parsed.
+pub fn parse_mounts(input: &str) -> Vec<Mount> {
+    input.lines().filter_map(parse_mount).collect()
 }
 
 #[cfg(test)]
@@ -56,4 +73,29 @@ mod test {
         assert_eq!(decode_path("foo\\011bar"), Ok("foo\tbar".to_owned()));
         assert_eq!(decode_path("foo\\012bar"), Ok("foo\nbar".to_owned()));
     }
+
+    #[test]
+    fn test_parse_mounts() {
+        let mounts = parse_mounts(
+            "/dev/sda1 / ext4 rw,relatime 0 0\n\
+             /dev/sdb1 /mnt/my\\040disk xfs rw 0 0\n\
+             garbage\n",
+        );
+
+        assert_eq!(
+            vec![
+                Mount {
+                    device: "/dev/sda1".to_owned(),
+                    mountpoint: "/".to_owned(),
+                    fstype: "ext4".to_owned(),
+                },
+                Mount {
+                    device: "/dev/sdb1".to_owned(),
+                    mountpoint: "/mnt/my disk".to_owned(),
+                    fstype: "xfs".to_owned(),
+                },
+            ],
+            mounts
+        );
+    }
 }
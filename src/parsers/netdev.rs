@@ -0,0 +1,87 @@
+/// Counters of a single interface in /proc/net/dev.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct NetDev {
+    pub interface: String,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_drops: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_drops: u64,
+}
+
+fn parse_line(line: &str) -> Option<NetDev> {
+    let mut parts = line.splitn(2, ':');
+
+    let (interface, counters) = match (parts.next(), parts.next()) {
+        (Some(interface), Some(counters)) => (interface.trim(), counters),
+        _ => return None,
+    };
+
+    let values = counters.split_whitespace().map(str::parse).collect::<Result<Vec<u64>, _>>();
+
+    let values = match values {
+        Ok(values) => values,
+        Err(_) => return None,
+    };
+
+    // receive and transmit have eight counters each.
+    if interface.is_empty() || values.len() < 16 {
+        return None;
+    }
+
+    Some(NetDev {
+        interface: interface.to_owned(),
+        rx_bytes: values[0],
+        rx_packets: values[1],
+        rx_errors: values[2],
+        rx_drops: values[3],
+        tx_bytes: values[8],
+        tx_packets: values[9],
+        tx_errors: values[10],
+        tx_drops: values[11],
+    })
+}
+
+/// Parse the contents of /proc/net/dev into counters per interface.
+///
+/// The header, and lines which can't be parsed, are ignored.
+pub fn parse_netdev(input: &str) -> Vec<NetDev> {
+    input.lines().filter_map(parse_line).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_netdev() {
+        let text = "Inter-|   Receive                                                |  Transmit\n \
+                    face |bytes    packets errs drop fifo frame compressed multicast|bytes    \
+                    packets errs drop fifo colls carrier compressed\n    \
+                    lo: 6880 80 0 0 0 0 0 0 6880 80 0 0 0 0 0 0\n  \
+                    eth0:1234567 1000 1 2 0 0 0 5 7654321 900 3 4 0 0 0 0\n \
+                    bad: 1 2 3\n";
+
+        let devs = parse_netdev(text);
+
+        assert_eq!(2, devs.len());
+        assert_eq!("lo", devs[0].interface);
+        assert_eq!(
+            NetDev {
+                interface: "eth0".to_owned(),
+                rx_bytes: 1234567,
+                rx_packets: 1000,
+                rx_errors: 1,
+                rx_drops: 2,
+                tx_bytes: 7654321,
+                tx_packets: 900,
+                tx_errors: 3,
+                tx_drops: 4,
+            },
+            devs[1]
+        );
+    }
+}
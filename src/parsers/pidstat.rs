@@ -0,0 +1,65 @@
+use errors::*;
+
+/// Fields of /proc/<pid>/stat which are of interest.
+#[derive(Debug, PartialEq)]
+pub struct PidStat {
+    /// file name of the executable
+    pub comm: String,
+    /// time spent in user mode, in clock ticks
+    pub utime: u64,
+    /// time spent in kernel mode, in clock ticks
+    pub stime: u64,
+}
+
+/// Parse the contents of /proc/<pid>/stat.
+///
+/// The executable name is enclosed in parentheses and may itself contain spaces and parentheses,
+/// so the remaining fields are located from the last closing parenthesis.
+pub fn parse_pid_stat(input: &str) -> Result<PidStat> {
+    let malformed = || ErrorKind::Message("malformed stat".to_owned());
+
+    let start = input.find('(').ok_or_else(&malformed)?;
+    let end = input.rfind(')').ok_or_else(&malformed)?;
+
+    if end < start {
+        return Err(malformed().into());
+    }
+
+    let comm = input[start + 1..end].to_owned();
+
+    // fields following the name start with the third field, the state.
+    let fields: Vec<&str> = input[end + 1..].split_whitespace().collect();
+
+    let field = |index: usize| -> Result<u64> {
+        fields.get(index).and_then(|f| f.parse().ok()).ok_or_else(|| malformed().into())
+    };
+
+    Ok(PidStat {
+        comm: comm,
+        utime: field(11)?,
+        stime: field(12)?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_pid_stat() {
+        let text = "1234 (my (odd) app) S 1 1234 1234 0 -1 4194560 1321 0 0 0 150 42 0 0 20 0 1 \
+                    0 1535 12345678 456 18446744073709551615\n";
+
+        assert_eq!(
+            PidStat {
+                comm: "my (odd) app".to_owned(),
+                utime: 150,
+                stime: 42,
+            },
+            parse_pid_stat(text).unwrap()
+        );
+
+        assert!(parse_pid_stat("1234 (app) S 1").is_err());
+        assert!(parse_pid_stat("garbage").is_err());
+    }
+}
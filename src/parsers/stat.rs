@@ -2,8 +2,10 @@ use nom::{line_ending, space};
 
 use parsers::common::*;
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
 pub struct StatCpu {
+    /// cpu number, or `None` for the line aggregating all cpus
+    pub id: Option<u64>,
     /// normal processes executing in user mode
     pub user: u64,
     /// niced processes executing in user mode
@@ -44,7 +46,8 @@ impl StatCpu {
 
 named!(pub parse_stat_cpu<StatCpu>,
        chain!(
-           cpu: tag!("cpu") ~ space ~
+           cpu: tag!("cpu") ~
+           id: opt!(type_u64) ~ space ~
            user: type_u64 ~ space ~
            nice: type_u64 ~ space ~
            system: type_u64 ~ space ~
@@ -57,6 +60,7 @@ named!(pub parse_stat_cpu<StatCpu>,
            guest_nice: type_u64 ~ line_ending,
            || {
                StatCpu {
+                   id: id,
                    user: user,
                    nice: nice,
                    system: system,
@@ -77,5 +81,16 @@ mod test {
         let cpu_text = b"cpu  347703 107 67084 8538266 10258 0 8753 0 0 0\n";
         let cpu = parse_stat_cpu(cpu_text).to_full_result().unwrap();
         assert_eq!(347703, cpu.user);
+        assert_eq!(None, cpu.id);
+    }
+
+    #[test]
+    fn test_parse_stat_cpu_with_id() {
+        let cpu_text = b"cpu12 1000 20 300 4000 50 0 6 0 0 0\n";
+        let cpu = parse_stat_cpu(cpu_text).to_full_result().unwrap();
+        assert_eq!(Some(12), cpu.id);
+        assert_eq!(1000, cpu.user);
+        assert_eq!(4000, cpu.idle);
+        assert_eq!(50, cpu.iowait);
     }
 }
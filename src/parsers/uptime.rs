@@ -0,0 +1,53 @@
+use errors::*;
+
+#[derive(Debug, PartialEq)]
+pub struct Uptime {
+    /// seconds since boot
+    pub uptime: f64,
+    /// seconds spent idle, summed over all cpus
+    pub idle: f64,
+}
+
+fn field(value: Option<&str>, name: &str) -> Result<f64> {
+    value.and_then(|v| v.parse::<f64>().ok()).ok_or_else(|| {
+        ErrorKind::Message(format!("malformed uptime: bad {} field", name)).into()
+    })
+}
+
+/// Parse the contents of /proc/uptime, like `350735.47 234388.90`.
+pub fn parse_uptime(input: &str) -> Result<Uptime> {
+    let mut parts = input.split_whitespace();
+
+    let uptime = field(parts.next(), "uptime")?;
+    let idle = field(parts.next(), "idle")?;
+
+    if parts.next().is_some() {
+        return Err(ErrorKind::Message("malformed uptime: trailing fields".to_owned()).into());
+    }
+
+    Ok(Uptime {
+        uptime: uptime,
+        idle: idle,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_uptime() {
+        assert_eq!(
+            Uptime {
+                uptime: 350735.47,
+                idle: 234388.90,
+            },
+            parse_uptime("350735.47 234388.90\n").unwrap()
+        );
+
+        assert!(parse_uptime("").is_err());
+        assert!(parse_uptime("350735.47").is_err());
+        assert!(parse_uptime("350735.47 x").is_err());
+        assert!(parse_uptime("350735.47 234388.90 1").is_err());
+    }
+}
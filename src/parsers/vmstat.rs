@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+
+/// Parse the contents of /proc/vmstat into a map of counter to value.
+///
+/// Lines which can't be parsed are ignored.
+pub fn parse_vmstat(input: &str) -> HashMap<String, u64> {
+    let mut counters = HashMap::new();
+
+    for line in input.lines() {
+        let mut parts = line.split_whitespace();
+
+        if let (Some(key), Some(value), None) = (parts.next(), parts.next(), parts.next()) {
+            if let Ok(value) = value.parse::<u64>() {
+                counters.insert(key.to_owned(), value);
+            }
+        }
+    }
+
+    counters
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_vmstat() {
+        let text = "nr_free_pages 2475592\n\
+                    pswpin 1204\n\
+                    this is not a counter\n\
+                    pswpout x\n";
+
+        let counters = parse_vmstat(text);
+
+        assert_eq!(Some(&2475592), counters.get("nr_free_pages"));
+        assert_eq!(Some(&1204), counters.get("pswpin"));
+        assert_eq!(2, counters.len());
+    }
+}
@@ -2,19 +2,38 @@ use errors::*;
 use futures::*;
 use futures_cpupool::CpuPool;
 use metric::MetricId;
-use std::collections::HashMap;
+use config;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
+use std::path::{Path, PathBuf};
+use dispatcher::Delivery;
+use spool::{self, Spool};
+use filter::SampleFilter;
+use health::Health;
+use telemetry::Telemetry;
 use std::sync::Arc;
-use std::cell::RefCell;
-use std::rc::Rc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use toml;
 use serde;
-use tokio_core;
+use serde_json;
+use tokio_core::reactor::Handle;
+use tokio_timer::Timer;
 
 pub type InputEntry = fn() -> Result<Box<Input>>;
 pub type OutputEntry = fn() -> Result<Box<Output>>;
 
+/// Machine-readable description of a registered plugin.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PluginDescription {
+    pub kind: &'static str,
+    #[serde(rename = "type")]
+    pub plugin_type: String,
+}
+
 pub struct PluginRegistry {
     input: HashMap<String, InputEntry>,
     output: HashMap<String, OutputEntry>,
@@ -46,13 +65,169 @@ impl PluginRegistry {
     pub fn output_types<'a>(&'a self) -> impl Iterator<Item = &'a String> + 'a {
         self.output.keys()
     }
+
+    /// Describe all registered plugins, sorted by kind and type.
+    pub fn describe(&self) -> Vec<PluginDescription> {
+        let inputs = self.input_types().map(|t| ("input", t));
+        let outputs = self.output_types().map(|t| ("output", t));
+
+        let mut descriptions: Vec<PluginDescription> = inputs
+            .chain(outputs)
+            .map(|(kind, plugin_type)| {
+                PluginDescription {
+                    kind: kind,
+                    plugin_type: plugin_type.clone(),
+                }
+            })
+            .collect();
+
+        descriptions.sort();
+        descriptions
+    }
+}
+
+/// Clock used to measure the time between two samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Clock {
+    /// Wall-clock time, affected by clock adjustments.
+    Wall,
+    /// Monotonic time, which never goes backwards.
+    Monotonic,
+}
+
+/// Where polls and updates of input instances run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reactor {
+    /// On the thread pool.
+    Pool,
+    /// On the thread of the reactor, except for instances which block.
+    Single,
+}
+
+impl Reactor {
+    pub fn parse(value: &str) -> Option<Reactor> {
+        match value {
+            "pool" => Some(Reactor::Pool),
+            "single" => Some(Reactor::Single),
+            _ => None,
+        }
+    }
+}
+
+/// Unit outputs render the timestamps of samples in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Precision {
+    Seconds,
+    Milliseconds,
+    Microseconds,
+    Nanoseconds,
+}
+
+impl Precision {
+    pub fn parse(value: &str) -> Option<Precision> {
+        match value {
+            "s" => Some(Precision::Seconds),
+            "ms" => Some(Precision::Milliseconds),
+            "us" => Some(Precision::Microseconds),
+            "ns" => Some(Precision::Nanoseconds),
+            _ => None,
+        }
+    }
+
+    /// Decode the `timestamp_precision` option of an output, using `default` if it is not set.
+    pub fn decode(value: Option<&String>, default: Precision) -> Result<Precision> {
+        let value = match value {
+            Some(value) => value,
+            None => return Ok(default),
+        };
+
+        let precision = Precision::parse(value).ok_or_else(|| {
+            ErrorKind::InvalidPluginConfig(
+                "timestamp_precision".to_owned(),
+                format!("expected s, ms, us, or ns but got {:?}", value),
+            )
+        })?;
+
+        Ok(precision)
+    }
+}
+
+/// What the value of a sample represents, so that outputs can report it correctly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleKind {
+    /// An instantaneous value, like the current memory usage.
+    Gauge,
+    /// A monotonically increasing total, like the number of bytes received since boot.
+    Counter,
+    /// A monotonically increasing total which is reported as its rate per second.
+    ///
+    /// Rates are computed by the poller from consecutive polls, so outputs never see this kind.
+    Derive,
+    /// A single observation, like the duration of a request, counted into a histogram.
+    ///
+    /// Histograms are computed by the poller, so outputs never see this kind either.
+    Observation,
+    /// A cumulative series of a histogram, like the count of one of its buckets.
+    ///
+    /// Outputs without a notion of histograms report these like counters.
+    Histogram,
+}
+
+impl SampleKind {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            SampleKind::Gauge => "gauge",
+            SampleKind::Counter => "counter",
+            SampleKind::Derive => "derive",
+            SampleKind::Observation => "observation",
+            SampleKind::Histogram => "histogram",
+        }
+    }
+}
+
+/// Describes what a metric measures, for outputs which document the metrics they expose.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct SampleMeta {
+    /// What the metric measures, like `Time since boot`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Unit of the value, like `seconds` or `bytes`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+}
+
+impl SampleMeta {
+    pub fn new() -> SampleMeta {
+        SampleMeta::default()
+    }
+
+    pub fn description(mut self, description: &str) -> SampleMeta {
+        self.description = Some(description.to_owned());
+        self
+    }
+
+    pub fn unit(mut self, unit: &str) -> SampleMeta {
+        self.unit = Some(unit.to_owned());
+        self
+    }
 }
 
 /// A single data sample.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 pub struct Sample {
     pub metric_id: Arc<MetricId>,
     pub value: f64,
+    #[serde(serialize_with = "serialize_kind")]
+    pub kind: SampleKind,
+    /// Wall-clock time at which the sample was taken.
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: SystemTime,
+    /// Monotonic time at which the sample was taken.
+    #[serde(skip_serializing)]
+    pub instant: Instant,
+    /// Metadata of the metric, shared by all of its samples, if the plugin provides any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Arc<SampleMeta>>,
 }
 
 impl Sample {
@@ -60,12 +235,92 @@ impl Sample {
         Sample {
             metric_id: metric_id,
             value: value,
+            kind: SampleKind::Gauge,
+            timestamp: SystemTime::now(),
+            instant: Instant::now(),
+            meta: None,
+        }
+    }
+
+    /// Change the kind of the sample, samples are gauges by default.
+    pub fn with_kind(mut self, kind: SampleKind) -> Sample {
+        self.kind = kind;
+        self
+    }
+
+    /// Attach metadata describing the metric.
+    pub fn with_meta(mut self, meta: Arc<SampleMeta>) -> Sample {
+        self.meta = Some(meta);
+        self
+    }
+
+    /// Timestamp as nanoseconds since the unix epoch.
+    pub fn timestamp_nanos(&self) -> u64 {
+        let since = self.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+        since.as_secs() * 1_000_000_000 + since.subsec_nanos() as u64
+    }
+
+    /// Timestamp since the unix epoch, truncated to the given precision.
+    pub fn timestamp_with(&self, precision: Precision) -> u64 {
+        let nanos = self.timestamp_nanos();
+
+        match precision {
+            Precision::Seconds => nanos / 1_000_000_000,
+            Precision::Milliseconds => nanos / 1_000_000,
+            Precision::Microseconds => nanos / 1_000,
+            Precision::Nanoseconds => nanos,
+        }
+    }
+
+    /// Time elapsed between an earlier sample and this one, as measured by the given clock.
+    ///
+    /// Returns `None` if the wall clock went backwards between the samples.
+    pub fn elapsed_since(&self, earlier: &Sample, clock: Clock) -> Option<Duration> {
+        match clock {
+            Clock::Wall => self.timestamp.duration_since(earlier.timestamp).ok(),
+            Clock::Monotonic if self.instant >= earlier.instant => {
+                Some(self.instant.duration_since(earlier.instant))
+            }
+            Clock::Monotonic => None,
         }
     }
 }
 
+fn serialize_kind<S>(kind: &SampleKind, serializer: &mut S) -> ::std::result::Result<(), S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(kind.as_str())
+}
+
+/// Serialize a timestamp as milliseconds since the unix epoch.
+fn serialize_timestamp<S>(
+    timestamp: &SystemTime,
+    serializer: &mut S,
+) -> ::std::result::Result<(), S::Error>
+where
+    S: serde::Serializer,
+{
+    let since = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let millis = since.as_secs() * 1000 + since.subsec_nanos() as u64 / 1_000_000;
+    serializer.serialize_u64(millis)
+}
+
 pub type Samples = Vec<Sample>;
 
+/// Carry-over state of an input instance, like the counters read by its last poll.
+///
+/// When the section of an instance is changed through a reload, the state of the running instance
+/// is saved and restored into the instance replacing it, as long as both have the same id and
+/// plugin type. This keeps rates continuous through reloads.
+pub trait PluginState {
+    /// Serialize the state, `None` if there is nothing to hand over.
+    fn save(&self) -> Result<Option<serde_json::Value>>;
+
+    /// Restore state saved by another instance of the same plugin.
+    fn restore(&self, state: serde_json::Value) -> Result<()>;
+}
+
 pub trait InputInstance: fmt::Debug + Send + Sync {
     /// Poll the state of the plugin instance.
     ///
@@ -74,6 +329,11 @@ pub trait InputInstance: fmt::Debug + Send + Sync {
         Ok(Vec::new())
     }
 
+    /// State to hand over to the instance replacing this one on reload, if any.
+    fn state(&self) -> Option<&PluginState> {
+        None
+    }
+
     /// Update the state of the plugin instance.
     ///
     /// Returns a future since the operation could be potentially long-running.
@@ -88,34 +348,455 @@ pub trait InputInstance: fmt::Debug + Send + Sync {
     fn next_update(&self) -> Duration {
         Duration::from_millis(0)
     }
+
+    /// If polls and updates might block, like when running commands or talking to the network.
+    ///
+    /// With `Reactor::Single`, instances which don't block are polled and updated on the reactor,
+    /// where a slow poll holds up everything else. Instances which block are always run on the
+    /// thread pool.
+    fn blocking(&self) -> bool {
+        true
+    }
+
+    /// Release what the instance holds, like open files and sockets.
+    ///
+    /// Called once the instance is removed through a reload, or when sysmon exits, after polls and
+    /// updates in progress have completed. Teardown is best-effort: failures are logged, and a
+    /// teardown which takes longer than `TEARDOWN_TIMEOUT_SECS` is abandoned.
+    fn teardown(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Framework options which can be specified in any plugin section.
+#[derive(Debug, Clone, Default)]
+pub struct InstanceOptions {
+    /// Name used to identify the instance in logs and sample tags, defaults to the section id.
+    pub name: String,
+    /// Interval at which the instance is polled, falls back to the global poll interval.
+    pub interval: Option<Duration>,
+    /// Interval at which the instance is updated, falls back to the global update interval.
+    pub update_interval: Option<Duration>,
+    /// How long a single poll may take, falls back to the global poll timeout.
+    pub poll_timeout: Option<Duration>,
+    /// Number of samples to buffer before delivering them to an output.
+    pub batch_size: Option<usize>,
+    /// Maximum time samples are buffered before being delivered to an output.
+    pub flush_interval: Option<Duration>,
+    /// Delay before retrying delivery to an output after the first failure.
+    pub initial_backoff: Option<Duration>,
+    /// Upper bound for the delay between delivery attempts to an output.
+    pub max_backoff: Option<Duration>,
+    /// Number of samples retained for an output while backing off.
+    pub backoff_buffer_size: Option<usize>,
+    /// Directory samples which don't fit in the backoff buffer of an output are spooled to.
+    pub spool_dir: Option<PathBuf>,
+    /// Largest size of the spool file of an output.
+    pub max_spool_bytes: Option<u64>,
+    /// Poll the instance on wall-clock boundaries of its interval.
+    pub align: bool,
+    /// Report sysmon as unhealthy when the instance keeps failing.
+    pub critical: bool,
+    /// Largest number of samples kept from a single poll, the rest are dropped.
+    pub max_samples_per_poll: Option<usize>,
+    /// Samples an output receives, every sample is received if not set.
+    pub filter: Option<SampleFilter>,
+    /// Names of the outputs which receive the samples of an input, every output does if not set.
+    pub route: Option<Vec<String>>,
+    /// Suppress gauges delivered to an output whose value didn't change.
+    pub dedup: bool,
+    /// Deliver an unchanged gauge anyway every this many times it is seen.
+    pub dedup_heartbeat: Option<usize>,
+    /// Number of times a failing setup is retried.
+    pub setup_retries: Option<usize>,
+    /// Delay before the first setup retry, doubled for every retry after it.
+    pub setup_retry_delay: Option<Duration>,
+}
+
+impl InstanceOptions {
+    /// If samples should be buffered before they are delivered to an output.
+    pub fn is_batched(&self) -> bool {
+        self.batch_size.is_some() || self.flush_interval.is_some()
+    }
+}
+
+/// An input instance, together with the options it was configured with.
+pub struct ConfiguredInput {
+    pub id: String,
+    pub options: InstanceOptions,
+    /// Tags added to every sample from this instance.
+    pub tags: Vec<(String, String)>,
+    pub instance: Box<InputInstance>,
+}
+
+/// An output instance, together with the options it was configured with.
+pub struct ConfiguredOutput {
+    pub id: String,
+    pub options: InstanceOptions,
+    pub instance: Box<OutputInstance>,
+    delivery: Delivery,
+    /// Set once the instance has been torn down.
+    torn_down: AtomicBool,
+}
+
+impl ConfiguredOutput {
+    pub fn new(
+        id: String,
+        options: InstanceOptions,
+        instance: Box<OutputInstance>,
+        telemetry: Arc<Telemetry>,
+    ) -> ConfiguredOutput {
+        ConfiguredOutput {
+            id: id,
+            options: options,
+            instance: instance,
+            delivery: Delivery::new(telemetry),
+            torn_down: AtomicBool::new(false),
+        }
+    }
+
+    /// Spool samples which don't fit in the backoff buffer, instead of dropping them.
+    pub fn with_spool(mut self, spool: Spool) -> ConfiguredOutput {
+        self.delivery = self.delivery.with_spool(spool);
+        self
+    }
+
+    /// Path of the spool of the output, if it is configured to spool.
+    pub fn spool_path(&self) -> Option<PathBuf> {
+        self.options.spool_dir.as_ref().map(|dir| spool::spool_path(dir, &self.id))
+    }
+
+    /// If samples which don't fit in the backoff buffer are spooled.
+    pub fn has_spool(&self) -> bool {
+        self.delivery.has_spool()
+    }
+
+    /// Move the spool of this output to its replacement, which uses the same spool file.
+    ///
+    /// Samples which don't fit in the backoff buffer of this output are dropped afterwards.
+    pub fn hand_over_spool(&self, to: &ConfiguredOutput) {
+        if self.spool_path().is_none() || self.spool_path() != to.spool_path() {
+            return;
+        }
+
+        if let Some(mut spool) = self.delivery.take_spool() {
+            spool.set_max_bytes(to.options.max_spool_bytes);
+
+            if let Some(spool) = to.delivery.set_spool(spool) {
+                // the replacement opened a spool of its own after all.
+                let _ = self.delivery.set_spool(spool);
+                return;
+            }
+
+            debug!("{}: handed over spool to its replacement", self.options.name);
+        }
+    }
+
+    /// Feed a sample to the output.
+    ///
+    /// If batching is enabled, the sample is buffered until the batch is full or delivered.
+    /// While the output is backing off after a failure, samples are retained instead.
+    pub fn feed(&self, sample: &Sample) -> Result<()> {
+        self.delivery.feed(self, sample)
+    }
+
+    /// Deliver all buffered samples to the output, unless it is backing off.
+    pub fn deliver(&self) -> Result<()> {
+        self.delivery.deliver(self, false)
+    }
+
+    /// Deliver all buffered and retained samples, then flush the output.
+    ///
+    /// Used when the output is shut down, so a pending backoff is ignored.
+    pub fn flush(&self) -> Result<()> {
+        self.delivery.deliver(self, true)?;
+        self.instance.flush()
+    }
+
+    /// Tear down the instance, unless that has happened already.
+    pub fn teardown(&self) -> Result<()> {
+        if self.torn_down.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        self.instance.teardown()
+    }
 }
 
 pub trait OutputInstance: Send + Sync {
     fn feed(&self, sample: &Sample) -> Result<()>;
+
+    /// Feed a batch of samples.
+    ///
+    /// Outputs which can write several samples at once should override this.
+    fn feed_batch(&self, samples: &[Sample]) -> Result<()> {
+        for sample in samples {
+            self.feed(sample)?;
+        }
+
+        Ok(())
+    }
+
+    /// Flush any buffered state.
+    ///
+    /// Called once during shutdown, after polling has stopped.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Release anything held outside of the process, like remotely stored state.
+    ///
+    /// Called once when sysmon exits, after the final flush. Outputs removed through a reload are
+    /// only flushed and torn down.
+    fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Release what the instance holds, like connections and listening sockets.
+    ///
+    /// Called once the instance has been flushed for the last time, either because it was removed
+    /// through a reload or because sysmon exits. Like for inputs, teardown is best-effort and
+    /// abandoned after `TEARDOWN_TIMEOUT_SECS`.
+    fn teardown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// If a replacement of the instance can't be set up while it is running, like when it listens
+    /// on a port.
+    ///
+    /// Such instances are flushed and torn down by a reload before the new instances are set up,
+    /// unless their section is unchanged.
+    fn exclusive(&self) -> bool {
+        false
+    }
+}
+
+/// How long the teardown of a single instance may take, before it is abandoned.
+pub const TEARDOWN_TIMEOUT_SECS: u64 = 5;
+
+/// Run the teardown of the named instance on a separate thread, waiting for it at most
+/// `TEARDOWN_TIMEOUT_SECS`.
+///
+/// Failures are logged, since there is nothing left to do about them.
+pub fn teardown<F>(name: &str, f: F)
+where
+    F: FnOnce() -> Result<()> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let _ = tx.send(f());
+    });
+
+    match rx.recv_timeout(Duration::new(TEARDOWN_TIMEOUT_SECS, 0)) {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!("{}: teardown failed: {}", name, e),
+        Err(RecvTimeoutError::Timeout) => {
+            warn!("{}: teardown took longer than {}s, abandoning it", name, TEARDOWN_TIMEOUT_SECS);
+        }
+        // the sender is dropped without sending if the teardown panics.
+        Err(RecvTimeoutError::Disconnected) => error!("{}: teardown panicked", name),
+    }
 }
 
 /// Context used for when setting up a plugin.
 pub struct PluginContext<'a> {
     pub id: &'a String,
+    pub name: &'a String,
     pub config: &'a toml::Table,
+    /// Directory of the configuration file the section was read from.
+    pub base_dir: &'a Path,
     pub cpupool: Arc<CpuPool>,
-    pub core: Rc<RefCell<tokio_core::reactor::Core>>,
+    pub handle: Handle,
+    /// Clock to use when computing rates between samples.
+    pub clock: Clock,
+    /// Where polls and updates run, `cpupool` is available regardless.
+    pub reactor: Reactor,
+    /// Health of the running input instances.
+    pub health: Arc<Health>,
+    /// Timer for timeouts and delays of work the plugin runs itself.
+    pub timer: Arc<Timer>,
+    /// Internal metrics, needed to run tasks of the plugin through `scheduler::schedule`.
+    ///
+    /// A plugin which schedules tasks must cancel them when the instance is dropped, since the
+    /// handles returned by `schedule` leave the task running otherwise. Instances are dropped when
+    /// they are removed or changed through a reload, and tasks which outlive them leak.
+    pub telemetry: Arc<Telemetry>,
+    /// Keys of the section which have been read by the plugin.
+    pub keys: &'a RefCell<ConfigKeys>,
 }
 
 impl<'a> PluginContext<'a> {
+    /// Decode the section into the configuration of the plugin.
+    ///
+    /// Fields which are missing or of the wrong type are reported as invalid plugin configuration,
+    /// so that they fail the setup of the instance instead of its first poll.
+    ///
+    /// Keys which are not part of the configuration are left unread, so that they can be reported
+    /// as unknown once the plugin has been set up.
     pub fn decode_config<T>(&self) -> Result<T>
     where
         T: serde::Deserialize,
     {
         let mut decoder = toml::Decoder::new(toml::Value::Table(self.config.clone()));
-        serde::Deserialize::deserialize(&mut decoder).map_err(Into::into)
+
+        let value: T = serde::Deserialize::deserialize(&mut decoder).map_err(
+            |e: toml::DecodeError| {
+                let field = e.field.clone().unwrap_or_else(|| "<section>".to_owned());
+                ErrorKind::InvalidPluginConfig(field, e.to_string())
+            },
+        )?;
+
+        // the decoder keeps everything it didn't consume.
+        let leftover = match decoder.toml {
+            Some(toml::Value::Table(leftover)) => leftover,
+            _ => toml::Table::new(),
+        };
+
+        self.keys.borrow_mut().record(self.config, &leftover);
+        Ok(value)
+    }
+
+    /// Resolve a path read from the section against the directory of its configuration file.
+    ///
+    /// Absolute paths are returned as they are.
+    pub fn resolve_path(&self, path: &str) -> String {
+        self.base_dir.join(path).to_string_lossy().into_owned()
+    }
+
+    /// Read an optional duration from the section.
+    pub fn decode_duration(&self, key: &str) -> Result<Option<Duration>> {
+        self.keys.borrow_mut().used.insert(key.to_owned());
+        config::decode_duration(self.config, key)
+    }
+}
+
+/// Keeps track of which keys of a section have been read.
+#[derive(Debug, Default)]
+pub struct ConfigKeys {
+    /// Top-level keys which have been read.
+    used: BTreeSet<String>,
+    /// Nested keys which were left unread, like `targets[0].adress`.
+    unknown: BTreeSet<String>,
+}
+
+impl ConfigKeys {
+    /// Mark a key as read, for keys which are not read through the context.
+    pub fn use_key(&mut self, key: &str) {
+        self.used.insert(key.to_owned());
+    }
+
+    /// Record what was read from a table, given what was left over after decoding it.
+    fn record(&mut self, table: &toml::Table, leftover: &toml::Table) {
+        for (key, value) in table {
+            match leftover.get(key) {
+                None => {
+                    self.used.insert(key.clone());
+                }
+                // partially read, so the key itself is known.
+                Some(rest) if rest != value => {
+                    self.used.insert(key.clone());
+                    self.unknown.extend(unread(key, value, rest));
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// All keys of the table which have not been read, sorted.
+    pub fn unknown(&self, table: &toml::Table) -> Vec<String> {
+        let mut unknown: BTreeSet<String> = table
+            .keys()
+            .filter(|key| !self.used.contains(*key))
+            .cloned()
+            .collect();
+
+        unknown.extend(self.unknown.iter().cloned());
+        unknown.into_iter().collect()
+    }
+}
+
+/// Paths of the values which were left unread in a partially read value.
+fn unread(path: &str, value: &toml::Value, rest: &toml::Value) -> Vec<String> {
+    match (value, rest) {
+        (&toml::Value::Table(ref table), &toml::Value::Table(ref rest)) => {
+            rest.iter()
+                .flat_map(|(key, rest)| {
+                    let path = format!("{}.{}", path, key);
+
+                    match table.get(key) {
+                        Some(value) if value != rest => unread(&path, value, rest),
+                        _ => vec![path],
+                    }
+                })
+                .collect()
+        }
+        (&toml::Value::Array(ref values), &toml::Value::Array(ref rest)) => {
+            values
+                .iter()
+                .zip(rest.iter())
+                .enumerate()
+                .filter(|&(_, (value, rest))| value != rest)
+                .flat_map(|(index, (value, rest))| {
+                    unread(&format!("{}[{}]", path, index), value, rest)
+                })
+                .collect()
+        }
+        _ => vec![path.to_owned()],
     }
 }
 
 pub trait Input: fmt::Debug {
     fn setup(&self, ctx: PluginContext) -> Result<Box<InputInstance>>;
+
+    /// Example of the plugin-specific options, as TOML with the default value of every key.
+    fn example_config(&self) -> Option<&'static str> {
+        None
+    }
 }
 
 pub trait Output: fmt::Debug {
     fn setup(&self, ctx: PluginContext) -> Result<Box<OutputInstance>>;
+
+    /// Example of the plugin-specific options, as TOML with the default value of every key.
+    fn example_config(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn table(content: &str) -> toml::Table {
+        toml::Parser::new(content).parse().unwrap()
+    }
+
+    #[test]
+    fn test_timestamp_with() {
+        let mut sample = Sample::new(Arc::new(MetricId::new_with_key("test").build()), 1f64);
+        sample.timestamp = UNIX_EPOCH + Duration::new(1500000000, 123456789);
+
+        assert_eq!(1500000000, sample.timestamp_with(Precision::Seconds));
+        assert_eq!(1500000000123, sample.timestamp_with(Precision::Milliseconds));
+        assert_eq!(1500000000123456, sample.timestamp_with(Precision::Microseconds));
+        assert_eq!(1500000000123456789, sample.timestamp_with(Precision::Nanoseconds));
+
+        assert_eq!(Some(Precision::Milliseconds), Precision::parse("ms"));
+        assert_eq!(None, Precision::parse("seconds"));
+    }
+
+    #[test]
+    fn test_config_keys() {
+        let config = table("a = 1\nb = 2\nc = { d = 3, e = 4 }");
+        let leftover = table("b = 2\nc = { e = 4 }");
+
+        let mut keys = ConfigKeys::default();
+        keys.record(&config, &leftover);
+
+        assert_eq!(vec!["b".to_owned(), "c.e".to_owned()], keys.unknown(&config));
+
+        keys.use_key("b");
+        assert_eq!(vec!["c.e".to_owned()], keys.unknown(&config));
+    }
 }
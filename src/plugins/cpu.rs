@@ -3,13 +3,16 @@ use plugin::*;
 use errors::*;
 use parsers::stat::*;
 
-use futures::*;
+use libc;
+use std::collections::HashMap;
 use std::fmt;
 use std::fs::File;
 use std::io::{BufReader, BufRead};
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::time::Duration;
+use serde_json;
+
+const PROC_STAT: &'static str = "/proc/stat";
 
 #[derive(Debug)]
 struct Cpu {}
@@ -26,45 +29,142 @@ impl Input for Cpu {
     }
 }
 
-struct Metrics {
-    previous: Option<StatCpu>,
-    used: (Arc<MetricId>, Gauge),
-    free: (Arc<MetricId>, Gauge),
+/// Read all cpu lines from /proc/stat.
+fn read_stat() -> Result<Vec<StatCpu>> {
+    let file = File::open(PROC_STAT).chain_err(
+        || format!("could not open {}", PROC_STAT),
+    )?;
+
+    let mut reader = BufReader::new(file);
+    let mut buffer = String::new();
+    let mut cpus = Vec::new();
+
+    loop {
+        buffer.clear();
+
+        let n = reader.read_line(&mut buffer).chain_err(
+            || format!("could not read {}", PROC_STAT),
+        )?;
+
+        if n == 0 {
+            break;
+        }
+
+        if buffer.starts_with("cpu") {
+            cpus.push(parse_stat_cpu(buffer.as_bytes()).to_full_result()?);
+        }
+    }
+
+    Ok(cpus)
 }
 
-impl Metrics {
-    pub fn update(&mut self) -> Result<()> {
-        let file = File::open("/proc/stat")?;
-        let mut reader = BufReader::new(file);
-        let mut buffer = String::new();
+/// Compute the utilization (in percent) of a single cpu since the previous reading.
+fn usage(next: &StatCpu, prev: &StatCpu) -> Option<Vec<(&'static str, f64)>> {
+    let total = next.total().saturating_sub(prev.total());
 
-        reader.read_line(&mut buffer)?;
+    if total == 0 {
+        return None;
+    }
 
-        let next = parse_stat_cpu(buffer.as_bytes()).to_full_result()?;
+    let percent = |n: u64, p: u64| 100f64 * (n.saturating_sub(p) as f64) / total as f64;
 
-        self.previous = match self.previous {
-            None => Some(next),
-            Some(ref prev) => {
-                let total_diff = next.total() - prev.total();
+    Some(vec![
+        ("user", percent(next.user, prev.user)),
+        ("system", percent(next.system, prev.system)),
+        ("idle", percent(next.idle, prev.idle)),
+        ("iowait", percent(next.iowait, prev.iowait)),
+        ("used", percent(next.used(), prev.used())),
+        ("free", percent(next.free(), prev.free())),
+    ])
+}
 
-                if total_diff > 0 {
-                    let differ = |n, p| ((n - p) as f64) / total_diff as f64;
+/// Time spent in every state since boot, in clock ticks.
+fn times(cpu: &StatCpu) -> Vec<(&'static str, u64)> {
+    vec![
+        ("user", cpu.user),
+        ("nice", cpu.nice),
+        ("system", cpu.system),
+        ("idle", cpu.idle),
+        ("iowait", cpu.iowait),
+        ("irq", cpu.irq),
+        ("softirq", cpu.softirq),
+        ("steal", cpu.steal),
+    ]
+}
 
-                    self.used.1.set(differ(next.used(), prev.used()));
-                    self.free.1.set(differ(next.free(), prev.free()));
-                }
+/// Number of clock ticks per second, which `/proc/stat` counts in.
+fn ticks_per_second() -> f64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as f64,
+        _ => 100f64,
+    }
+}
 
-                Some(next)
-            }
-        };
+/// Cached metric ids, keyed by cpu and state.
+struct MetricIds {
+    system: MetricIdBuilder,
+    ids: HashMap<(Option<u64>, &'static str), Arc<MetricId>>,
+    times: HashMap<(Option<u64>, &'static str), Arc<MetricId>>,
+}
 
-        Ok(())
+impl MetricIds {
+    fn get(&mut self, cpu: Option<u64>, state: &'static str) -> Arc<MetricId> {
+        let system = &self.system;
+
+        self.ids
+            .entry((cpu, state))
+            .or_insert_with(|| {
+                let builder = match (cpu, state) {
+                    // aggregate used and free cpu, as reported historically.
+                    (None, "used") => system.clone().tag("what", "cpu-used"),
+                    (None, "free") => system.clone().tag("what", "cpu-free"),
+                    (cpu, state) => {
+                        let cpu = cpu.map(|c| c.to_string()).unwrap_or("total".to_owned());
+
+                        system
+                            .clone()
+                            .tag("what", "cpu-usage")
+                            .tag("cpu", &cpu)
+                            .tag("state", state)
+                    }
+                };
+
+                Arc::new(builder.tag("unit", "%").build())
+            })
+            .clone()
+    }
+
+    fn time(&mut self, cpu: Option<u64>, state: &'static str) -> Arc<MetricId> {
+        let system = &self.system;
+
+        self.times
+            .entry((cpu, state))
+            .or_insert_with(|| {
+                let cpu = cpu.map(|c| c.to_string()).unwrap_or("total".to_owned());
+
+                Arc::new(
+                    system
+                        .clone()
+                        .tag("what", "cpu-time")
+                        .tag("cpu", &cpu)
+                        .tag("state", state)
+                        .tag("unit", "s")
+                        .build(),
+                )
+            })
+            .clone()
     }
 }
 
+struct CpuState {
+    /// Counters from the previous poll, keyed by cpu.
+    previous: HashMap<Option<u64>, StatCpu>,
+    metric_ids: MetricIds,
+}
+
 struct CpuInputInstance {
-    metrics: Arc<Mutex<Metrics>>,
-    next_update: Duration,
+    state: Mutex<CpuState>,
+    ticks_per_second: f64,
 }
 
 impl fmt::Debug for CpuInputInstance {
@@ -75,63 +175,73 @@ impl fmt::Debug for CpuInputInstance {
 
 impl CpuInputInstance {
     pub fn new() -> CpuInputInstance {
-        let system = key("system");
-
         CpuInputInstance {
-            next_update: Duration::from_millis(1000),
-            metrics: Arc::new(Mutex::new(Metrics {
-                previous: None,
-                used: (
-                    Arc::new(
-                        system
-                            .clone()
-                            .tag("what", "cpu-used")
-                            .tag("unit", "%")
-                            .build(),
-                    ),
-                    Gauge::new(),
-                ),
-                free: (
-                    Arc::new(
-                        system
-                            .clone()
-                            .tag("what", "cpu-free")
-                            .tag("unit", "%")
-                            .build(),
-                    ),
-                    Gauge::new(),
-                ),
-            })),
+            state: Mutex::new(CpuState {
+                previous: HashMap::new(),
+                metric_ids: MetricIds {
+                    system: key("system"),
+                    ids: HashMap::new(),
+                    times: HashMap::new(),
+                },
+            }),
+            ticks_per_second: ticks_per_second(),
         }
     }
 }
 
+impl PluginState for CpuInputInstance {
+    fn save(&self) -> Result<Option<serde_json::Value>> {
+        let state = self.state.lock()?;
+        let previous: Vec<&StatCpu> = state.previous.values().collect();
+        Ok(Some(serde_json::to_value(&previous)))
+    }
+
+    fn restore(&self, saved: serde_json::Value) -> Result<()> {
+        let previous: Vec<StatCpu> = serde_json::from_value(saved)?;
+        let mut state = self.state.lock()?;
+        state.previous = previous.into_iter().map(|cpu| (cpu.id, cpu)).collect();
+        Ok(())
+    }
+}
+
 impl InputInstance for CpuInputInstance {
+    /// Diff the current counters against the ones read during the previous poll.
+    ///
+    /// Nothing is reported for a cpu until it has been read twice.
     fn poll(&self) -> Result<Samples> {
-        let ref mut m = self.metrics.lock()?;
+        let cpus = read_stat()?;
 
-        let results = vec![
-            Sample::new(m.free.0.clone(), m.free.1.snapshot()),
-            Sample::new(m.used.0.clone(), m.used.1.snapshot()),
-        ];
+        let mut guard = self.state.lock()?;
+        let state = &mut *guard;
+        let mut samples = Vec::new();
 
-        Ok(results)
-    }
+        for next in cpus {
+            for (state_name, ticks) in times(&next) {
+                let metric_id = state.metric_ids.time(next.id, state_name);
+                let value = ticks as f64 / self.ticks_per_second;
+                samples.push(Sample::new(metric_id, value).with_kind(SampleKind::Counter));
+            }
 
-    fn update(&self) -> Box<Future<Item = (), Error = Error> + Send> {
-        let m = self.metrics.clone();
+            if let Some(prev) = state.previous.get(&next.id) {
+                for (name, value) in usage(&next, prev).unwrap_or_default() {
+                    let metric_id = state.metric_ids.get(next.id, name);
+                    samples.push(Sample::new(metric_id, value));
+                }
+            }
 
-        Box::new(future::lazy(move || {
-            let result: Result<()> = m.lock().map_err(Into::into).and_then(
-                |mut locked| locked.update(),
-            );
+            state.previous.insert(next.id, next);
+        }
 
-            future::result(result)
-        }))
+        Ok(samples)
     }
 
-    fn next_update(&self) -> Duration {
-        self.next_update
+    fn state(&self) -> Option<&PluginState> {
+        Some(self)
+    }
+
+    /// Reading `/proc/stat` doesn't block.
+    fn blocking(&self) -> bool {
+        false
     }
 }
 
@@ -145,4 +255,48 @@ mod test {
 
     #[test]
     fn snapshot() {}
+
+    #[test]
+    fn test_state() {
+        let instance = CpuInputInstance::new();
+
+        instance.state.lock().unwrap().previous.insert(
+            Some(1),
+            StatCpu {
+                id: Some(1),
+                user: 100,
+                ..StatCpu::default()
+            },
+        );
+
+        let saved = instance.save().unwrap().unwrap();
+        let restored = CpuInputInstance::new();
+        restored.restore(saved).unwrap();
+
+        assert_eq!(100, restored.state.lock().unwrap().previous[&Some(1)].user);
+    }
+
+    #[test]
+    fn test_usage() {
+        let prev = StatCpu {
+            user: 100,
+            idle: 100,
+            ..StatCpu::default()
+        };
+
+        let next = StatCpu {
+            user: 150,
+            system: 25,
+            idle: 125,
+            ..StatCpu::default()
+        };
+
+        let usage: HashMap<_, _> = usage(&next, &prev).unwrap().into_iter().collect();
+
+        assert_eq!(50f64, usage["user"]);
+        assert_eq!(25f64, usage["system"]);
+        assert_eq!(25f64, usage["idle"]);
+        assert_eq!(75f64, usage["used"]);
+        assert!(super::usage(&prev, &prev).is_none());
+    }
 }
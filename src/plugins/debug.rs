@@ -6,7 +6,7 @@ struct DebugOutput {}
 
 impl Output for DebugOutput {
     fn setup(&self, ctx: PluginContext) -> Result<Box<OutputInstance>> {
-        Ok(Box::new(DebugOutputInstance::new(ctx.id.clone())))
+        Ok(Box::new(DebugOutputInstance::new(ctx.name.clone())))
     }
 }
 
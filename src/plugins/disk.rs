@@ -1,5 +1,29 @@
+use metric::*;
 use errors::*;
 use plugin::*;
+use parsers::diskstats::*;
+use parsers::mounts::*;
+
+use futures::*;
+use libc;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+const PROC_MOUNTS: &'static str = "/proc/mounts";
+const PROC_DISKSTATS: &'static str = "/proc/diskstats";
+/// Size of the sectors counted in /proc/diskstats, regardless of the device.
+const SECTOR_SIZE: u64 = 512;
+
+#[derive(Deserialize, Debug)]
+struct DiskInputConfig {
+    /// Mountpoints to report on, defaults to all mounts.
+    mountpoints: Option<Vec<String>>,
+    /// Filesystem types to ignore.
+    ignore_fs_types: Option<Vec<String>>,
+}
 
 #[derive(Debug)]
 struct DiskInput {}
@@ -11,21 +35,209 @@ impl DiskInput {
 }
 
 impl Input for DiskInput {
-    fn setup(&self, _ctx: PluginContext) -> Result<Box<InputInstance>> {
-        Ok(Box::new(DiskInputInstance::new()))
+    fn setup(&self, ctx: PluginContext) -> Result<Box<InputInstance>> {
+        let config: DiskInputConfig = ctx.decode_config()?;
+
+        if config.mountpoints.as_ref().map(|m| m.is_empty()).unwrap_or(false) {
+            return Err(
+                ErrorKind::InvalidPluginConfig(
+                    "mountpoints".to_owned(),
+                    "must not be empty, leave it out to report on all mounts".to_owned(),
+                ).into(),
+            );
+        }
+
+        Ok(Box::new(DiskInputInstance {
+            disks: Arc::new(Disks {
+                mountpoints: config.mountpoints,
+                ignore_fs_types: config.ignore_fs_types.unwrap_or_default(),
+                samples: Mutex::new(Vec::new()),
+            }),
+        }))
     }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some(
+            "# mountpoints to report on, defaults to all mounts.\n\
+             mountpoints = [\"/\"]\n\
+             ignore_fs_types = [\"tmpfs\", \"proc\"]",
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Disks {
+    mountpoints: Option<Vec<String>>,
+    ignore_fs_types: Vec<String>,
+    /// Samples collected during the last update.
+    samples: Mutex<Vec<Sample>>,
 }
 
 #[derive(Debug)]
-struct DiskInputInstance {}
+struct DiskInputInstance {
+    disks: Arc<Disks>,
+}
+
+fn read_mounts() -> Result<Vec<Mount>> {
+    let mut content = String::new();
+
+    File::open(PROC_MOUNTS)
+        .and_then(|mut f| f.read_to_string(&mut content))
+        .chain_err(|| format!("could not read {}", PROC_MOUNTS))?;
+
+    Ok(parse_mounts(&content))
+}
+
+fn read_diskstats() -> Result<Vec<DiskStat>> {
+    let mut content = String::new();
+
+    File::open(PROC_DISKSTATS)
+        .and_then(|mut f| f.read_to_string(&mut content))
+        .chain_err(|| format!("could not read {}", PROC_DISKSTATS))?;
+
+    Ok(parse_diskstats(&content))
+}
+
+fn statvfs(path: &str) -> Result<libc::statvfs> {
+    let c_path = CString::new(path).map_err(
+        |e| ErrorKind::Message(e.to_string()),
+    )?;
+
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error()).chain_err(
+            || format!("statvfs failed for {}", path),
+        );
+    }
+
+    Ok(stat)
+}
+
+fn disk_samples(mount: &Mount, stat: &libc::statvfs) -> Vec<Sample> {
+    let base = key("system")
+        .tag("mountpoint", &mount.mountpoint)
+        .tag("fstype", &mount.fstype);
+
+    let bytes = |what: &str, blocks: u64| {
+        let metric_id = base.clone().tag("what", what).tag("unit", "B").build();
+        Sample::new(Arc::new(metric_id), (blocks * stat.f_frsize as u64) as f64)
+    };
+
+    let inodes = |what: &str, count: u64| {
+        let metric_id = base.clone().tag("what", what).build();
+        Sample::new(Arc::new(metric_id), count as f64)
+    };
+
+    let blocks = stat.f_blocks as u64;
+    let files = stat.f_files as u64;
+
+    vec![
+        bytes("disk-total", blocks),
+        bytes("disk-free", stat.f_bavail as u64),
+        bytes("disk-used", blocks.saturating_sub(stat.f_bfree as u64)),
+        inodes("inodes-total", files),
+        inodes("inodes-free", stat.f_ffree as u64),
+        inodes("inodes-used", files.saturating_sub(stat.f_ffree as u64)),
+    ]
+}
+
+/// I/O counters of the device of a mount, as derive samples which are reported as rates.
+fn io_samples(mount: &Mount, stat: &DiskStat) -> Vec<Sample> {
+    let base = key("system")
+        .tag("mountpoint", &mount.mountpoint)
+        .tag("fstype", &mount.fstype)
+        .tag("device", &stat.device);
+
+    let derive = |what: &str, unit: &str, value: u64| {
+        let metric_id = base.clone().tag("what", what).tag("unit", unit).build();
+        Sample::new(Arc::new(metric_id), value as f64).with_kind(SampleKind::Derive)
+    };
+
+    vec![
+        derive("disk-reads", "ops/s", stat.reads),
+        derive("disk-read-bytes", "B/s", stat.read_sectors * SECTOR_SIZE),
+        derive("disk-writes", "ops/s", stat.writes),
+        derive("disk-written-bytes", "B/s", stat.written_sectors * SECTOR_SIZE),
+    ]
+}
+
+impl Disks {
+    /// Mounts to collect statistics for.
+    fn mounts(&self) -> Result<Vec<Mount>> {
+        let mounts = read_mounts()?;
+
+        let mounts = match self.mountpoints {
+            Some(ref mountpoints) => {
+                mountpoints
+                    .iter()
+                    .map(|mountpoint| {
+                        let mount = mounts.iter().rev().find(|m| &m.mountpoint == mountpoint);
 
-impl DiskInputInstance {
-    pub fn new() -> DiskInputInstance {
-        DiskInputInstance {}
+                        Mount {
+                            device: mount.map(|m| m.device.clone()).unwrap_or_default(),
+                            mountpoint: mountpoint.clone(),
+                            fstype: mount
+                                .map(|m| m.fstype.clone())
+                                .unwrap_or("unknown".to_owned()),
+                        }
+                    })
+                    .collect()
+            }
+            None => mounts,
+        };
+
+        Ok(
+            mounts
+                .into_iter()
+                .filter(|m| !self.ignore_fs_types.contains(&m.fstype))
+                .collect(),
+        )
+    }
+
+    fn update(&self) -> Result<()> {
+        let mut samples = Vec::new();
+
+        // I/O counters are optional, like in containers which don't expose them.
+        let diskstats = read_diskstats().unwrap_or_else(|e| {
+            debug!("{}", e);
+            Vec::new()
+        });
+
+        for mount in self.mounts()? {
+            match statvfs(&mount.mountpoint) {
+                Ok(stat) => samples.extend(disk_samples(&mount, &stat)),
+                Err(e) => debug!("{}: {}", mount.mountpoint, e),
+            }
+
+            let device = if mount.device.starts_with("/dev/") {
+                &mount.device[5..]
+            } else {
+                continue;
+            };
+
+            if let Some(stat) = diskstats.iter().find(|s| s.device == device) {
+                samples.extend(io_samples(&mount, stat));
+            }
+        }
+
+        *self.samples.lock()? = samples;
+        Ok(())
     }
 }
 
-impl InputInstance for DiskInputInstance {}
+impl InputInstance for DiskInputInstance {
+    fn poll(&self) -> Result<Samples> {
+        let samples = self.disks.samples.lock()?;
+        Ok(samples.clone())
+    }
+
+    /// Collect statistics, this runs on the thread pool since statvfs might block.
+    fn update(&self) -> Box<Future<Item = (), Error = Error> + Send> {
+        let disks = self.disks.clone();
+        Box::new(future::lazy(move || disks.update()))
+    }
+}
 
 pub fn input() -> Result<Box<Input>> {
     Ok(Box::new(DiskInput::new()))
@@ -0,0 +1,61 @@
+//! Dotted metric names, as used by the graphite and statsd outputs.
+
+use metric::*;
+
+/// Replace characters which are not valid in a component of a dotted name.
+fn sanitize(part: &str) -> String {
+    part.chars()
+        .map(|c| match c {
+            'a'...'z' | 'A'...'Z' | '0'...'9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Readable component of a unit, so that `%` doesn't end up as `_`.
+fn unit_part(unit: &str) -> String {
+    sanitize(&unit.replace('%', "percent").replace('/', "_per_"))
+}
+
+/// Build a dotted name from the key, tag values, and resource values of a metric.
+pub fn dotted_name(prefix: Option<&str>, metric_id: &MetricId) -> String {
+    let mut parts: Vec<String> = Vec::new();
+
+    if let Some(prefix) = prefix {
+        parts.push(prefix.to_owned());
+    }
+
+    parts.push(sanitize(metric_id.key().unwrap_or("sysmon")));
+
+    for &(ref name, ref value) in metric_id.tags().iter().chain(metric_id.resource().iter()) {
+        if value.is_empty() {
+            continue;
+        }
+
+        if name == "unit" {
+            parts.push(unit_part(value));
+        } else {
+            parts.push(sanitize(value));
+        }
+    }
+
+    parts.join(".")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dotted_name() {
+        let id = key("system").tag("what", "cpu-used").tag("unit", "%").build();
+        assert_eq!("system.cpu-used.percent", dotted_name(None, &id));
+        assert_eq!("host1.system.cpu-used.percent", dotted_name(Some("host1"), &id));
+
+        let id = key("disk-read-bytes").tag("unit", "B/s").build();
+        assert_eq!("disk-read-bytes.B_per_s", dotted_name(None, &id));
+
+        let id = key("load").tag("what", "load.1").resource("host", "a.b").build();
+        assert_eq!("load.load_1.a_b", dotted_name(None, &id));
+    }
+}
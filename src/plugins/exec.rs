@@ -0,0 +1,685 @@
+//! Input plugin that runs a command on every poll, and parses samples from its output.
+
+use metric::*;
+use plugin::*;
+use errors::*;
+use plugins::timeout::wait_with_timeout;
+
+use futures::Future;
+use futures::sync::oneshot;
+use libc;
+use serde_json::{self, Value};
+use std::fmt;
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio_timer::Timer;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_KILL_GRACE_SECS: u64 = 2;
+const DEFAULT_MAX_OUTPUT: usize = 1024 * 1024;
+
+#[derive(Deserialize, Debug)]
+struct ExecInputConfig {
+    /// Program to run, followed by its arguments.
+    command: Vec<String>,
+    /// Format of the output of the command.
+    format: Option<String>,
+    /// Largest output in bytes that is accepted from the command.
+    max_output: Option<usize>,
+    /// Largest address space in bytes the command may use.
+    max_memory: Option<u64>,
+    /// Key of the reported samples, unless the format provides one.
+    key: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    /// InfluxDB line protocol.
+    Influx,
+    /// A JSON object, where numeric fields become samples.
+    Json,
+    /// `key=value` pairs, separated by whitespace.
+    KeyValue,
+}
+
+impl Format {
+    fn parse(value: &str) -> Option<Format> {
+        match value {
+            "influx" => Some(Format::Influx),
+            "json" => Some(Format::Json),
+            "kv" => Some(Format::KeyValue),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ExecInput {}
+
+impl Input for ExecInput {
+    fn setup(&self, ctx: PluginContext) -> Result<Box<InputInstance>> {
+        let config: ExecInputConfig = ctx.decode_config()?;
+
+        if config.command.is_empty() {
+            return Err(
+                ErrorKind::InvalidPluginConfig("command".to_owned(), "must not be empty".to_owned())
+                    .into(),
+            );
+        }
+
+        let format = match config.format {
+            Some(format) => {
+                Format::parse(&format).ok_or_else(|| {
+                    ErrorKind::InvalidPluginConfig(
+                        "format".to_owned(),
+                        format!("expected influx, json, or kv but got {:?}", format),
+                    )
+                })?
+            }
+            None => Format::KeyValue,
+        };
+
+        let timeout = ctx.decode_duration("timeout")?.unwrap_or(
+            Duration::new(DEFAULT_TIMEOUT_SECS, 0),
+        );
+
+        let kill_grace = ctx.decode_duration("kill_grace")?.unwrap_or(
+            Duration::new(DEFAULT_KILL_GRACE_SECS, 0),
+        );
+
+        // rounded up, since the limit is in whole seconds.
+        let max_cpu_time = ctx.decode_duration("max_cpu_time")?.map(|t| {
+            t.as_secs() + if t.subsec_nanos() > 0 { 1 } else { 0 }
+        });
+
+        let base = config.key.unwrap_or("exec".to_owned());
+
+        let mut command = config.command;
+
+        // programs given by path are relative to the configuration file, others are looked up in
+        // PATH.
+        if command[0].contains('/') {
+            command[0] = ctx.resolve_path(&command[0]);
+        }
+
+        Ok(Box::new(ExecInputInstance {
+            name: ctx.name.clone(),
+            command: command,
+            format: format,
+            timeout: timeout,
+            kill_grace: kill_grace,
+            max_output: config.max_output.unwrap_or(DEFAULT_MAX_OUTPUT),
+            limits: Limits {
+                memory: config.max_memory,
+                cpu_time: max_cpu_time,
+            },
+            exit_code: Arc::new(key(&base).tag("what", "exec-exit-code").build()),
+            signal: key(&base).tag("what", "exec-signal"),
+            duration: Arc::new(
+                key(&base).tag("what", "exec-duration").tag("unit", "s").build(),
+            ),
+            key: base,
+            timer: ctx.timer.clone(),
+        }))
+    }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some(
+            "command = [\"/usr/local/bin/check-queue\", \"--json\"]\n\
+             # influx, json, or kv\n\
+             format = \"json\"\n\
+             timeout = \"10s\"\n\
+             kill_grace = \"2s\"\n\
+             max_output = 1048576\n\
+             max_memory = 268435456\n\
+             max_cpu_time = \"5s\"\n\
+             key = \"queue\"",
+        )
+    }
+}
+
+/// Resource limits applied to the command, and anything it starts.
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    /// Largest address space in bytes.
+    memory: Option<u64>,
+    /// Seconds of CPU time, after which the command receives `SIGXCPU`.
+    cpu_time: Option<u64>,
+}
+
+impl Limits {
+    /// Apply the limits to the calling process.
+    fn apply(&self) -> io::Result<()> {
+        let rlimit = |limit: u64| {
+            libc::rlimit {
+                rlim_cur: limit as libc::rlim_t,
+                rlim_max: limit as libc::rlim_t,
+            }
+        };
+
+        if let Some(memory) = self.memory {
+            check(unsafe { libc::setrlimit(libc::RLIMIT_AS, &rlimit(memory)) })?;
+        }
+
+        if let Some(cpu_time) = self.cpu_time {
+            check(unsafe { libc::setrlimit(libc::RLIMIT_CPU, &rlimit(cpu_time)) })?;
+        }
+
+        Ok(())
+    }
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Send a signal to the process group of the command, which includes anything it started.
+fn signal_group(pid: u32, signal: libc::c_int) {
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), signal);
+    }
+}
+
+/// Name of a signal, for the ones a command is likely to be stopped by.
+fn signal_name(signal: libc::c_int) -> String {
+    let name = match signal {
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGINT => "SIGINT",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGXCPU => "SIGXCPU",
+        signal => return signal.to_string(),
+    };
+
+    name.to_owned()
+}
+
+/// How a run of the command ended.
+#[derive(Debug)]
+enum Outcome {
+    /// The command exited by itself, or was stopped by a signal it didn't get from sysmon.
+    Exited(ExitStatus, Vec<u8>),
+    /// The command ran for longer than the timeout, and was stopped by the given signal.
+    Killed(libc::c_int),
+}
+
+struct ExecInputInstance {
+    name: String,
+    command: Vec<String>,
+    format: Format,
+    timeout: Duration,
+    /// How long to wait for the command to exit after `SIGTERM`, before sending `SIGKILL`.
+    kill_grace: Duration,
+    max_output: usize,
+    limits: Limits,
+    key: String,
+    exit_code: Arc<MetricId>,
+    signal: MetricIdBuilder,
+    /// Reported as an observation for every command which exits.
+    duration: Arc<MetricId>,
+    /// Used to time out the command.
+    timer: Arc<Timer>,
+}
+
+impl fmt::Debug for ExecInputInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ExecInputInstance({:?})", self.command)
+    }
+}
+
+fn as_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+/// Read at most `max_output` bytes from the given reader.
+///
+/// Returns `None` if the output is larger than that.
+fn read_capped<R: Read>(reader: R, max_output: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut output = Vec::new();
+    reader.take(max_output as u64 + 1).read_to_end(&mut output)?;
+
+    if output.len() > max_output {
+        return Ok(None);
+    }
+
+    Ok(Some(output))
+}
+
+/// Split on `sep`, unless it is escaped with a backslash or inside of double quotes.
+///
+/// Escapes are kept, so that the parts can be split again.
+fn split_unescaped(input: &str, sep: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut escaped = false;
+    let mut quoted = false;
+
+    for c in input.chars() {
+        if escaped {
+            current.push(c);
+            escaped = false;
+            continue;
+        }
+
+        match c {
+            '\\' => {
+                escaped = true;
+                current.push(c);
+            }
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            c if c == sep && !quoted => {
+                parts.push(mem::replace(&mut current, String::new()));
+            }
+            c => current.push(c),
+        }
+    }
+
+    parts.push(current);
+    parts
+}
+
+/// Remove backslash escapes.
+fn unescape(input: &str) -> String {
+    let mut out = String::new();
+    let mut escaped = false;
+
+    for c in input.chars() {
+        if c == '\\' && !escaped {
+            escaped = true;
+            continue;
+        }
+
+        out.push(c);
+        escaped = false;
+    }
+
+    out
+}
+
+fn parse_field_value(value: &str) -> Option<f64> {
+    if value.ends_with('i') {
+        return value[..value.len() - 1].parse::<i64>().ok().map(|v| v as f64);
+    }
+
+    match value {
+        "t" | "T" | "true" | "True" | "TRUE" => Some(1f64),
+        "f" | "F" | "false" | "False" | "FALSE" => Some(0f64),
+        // string fields can't be represented as samples.
+        value if value.starts_with('"') => None,
+        value => value.parse::<f64>().ok(),
+    }
+}
+
+/// Parse the InfluxDB line protocol.
+///
+/// The measurement becomes the key of the sample, tags are kept, and every numeric field becomes a
+/// sample with a `what` tag. Timestamps are ignored, since samples are stamped when polled.
+fn parse_influx(output: &str) -> Result<Vec<(MetricId, f64)>> {
+    let mut samples = Vec::new();
+
+    for line in output.lines() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parts: Vec<String> = split_unescaped(line, ' ')
+            .into_iter()
+            .filter(|p| !p.is_empty())
+            .collect();
+
+        if parts.len() < 2 {
+            return Err(ErrorKind::Message(format!("malformed line: {:?}", line)).into());
+        }
+
+        let series = split_unescaped(&parts[0], ',');
+        let mut base = key(&unescape(&series[0]));
+
+        for tag in series.iter().skip(1) {
+            let mut kv = tag.splitn(2, '=');
+
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) => base = base.tag(&unescape(k), &unescape(v)),
+                _ => return Err(ErrorKind::Message(format!("malformed tag: {:?}", tag)).into()),
+            }
+        }
+
+        for field in split_unescaped(&parts[1], ',') {
+            let mut kv = field.splitn(2, '=');
+
+            let (name, value) = match (kv.next(), kv.next()) {
+                (Some(name), Some(value)) => (name, value),
+                _ => {
+                    return Err(ErrorKind::Message(format!("malformed field: {:?}", field)).into());
+                }
+            };
+
+            if let Some(value) = parse_field_value(value) {
+                samples.push((base.clone().tag("what", &unescape(name)).build(), value));
+            }
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Collect numeric values of a JSON document, naming nested values by their path joined with `.`.
+fn flatten_json(path: &str, value: &Value, out: &mut Vec<(String, f64)>) {
+    let join = |name: &str| if path.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", path, name)
+    };
+
+    match *value {
+        Value::I64(v) => out.push((path.to_owned(), v as f64)),
+        Value::U64(v) => out.push((path.to_owned(), v as f64)),
+        Value::F64(v) => out.push((path.to_owned(), v)),
+        Value::Bool(v) => out.push((path.to_owned(), if v { 1f64 } else { 0f64 })),
+        Value::Object(ref fields) => {
+            for (name, value) in fields {
+                flatten_json(&join(name), value, out);
+            }
+        }
+        Value::Array(ref values) => {
+            for (index, value) in values.iter().enumerate() {
+                flatten_json(&join(&index.to_string()), value, out);
+            }
+        }
+        Value::Null | Value::String(_) => {}
+    }
+}
+
+fn parse_json(base: &str, output: &str) -> Result<Vec<(MetricId, f64)>> {
+    let value: Value = serde_json::from_str(output)?;
+
+    let mut values = Vec::new();
+    flatten_json("", &value, &mut values);
+
+    Ok(
+        values
+            .into_iter()
+            .filter(|&(ref name, _)| !name.is_empty())
+            .map(|(name, value)| (key(base).tag("what", &name).build(), value))
+            .collect(),
+    )
+}
+
+/// Parse whitespace-separated `key=value` pairs.
+fn parse_key_value(base: &str, output: &str) -> Result<Vec<(MetricId, f64)>> {
+    let mut samples = Vec::new();
+
+    for pair in output.split_whitespace() {
+        let mut kv = pair.splitn(2, '=');
+
+        let (name, value) = match (kv.next(), kv.next()) {
+            (Some(name), Some(value)) if !name.is_empty() => (name, value),
+            _ => return Err(ErrorKind::Message(format!("malformed pair: {:?}", pair)).into()),
+        };
+
+        let value = value.parse::<f64>().map_err(|_| {
+            ErrorKind::Message(format!("not a number: {:?}", pair))
+        })?;
+
+        samples.push((key(base).tag("what", name).build(), value));
+    }
+
+    Ok(samples)
+}
+
+/// Read the output of the command and wait for it to exit.
+///
+/// Returns `None` if the output is larger than `max_output`, in which case the command is killed.
+fn collect(mut child: Child, max_output: usize) -> Result<Option<(ExitStatus, Vec<u8>)>> {
+    let output = match child.stdout.take() {
+        Some(stdout) => read_capped(stdout, max_output)?,
+        None => Some(Vec::new()),
+    };
+
+    match output {
+        Some(output) => Ok(Some((child.wait()?, output))),
+        None => {
+            signal_group(child.id(), libc::SIGKILL);
+            child.wait()?;
+            Ok(None)
+        }
+    }
+}
+
+impl ExecInputInstance {
+    /// Run the command, stopping it if it doesn't complete within the timeout.
+    ///
+    /// The command is sent `SIGTERM` once the timeout expires, and `SIGKILL` if it is still running
+    /// after the grace period. Signals are sent to its process group, so that anything the command
+    /// started is stopped as well.
+    fn run(&self) -> Result<Outcome> {
+        let limits = self.limits;
+
+        let child = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .before_exec(move || {
+                check(unsafe { libc::setpgid(0, 0) })?;
+                limits.apply()
+            })
+            .spawn()
+            .chain_err(|| format!("failed to run {:?}", self.command[0]))?;
+
+        let pid = child.id();
+        let max_output = self.max_output;
+        let (tx, rx) = oneshot::channel();
+
+        // the thread notices the process going away when it is killed, and reaps it.
+        thread::spawn(move || { let _ = tx.send(collect(child, max_output)); });
+
+        let done = rx.then(|result| match result {
+            Ok(result) => result,
+            Err(_) => Err(ErrorKind::Message("reader thread stopped".to_owned()).into()),
+        });
+
+        let done = match wait_with_timeout(&self.timer, self.timeout, done)? {
+            Ok(Some((status, output))) => return Ok(Outcome::Exited(status, output)),
+            Ok(None) => {
+                return Err(
+                    ErrorKind::Message(format!("output larger than {} bytes", max_output)).into(),
+                );
+            }
+            Err(done) => done,
+        };
+
+        warn!("{}: timed out after {:?}, terminating", self.name, self.timeout);
+        signal_group(pid, libc::SIGTERM);
+
+        if wait_with_timeout(&self.timer, self.kill_grace, done)?.is_ok() {
+            return Ok(Outcome::Killed(libc::SIGTERM));
+        }
+
+        warn!("{}: still running after {:?}, killing", self.name, self.kill_grace);
+        signal_group(pid, libc::SIGKILL);
+        Ok(Outcome::Killed(libc::SIGKILL))
+    }
+
+    /// Samples reporting that the command was stopped by a signal.
+    fn stopped(&self, signal: libc::c_int) -> Samples {
+        let signal_id = self.signal.clone().tag("signal", &signal_name(signal)).build();
+
+        vec![
+            Sample::new(self.exit_code.clone(), -1f64),
+            Sample::new(Arc::new(signal_id), signal as f64),
+        ]
+    }
+}
+
+impl InputInstance for ExecInputInstance {
+    /// Polls run on the thread pool, so waiting for the command doesn't block the reactor.
+    fn poll(&self) -> Result<Samples> {
+        let started = Instant::now();
+
+        let (status, output) = match self.run()? {
+            Outcome::Exited(status, output) => (status, output),
+            Outcome::Killed(signal) => return Ok(self.stopped(signal)),
+        };
+
+        let duration = as_secs(started.elapsed());
+
+        // processes stopped by a signal have no exit code.
+        if let Some(signal) = status.signal() {
+            warn!("{}: command was stopped by {}", self.name, signal_name(signal));
+            return Ok(self.stopped(signal));
+        }
+
+        let code = status.code().unwrap_or(-1);
+
+        let mut samples = vec![
+            Sample::new(self.exit_code.clone(), code as f64),
+            Sample::new(self.duration.clone(), duration).with_kind(SampleKind::Observation),
+        ];
+
+        if !status.success() {
+            warn!("{}: command exited with {}", self.name, status);
+            return Ok(samples);
+        }
+
+        let output = String::from_utf8(output).map_err(|_| {
+            ErrorKind::Message("output is not valid utf-8".to_owned())
+        })?;
+
+        let values = match self.format {
+            Format::Influx => parse_influx(&output)?,
+            Format::Json => parse_json(&self.key, &output)?,
+            Format::KeyValue => parse_key_value(&self.key, &output)?,
+        };
+
+        for (metric_id, value) in values {
+            samples.push(Sample::new(Arc::new(metric_id), value));
+        }
+
+        Ok(samples)
+    }
+}
+
+pub fn input() -> Result<Box<Input>> {
+    Ok(Box::new(ExecInput {}))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_influx() {
+        let output = "# comment\n\
+                      queue,name=jobs\\ high depth=12i,rate=0.5,label=\"a b\" 1500000000\n";
+
+        let samples = parse_influx(output).unwrap();
+
+        assert_eq!(
+            vec![
+                (key("queue").tag("name", "jobs high").tag("what", "depth").build(), 12f64),
+                (key("queue").tag("name", "jobs high").tag("what", "rate").build(), 0.5f64),
+            ],
+            samples
+        );
+
+        assert!(parse_influx("queue").is_err());
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let output = "{\"depth\": 12, \"workers\": {\"busy\": 3}, \"name\": \"jobs\"}";
+
+        assert_eq!(
+            vec![
+                (key("queue").tag("what", "depth").build(), 12f64),
+                (key("queue").tag("what", "workers.busy").build(), 3f64),
+            ],
+            parse_json("queue", output).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_key_value() {
+        assert_eq!(
+            vec![
+                (key("exec").tag("what", "depth").build(), 12f64),
+                (key("exec").tag("what", "rate").build(), 0.5f64),
+            ],
+            parse_key_value("exec", "depth=12\nrate=0.5\n").unwrap()
+        );
+
+        assert!(parse_key_value("exec", "depth").is_err());
+        assert!(parse_key_value("exec", "depth=many").is_err());
+    }
+
+    fn instance(script: &str) -> ExecInputInstance {
+        ExecInputInstance {
+            name: "exec".to_owned(),
+            command: vec!["sh".to_owned(), "-c".to_owned(), script.to_owned()],
+            format: Format::KeyValue,
+            timeout: Duration::from_millis(200),
+            kill_grace: Duration::from_millis(200),
+            max_output: DEFAULT_MAX_OUTPUT,
+            limits: Limits {
+                memory: None,
+                cpu_time: None,
+            },
+            key: "exec".to_owned(),
+            exit_code: Arc::new(key("exec").tag("what", "exec-exit-code").build()),
+            signal: key("exec").tag("what", "exec-signal"),
+            duration: Arc::new(key("exec").tag("what", "exec-duration").tag("unit", "s").build()),
+            timer: Arc::new(Timer::default()),
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        let signal = |script: &str| match instance(script).run().unwrap() {
+            Outcome::Killed(signal) => signal,
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        };
+
+        assert_eq!(libc::SIGTERM, signal("sleep 5"));
+        // ignoring SIGTERM doesn't help, and the sleep started by the shell is killed too.
+        assert_eq!(libc::SIGKILL, signal("trap '' TERM; sleep 5"));
+
+        let samples = instance("sleep 5").poll().unwrap();
+        let signal_id = key("exec").tag("what", "exec-signal").tag("signal", "SIGTERM").build();
+        assert_eq!(vec![-1f64, 15f64], samples.iter().map(|s| s.value).collect::<Vec<_>>());
+        assert_eq!(signal_id, *samples[1].metric_id);
+    }
+
+    #[test]
+    fn test_duration() {
+        let samples = instance("echo depth=12").poll().unwrap();
+
+        assert_eq!(3, samples.len());
+        assert_eq!(SampleKind::Observation, samples[1].kind);
+        assert_eq!(
+            key("exec").tag("what", "exec-duration").tag("unit", "s").build(),
+            *samples[1].metric_id
+        );
+        assert!(samples[1].value >= 0f64);
+    }
+
+    #[test]
+    fn test_read_capped() {
+        assert_eq!(Some(b"abc".to_vec()), read_capped(&b"abc"[..], 3).unwrap());
+        assert_eq!(None, read_capped(&b"abcd"[..], 3).unwrap());
+    }
+}
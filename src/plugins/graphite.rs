@@ -0,0 +1,289 @@
+//! Output plugin that writes samples to Graphite using the plaintext protocol.
+
+use errors::*;
+use plugin::*;
+use metric::*;
+use plugins::dotted::dotted_name;
+
+use std::cmp;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+const DEFAULT_PORT: u16 = 2003;
+const DEFAULT_QUEUE_SIZE: usize = 10000;
+
+/// How long to wait for queued lines to be written during shutdown.
+const FLUSH_TIMEOUT_MS: u64 = 5000;
+
+#[derive(Deserialize, Debug)]
+struct GraphiteOutputConfig {
+    host: String,
+    port: Option<u16>,
+    prefix: Option<String>,
+    /// Number of lines to queue while disconnected, before dropping lines.
+    queue_size: Option<usize>,
+    /// Precision of timestamps, carbon expects seconds.
+    timestamp_precision: Option<String>,
+}
+
+#[derive(Debug)]
+struct GraphiteOutput {}
+
+impl Output for GraphiteOutput {
+    fn setup(&self, ctx: PluginContext) -> Result<Box<OutputInstance>> {
+        let config: GraphiteOutputConfig = ctx.decode_config()?;
+
+        // delay before the first reconnect attempt, doubled for every failed attempt.
+        let policy = ReconnectPolicy {
+            delay: ctx.decode_duration("reconnect_delay")?.unwrap_or(Duration::new(1, 0)),
+            max_delay: ctx.decode_duration("reconnect_max_delay")?.unwrap_or(
+                Duration::new(60, 0),
+            ),
+        };
+
+        let precision =
+            Precision::decode(config.timestamp_precision.as_ref(), Precision::Seconds)?;
+
+        let address = format!("{}:{}", config.host, config.port.unwrap_or(DEFAULT_PORT));
+        let (tx, rx) = mpsc::sync_channel(config.queue_size.unwrap_or(DEFAULT_QUEUE_SIZE));
+
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let writer = Writer {
+            id: ctx.name.clone(),
+            address: address,
+            policy: policy,
+            delay: policy.delay,
+            stream: None,
+            closed: closed.clone(),
+        };
+
+        let handle = thread::Builder::new()
+            .name(format!("graphite-{}", ctx.name))
+            .spawn(move || writer.run(rx))?;
+
+        Ok(Box::new(GraphiteOutputInstance {
+            id: ctx.name.clone(),
+            prefix: config.prefix,
+            precision: precision,
+            queue: Mutex::new(tx),
+            closed: closed,
+            writer: handle.thread().clone(),
+        }))
+    }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some(
+            "host = \"localhost\"\n\
+             port = 2003\n\
+             prefix = \"sysmon\"\n\
+             reconnect_delay = \"1s\"\n\
+             reconnect_max_delay = \"1m\"\n\
+             queue_size = 10000\n\
+             timestamp_precision = \"s\"",
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ReconnectPolicy {
+    delay: Duration,
+    max_delay: Duration,
+}
+
+enum Message {
+    Line(String),
+    /// Acknowledged once every line queued before it has been handled.
+    Flush(mpsc::Sender<()>),
+}
+
+/// Owns the connection to carbon, and writes queued lines to it.
+struct Writer {
+    id: String,
+    address: String,
+    policy: ReconnectPolicy,
+    /// Delay before the next reconnect attempt.
+    delay: Duration,
+    stream: Option<TcpStream>,
+    /// Set once the instance is dropped, so that the writer stops reconnecting.
+    closed: Arc<AtomicBool>,
+}
+
+impl Writer {
+    /// Write lines until the instance is dropped.
+    ///
+    /// Nothing is taken off the queue while disconnected, so that lines are queued up to
+    /// `queue_size` until the connection is back.
+    fn run(mut self, rx: Receiver<Message>) {
+        for message in rx.iter() {
+            let line = match message {
+                Message::Line(line) => line,
+                Message::Flush(ack) => {
+                    let _ = ack.send(());
+                    continue;
+                }
+            };
+
+            if !self.write(&line) {
+                warn!("{}: dropped, discarding queued lines", self.id);
+                break;
+            }
+        }
+
+        debug!("{}: writer stopped", self.id);
+    }
+
+    /// Write a single line, reconnecting until it has been written.
+    ///
+    /// Returns `false` if the instance was dropped while reconnecting.
+    fn write(&mut self, line: &str) -> bool {
+        loop {
+            if self.stream.is_none() && !self.connect() {
+                return false;
+            }
+
+            let result = match self.stream {
+                Some(ref mut stream) => stream.write_all(line.as_bytes()),
+                None => continue,
+            };
+
+            match result {
+                Ok(()) => return true,
+                // the line is written again once reconnected.
+                Err(e) => {
+                    warn!("{}: connection to {} lost: {}", self.id, self.address, e);
+                    self.stream = None;
+                }
+            }
+        }
+    }
+
+    /// Connect to carbon, backing off between failed attempts.
+    ///
+    /// Returns `false` if the instance was dropped while backing off.
+    fn connect(&mut self) -> bool {
+        while !self.closed.load(Ordering::Relaxed) {
+            let e = match TcpStream::connect(self.address.as_str()) {
+                Ok(stream) => {
+                    info!("{}: connected to {}", self.id, self.address);
+                    self.delay = self.policy.delay;
+                    self.stream = Some(stream);
+                    return true;
+                }
+                Err(e) => e,
+            };
+
+            warn!(
+                "{}: failed to connect to {}, retrying in {:?}: {}",
+                self.id,
+                self.address,
+                self.delay,
+                e
+            );
+
+            let next_attempt = Instant::now() + self.delay;
+            self.delay = cmp::min(self.delay * 2, self.policy.max_delay);
+
+            // woken up early when the instance is dropped.
+            while !self.closed.load(Ordering::Relaxed) {
+                let now = Instant::now();
+
+                if now >= next_attempt {
+                    break;
+                }
+
+                thread::park_timeout(next_attempt - now);
+            }
+        }
+
+        false
+    }
+}
+
+struct GraphiteOutputInstance {
+    id: String,
+    prefix: Option<String>,
+    precision: Precision,
+    queue: Mutex<SyncSender<Message>>,
+    closed: Arc<AtomicBool>,
+    /// Thread of the writer, which is woken up if it is backing off when the instance is dropped.
+    writer: Thread,
+}
+
+impl Drop for GraphiteOutputInstance {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.writer.unpark();
+    }
+}
+
+fn format_line(prefix: Option<&str>, metric_id: &MetricId, value: f64, timestamp: u64) -> String {
+    format!("{} {} {}\n", dotted_name(prefix, metric_id), value, timestamp)
+}
+
+impl OutputInstance for GraphiteOutputInstance {
+    fn feed(&self, sample: &Sample) -> Result<()> {
+        if !sample.value.is_finite() {
+            return Ok(());
+        }
+
+        let line = format_line(
+            self.prefix.as_ref().map(String::as_str),
+            &sample.metric_id,
+            sample.value,
+            sample.timestamp_with(self.precision),
+        );
+
+        match self.queue.lock()?.try_send(Message::Line(line)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                warn!("{}: queue is full, dropping sample", self.id);
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                Err(ErrorKind::Message("graphite writer has stopped".to_owned()).into())
+            }
+        }
+    }
+
+    fn flush(&self) -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        self.queue.lock()?.send(Message::Flush(tx)).map_err(|_| {
+            ErrorKind::Message("graphite writer has stopped".to_owned())
+        })?;
+
+        match rx.recv_timeout(Duration::from_millis(FLUSH_TIMEOUT_MS)) {
+            Ok(()) => Ok(()),
+            Err(RecvTimeoutError::Timeout) => Err(
+                ErrorKind::Message("timed out writing queued lines".to_owned()).into(),
+            ),
+            Err(RecvTimeoutError::Disconnected) => Err(
+                ErrorKind::Message("graphite writer has stopped".to_owned()).into(),
+            ),
+        }
+    }
+}
+
+pub fn output() -> Result<Box<Output>> {
+    Ok(Box::new(GraphiteOutput {}))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_line() {
+        let id = key("system").tag("what", "load.1").build();
+
+        assert_eq!(
+            "sysmon.system.load_1 0.5 1500000000\n",
+            format_line(Some("sysmon"), &id, 0.5, 1500000000)
+        );
+    }
+}
@@ -1,9 +1,36 @@
+//! Input plugin that polls a JSON document over HTTP, and reports values selected from it.
+
 use errors::*;
+use metric::*;
 use plugin::*;
+use plugins::timeout::run_with_timeout;
+use plugins::tls::{self, TlsConfig};
+
+use reqwest;
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_timer::Timer;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+/// Largest response body that is accepted.
+const MAX_BODY: u64 = 16 * 1024 * 1024;
 
 #[derive(Deserialize, Debug)]
 struct HttpInputConfig {
-    target: String,
+    url: Option<String>,
+    /// Older name of `url`.
+    target: Option<String>,
+    /// Headers to send with every request.
+    headers: Option<BTreeMap<String, String>>,
+    /// Map from the name of a sample to the selector of its value.
+    values: Option<BTreeMap<String, String>>,
+    /// Key of the reported samples.
+    key: Option<String>,
+    tls: Option<TlsConfig>,
 }
 
 #[derive(Debug)]
@@ -11,22 +38,289 @@ struct HttpInput {}
 
 impl Input for HttpInput {
     fn setup(&self, ctx: PluginContext) -> Result<Box<InputInstance>> {
-        let _c: HttpInputConfig = ctx.decode_config()?;
-        Ok(Box::new(HttpInputInstance::new()))
+        let config: HttpInputConfig = ctx.decode_config()?;
+
+        let url = config.url.or(config.target).ok_or_else(|| {
+            ErrorKind::InvalidPluginConfig("url".to_owned(), "is required".to_owned())
+        })?;
+
+        let url = reqwest::Url::parse(&url).map_err(|e| {
+            ErrorKind::InvalidPluginConfig("url".to_owned(), e.to_string())
+        })?;
+
+        let mut headers = reqwest::header::Headers::new();
+
+        for (name, value) in config.headers.unwrap_or_default() {
+            headers.set_raw(name, value);
+        }
+
+        let mut selectors = Vec::new();
+
+        for (name, selector) in config.values.unwrap_or_default() {
+            let path = parse_selector(&selector).chain_err(|| {
+                ErrorKind::InvalidPluginConfig(
+                    format!("values.{}", name),
+                    "invalid selector".to_owned(),
+                )
+            })?;
+
+            selectors.push((name, path));
+        }
+
+        let timeout = ctx.decode_duration("timeout")?.unwrap_or(
+            Duration::new(DEFAULT_TIMEOUT_SECS, 0),
+        );
+
+        let base = key(&config.key.unwrap_or("http".to_owned()));
+
+        // the client gives up by itself, so that a timed out request doesn't hold on to a thread.
+        let mut builder = tls::builder(config.tls.as_ref(), ctx.base_dir)?;
+        builder.timeout(timeout);
+
+        Ok(Box::new(HttpInputInstance {
+            request: Arc::new(Request {
+                client: tls::build(builder)?,
+                url: url,
+                headers: headers,
+            }),
+            selectors: selectors
+                .into_iter()
+                .map(|(name, path)| {
+                    (Arc::new(base.clone().tag("what", &name).build()), path)
+                })
+                .collect(),
+            status: Arc::new(base.clone().tag("what", "http-status").build()),
+            latency: Arc::new(base.clone().tag("what", "http-latency").tag("unit", "s").build()),
+            timeout: timeout,
+            timer: ctx.timer.clone(),
+        }))
+    }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some(
+            "url = \"http://localhost:8080/stats\"\n\
+             headers = { \"Authorization\" = \"Bearer secret\" }\n\
+             timeout = \"10s\"\n\
+             key = \"app\"\n\
+             values = { \"queue-depth\" = \"$.queue.depth\", \"busy\" = \"$.workers[0].busy\" }",
+        )
     }
 }
 
-#[derive(Debug)]
-struct HttpInputInstance {}
+/// A single step into a JSON document.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Field(String),
+    Index(usize),
+}
+
+/// Parse a selector like `$.queue.depth` or `workers[0].busy`.
+///
+/// The leading `$` is optional.
+fn parse_selector(selector: &str) -> Result<Vec<Segment>> {
+    let selector = selector.trim();
+    let selector = if selector.starts_with('$') {
+        &selector[1..]
+    } else {
+        selector
+    };
+
+    let mut segments = Vec::new();
+
+    for part in selector.split('.') {
+        // an empty part is only allowed for the leading dot.
+        if part.is_empty() {
+            if segments.is_empty() {
+                continue;
+            }
+
+            return Err(ErrorKind::Message("empty field in selector".to_owned()).into());
+        }
+
+        let (field, mut rest) = match part.find('[') {
+            Some(index) => (&part[..index], &part[index..]),
+            None => (part, ""),
+        };
+
+        if !field.is_empty() {
+            segments.push(Segment::Field(field.to_owned()));
+        }
+
+        while !rest.is_empty() {
+            let end = match (rest.starts_with('['), rest.find(']')) {
+                (true, Some(end)) => end,
+                _ => return Err(ErrorKind::Message(format!("bad index: {:?}", rest)).into()),
+            };
+
+            let index = rest[1..end].parse::<usize>().map_err(|_| {
+                ErrorKind::Message(format!("bad index: {:?}", &rest[..end + 1]))
+            })?;
+
+            segments.push(Segment::Index(index));
+            rest = &rest[end + 1..];
+        }
+    }
+
+    if segments.is_empty() {
+        return Err(ErrorKind::Message("selector is empty".to_owned()).into());
+    }
+
+    Ok(segments)
+}
+
+/// Select a numeric value from a document.
+fn select(value: &Value, path: &[Segment]) -> Option<f64> {
+    let mut current = value;
+
+    for segment in path {
+        let next = match (segment, current) {
+            (&Segment::Field(ref name), &Value::Object(ref fields)) => fields.get(name),
+            (&Segment::Index(index), &Value::Array(ref values)) => values.get(index),
+            _ => None,
+        };
+
+        current = match next {
+            Some(next) => next,
+            None => return None,
+        };
+    }
+
+    match *current {
+        Value::I64(v) => Some(v as f64),
+        Value::U64(v) => Some(v as f64),
+        Value::F64(v) => Some(v),
+        Value::Bool(v) => Some(if v { 1f64 } else { 0f64 }),
+        _ => None,
+    }
+}
+
+fn as_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+struct Request {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    headers: reqwest::header::Headers,
+}
+
+impl Request {
+    fn send(&self) -> Result<(u16, String)> {
+        let response = self.client
+            .get(self.url.clone())
+            .headers(self.headers.clone())
+            .send()
+            .map_err(|e| ErrorKind::Message(format!("request failed: {}", e)))?;
+
+        let status = response.status().as_u16();
+
+        let mut body = String::new();
+        response.take(MAX_BODY).read_to_string(&mut body)?;
+        Ok((status, body))
+    }
+}
+
+struct HttpInputInstance {
+    request: Arc<Request>,
+    selectors: Vec<(Arc<MetricId>, Vec<Segment>)>,
+    status: Arc<MetricId>,
+    /// Reported as an observation for every request which completes.
+    latency: Arc<MetricId>,
+    timeout: Duration,
+    /// Used to time out requests.
+    timer: Arc<Timer>,
+}
+
+impl fmt::Debug for HttpInputInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HttpInputInstance({})", self.request.url)
+    }
+}
 
 impl HttpInputInstance {
-    pub fn new() -> HttpInputInstance {
-        return HttpInputInstance {};
+    /// Perform the request, giving up if it doesn't complete within the timeout.
+    ///
+    /// The request runs on a separate thread, so that a request which hangs doesn't occupy a
+    /// thread of the pool once it has timed out.
+    fn fetch(&self) -> Result<(u16, String)> {
+        let request = self.request.clone();
+
+        match run_with_timeout(&self.timer, self.timeout, move || request.send())? {
+            Some(result) => Ok(result),
+            None => Err(ErrorKind::Message(format!("timed out after {:?}", self.timeout)).into()),
+        }
     }
 }
 
-impl InputInstance for HttpInputInstance {}
+impl InputInstance for HttpInputInstance {
+    fn poll(&self) -> Result<Samples> {
+        let started = Instant::now();
+        let (status, body) = self.fetch()?;
+        let latency = as_secs(started.elapsed());
+
+        let mut samples = vec![
+            Sample::new(self.status.clone(), status as f64),
+            Sample::new(self.latency.clone(), latency).with_kind(SampleKind::Observation),
+        ];
+
+        // the status sample reports the error, the body is unlikely to be the expected document.
+        if status < 200 || status >= 300 {
+            return Ok(samples);
+        }
+
+        let document: Value = serde_json::from_str(&body)?;
+
+        for &(ref metric_id, ref path) in self.selectors.iter() {
+            if let Some(value) = select(&document, path) {
+                samples.push(Sample::new(metric_id.clone(), value));
+            }
+        }
+
+        Ok(samples)
+    }
+}
 
 pub fn input() -> Result<Box<Input>> {
     Ok(Box::new(HttpInput {}))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_selector() {
+        assert_eq!(
+            vec![Segment::Field("queue".to_owned()), Segment::Field("depth".to_owned())],
+            parse_selector("$.queue.depth").unwrap()
+        );
+
+        assert_eq!(
+            vec![
+                Segment::Field("workers".to_owned()),
+                Segment::Index(0),
+                Segment::Field("busy".to_owned()),
+            ],
+            parse_selector("workers[0].busy").unwrap()
+        );
+
+        assert!(parse_selector("$").is_err());
+        assert!(parse_selector("a..b").is_err());
+        assert!(parse_selector("a[x]").is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let document: Value =
+            serde_json::from_str("{\"queue\": {\"depth\": 12}, \"workers\": [{\"busy\": true}]}")
+                .unwrap();
+
+        let depth = parse_selector("$.queue.depth").unwrap();
+        let busy = parse_selector("$.workers[0].busy").unwrap();
+        let missing = parse_selector("$.workers[1].busy").unwrap();
+
+        assert_eq!(Some(12f64), select(&document, &depth));
+        assert_eq!(Some(1f64), select(&document, &busy));
+        assert_eq!(None, select(&document, &missing));
+    }
+}
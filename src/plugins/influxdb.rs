@@ -0,0 +1,219 @@
+//! Output plugin that writes samples to InfluxDB using the line protocol.
+
+use errors::*;
+use plugin::*;
+use metric::*;
+
+use plugins::tls::{self, TlsConfig};
+
+use reqwest;
+
+#[derive(Deserialize, Debug)]
+struct InfluxDbOutputConfig {
+    url: String,
+    database: String,
+    username: Option<String>,
+    password: Option<String>,
+    tls: Option<TlsConfig>,
+    timestamp_precision: Option<String>,
+}
+
+#[derive(Debug)]
+struct InfluxDbOutput {}
+
+impl Output for InfluxDbOutput {
+    fn setup(&self, ctx: PluginContext) -> Result<Box<OutputInstance>> {
+        let config: InfluxDbOutputConfig = ctx.decode_config()?;
+
+        for &(field, value) in &[("url", &config.url), ("database", &config.database)] {
+            if value.trim().is_empty() {
+                return Err(
+                    ErrorKind::InvalidPluginConfig(field.to_owned(), "is required".to_owned())
+                        .into(),
+                );
+            }
+        }
+
+        let precision =
+            Precision::decode(config.timestamp_precision.as_ref(), Precision::Nanoseconds)?;
+
+        let mut url = reqwest::Url::parse(&format!("{}/write", config.url.trim_right_matches('/')))
+            .map_err(|e| ErrorKind::InvalidPluginConfig("url".to_owned(), e.to_string()))?;
+
+        url.query_pairs_mut()
+            .append_pair("db", &config.database)
+            .append_pair("precision", precision_param(precision));
+
+        let target = Target {
+            id: ctx.name.clone(),
+            client: tls::client(config.tls.as_ref(), ctx.base_dir)?,
+            url: url,
+            username: config.username,
+            password: config.password,
+        };
+
+        Ok(Box::new(InfluxDbOutputInstance {
+            target: target,
+            precision: precision,
+        }))
+    }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some(
+            "url = \"http://localhost:8086\"\n\
+             database = \"sysmon\"\n\
+             username = \"sysmon\"\n\
+             password = \"secret\"\n\
+             batch_size = 1000\n\
+             timestamp_precision = \"ns\"\n\
+             tls = { ca_file = \"/etc/ssl/influxdb-ca.pem\" }",
+        )
+    }
+}
+
+/// The `precision` parameter of a write, which tells InfluxDB how to interpret timestamps.
+fn precision_param(precision: Precision) -> &'static str {
+    match precision {
+        Precision::Seconds => "s",
+        Precision::Milliseconds => "ms",
+        Precision::Microseconds => "u",
+        Precision::Nanoseconds => "ns",
+    }
+}
+
+/// Where, and how, to write batches of points.
+struct Target {
+    id: String,
+    client: reqwest::Client,
+    url: reqwest::Url,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl Target {
+    fn send(&self, body: &str) -> Result<reqwest::StatusCode> {
+        let response = match self.username {
+            Some(ref username) => {
+                self.client
+                    .post(self.url.clone())
+                    .basic_auth(username.clone(), self.password.clone())
+                    .body(body.to_owned())
+                    .send()
+            }
+            None => self.client.post(self.url.clone()).body(body.to_owned()).send(),
+        };
+
+        response.map(|r| r.status()).map_err(|e| {
+            ErrorKind::Message(format!("influxdb request failed: {}", e)).into()
+        })
+    }
+
+    /// Write a batch of points, retrying once if the server fails.
+    fn write(&self, lines: Vec<String>) -> Result<()> {
+        let mut body = lines.join("\n");
+        body.push('\n');
+
+        let mut status = self.send(&body)?;
+
+        if status.is_server_error() {
+            warn!("{}: influxdb responded with {}, retrying", self.id, status);
+            status = self.send(&body)?;
+        }
+
+        if !status.is_success() {
+            return Err(
+                ErrorKind::Message(format!("influxdb responded with {}", status)).into(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+struct InfluxDbOutputInstance {
+    target: Target,
+    precision: Precision,
+}
+
+impl OutputInstance for InfluxDbOutputInstance {
+    /// Samples which are not batched by the framework are written one at a time.
+    ///
+    /// The write is synchronous, so that failures cause the framework to back off.
+    fn feed(&self, sample: &Sample) -> Result<()> {
+        // NaN and infinity can't be represented in the line protocol.
+        if !sample.value.is_finite() {
+            return Ok(());
+        }
+
+        let timestamp = sample.timestamp_with(self.precision);
+        self.target.write(vec![format_line(&sample.metric_id, sample.value, timestamp)])
+    }
+
+    /// Batches delivered by the framework are written as-is, in a single request.
+    ///
+    /// The write is synchronous, so that failures cause the framework to back off.
+    fn feed_batch(&self, samples: &[Sample]) -> Result<()> {
+        let lines: Vec<String> = samples
+            .iter()
+            .filter(|s| s.value.is_finite())
+            .map(|s| format_line(&s.metric_id, s.value, s.timestamp_with(self.precision)))
+            .collect();
+
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        self.target.write(lines)
+    }
+}
+
+/// Escape measurement names, tag keys, and tag values.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+/// Format a single point in the line protocol.
+fn format_line(metric_id: &MetricId, value: f64, timestamp: u64) -> String {
+    let mut line = escape(metric_id.key().unwrap_or("sysmon"));
+
+    for &(ref k, ref v) in metric_id.tags().iter().chain(metric_id.resource().iter()) {
+        if v.is_empty() {
+            continue;
+        }
+
+        line.push(',');
+        line.push_str(&escape(k));
+        line.push('=');
+        line.push_str(&escape(v));
+    }
+
+    line.push_str(&format!(" value={} {}", value, timestamp));
+    line
+}
+
+pub fn output() -> Result<Box<Output>> {
+    Ok(Box::new(InfluxDbOutput {}))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_line() {
+        let id = key("system").tag("what", "cpu-used").tag("unit", "%").build();
+        assert_eq!(
+            "system,what=cpu-used,unit=% value=0.25 1500000000000000000",
+            format_line(&id, 0.25f64, 1500000000000000000)
+        );
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!("a\\ b\\,c\\=d", escape("a b,c=d"));
+    }
+}
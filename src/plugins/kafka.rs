@@ -0,0 +1,169 @@
+//! Output plugin that publishes samples to a Kafka topic.
+
+use errors::*;
+use plugin::*;
+use plugins::stdout::Format;
+
+use kafka::producer::{Producer, Record, RequiredAcks};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const DEFAULT_ACK_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Deserialize, Debug)]
+struct KafkaOutputConfig {
+    /// Brokers to bootstrap from, like `localhost:9092`.
+    brokers: Vec<String>,
+    topic: String,
+    /// How samples are serialized, `json` or `line`.
+    serialization: Option<String>,
+    /// Tag whose value is used as the key of every message, which decides its partition.
+    partition_key: Option<String>,
+}
+
+#[derive(Debug)]
+struct KafkaOutput {}
+
+impl Output for KafkaOutput {
+    fn setup(&self, ctx: PluginContext) -> Result<Box<OutputInstance>> {
+        let config: KafkaOutputConfig = ctx.decode_config()?;
+
+        if config.brokers.is_empty() {
+            return Err(
+                ErrorKind::InvalidPluginConfig("brokers".to_owned(), "must not be empty".to_owned())
+                    .into(),
+            );
+        }
+
+        let format = match config.serialization {
+            Some(format) => {
+                Format::parse(&format).ok_or_else(|| {
+                    ErrorKind::InvalidPluginConfig(
+                        "serialization".to_owned(),
+                        format!("expected line or json but got {:?}", format),
+                    )
+                })?
+            }
+            None => Format::Json,
+        };
+
+        let ack_timeout = ctx.decode_duration("ack_timeout")?.unwrap_or(
+            Duration::new(DEFAULT_ACK_TIMEOUT_SECS, 0),
+        );
+
+        let producer = Producer::from_hosts(config.brokers)
+            .with_ack_timeout(ack_timeout)
+            .with_required_acks(RequiredAcks::One)
+            .create()
+            .map_err(|e| ErrorKind::Message(format!("failed to connect to kafka: {}", e)))?;
+
+        Ok(Box::new(KafkaOutputInstance {
+            topic: config.topic,
+            format: format,
+            partition_key: config.partition_key,
+            producer: Mutex::new(producer),
+        }))
+    }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some(
+            "brokers = [\"localhost:9092\"]\n\
+             topic = \"sysmon\"\n\
+             # json or line\n\
+             serialization = \"json\"\n\
+             partition_key = \"host\"\n\
+             ack_timeout = \"10s\"\n\
+             batch_size = 1000\n\
+             flush_interval = \"10s\"",
+        )
+    }
+}
+
+struct KafkaOutputInstance {
+    topic: String,
+    format: Format,
+    partition_key: Option<String>,
+    producer: Mutex<Producer>,
+}
+
+/// Value of the given tag of a sample, if it has one.
+fn tag_value<'a>(sample: &'a Sample, tag: &str) -> Option<&'a str> {
+    let metric_id = &sample.metric_id;
+
+    metric_id
+        .tags()
+        .iter()
+        .chain(metric_id.resource().iter())
+        .find(|&&(ref k, _)| k == tag)
+        .map(|&(_, ref v)| v.as_str())
+}
+
+impl OutputInstance for KafkaOutputInstance {
+    fn feed(&self, sample: &Sample) -> Result<()> {
+        self.feed_batch(&[sample.clone()])
+    }
+
+    /// Publish all samples in a single request.
+    ///
+    /// A failed request fails the whole batch, which is retried by backing off.
+    fn feed_batch(&self, samples: &[Sample]) -> Result<()> {
+        let mut values = Vec::new();
+
+        for sample in samples {
+            values.push(self.format.format(sample)?);
+        }
+
+        let mut producer = self.producer.lock()?;
+
+        // messages without a key are spread over all partitions.
+        let result = match self.partition_key {
+            Some(ref tag) => {
+                let records: Vec<_> = samples
+                    .iter()
+                    .zip(values.iter())
+                    .map(|(sample, value)| {
+                        let key = tag_value(sample, tag).unwrap_or("");
+                        Record::from_key_value(&self.topic, key.as_bytes(), value.as_bytes())
+                    })
+                    .collect();
+
+                producer.send_all(&records)
+            }
+            None => {
+                let records: Vec<_> = values
+                    .iter()
+                    .map(|value| Record::from_value(&self.topic, value.as_bytes()))
+                    .collect();
+
+                producer.send_all(&records)
+            }
+        };
+
+        result.map_err(|e| {
+            ErrorKind::Message(format!("failed to publish to {}: {}", self.topic, e))
+        })?;
+
+        Ok(())
+    }
+}
+
+pub fn output() -> Result<Box<Output>> {
+    Ok(Box::new(KafkaOutput {}))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metric::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_tag_value() {
+        let id = key("system").tag("what", "cpu-used").resource("host", "web-1").build();
+        let sample = Sample::new(Arc::new(id), 1f64);
+
+        assert_eq!(Some("cpu-used"), tag_value(&sample, "what"));
+        assert_eq!(Some("web-1"), tag_value(&sample, "host"));
+        assert_eq!(None, tag_value(&sample, "unit"));
+    }
+}
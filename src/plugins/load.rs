@@ -1,5 +1,13 @@
-use errors::*;
+use metric::*;
 use plugin::*;
+use errors::*;
+use parsers::loadavg::*;
+
+use std::fs::File;
+use std::io::Read;
+use std::sync::Arc;
+
+const PROC_LOADAVG: &'static str = "/proc/loadavg";
 
 #[derive(Debug)]
 struct LoadInput {}
@@ -11,15 +19,56 @@ impl Input for LoadInput {
 }
 
 #[derive(Debug)]
-struct LoadInputInstance {}
+struct LoadInputInstance {
+    load1: Arc<MetricId>,
+    load5: Arc<MetricId>,
+    load15: Arc<MetricId>,
+    running: Arc<MetricId>,
+    total: Arc<MetricId>,
+}
+
+fn load_metric(what: &str) -> Arc<MetricId> {
+    Arc::new(key("system").tag("what", what).build())
+}
 
 impl LoadInputInstance {
     pub fn new() -> LoadInputInstance {
-        LoadInputInstance {}
+        LoadInputInstance {
+            load1: load_metric("load-1"),
+            load5: load_metric("load-5"),
+            load15: load_metric("load-15"),
+            running: load_metric("processes-running"),
+            total: load_metric("processes-total"),
+        }
     }
 }
 
-impl InputInstance for LoadInputInstance {}
+impl InputInstance for LoadInputInstance {
+    fn poll(&self) -> Result<Samples> {
+        let mut content = String::new();
+
+        File::open(PROC_LOADAVG)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .chain_err(|| format!("could not read {}", PROC_LOADAVG))?;
+
+        let loadavg = parse_loadavg(&content).chain_err(
+            || format!("could not parse {}", PROC_LOADAVG),
+        )?;
+
+        Ok(vec![
+            Sample::new(self.load1.clone(), loadavg.load1),
+            Sample::new(self.load5.clone(), loadavg.load5),
+            Sample::new(self.load15.clone(), loadavg.load15),
+            Sample::new(self.running.clone(), loadavg.running as f64),
+            Sample::new(self.total.clone(), loadavg.total as f64),
+        ])
+    }
+
+    /// Reading `/proc/loadavg` doesn't block.
+    fn blocking(&self) -> bool {
+        false
+    }
+}
 
 pub fn input() -> Result<Box<Input>> {
     Ok(Box::new(LoadInput {}))
@@ -0,0 +1,88 @@
+use metric::*;
+use plugin::*;
+use errors::*;
+use parsers::meminfo::*;
+
+use std::fs::File;
+use std::io::Read;
+use std::sync::Arc;
+
+const PROC_MEMINFO: &'static str = "/proc/meminfo";
+
+/// Fields of /proc/meminfo which are reported, and the name they are reported as.
+const FIELDS: &'static [(&'static str, &'static str)] = &[
+    ("MemTotal", "memory-total"),
+    ("MemFree", "memory-free"),
+    ("MemAvailable", "memory-available"),
+    ("Buffers", "memory-buffers"),
+    ("Cached", "memory-cached"),
+    ("SwapTotal", "swap-total"),
+    ("SwapFree", "swap-free"),
+];
+
+#[derive(Debug)]
+struct MemoryInput {}
+
+impl Input for MemoryInput {
+    fn setup(&self, _ctx: PluginContext) -> Result<Box<InputInstance>> {
+        Ok(Box::new(MemoryInputInstance::new()))
+    }
+}
+
+#[derive(Debug)]
+struct MemoryInputInstance {
+    fields: Vec<(&'static str, Arc<MetricId>)>,
+    swap_used: Arc<MetricId>,
+}
+
+fn memory_metric(what: &str) -> Arc<MetricId> {
+    Arc::new(key("system").tag("what", what).tag("unit", "B").build())
+}
+
+impl MemoryInputInstance {
+    pub fn new() -> MemoryInputInstance {
+        MemoryInputInstance {
+            fields: FIELDS
+                .iter()
+                .map(|&(field, what)| (field, memory_metric(what)))
+                .collect(),
+            swap_used: memory_metric("swap-used"),
+        }
+    }
+}
+
+impl InputInstance for MemoryInputInstance {
+    fn poll(&self) -> Result<Samples> {
+        let mut content = String::new();
+
+        File::open(PROC_MEMINFO)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .chain_err(|| format!("could not read {}", PROC_MEMINFO))?;
+
+        let meminfo = parse_meminfo(&content);
+
+        let mut samples = Vec::new();
+
+        for &(field, ref metric_id) in self.fields.iter() {
+            if let Some(value) = meminfo.get(field) {
+                samples.push(Sample::new(metric_id.clone(), *value as f64));
+            }
+        }
+
+        if let (Some(total), Some(free)) = (meminfo.get("SwapTotal"), meminfo.get("SwapFree")) {
+            let used = total.saturating_sub(*free);
+            samples.push(Sample::new(self.swap_used.clone(), used as f64));
+        }
+
+        Ok(samples)
+    }
+
+    /// Reading `/proc/meminfo` doesn't block.
+    fn blocking(&self) -> bool {
+        false
+    }
+}
+
+pub fn input() -> Result<Box<Input>> {
+    Ok(Box::new(MemoryInput {}))
+}
@@ -1,10 +1,32 @@
 mod cpu;
 mod disk;
+mod exec;
 mod load;
+mod memory;
+mod net;
+mod process;
+mod swap;
+mod uptime;
 #[cfg(feature = "http")]
 mod http_poller;
+#[cfg(feature = "http")]
+mod influxdb;
 mod debug;
+mod dotted;
+mod graphite;
+#[cfg(feature = "kafka")]
+mod kafka;
 mod snoop;
+mod prometheus;
+#[cfg(feature = "http")]
+mod pushgateway;
+mod statsd;
+mod stdout;
+mod tcp_check;
+mod thermal;
+mod timeout;
+#[cfg(feature = "http")]
+mod tls;
 
 use plugin::*;
 use std::collections::HashMap;
@@ -13,15 +35,32 @@ pub fn load_plugins() -> PluginRegistry {
     let mut input: HashMap<String, InputEntry> = HashMap::new();
 
     input.insert("disk".to_owned(), disk::input);
+    input.insert("exec".to_owned(), exec::input);
     input.insert("cpu".to_owned(), cpu::input);
     input.insert("load".to_owned(), load::input);
+    input.insert("memory".to_owned(), memory::input);
+    input.insert("net".to_owned(), net::input);
+    input.insert("process".to_owned(), process::input);
+    input.insert("swap".to_owned(), swap::input);
+    input.insert("tcp_check".to_owned(), tcp_check::input);
+    input.insert("thermal".to_owned(), thermal::input);
+    input.insert("uptime".to_owned(), uptime::input);
 
+    #[cfg(feature = "http")] input.insert("http".to_owned(), http_poller::input);
     #[cfg(feature = "http")] input.insert("http_poller".to_owned(), http_poller::input);
 
     let mut output: HashMap<String, OutputEntry> = HashMap::new();
 
     output.insert("debug".to_owned(), debug::output);
+    output.insert("graphite".to_owned(), graphite::output);
     output.insert("snoop".to_owned(), snoop::output);
+    output.insert("prometheus".to_owned(), prometheus::output);
+    output.insert("statsd".to_owned(), statsd::output);
+    output.insert("stdout".to_owned(), stdout::output);
+
+    #[cfg(feature = "http")] output.insert("influxdb".to_owned(), influxdb::output);
+    #[cfg(feature = "http")] output.insert("pushgateway".to_owned(), pushgateway::output);
+    #[cfg(feature = "kafka")] output.insert("kafka".to_owned(), kafka::output);
 
     PluginRegistry::new(input, output)
 }
@@ -0,0 +1,345 @@
+//! Input plugin that reports traffic rates of network interfaces from /proc/net/dev.
+
+use metric::*;
+use plugin::*;
+use errors::*;
+use parsers::netdev::*;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::u32;
+use serde_json;
+
+const PROC_NET_DEV: &'static str = "/proc/net/dev";
+
+#[derive(Deserialize, Debug)]
+struct NetInputConfig {
+    /// Interfaces to report on, defaults to all interfaces.
+    interfaces: Option<Vec<String>>,
+    /// Interfaces to skip.
+    exclude_interfaces: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+struct NetInput {}
+
+impl Input for NetInput {
+    fn setup(&self, ctx: PluginContext) -> Result<Box<InputInstance>> {
+        let config: NetInputConfig = ctx.decode_config()?;
+
+        Ok(Box::new(NetInputInstance {
+            interfaces: config.interfaces,
+            exclude_interfaces: config.exclude_interfaces.unwrap_or_default(),
+            clock: ctx.clock,
+            state: Mutex::new(NetState {
+                previous: HashMap::new(),
+                metric_ids: HashMap::new(),
+            }),
+        }))
+    }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some(
+            "# interfaces to report on, defaults to all interfaces.\n\
+             interfaces = [\"eth*\"]\n\
+             exclude_interfaces = [\"lo\", \"veth*\"]",
+        )
+    }
+}
+
+/// Counters read during a poll, together with the time they were read.
+struct Reading {
+    timestamp: SystemTime,
+    instant: Instant,
+    dev: NetDev,
+}
+
+/// A reading as handed over to the instance replacing this one on reload.
+///
+/// Instants can't be serialized, so the monotonic time of the reading is reconstructed from how
+/// long ago it was read.
+#[derive(Serialize, Deserialize, Debug)]
+struct SavedReading {
+    /// Milliseconds since the unix epoch.
+    timestamp: u64,
+    /// Milliseconds since the reading was saved.
+    age: u64,
+    dev: NetDev,
+}
+
+fn as_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + duration.subsec_nanos() as u64 / 1_000_000
+}
+
+/// Metric ids keyed by interface and counter.
+type MetricIds = HashMap<(String, &'static str), Arc<MetricId>>;
+
+struct NetState {
+    /// Counters from the previous poll, keyed by interface.
+    previous: HashMap<String, Reading>,
+    /// Cached metric ids, keyed by interface and counter.
+    metric_ids: MetricIds,
+}
+
+struct NetInputInstance {
+    interfaces: Option<Vec<String>>,
+    exclude_interfaces: Vec<String>,
+    clock: Clock,
+    state: Mutex<NetState>,
+}
+
+impl fmt::Debug for NetInputInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NetInputInstance")
+    }
+}
+
+/// Match an interface name against a pattern, where a trailing `*` matches any suffix.
+fn matches(pattern: &str, interface: &str) -> bool {
+    if pattern.ends_with('*') {
+        return interface.starts_with(&pattern[..pattern.len() - 1]);
+    }
+
+    pattern == interface
+}
+
+/// Difference between two readings of a counter.
+///
+/// A counter which went backwards from a value close to the end of the 32-bit range is assumed to
+/// have wrapped around, since some kernels and drivers only keep 32-bit counters. Otherwise the
+/// counter was reset, like when a driver is reloaded, and no difference can be computed.
+fn counter_delta(next: u64, prev: u64) -> Option<u64> {
+    if next >= prev {
+        return Some(next - prev);
+    }
+
+    if prev > u32::MAX as u64 / 2 && prev <= u32::MAX as u64 && next <= u32::MAX as u64 {
+        return Some((u32::MAX as u64 - prev) + next + 1);
+    }
+
+    None
+}
+
+fn counters(dev: &NetDev) -> Vec<(&'static str, &'static str, u64)> {
+    vec![
+        ("net-rx-bytes", "B/s", dev.rx_bytes),
+        ("net-rx-packets", "packets/s", dev.rx_packets),
+        ("net-rx-errors", "errors/s", dev.rx_errors),
+        ("net-rx-drops", "packets/s", dev.rx_drops),
+        ("net-tx-bytes", "B/s", dev.tx_bytes),
+        ("net-tx-packets", "packets/s", dev.tx_packets),
+        ("net-tx-errors", "errors/s", dev.tx_errors),
+        ("net-tx-drops", "packets/s", dev.tx_drops),
+    ]
+}
+
+/// Counters reported as they are, next to their rates.
+fn totals(dev: &NetDev) -> Vec<(&'static str, &'static str, u64)> {
+    vec![
+        ("net-rx-bytes-total", "B", dev.rx_bytes),
+        ("net-rx-packets-total", "packets", dev.rx_packets),
+        ("net-rx-errors-total", "errors", dev.rx_errors),
+        ("net-rx-drops-total", "packets", dev.rx_drops),
+        ("net-tx-bytes-total", "B", dev.tx_bytes),
+        ("net-tx-packets-total", "packets", dev.tx_packets),
+        ("net-tx-errors-total", "errors", dev.tx_errors),
+        ("net-tx-drops-total", "packets", dev.tx_drops),
+    ]
+}
+
+/// Cached metric id of a counter of an interface.
+fn counter_id(
+    ids: &mut MetricIds,
+    interface: &str,
+    what: &'static str,
+    unit: &str,
+) -> Arc<MetricId> {
+    ids.entry((interface.to_owned(), what))
+        .or_insert_with(|| {
+            Arc::new(
+                key("system")
+                    .tag("what", what)
+                    .tag("interface", interface)
+                    .tag("unit", unit)
+                    .build(),
+            )
+        })
+        .clone()
+}
+
+fn as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+impl NetInputInstance {
+    fn is_included(&self, interface: &str) -> bool {
+        let included = match self.interfaces {
+            Some(ref interfaces) => interfaces.iter().any(|p| matches(p, interface)),
+            None => true,
+        };
+
+        included && !self.exclude_interfaces.iter().any(|p| matches(p, interface))
+    }
+
+    fn elapsed(&self, next: &Reading, prev: &Reading) -> Option<Duration> {
+        match self.clock {
+            Clock::Wall => next.timestamp.duration_since(prev.timestamp).ok(),
+            Clock::Monotonic => Some(next.instant.duration_since(prev.instant)),
+        }
+    }
+}
+
+impl PluginState for NetInputInstance {
+    fn save(&self) -> Result<Option<serde_json::Value>> {
+        let state = self.state.lock()?;
+
+        let saved: Vec<SavedReading> = state
+            .previous
+            .values()
+            .map(|reading| {
+                SavedReading {
+                    timestamp: as_millis(
+                        reading.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default(),
+                    ),
+                    age: as_millis(reading.instant.elapsed()),
+                    dev: reading.dev.clone(),
+                }
+            })
+            .collect();
+
+        Ok(Some(serde_json::to_value(&saved)))
+    }
+
+    fn restore(&self, saved: serde_json::Value) -> Result<()> {
+        let saved: Vec<SavedReading> = serde_json::from_value(saved)?;
+        let now = Instant::now();
+        let mut state = self.state.lock()?;
+
+        for reading in saved {
+            let reading = Reading {
+                timestamp: UNIX_EPOCH + Duration::from_millis(reading.timestamp),
+                instant: now - Duration::from_millis(reading.age),
+                dev: reading.dev,
+            };
+
+            state.previous.insert(reading.dev.interface.clone(), reading);
+        }
+
+        Ok(())
+    }
+}
+
+impl InputInstance for NetInputInstance {
+    /// Diff the current counters against the ones read during the previous poll.
+    ///
+    /// Counters are reported as they are on every poll, rates only once an interface has been read
+    /// twice.
+    fn poll(&self) -> Result<Samples> {
+        let mut content = String::new();
+
+        File::open(PROC_NET_DEV)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .chain_err(|| format!("could not read {}", PROC_NET_DEV))?;
+
+        let timestamp = SystemTime::now();
+        let instant = Instant::now();
+
+        let mut guard = self.state.lock()?;
+        let state = &mut *guard;
+        let mut samples = Vec::new();
+        let mut previous = HashMap::new();
+
+        for dev in parse_netdev(&content) {
+            if !self.is_included(&dev.interface) {
+                continue;
+            }
+
+            let next = Reading {
+                timestamp: timestamp,
+                instant: instant,
+                dev: dev,
+            };
+
+            for (what, unit, value) in totals(&next.dev) {
+                let metric_id = counter_id(&mut state.metric_ids, &next.dev.interface, what, unit);
+                samples.push(Sample::new(metric_id, value as f64).with_kind(SampleKind::Counter));
+            }
+
+            let elapsed = state.previous.get(&next.dev.interface).and_then(|prev| {
+                self.elapsed(&next, prev).map(|elapsed| (prev, elapsed))
+            });
+
+            if let Some((prev, elapsed)) = elapsed {
+                let secs = as_secs_f64(elapsed);
+
+                if secs > 0f64 {
+                    let prev = counters(&prev.dev);
+
+                    for (&(what, unit, value), &(_, _, prev)) in
+                        counters(&next.dev).iter().zip(prev.iter())
+                    {
+                        let delta = match counter_delta(value, prev) {
+                            Some(delta) => delta,
+                            None => continue,
+                        };
+
+                        let interface = &next.dev.interface;
+                        let metric_id = counter_id(&mut state.metric_ids, interface, what, unit);
+                        samples.push(Sample::new(metric_id, delta as f64 / secs));
+                    }
+                }
+            }
+
+            previous.insert(next.dev.interface.clone(), next);
+        }
+
+        // interfaces which have disappeared are forgotten.
+        state.previous = previous;
+
+        let previous = &state.previous;
+        state.metric_ids.retain(|&(ref interface, _), _| {
+            previous.contains_key(interface)
+        });
+
+        Ok(samples)
+    }
+
+    fn state(&self) -> Option<&PluginState> {
+        Some(self)
+    }
+
+    /// Reading `/proc/net/dev` doesn't block.
+    fn blocking(&self) -> bool {
+        false
+    }
+}
+
+pub fn input() -> Result<Box<Input>> {
+    Ok(Box::new(NetInput {}))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_counter_delta() {
+        assert_eq!(Some(10), counter_delta(110, 100));
+        assert_eq!(Some(11), counter_delta(5, u32::MAX as u64 - 5));
+        assert_eq!(None, counter_delta(5, u32::MAX as u64 + 100));
+        // small counters going backwards were reset, rather than wrapped around.
+        assert_eq!(None, counter_delta(5, 1000));
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("lo", "lo"));
+        assert!(matches("veth*", "veth1234"));
+        assert!(!matches("veth*", "eth0"));
+        assert!(!matches("eth", "eth0"));
+    }
+}
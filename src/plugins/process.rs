@@ -0,0 +1,279 @@
+//! Input plugin that reports resource usage of specific processes.
+
+use metric::*;
+use plugin::*;
+use errors::*;
+use parsers::meminfo::*;
+use parsers::pidstat::*;
+
+use libc;
+use std::fs::{self, File};
+use std::io::Read;
+use std::sync::Arc;
+
+#[derive(Deserialize, Debug)]
+struct ProcessInputConfig {
+    /// File containing the pid of the process.
+    pid_file: Option<String>,
+    /// Name of the executable to match processes against.
+    process: Option<String>,
+    /// Sum the usage of all matching processes, instead of reporting each process.
+    aggregate: Option<bool>,
+}
+
+#[derive(Debug)]
+enum Matcher {
+    PidFile(String),
+    Name(String),
+}
+
+#[derive(Debug)]
+struct ProcessInput {}
+
+impl Input for ProcessInput {
+    fn setup(&self, ctx: PluginContext) -> Result<Box<InputInstance>> {
+        let config: ProcessInputConfig = ctx.decode_config()?;
+
+        let matcher = match (config.pid_file, config.process) {
+            (Some(pid_file), None) => Matcher::PidFile(pid_file),
+            (None, Some(process)) => Matcher::Name(process),
+            _ => {
+                return Err(
+                    ErrorKind::InvalidPluginConfig(
+                        "pid_file".to_owned(),
+                        "exactly one of pid_file or process must be set".to_owned(),
+                    ).into(),
+                );
+            }
+        };
+
+        let label = match matcher {
+            Matcher::PidFile(ref pid_file) => pid_file.clone(),
+            Matcher::Name(ref process) => process.clone(),
+        };
+
+        // the tag keeps the pid file as configured.
+        let matcher = match matcher {
+            Matcher::PidFile(pid_file) => Matcher::PidFile(ctx.resolve_path(&pid_file)),
+            matcher => matcher,
+        };
+
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+
+        Ok(Box::new(ProcessInputInstance {
+            matcher: matcher,
+            aggregate: config.aggregate.unwrap_or(true),
+            base: key("system").tag("process", &label),
+            ticks_per_second: if ticks > 0 { ticks as f64 } else { 100f64 },
+        }))
+    }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some(
+            "# either pid_file or process has to be set.\n\
+             pid_file = \"/var/run/nginx.pid\"\n\
+             # process = \"nginx\"\n\
+             aggregate = true",
+        )
+    }
+}
+
+/// Resource usage of a single process.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Usage {
+    /// Time spent on cpu, in seconds.
+    cpu_time: f64,
+    rss: u64,
+    vsz: u64,
+    fds: u64,
+}
+
+impl Usage {
+    fn add(&mut self, other: &Usage) {
+        self.cpu_time += other.cpu_time;
+        self.rss += other.rss;
+        self.vsz += other.vsz;
+        self.fds += other.fds;
+    }
+}
+
+#[derive(Debug)]
+struct ProcessInputInstance {
+    matcher: Matcher,
+    aggregate: bool,
+    base: MetricIdBuilder,
+    ticks_per_second: f64,
+}
+
+fn read_to_string(path: &str) -> Result<String> {
+    let mut content = String::new();
+
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut content))
+        .chain_err(|| format!("could not read {}", path))?;
+
+    Ok(content)
+}
+
+/// All pids which currently exist.
+fn all_pids() -> Result<Vec<u32>> {
+    let mut pids = Vec::new();
+
+    for entry in fs::read_dir("/proc").chain_err(|| "could not list /proc")? {
+        if let Some(pid) = entry?.file_name().to_str().and_then(|n| n.parse().ok()) {
+            pids.push(pid);
+        }
+    }
+
+    Ok(pids)
+}
+
+impl ProcessInputInstance {
+    /// Find the pids of processes to report on.
+    ///
+    /// A process which is not running is not an error, so that it can be reported as down.
+    fn find_pids(&self) -> Result<Vec<u32>> {
+        match self.matcher {
+            Matcher::PidFile(ref pid_file) => {
+                let pid = read_to_string(pid_file).ok().and_then(
+                    |c| c.trim().parse::<u32>().ok(),
+                );
+
+                Ok(pid.into_iter().collect())
+            }
+            Matcher::Name(ref name) => {
+                let mut pids = Vec::new();
+
+                for pid in all_pids()? {
+                    let stat = match read_to_string(&format!("/proc/{}/stat", pid)) {
+                        Ok(stat) => stat,
+                        // the process exited after the directory was listed.
+                        Err(_) => continue,
+                    };
+
+                    if parse_pid_stat(&stat).map(|s| s.comm == *name).unwrap_or(false) {
+                        pids.push(pid);
+                    }
+                }
+
+                Ok(pids)
+            }
+        }
+    }
+
+    fn read_usage(&self, pid: u32) -> Result<Usage> {
+        let stat = parse_pid_stat(&read_to_string(&format!("/proc/{}/stat", pid))?)?;
+        let status = parse_meminfo(&read_to_string(&format!("/proc/{}/status", pid))?);
+
+        let fds = fs::read_dir(format!("/proc/{}/fd", pid))
+            .map(|entries| entries.count() as u64)
+            .unwrap_or(0);
+
+        Ok(Usage {
+            cpu_time: (stat.utime + stat.stime) as f64 / self.ticks_per_second,
+            rss: status.get("VmRSS").cloned().unwrap_or(0),
+            vsz: status.get("VmSize").cloned().unwrap_or(0),
+            fds: fds,
+        })
+    }
+
+    fn samples(&self, base: &MetricIdBuilder, usage: &Usage) -> Vec<Sample> {
+        let sample = |what: &str, unit: Option<&str>, value: f64| {
+            let mut builder = base.clone().tag("what", what);
+
+            if let Some(unit) = unit {
+                builder = builder.tag("unit", unit);
+            }
+
+            Sample::new(Arc::new(builder.build()), value)
+        };
+
+        vec![
+            sample("process-cpu-time", Some("s"), usage.cpu_time),
+            sample("process-rss", Some("B"), usage.rss as f64),
+            sample("process-vsz", Some("B"), usage.vsz as f64),
+            sample("process-fds", None, usage.fds as f64),
+        ]
+    }
+
+    fn up(&self, base: &MetricIdBuilder, up: bool) -> Sample {
+        let metric_id = base.clone().tag("what", "process-up").build();
+        Sample::new(Arc::new(metric_id), if up { 1f64 } else { 0f64 })
+    }
+}
+
+impl InputInstance for ProcessInputInstance {
+    fn poll(&self) -> Result<Samples> {
+        let mut usages = Vec::new();
+
+        for pid in self.find_pids()? {
+            // processes can exit at any time, those are skipped.
+            if let Ok(usage) = self.read_usage(pid) {
+                usages.push((pid, usage));
+            }
+        }
+
+        let mut samples = Vec::new();
+
+        if usages.is_empty() {
+            samples.push(self.up(&self.base, false));
+            return Ok(samples);
+        }
+
+        if self.aggregate {
+            let mut total = Usage::default();
+
+            for &(_, ref usage) in usages.iter() {
+                total.add(usage);
+            }
+
+            let count = self.base.clone().tag("what", "process-count").build();
+            samples.push(self.up(&self.base, true));
+            samples.push(Sample::new(Arc::new(count), usages.len() as f64));
+            samples.extend(self.samples(&self.base, &total));
+            return Ok(samples);
+        }
+
+        for &(pid, ref usage) in usages.iter() {
+            let base = self.base.clone().tag("pid", &pid.to_string());
+            samples.push(self.up(&base, true));
+            samples.extend(self.samples(&base, usage));
+        }
+
+        Ok(samples)
+    }
+}
+
+pub fn input() -> Result<Box<Input>> {
+    Ok(Box::new(ProcessInput {}))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_usage_add() {
+        let mut total = Usage::default();
+
+        let usage = Usage {
+            cpu_time: 1.5,
+            rss: 1024,
+            vsz: 4096,
+            fds: 3,
+        };
+
+        total.add(&usage);
+        total.add(&usage);
+
+        assert_eq!(
+            Usage {
+                cpu_time: 3.0,
+                rss: 2048,
+                vsz: 8192,
+                fds: 6,
+            },
+            total
+        );
+    }
+}
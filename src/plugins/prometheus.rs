@@ -0,0 +1,605 @@
+//! Output plugin that exposes samples in the Prometheus text exposition format, or in the
+//! OpenMetrics text format.
+
+use errors::*;
+use plugin::*;
+use metric::*;
+use health::Health;
+use http::{self, Request, Response};
+use serde_json;
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const DEFAULT_PORT: u16 = 9105;
+const DEFAULT_STALE_SECS: u64 = 300;
+
+#[derive(Deserialize, Debug)]
+struct PrometheusOutputConfig {
+    address: Option<IpAddr>,
+    port: Option<u16>,
+    format: Option<String>,
+}
+
+#[derive(Debug)]
+struct PrometheusOutput {}
+
+/// Format metrics are exposed in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// The Prometheus text exposition format, version 0.0.4.
+    Prometheus,
+    /// The OpenMetrics text format, version 1.0.0.
+    OpenMetrics,
+}
+
+impl Format {
+    fn parse(format: &str) -> Option<Format> {
+        match format {
+            "prometheus" => Some(Format::Prometheus),
+            "openmetrics" => Some(Format::OpenMetrics),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match *self {
+            Format::Prometheus => "text/plain; version=0.0.4",
+            Format::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        }
+    }
+}
+
+/// Latest sample of a metric, as it is exposed.
+#[derive(Debug, Clone)]
+pub struct Latest {
+    pub value: f64,
+    pub kind: SampleKind,
+    pub meta: Option<Arc<SampleMeta>>,
+    /// When the sample was last fed.
+    pub seen: Instant,
+}
+
+impl Latest {
+    pub fn of(sample: &Sample) -> Latest {
+        Latest {
+            value: sample.value,
+            kind: sample.kind,
+            meta: sample.meta.clone(),
+            seen: Instant::now(),
+        }
+    }
+}
+
+type Snapshot = Arc<Mutex<BTreeMap<Arc<MetricId>, Latest>>>;
+
+/// Forget every series which hasn't been fed since `stale` before `now`.
+///
+/// Series of a metric which went away, like a process which exited, would otherwise be exposed
+/// with their last value forever.
+fn expire(snapshot: &mut BTreeMap<Arc<MetricId>, Latest>, stale: Duration, now: Instant) {
+    let expired: Vec<_> = snapshot
+        .iter()
+        .filter(|&(_, latest)| now.duration_since(latest.seen) > stale)
+        .map(|(metric_id, _)| metric_id.clone())
+        .collect();
+
+    for metric_id in expired {
+        snapshot.remove(&metric_id);
+    }
+}
+
+impl Output for PrometheusOutput {
+    fn setup(&self, ctx: PluginContext) -> Result<Box<OutputInstance>> {
+        let config: PrometheusOutputConfig = ctx.decode_config()?;
+
+        let default_address = "0.0.0.0".parse::<IpAddr>().map_err(|e| {
+            ErrorKind::Message(e.to_string())
+        })?;
+
+        let addr = SocketAddr::new(
+            config.address.unwrap_or(default_address),
+            config.port.unwrap_or(DEFAULT_PORT),
+        );
+
+        let format = match config.format {
+            Some(format) => {
+                Format::parse(&format).ok_or_else(|| {
+                    ErrorKind::InvalidPluginConfig(
+                        "format".to_owned(),
+                        format!("expected prometheus or openmetrics but got {:?}", format),
+                    )
+                })?
+            }
+            None => Format::Prometheus,
+        };
+
+        let stale = ctx.decode_duration("stale")?.unwrap_or(
+            Duration::new(DEFAULT_STALE_SECS, 0),
+        );
+
+        let snapshot: Snapshot = Arc::new(Mutex::new(BTreeMap::new()));
+
+        let handler_snapshot = snapshot.clone();
+        let health = ctx.health.clone();
+
+        let handler = Rc::new(move |request: &Request| match request.path.as_str() {
+            "/metrics" => {
+                match handler_snapshot.lock() {
+                    Ok(mut snapshot) => {
+                        expire(&mut snapshot, stale, Instant::now());
+
+                        Response::new(
+                            200,
+                            format.content_type(),
+                            render_with(&snapshot, format).into_bytes(),
+                        )
+                    }
+                    Err(_) => Response::text(500, "snapshot poisoned\n"),
+                }
+            }
+            "/health" => health_response(&health),
+            "/status" => status_response(&health),
+            _ => Response::not_found(),
+        });
+
+        let server = http::serve(&ctx.handle, &addr, handler)?;
+
+        info!("{}: exposing metrics on http://{}/metrics", ctx.name, addr);
+
+        Ok(Box::new(PrometheusOutputInstance {
+            snapshot: snapshot,
+            server: server,
+        }))
+    }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some(
+            "address = \"0.0.0.0\"\nport = 9105\nformat = \"prometheus\"\nstale = \"5m\"",
+        )
+    }
+}
+
+struct PrometheusOutputInstance {
+    snapshot: Snapshot,
+    server: http::Server,
+}
+
+impl OutputInstance for PrometheusOutputInstance {
+    fn feed(&self, sample: &Sample) -> Result<()> {
+        let mut snapshot = self.snapshot.lock()?;
+        snapshot.insert(sample.metric_id.clone(), Latest::of(sample));
+        Ok(())
+    }
+
+    /// Stop serving, so that the port is released.
+    fn teardown(&self) -> Result<()> {
+        self.server.stop();
+        Ok(())
+    }
+
+    fn exclusive(&self) -> bool {
+        true
+    }
+}
+
+/// Readiness probe, failing when any critical instance is unhealthy.
+fn health_response(health: &Health) -> Response {
+    match health.failing() {
+        Ok(ref failing) if failing.is_empty() => Response::text(200, "ok\n"),
+        Ok(failing) => Response::text(503, &format!("failing: {}\n", failing.join(", "))),
+        Err(e) => Response::text(500, &format!("{}\n", e)),
+    }
+}
+
+/// Health of every instance, as JSON.
+fn status_response(health: &Health) -> Response {
+    let body = health.status().and_then(|status| {
+        serde_json::to_vec(&status).map_err(Into::into)
+    });
+
+    match body {
+        Ok(body) => Response::new(200, "application/json", body),
+        Err(e) => Response::text(500, &format!("{}\n", e)),
+    }
+}
+
+/// Sanitize a metric name to only contain `[a-zA-Z0-9_:]`.
+fn sanitize_name(name: &str) -> String {
+    let mut out: String = name.chars()
+        .map(|c| match c {
+            'a'...'z' | 'A'...'Z' | '0'...'9' | '_' | ':' => c,
+            _ => '_',
+        })
+        .collect();
+
+    if out.chars().next().map(|c| c.is_digit(10)).unwrap_or(true) {
+        out.insert(0, '_');
+    }
+
+    out
+}
+
+/// Sanitize a label name to only contain `[a-zA-Z0-9_]`.
+fn sanitize_label(name: &str) -> String {
+    sanitize_name(name).replace(':', "_")
+}
+
+/// Escape the text of a `# HELP` line.
+fn escape_help(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        return "NaN".to_owned();
+    }
+
+    if value.is_infinite() {
+        return if value > 0f64 { "+Inf" } else { "-Inf" }.to_owned();
+    }
+
+    value.to_string()
+}
+
+/// Render a single line of the exposition format.
+fn render_line(metric_id: &MetricId, value: f64) -> String {
+    let name = sanitize_name(metric_id.key().unwrap_or("sysmon"));
+    render_named(&name, metric_id, value)
+}
+
+/// Render a single line of the exposition format, under the given name.
+fn render_named(name: &str, metric_id: &MetricId, value: f64) -> String {
+    let labels: Vec<String> = metric_id
+        .tags()
+        .iter()
+        .chain(metric_id.resource().iter())
+        .map(|&(ref k, ref v)| {
+            format!("{}=\"{}\"", sanitize_label(k), escape_label_value(v))
+        })
+        .collect();
+
+    if labels.is_empty() {
+        return format!("{} {}\n", name, format_value(value));
+    }
+
+    format!("{}{{{}}} {}\n", name, labels.join(","), format_value(value))
+}
+
+/// Prometheus type of a family of samples sharing a name.
+///
+/// A family mixing gauges and counters, which happens when they share a key, is untyped.
+fn family_type<'a, I>(kinds: I) -> &'static str
+where
+    I: IntoIterator<Item = &'a SampleKind>,
+{
+    let mut kinds = kinds.into_iter();
+
+    let first = match kinds.next() {
+        Some(first) => *first,
+        None => return "untyped",
+    };
+
+    if !kinds.all(|kind| *kind == first) {
+        return "untyped";
+    }
+
+    match first {
+        SampleKind::Counter => "counter",
+        SampleKind::Gauge | SampleKind::Derive => "gauge",
+        SampleKind::Histogram => "histogram",
+        SampleKind::Observation => "untyped",
+    }
+}
+
+/// Name of the family a sample belongs to.
+///
+/// The `_bucket`, `_sum`, and `_count` series of a histogram belong to the family of the histogram.
+/// In OpenMetrics, the `_total` suffix of a counter is not part of the name of its family.
+fn family_name(metric_id: &MetricId, kind: SampleKind, format: Format) -> String {
+    let name = sanitize_name(metric_id.key().unwrap_or("sysmon"));
+
+    let suffixes: &[&str] = match (kind, format) {
+        (SampleKind::Histogram, _) => &["_bucket", "_sum", "_count"],
+        (SampleKind::Counter, Format::OpenMetrics) => &["_total"],
+        _ => return name,
+    };
+
+    for suffix in suffixes {
+        if name.ends_with(suffix) {
+            return name[..name.len() - suffix.len()].to_owned();
+        }
+    }
+
+    name
+}
+
+/// Help text of a family, taken from the first sample which has a description.
+fn family_help<'a, I>(latest: I) -> Option<String>
+where
+    I: IntoIterator<Item = &'a Latest>,
+{
+    latest
+        .into_iter()
+        .filter_map(|l| l.meta.as_ref())
+        .filter_map(|m| m.description.as_ref())
+        .next()
+        .map(|description| escape_help(description))
+}
+
+/// Unit of a family, taken from the first sample which has one.
+fn family_unit<'a, I>(latest: I) -> Option<String>
+where
+    I: IntoIterator<Item = &'a Latest>,
+{
+    latest
+        .into_iter()
+        .filter_map(|l| l.meta.as_ref())
+        .filter_map(|m| m.unit.as_ref())
+        .next()
+        .map(|unit| sanitize_label(unit))
+}
+
+/// Render the latest value of every sample in the text exposition format.
+pub fn render(snapshot: &BTreeMap<Arc<MetricId>, Latest>) -> String {
+    render_with(snapshot, Format::Prometheus)
+}
+
+/// Render the latest value of every sample in the given format.
+///
+/// In OpenMetrics, a family with a unit has to be named after it, so the unit is appended to names
+/// which don't end with it. Samples of a counter get the `_total` suffix, and the exposition is
+/// terminated by `# EOF`.
+pub fn render_with(snapshot: &BTreeMap<Arc<MetricId>, Latest>, format: Format) -> String {
+    // samples are sorted by key, but sanitizing can make different keys the same name.
+    let mut families: BTreeMap<String, Vec<(&MetricId, &Latest)>> = BTreeMap::new();
+
+    for (metric_id, latest) in snapshot.iter() {
+        let name = family_name(metric_id, latest.kind, format);
+        families.entry(name).or_insert_with(Vec::new).push((metric_id, latest));
+    }
+
+    let mut out = String::new();
+
+    for (base, samples) in families {
+        let kind = family_type(samples.iter().map(|&(_, latest)| &latest.kind));
+
+        let unit = match format {
+            Format::OpenMetrics => family_unit(samples.iter().map(|&(_, latest)| latest)),
+            Format::Prometheus => None,
+        };
+
+        let name = match unit {
+            Some(ref unit) if !base.ends_with(&format!("_{}", unit)) => {
+                format!("{}_{}", base, unit)
+            }
+            _ => base.clone(),
+        };
+
+        if let Some(help) = family_help(samples.iter().map(|&(_, latest)| latest)) {
+            let help = match format {
+                Format::OpenMetrics => help.replace('"', "\\\""),
+                Format::Prometheus => help,
+            };
+
+            out.push_str(&format!("# HELP {} {}\n", name, help));
+        }
+
+        let kind = match (format, kind) {
+            (Format::OpenMetrics, "untyped") => "unknown",
+            (_, kind) => kind,
+        };
+
+        out.push_str(&format!("# TYPE {} {}\n", name, kind));
+
+        if let Some(unit) = unit {
+            out.push_str(&format!("# UNIT {} {}\n", name, unit));
+        }
+
+        for (metric_id, latest) in samples {
+            let sanitized = sanitize_name(metric_id.key().unwrap_or("sysmon"));
+
+            // the family name is a prefix of every name in it, like `_bucket` of a histogram.
+            let suffix = match (format, kind) {
+                (Format::OpenMetrics, "counter") => "_total",
+                _ => &sanitized[base.len()..],
+            };
+
+            let series = format!("{}{}", name, suffix);
+            out.push_str(&render_named(&series, metric_id, latest.value));
+        }
+    }
+
+    if format == Format::OpenMetrics {
+        out.push_str("# EOF\n");
+    }
+
+    out
+}
+
+pub fn output() -> Result<Box<Output>> {
+    Ok(Box::new(PrometheusOutput {}))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!("cpu_used", sanitize_name("cpu-used"));
+        assert_eq!("system:load", sanitize_name("system:load"));
+        assert_eq!("_1m", sanitize_name("1m"));
+        assert_eq!("host_name", sanitize_label("host:name"));
+    }
+
+    #[test]
+    fn test_render_line() {
+        let id = key("system").tag("what", "cpu-used").tag("unit", "%").build();
+        assert_eq!(
+            "system{what=\"cpu-used\",unit=\"%\"} 0.5\n",
+            render_line(&id, 0.5f64)
+        );
+
+        let id = key("uptime").build();
+        assert_eq!("uptime NaN\n", render_line(&id, ::std::f64::NAN));
+
+        let id = key("quoted").tag("path", "a\"b").build();
+        assert_eq!("quoted{path=\"a\\\"b\"} 1\n", render_line(&id, 1f64));
+    }
+
+    fn latest(value: f64, kind: SampleKind, meta: Option<SampleMeta>) -> Latest {
+        Latest {
+            value: value,
+            kind: kind,
+            meta: meta.map(Arc::new),
+            seen: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_expire() {
+        let now = Instant::now();
+        let fresh = Arc::new(key("fresh").build());
+        let old = Arc::new(key("old").build());
+
+        let mut snapshot = BTreeMap::new();
+        snapshot.insert(fresh.clone(), latest(1f64, SampleKind::Gauge, None));
+        snapshot.insert(old.clone(), latest(2f64, SampleKind::Gauge, None));
+        snapshot.get_mut(&fresh).unwrap().seen = now;
+
+        expire(&mut snapshot, Duration::new(60, 0), now + Duration::new(30, 0));
+        assert_eq!(2, snapshot.len());
+
+        let expired = now + Duration::new(90, 0);
+        snapshot.get_mut(&fresh).unwrap().seen = expired;
+        expire(&mut snapshot, Duration::new(60, 0), expired);
+        assert_eq!(vec![fresh], snapshot.keys().cloned().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_render() {
+        let mut snapshot = BTreeMap::new();
+        snapshot.insert(
+            Arc::new(key("uptime").build()),
+            latest(10f64, SampleKind::Counter, None),
+        );
+        snapshot.insert(
+            Arc::new(key("system").tag("what", "cpu-used").build()),
+            latest(0.5f64, SampleKind::Gauge, None),
+        );
+        snapshot.insert(
+            Arc::new(key("system").tag("what", "polls").build()),
+            latest(3f64, SampleKind::Counter, None),
+        );
+
+        assert_eq!(
+            "# TYPE system untyped\n\
+             system{what=\"cpu-used\"} 0.5\n\
+             system{what=\"polls\"} 3\n\
+             # TYPE uptime counter\n\
+             uptime 10\n",
+            render(&snapshot)
+        );
+    }
+
+    #[test]
+    fn test_render_histogram() {
+        let mut snapshot = BTreeMap::new();
+
+        for &(key_name, le, value) in &[
+            ("latency_bucket", Some("10"), 1f64),
+            ("latency_bucket", Some("+Inf"), 2f64),
+            ("latency_sum", None, 30f64),
+            ("latency_count", None, 2f64),
+        ]
+        {
+            let id = match le {
+                Some(le) => key(key_name).tag("le", le).build(),
+                None => key(key_name).build(),
+            };
+
+            snapshot.insert(Arc::new(id), latest(value, SampleKind::Histogram, None));
+        }
+
+        assert_eq!(
+            "# TYPE latency histogram\n\
+             latency_bucket{le=\"+Inf\"} 2\n\
+             latency_bucket{le=\"10\"} 1\n\
+             latency_count 2\n\
+             latency_sum 30\n",
+            render(&snapshot)
+        );
+    }
+
+    #[test]
+    fn test_render_openmetrics() {
+        let meta = SampleMeta::new().description("Time since \"boot\"").unit("seconds");
+
+        let mut snapshot = BTreeMap::new();
+        snapshot.insert(
+            Arc::new(key("uptime").build()),
+            latest(10f64, SampleKind::Counter, Some(meta)),
+        );
+        snapshot.insert(
+            Arc::new(key("requests_total").build()),
+            latest(3f64, SampleKind::Counter, None),
+        );
+        snapshot.insert(
+            Arc::new(key("system").tag("what", "cpu-used").build()),
+            latest(0.5f64, SampleKind::Gauge, None),
+        );
+        snapshot.insert(
+            Arc::new(key("mixed").tag("what", "a").build()),
+            latest(1f64, SampleKind::Gauge, None),
+        );
+        snapshot.insert(
+            Arc::new(key("mixed").tag("what", "b").build()),
+            latest(2f64, SampleKind::Counter, None),
+        );
+
+        assert_eq!(
+            "# TYPE mixed unknown\n\
+             mixed{what=\"a\"} 1\n\
+             mixed{what=\"b\"} 2\n\
+             # TYPE requests counter\n\
+             requests_total 3\n\
+             # TYPE system gauge\n\
+             system{what=\"cpu-used\"} 0.5\n\
+             # HELP uptime_seconds Time since \\\"boot\\\"\n\
+             # TYPE uptime_seconds counter\n\
+             # UNIT uptime_seconds seconds\n\
+             uptime_seconds_total 10\n\
+             # EOF\n",
+            render_with(&snapshot, Format::OpenMetrics)
+        );
+    }
+
+    #[test]
+    fn test_render_help() {
+        let meta = SampleMeta::new().description("Time since boot\n\\o/").unit("seconds");
+
+        let mut snapshot = BTreeMap::new();
+        snapshot.insert(
+            Arc::new(key("uptime").build()),
+            latest(10f64, SampleKind::Counter, Some(meta)),
+        );
+
+        assert_eq!(
+            "# HELP uptime Time since boot\\n\\\\o/\n\
+             # TYPE uptime counter\n\
+             uptime 10\n",
+            render(&snapshot)
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Output plugin that pushes samples to a Prometheus Pushgateway, for jobs which can't be scraped.
+
+use errors::*;
+use plugin::*;
+use metric::*;
+
+use plugins::prometheus;
+use plugins::tls::{self, TlsConfig};
+
+use reqwest;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Deserialize, Debug)]
+struct PushgatewayOutputConfig {
+    url: String,
+    job: String,
+    instance: Option<String>,
+    delete_on_shutdown: Option<bool>,
+    tls: Option<TlsConfig>,
+}
+
+#[derive(Debug)]
+struct PushgatewayOutput {}
+
+impl Output for PushgatewayOutput {
+    fn setup(&self, ctx: PluginContext) -> Result<Box<OutputInstance>> {
+        let config: PushgatewayOutputConfig = ctx.decode_config()?;
+
+        for &(field, value) in &[("url", &config.url), ("job", &config.job)] {
+            if value.trim().is_empty() {
+                return Err(
+                    ErrorKind::InvalidPluginConfig(field.to_owned(), "is required".to_owned())
+                        .into(),
+                );
+            }
+        }
+
+        let url = group_url(&config.url, &config.job, config.instance.as_ref())?;
+
+        Ok(Box::new(PushgatewayOutputInstance {
+            id: ctx.name.clone(),
+            client: tls::client(config.tls.as_ref(), ctx.base_dir)?,
+            url: url,
+            delete_on_shutdown: config.delete_on_shutdown.unwrap_or(false),
+            snapshot: Mutex::new(BTreeMap::new()),
+        }))
+    }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some(
+            "url = \"http://localhost:9091\"\n\
+             job = \"sysmon\"\n\
+             instance = \"web-1\"\n\
+             flush_interval = \"15s\"\n\
+             delete_on_shutdown = false",
+        )
+    }
+}
+
+/// URL of the group identified by the job and instance labels.
+fn group_url(base: &str, job: &str, instance: Option<&String>) -> Result<reqwest::Url> {
+    let invalid = |reason: String| ErrorKind::InvalidPluginConfig("url".to_owned(), reason);
+
+    let mut url = reqwest::Url::parse(base).map_err(|e| invalid(e.to_string()))?;
+
+    {
+        let mut segments = url.path_segments_mut().map_err(|_| {
+            invalid(format!("{}: can't be used as a base", base))
+        })?;
+
+        // label values are escaped as path segments.
+        segments.pop_if_empty().extend(&["metrics", "job", job]);
+
+        if let Some(instance) = instance {
+            segments.extend(&["instance", instance]);
+        }
+    }
+
+    Ok(url)
+}
+
+struct PushgatewayOutputInstance {
+    id: String,
+    client: reqwest::Client,
+    url: reqwest::Url,
+    delete_on_shutdown: bool,
+    /// Latest value of every sample, which is pushed as a whole.
+    snapshot: Mutex<BTreeMap<Arc<MetricId>, prometheus::Latest>>,
+}
+
+impl PushgatewayOutputInstance {
+    fn check(&self, what: &str, response: reqwest::Result<reqwest::Response>) -> Result<()> {
+        let status = response.map(|r| r.status()).map_err(|e| {
+            ErrorKind::Message(format!("pushgateway {} failed: {}", what, e))
+        })?;
+
+        if !status.is_success() {
+            return Err(
+                ErrorKind::Message(format!("pushgateway responded with {}", status)).into(),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Push the snapshot, replacing the metrics of the group which share a name with it.
+    fn push(&self) -> Result<()> {
+        let body = {
+            let snapshot = self.snapshot.lock()?;
+
+            if snapshot.is_empty() {
+                return Ok(());
+            }
+
+            prometheus::render(&snapshot)
+        };
+
+        let response = self.client.post(self.url.clone()).body(body).send();
+        self.check("push", response)
+    }
+}
+
+impl OutputInstance for PushgatewayOutputInstance {
+    fn feed(&self, sample: &Sample) -> Result<()> {
+        let mut snapshot = self.snapshot.lock()?;
+        snapshot.insert(sample.metric_id.clone(), prometheus::Latest::of(sample));
+        Ok(())
+    }
+
+    /// Every batch delivered by the framework results in a push.
+    ///
+    /// The push is synchronous, so that failures cause the framework to back off.
+    fn feed_batch(&self, samples: &[Sample]) -> Result<()> {
+        for sample in samples {
+            self.feed(sample)?;
+        }
+
+        self.push()
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.push()
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        if !self.delete_on_shutdown {
+            return Ok(());
+        }
+
+        info!("{}: deleting group {}", self.id, self.url);
+        let response = self.client.delete(self.url.clone()).send();
+        self.check("delete", response)
+    }
+}
+
+pub fn output() -> Result<Box<Output>> {
+    Ok(Box::new(PushgatewayOutput {}))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_group_url() {
+        assert_eq!(
+            "http://localhost:9091/metrics/job/sysmon",
+            group_url("http://localhost:9091", "sysmon", None).unwrap().as_str()
+        );
+
+        let instance = "web 1/a".to_owned();
+        assert_eq!(
+            "http://gw/prefix/metrics/job/sysmon/instance/web%201%2Fa",
+            group_url("http://gw/prefix/", "sysmon", Some(&instance)).unwrap().as_str()
+        );
+
+        assert!(group_url("not a url", "sysmon", None).is_err());
+    }
+}
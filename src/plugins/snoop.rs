@@ -1,6 +1,7 @@
 //! Snoop plugin that exposes metrics on a local socket.
 
 use errors::*;
+use http;
 use plugin::*;
 use metric::*;
 
@@ -8,7 +9,6 @@ use std::collections::HashMap;
 use std::sync::{Mutex, Arc};
 use std::net::SocketAddr;
 use tokio_io::{io, AsyncRead};
-use tokio_core::net;
 use futures::{sync, Future};
 use futures::stream::Stream;
 use std::convert::AsRef;
@@ -33,24 +33,22 @@ impl Output for SnoopOutput {
     fn setup(&self, ctx: PluginContext) -> Result<Box<OutputInstance>> {
         let config: SnoopInputConfig = ctx.decode_config()?;
 
-        let ref mut core = ctx.core.try_borrow_mut()?;
-
         let default_addr = "127.0.0.1:8080".parse::<SocketAddr>().map_err(|e| {
             ErrorKind::Message(e.to_string())
         })?;
 
         let addr = config.bind.unwrap_or(default_addr);
 
-        let handle = core.handle();
+        let handle = ctx.handle.clone();
 
-        let socket = net::TcpListener::bind(&addr, &handle)?;
+        let (server, incoming) = http::listen(&handle, &addr)?;
 
         let connections: Arc<Mutex<HashMap<SocketAddr, Sender>>> =
             Arc::new(Mutex::new(HashMap::new()));
 
         let hello_connections = connections.clone();
 
-        let accept = socket.incoming().map_err(report_and_discard).for_each(
+        let accept = incoming.map_err(report_and_discard).for_each(
             move |(socket, addr)| {
                 info!("connect: {}", addr);
 
@@ -91,18 +89,24 @@ impl Output for SnoopOutput {
             },
         );
 
-        core.handle().spawn(accept);
+        ctx.handle.spawn(accept);
 
         Ok(Box::new(SnoopOutputInstance {
             id: ctx.id.clone(),
             connections: connections.clone(),
+            server: server,
         }))
     }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some("bind = \"127.0.0.1:8080\"")
+    }
 }
 
 struct SnoopOutputInstance {
     id: String,
     connections: Arc<Mutex<HashMap<SocketAddr, Sender>>>,
+    server: http::Server,
 }
 
 #[derive(Serialize)]
@@ -140,6 +144,18 @@ impl OutputInstance for SnoopOutputInstance {
 
         Ok(())
     }
+
+    /// Stop accepting connections, and disconnect the clients.
+    fn teardown(&self) -> Result<()> {
+        self.server.stop();
+        // the writer of a connection completes once its sender is dropped.
+        self.connections.lock()?.clear();
+        Ok(())
+    }
+
+    fn exclusive(&self) -> bool {
+        true
+    }
 }
 
 pub fn output() -> Result<Box<Output>> {
@@ -0,0 +1,192 @@
+//! Output plugin that forwards samples to StatsD over UDP.
+
+use errors::*;
+use plugin::*;
+use metric::*;
+use plugins::dotted::dotted_name;
+
+use std::collections::HashMap;
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex};
+
+const DEFAULT_PORT: u16 = 8125;
+
+#[derive(Deserialize, Debug)]
+struct StatsdOutputConfig {
+    host: String,
+    port: Option<u16>,
+    prefix: Option<String>,
+    /// Map from the `what` tag of a sample to the StatsD type to send it as.
+    types: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StatsdType {
+    Gauge,
+    Counter,
+    Timer,
+}
+
+impl StatsdType {
+    fn parse(value: &str) -> Option<StatsdType> {
+        match value {
+            "gauge" => Some(StatsdType::Gauge),
+            "counter" => Some(StatsdType::Counter),
+            "timer" => Some(StatsdType::Timer),
+            _ => None,
+        }
+    }
+
+    fn suffix(&self) -> &'static str {
+        match *self {
+            StatsdType::Gauge => "g",
+            StatsdType::Counter => "c",
+            StatsdType::Timer => "ms",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct StatsdOutput {}
+
+impl Output for StatsdOutput {
+    fn setup(&self, ctx: PluginContext) -> Result<Box<OutputInstance>> {
+        let config: StatsdOutputConfig = ctx.decode_config()?;
+
+        let mut types = HashMap::new();
+
+        for (what, value) in config.types.unwrap_or_default() {
+            let statsd_type = StatsdType::parse(&value).ok_or_else(|| {
+                ErrorKind::InvalidPluginConfig(
+                    format!("types.{}", what),
+                    format!("expected gauge, counter, or timer but got {:?}", value),
+                )
+            })?;
+
+            types.insert(what, statsd_type);
+        }
+
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect((config.host.as_str(), config.port.unwrap_or(DEFAULT_PORT)))?;
+
+        Ok(Box::new(StatsdOutputInstance {
+            id: ctx.name.clone(),
+            socket: socket,
+            prefix: config.prefix,
+            types: types,
+            totals: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some(
+            "host = \"localhost\"\n\
+             port = 8125\n\
+             prefix = \"sysmon\"\n\
+             types = { \"cpu-used\" = \"gauge\" }",
+        )
+    }
+}
+
+struct StatsdOutputInstance {
+    id: String,
+    socket: UdpSocket,
+    prefix: Option<String>,
+    types: HashMap<String, StatsdType>,
+    /// Last total of every counter sample, since StatsD counters are sent as increments.
+    totals: Mutex<HashMap<Arc<MetricId>, f64>>,
+}
+
+fn what(metric_id: &MetricId) -> Option<&str> {
+    metric_id
+        .tags()
+        .iter()
+        .find(|&&(ref k, _)| k == "what")
+        .map(|&(_, ref v)| v.as_str())
+}
+
+/// Increment of a counter since its last total, or `None` if there is no earlier total.
+///
+/// A total which went down means that the counter was reset, so all of it is the increment.
+fn increment(previous: Option<f64>, total: f64) -> Option<f64> {
+    match previous {
+        Some(previous) if total >= previous => Some(total - previous),
+        Some(_) => Some(total),
+        None => None,
+    }
+}
+
+impl StatsdOutputInstance {
+    /// Build the packet for a sample, or `None` if there is nothing to send yet.
+    ///
+    /// Types configured through `types` send the value as is. Otherwise counter samples, and the
+    /// series of histograms, are sent as StatsD counters of their increment, and everything else
+    /// as gauges.
+    fn packet(&self, sample: &Sample) -> Result<Option<String>> {
+        let configured = what(&sample.metric_id).and_then(|what| self.types.get(what)).cloned();
+
+        let (value, statsd_type) = match (configured, sample.kind) {
+            (Some(statsd_type), _) => (sample.value, statsd_type),
+            (None, SampleKind::Counter) |
+            (None, SampleKind::Histogram) => {
+                let mut totals = self.totals.lock()?;
+                let previous = totals.insert(sample.metric_id.clone(), sample.value);
+
+                match increment(previous, sample.value) {
+                    Some(value) => (value, StatsdType::Counter),
+                    None => return Ok(None),
+                }
+            }
+            (None, _) => (sample.value, StatsdType::Gauge),
+        };
+
+        Ok(Some(format!(
+            "{}:{}|{}",
+            dotted_name(self.prefix.as_ref().map(String::as_str), &sample.metric_id),
+            value,
+            statsd_type.suffix()
+        )))
+    }
+}
+
+impl OutputInstance for StatsdOutputInstance {
+    fn feed(&self, sample: &Sample) -> Result<()> {
+        if !sample.value.is_finite() {
+            return Ok(());
+        }
+
+        let packet = match self.packet(sample)? {
+            Some(packet) => packet,
+            None => return Ok(()),
+        };
+
+        // dropping a datagram is acceptable, so errors are only logged.
+        if let Err(e) = self.socket.send(packet.as_bytes()) {
+            warn!("{}: failed to send to statsd: {}", self.id, e);
+        }
+
+        Ok(())
+    }
+}
+
+pub fn output() -> Result<Box<Output>> {
+    Ok(Box::new(StatsdOutput {}))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_increment() {
+        assert_eq!(None, increment(None, 10f64));
+        assert_eq!(Some(5f64), increment(Some(10f64), 15f64));
+        assert_eq!(Some(3f64), increment(Some(10f64), 3f64));
+    }
+
+    #[test]
+    fn test_parse_type() {
+        assert_eq!(Some(StatsdType::Timer), StatsdType::parse("timer"));
+        assert_eq!(None, StatsdType::parse("histogram"));
+    }
+}
@@ -0,0 +1,185 @@
+//! Output plugin that prints every sample to stdout, useful while developing input plugins.
+
+use errors::*;
+use plugin::*;
+use metric::*;
+
+use serde_json;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use time;
+
+#[derive(Deserialize, Debug)]
+struct StdoutOutputConfig {
+    format: Option<String>,
+}
+
+/// How samples are serialized, shared with other outputs writing one sample per line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Line,
+    Json,
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Option<Format> {
+        match value {
+            "line" => Some(Format::Line),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    /// Serialize a sample in this format.
+    pub fn format(&self, sample: &Sample) -> Result<String> {
+        match *self {
+            Format::Line => Ok(format_line(sample)),
+            Format::Json => format_json(sample),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonSample<'a> {
+    name: &'a str,
+    value: f64,
+    kind: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<&'a str>,
+    tags: BTreeMap<&'a str, &'a str>,
+    timestamp: u64,
+}
+
+#[derive(Debug)]
+struct StdoutOutput {}
+
+impl Output for StdoutOutput {
+    fn setup(&self, ctx: PluginContext) -> Result<Box<OutputInstance>> {
+        let config: StdoutOutputConfig = ctx.decode_config()?;
+
+        let format = match config.format {
+            Some(format) => {
+                Format::parse(&format).ok_or_else(|| {
+                    ErrorKind::InvalidPluginConfig(
+                        "format".to_owned(),
+                        format!("expected line or json but got {:?}", format),
+                    )
+                })?
+            }
+            None => Format::Line,
+        };
+
+        Ok(Box::new(StdoutOutputInstance { format: format }))
+    }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some("# line or json\nformat = \"line\"")
+    }
+}
+
+struct StdoutOutputInstance {
+    format: Format,
+}
+
+fn tags(metric_id: &MetricId) -> Vec<(&str, &str)> {
+    metric_id
+        .tags()
+        .iter()
+        .chain(metric_id.resource().iter())
+        .map(|&(ref k, ref v)| (k.as_str(), v.as_str()))
+        .collect()
+}
+
+/// Format a sample as `<timestamp> <name> <tag>=<value>,... <value>`.
+fn format_line(sample: &Sample) -> String {
+    let nanos = sample.timestamp_nanos();
+    let timespec = time::Timespec::new((nanos / 1_000_000_000) as i64, 0);
+    let timestamp = time::at_utc(timespec).rfc3339().to_string();
+
+    let tags: Vec<String> = tags(&sample.metric_id)
+        .into_iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect();
+
+    format!(
+        "{} {} {} {}",
+        timestamp,
+        sample.metric_id.key().unwrap_or("-"),
+        tags.join(","),
+        sample.value
+    )
+}
+
+fn format_json(sample: &Sample) -> Result<String> {
+    let json = JsonSample {
+        name: sample.metric_id.key().unwrap_or(""),
+        value: sample.value,
+        kind: sample.kind.as_str(),
+        unit: sample.meta.as_ref().and_then(|m| m.unit.as_ref()).map(String::as_str),
+        tags: tags(&sample.metric_id).into_iter().collect(),
+        timestamp: sample.timestamp_nanos() / 1_000_000,
+    };
+
+    Ok(serde_json::to_string(&json)?)
+}
+
+impl OutputInstance for StdoutOutputInstance {
+    fn feed(&self, sample: &Sample) -> Result<()> {
+        let line = self.format.format(sample)?;
+
+        let stdout = io::stdout();
+        writeln!(stdout.lock(), "{}", line)?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        io::stdout().flush()?;
+        Ok(())
+    }
+}
+
+pub fn output() -> Result<Box<Output>> {
+    Ok(Box::new(StdoutOutput {}))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn sample() -> Sample {
+        let id = key("system").tag("what", "cpu-used").tag("unit", "%").build();
+        let mut sample = Sample::new(Arc::new(id), 12.5);
+        sample.timestamp = UNIX_EPOCH + Duration::new(1500000000, 0);
+        sample
+    }
+
+    #[test]
+    fn test_format_line() {
+        assert_eq!(
+            "2017-07-14T02:40:00Z system what=cpu-used,unit=% 12.5",
+            format_line(&sample())
+        );
+    }
+
+    #[test]
+    fn test_format_json() {
+        assert_eq!(
+            "{\"name\":\"system\",\"value\":12.5,\"kind\":\"gauge\",\"tags\":{\"unit\":\"%\",\"what\":\"cpu-used\"},\
+             \"timestamp\":1500000000000}",
+            format_json(&sample()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_format_json_unit() {
+        let sample = sample().with_meta(Arc::new(SampleMeta::new().unit("percent")));
+
+        assert_eq!(
+            "{\"name\":\"system\",\"value\":12.5,\"kind\":\"gauge\",\"unit\":\"percent\",\
+             \"tags\":{\"unit\":\"%\",\"what\":\"cpu-used\"},\"timestamp\":1500000000000}",
+            format_json(&sample).unwrap()
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Input plugin that reports swap usage from /proc/meminfo, and swap activity from /proc/vmstat.
+
+use metric::*;
+use plugin::*;
+use errors::*;
+use parsers::meminfo::*;
+use parsers::vmstat::*;
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+const PROC_MEMINFO: &'static str = "/proc/meminfo";
+const PROC_VMSTAT: &'static str = "/proc/vmstat";
+
+#[derive(Debug)]
+struct SwapInput {}
+
+impl Input for SwapInput {
+    fn setup(&self, ctx: PluginContext) -> Result<Box<InputInstance>> {
+        Ok(Box::new(SwapInputInstance::new(ctx.clock)))
+    }
+}
+
+/// Swap counters read during a poll, together with the time they were read.
+struct Reading {
+    timestamp: SystemTime,
+    instant: Instant,
+    /// Pages swapped in since boot.
+    swap_in: u64,
+    /// Pages swapped out since boot.
+    swap_out: u64,
+}
+
+struct SwapInputInstance {
+    total: Arc<MetricId>,
+    free: Arc<MetricId>,
+    used: Arc<MetricId>,
+    swap_in: Arc<MetricId>,
+    swap_out: Arc<MetricId>,
+    clock: Clock,
+    previous: Mutex<Option<Reading>>,
+}
+
+impl fmt::Debug for SwapInputInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SwapInputInstance")
+    }
+}
+
+fn swap_metric(what: &str, unit: &str) -> Arc<MetricId> {
+    Arc::new(key("system").tag("what", what).tag("unit", unit).build())
+}
+
+fn read_to_string(path: &str) -> Result<String> {
+    let mut content = String::new();
+
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut content))
+        .chain_err(|| format!("could not read {}", path))?;
+
+    Ok(content)
+}
+
+fn as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+impl SwapInputInstance {
+    pub fn new(clock: Clock) -> SwapInputInstance {
+        SwapInputInstance {
+            total: swap_metric("swap-total", "B"),
+            free: swap_metric("swap-free", "B"),
+            used: swap_metric("swap-used", "B"),
+            swap_in: swap_metric("swap-in", "pages/s"),
+            swap_out: swap_metric("swap-out", "pages/s"),
+            clock: clock,
+            previous: Mutex::new(None),
+        }
+    }
+
+    fn elapsed(&self, next: &Reading, prev: &Reading) -> Option<Duration> {
+        match self.clock {
+            Clock::Wall => next.timestamp.duration_since(prev.timestamp).ok(),
+            Clock::Monotonic => Some(next.instant.duration_since(prev.instant)),
+        }
+    }
+}
+
+impl InputInstance for SwapInputInstance {
+    /// Report swap usage, and the rate of swapping since the previous poll.
+    ///
+    /// Rates are not reported until the counters have been read twice.
+    fn poll(&self) -> Result<Samples> {
+        let meminfo = parse_meminfo(&read_to_string(PROC_MEMINFO)?);
+        let vmstat = parse_vmstat(&read_to_string(PROC_VMSTAT)?);
+
+        let mut samples = Vec::new();
+
+        if let (Some(total), Some(free)) = (meminfo.get("SwapTotal"), meminfo.get("SwapFree")) {
+            samples.push(Sample::new(self.total.clone(), *total as f64));
+            samples.push(Sample::new(self.free.clone(), *free as f64));
+            samples.push(Sample::new(
+                self.used.clone(),
+                total.saturating_sub(*free) as f64,
+            ));
+        }
+
+        let next = match (vmstat.get("pswpin"), vmstat.get("pswpout")) {
+            (Some(swap_in), Some(swap_out)) => {
+                Reading {
+                    timestamp: SystemTime::now(),
+                    instant: Instant::now(),
+                    swap_in: *swap_in,
+                    swap_out: *swap_out,
+                }
+            }
+            _ => return Ok(samples),
+        };
+
+        let mut previous = self.previous.lock()?;
+
+        if let Some(ref prev) = *previous {
+            let secs = self.elapsed(&next, prev).map(as_secs_f64).unwrap_or(0f64);
+
+            // counters which went backwards were reset, so no rate can be computed.
+            if secs > 0f64 && next.swap_in >= prev.swap_in && next.swap_out >= prev.swap_out {
+                let swap_in = (next.swap_in - prev.swap_in) as f64 / secs;
+                let swap_out = (next.swap_out - prev.swap_out) as f64 / secs;
+                samples.push(Sample::new(self.swap_in.clone(), swap_in));
+                samples.push(Sample::new(self.swap_out.clone(), swap_out));
+            }
+        }
+
+        *previous = Some(next);
+        Ok(samples)
+    }
+
+    /// Reading `/proc/meminfo` and `/proc/vmstat` doesn't block.
+    fn blocking(&self) -> bool {
+        false
+    }
+}
+
+pub fn input() -> Result<Box<Input>> {
+    Ok(Box::new(SwapInput {}))
+}
@@ -0,0 +1,287 @@
+//! Input plugin that checks if TCP services are reachable.
+
+use errors::*;
+use metric::*;
+use plugin::*;
+use plugins::timeout::spawn_with_timeout;
+
+use futures::{future, Future};
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_timer::Timer;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 5;
+/// Largest response read when looking for the expected substring.
+const MAX_RESPONSE: u64 = 64 * 1024;
+
+#[derive(Deserialize, Debug, Clone)]
+struct TargetConfig {
+    /// Address to connect to, like `localhost:5432`.
+    address: String,
+    /// Payload to send once connected.
+    send: Option<String>,
+    /// Substring which the response has to contain.
+    expect: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TcpCheckInputConfig {
+    targets: Vec<TargetConfig>,
+    /// Report latencies as observations, which are aggregated into histograms.
+    latency_histogram: Option<bool>,
+}
+
+#[derive(Debug)]
+struct TcpCheckInput {}
+
+impl Input for TcpCheckInput {
+    fn setup(&self, ctx: PluginContext) -> Result<Box<InputInstance>> {
+        let config: TcpCheckInputConfig = ctx.decode_config()?;
+
+        if config.targets.is_empty() {
+            return Err(
+                ErrorKind::InvalidPluginConfig("targets".to_owned(), "must not be empty".to_owned())
+                    .into(),
+            );
+        }
+
+        let timeout = ctx.decode_duration("timeout")?.unwrap_or(
+            Duration::new(DEFAULT_TIMEOUT_SECS, 0),
+        );
+
+        // histograms are in seconds, like the default buckets.
+        let (latency_kind, latency_unit) = if config.latency_histogram.unwrap_or(false) {
+            (SampleKind::Observation, LatencyUnit::Seconds)
+        } else {
+            (SampleKind::Gauge, LatencyUnit::Millis)
+        };
+
+        let targets = config
+            .targets
+            .into_iter()
+            .map(|target| {
+                let base = key("tcp_check").tag("target", &target.address);
+
+                Target {
+                    up: Arc::new(base.clone().tag("what", "tcp-up").build()),
+                    latency: Arc::new(
+                        base.clone()
+                            .tag("what", "tcp-latency")
+                            .tag("unit", latency_unit.tag())
+                            .build(),
+                    ),
+                    config: target,
+                }
+            })
+            .collect();
+
+        Ok(Box::new(TcpCheckInputInstance {
+            name: ctx.name.clone(),
+            targets: targets,
+            latency_kind: latency_kind,
+            latency_unit: latency_unit,
+            timeout: timeout,
+            timer: ctx.timer.clone(),
+        }))
+    }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some(
+            "timeout = \"5s\"\n\
+             targets = [\n  \
+               { address = \"localhost:5432\" },\n  \
+               { address = \"localhost:80\", send = \"HEAD / HTTP/1.0\\r\\n\\r\\n\", \
+             expect = \"HTTP/1\" },\n\
+             ]",
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LatencyUnit {
+    Millis,
+    Seconds,
+}
+
+impl LatencyUnit {
+    fn tag(&self) -> &'static str {
+        match *self {
+            LatencyUnit::Millis => "ms",
+            LatencyUnit::Seconds => "s",
+        }
+    }
+
+    fn convert(&self, duration: Duration) -> f64 {
+        let secs = duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9;
+
+        match *self {
+            LatencyUnit::Millis => secs * 1000f64,
+            LatencyUnit::Seconds => secs,
+        }
+    }
+}
+
+struct Target {
+    config: TargetConfig,
+    up: Arc<MetricId>,
+    latency: Arc<MetricId>,
+}
+
+struct TcpCheckInputInstance {
+    name: String,
+    targets: Vec<Target>,
+    latency_kind: SampleKind,
+    latency_unit: LatencyUnit,
+    timeout: Duration,
+    /// Used to time out checks.
+    timer: Arc<Timer>,
+}
+
+impl fmt::Debug for TcpCheckInputInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TcpCheckInputInstance")
+    }
+}
+
+/// Check if the response contains the expected substring.
+fn contains(response: &[u8], expect: &str) -> bool {
+    let expect = expect.as_bytes();
+
+    if expect.is_empty() {
+        return true;
+    }
+
+    response.windows(expect.len()).any(|w| w == expect)
+}
+
+/// Connect to the first reachable address of the target.
+fn connect(address: &str, timeout: Duration) -> Result<TcpStream> {
+    let mut last_error = None;
+
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    match last_error {
+        Some(e) => Err(e.into()),
+        None => Err(ErrorKind::Message(format!("{} did not resolve", address)).into()),
+    }
+}
+
+/// Connect to the target, returning the time it took to connect.
+///
+/// Every socket operation is bounded by the timeout, so that the thread running the check is
+/// released even when the check itself has been given up on.
+fn check(target: &TargetConfig, timeout: Duration) -> Result<Duration> {
+    let started = Instant::now();
+    let mut stream = connect(target.address.as_str(), timeout)?;
+    let latency = started.elapsed();
+
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    if let Some(ref send) = target.send {
+        stream.write_all(send.as_bytes())?;
+    }
+
+    if let Some(ref expect) = target.expect {
+        let mut response = Vec::new();
+        let mut buffer = [0u8; 4096];
+
+        // stop reading as soon as the substring shows up, the server might not close.
+        loop {
+            let n = stream.read(&mut buffer)?;
+
+            if n == 0 {
+                break;
+            }
+
+            response.extend_from_slice(&buffer[..n]);
+
+            if contains(&response, expect) || response.len() as u64 >= MAX_RESPONSE {
+                break;
+            }
+        }
+
+        if !contains(&response, expect) {
+            return Err(ErrorKind::Message(format!("response did not contain {:?}", expect)).into());
+        }
+    }
+
+    Ok(latency)
+}
+
+impl InputInstance for TcpCheckInputInstance {
+    /// Check all targets in parallel, reporting every target which can't be reached as down.
+    fn poll(&self) -> Result<Samples> {
+        let checks: Vec<_> = self.targets
+            .iter()
+            .map(|target| {
+                let config = target.config.clone();
+                let timeout = self.timeout;
+
+                // a failed check is reported as down, so it must not fail the others.
+                spawn_with_timeout(&self.timer, self.timeout, move || check(&config, timeout))
+                    .then(|result| Ok::<_, Error>(result))
+            })
+            .collect();
+
+        let results = future::join_all(checks).wait()?;
+
+        let mut samples = Vec::new();
+
+        for (target, result) in self.targets.iter().zip(results) {
+            let result = match result {
+                Ok(Some(latency)) => Ok(latency),
+                Ok(None) => Err(format!("timed out after {:?}", self.timeout)),
+                Err(e) => Err(e.to_string()),
+            };
+
+            match result {
+                Ok(latency) => {
+                    samples.push(Sample::new(target.up.clone(), 1f64));
+                    let value = self.latency_unit.convert(latency);
+                    let sample = Sample::new(target.latency.clone(), value);
+                    samples.push(sample.with_kind(self.latency_kind));
+                }
+                Err(e) => {
+                    debug!("{}: {} is down: {}", self.name, target.config.address, e);
+                    samples.push(Sample::new(target.up.clone(), 0f64));
+                }
+            }
+        }
+
+        Ok(samples)
+    }
+}
+
+pub fn input() -> Result<Box<Input>> {
+    Ok(Box::new(TcpCheckInput {}))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_contains() {
+        assert!(contains(b"HTTP/1.1 200 OK\r\n", "200 OK"));
+        assert!(contains(b"anything", ""));
+        assert!(!contains(b"HTTP/1.1 500", "200"));
+        assert!(!contains(b"", "200"));
+    }
+
+    #[test]
+    fn test_latency_unit() {
+        let latency = Duration::from_millis(1500);
+
+        assert_eq!(1500f64, LatencyUnit::Millis.convert(latency));
+        assert_eq!(1.5f64, LatencyUnit::Seconds.convert(latency));
+    }
+}
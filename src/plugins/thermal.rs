@@ -0,0 +1,199 @@
+//! Input plugin that reports temperatures of thermal zones, and optionally of hwmon sensors.
+
+use metric::*;
+use plugin::*;
+use errors::*;
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const THERMAL: &'static str = "/sys/class/thermal";
+const HWMON: &'static str = "/sys/class/hwmon";
+
+#[derive(Deserialize, Debug)]
+struct ThermalInputConfig {
+    /// Also report the temperature sensors of hardware monitoring chips.
+    hwmon: Option<bool>,
+}
+
+#[derive(Debug)]
+struct ThermalInput {}
+
+impl Input for ThermalInput {
+    fn setup(&self, ctx: PluginContext) -> Result<Box<InputInstance>> {
+        let config: ThermalInputConfig = ctx.decode_config()?;
+
+        Ok(Box::new(ThermalInputInstance {
+            hwmon: config.hwmon.unwrap_or(false),
+        }))
+    }
+
+    fn example_config(&self) -> Option<&'static str> {
+        Some("hwmon = false")
+    }
+}
+
+#[derive(Debug)]
+struct ThermalInputInstance {
+    hwmon: bool,
+}
+
+fn read_to_string(path: &Path) -> Result<String> {
+    let mut content = String::new();
+
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut content))
+        .chain_err(|| format!("could not read {}", path.display()))?;
+
+    Ok(content)
+}
+
+/// Parse a temperature in millidegrees Celsius, like `45000`.
+fn parse_millidegrees(content: &str) -> Option<f64> {
+    content.trim().parse::<i64>().ok().map(|value| value as f64 / 1000f64)
+}
+
+/// Entries of a directory whose file name starts with the given prefix, sorted.
+///
+/// A directory which doesn't exist has no entries, as in containers where sysfs is partial.
+fn entries(dir: &str, prefix: &str) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.file_name()
+                        .and_then(|n| n.to_str())
+                        .map(|n| n.starts_with(prefix))
+                        .unwrap_or(false)
+                })
+                .collect()
+        }
+        Err(e) => {
+            debug!("could not list {}: {}", dir, e);
+            Vec::new()
+        }
+    };
+
+    paths.sort();
+    paths
+}
+
+fn temperature(tags: Vec<(&str, String)>, value: f64) -> Sample {
+    let mut builder = key("system").tag("what", "temperature").tag("unit", "C");
+
+    for (name, value) in tags {
+        builder = builder.tag(name, &value);
+    }
+
+    Sample::new(Arc::new(builder.build()), value)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+impl ThermalInputInstance {
+    fn thermal_zones(&self, samples: &mut Samples) {
+        for zone in entries(THERMAL, "thermal_zone") {
+            let value = match read_to_string(&zone.join("temp")) {
+                Ok(content) => parse_millidegrees(&content),
+                // zones which are disabled can't be read.
+                Err(e) => {
+                    debug!("{}", e);
+                    continue;
+                }
+            };
+
+            if let Some(value) = value {
+                let zone_type = read_to_string(&zone.join("type"))
+                    .map(|t| t.trim().to_owned())
+                    .unwrap_or_default();
+
+                samples.push(temperature(
+                    vec![("zone", file_name(&zone)), ("type", zone_type)],
+                    value,
+                ));
+            }
+        }
+    }
+
+    fn hwmon_sensors(&self, samples: &mut Samples) {
+        for chip in entries(HWMON, "hwmon") {
+            let chip_name = read_to_string(&chip.join("name"))
+                .map(|t| t.trim().to_owned())
+                .unwrap_or_else(|_| file_name(&chip));
+
+            for input in entries(&chip.to_string_lossy(), "temp") {
+                let name = file_name(&input);
+
+                if !name.ends_with("_input") {
+                    continue;
+                }
+
+                let sensor = &name[..name.len() - "_input".len()];
+
+                let value = match read_to_string(&input) {
+                    Ok(content) => parse_millidegrees(&content),
+                    Err(e) => {
+                        debug!("{}", e);
+                        continue;
+                    }
+                };
+
+                if let Some(value) = value {
+                    let label = read_to_string(&chip.join(format!("{}_label", sensor)))
+                        .map(|t| t.trim().to_owned())
+                        .unwrap_or_else(|_| sensor.to_owned());
+
+                    samples.push(temperature(
+                        vec![("chip", chip_name.clone()), ("label", label)],
+                        value,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+impl InputInstance for ThermalInputInstance {
+    /// Report every sensor which can be read, sensors which can't be read are skipped.
+    fn poll(&self) -> Result<Samples> {
+        let mut samples = Vec::new();
+
+        self.thermal_zones(&mut samples);
+
+        if self.hwmon {
+            self.hwmon_sensors(&mut samples);
+        }
+
+        Ok(samples)
+    }
+
+    /// Sensors are read from sysfs, which doesn't block.
+    fn blocking(&self) -> bool {
+        false
+    }
+}
+
+pub fn input() -> Result<Box<Input>> {
+    Ok(Box::new(ThermalInput {}))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_millidegrees() {
+        assert_eq!(Some(45.5), parse_millidegrees("45500\n"));
+        assert_eq!(Some(-5f64), parse_millidegrees("-5000"));
+        assert_eq!(None, parse_millidegrees(""));
+        assert_eq!(None, parse_millidegrees("hot"));
+    }
+}
@@ -0,0 +1,64 @@
+//! Bounding the time plugins spend on blocking operations.
+
+use errors::*;
+
+use futures::Future;
+use futures::future::Either;
+use futures::sync::oneshot;
+use std::thread;
+use std::time::Duration;
+use tokio_timer::Timer;
+
+/// Run `f` on a separate thread, resolving to `None` if it doesn't complete within `timeout`.
+///
+/// The thread is left running if the timeout expires, so `f` has to eventually complete by
+/// itself, but whoever waits for the result no longer occupies a thread of the pool.
+pub fn spawn_with_timeout<T, F>(
+    timer: &Timer,
+    timeout: Duration,
+    f: F,
+) -> Box<Future<Item = Option<T>, Error = Error>>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+
+    thread::spawn(move || { let _ = tx.send(f()); });
+
+    Box::new(rx.select2(timer.sleep(timeout)).then(|result| match result {
+        Ok(Either::A((result, _))) => result.map(Some),
+        Ok(Either::B(..)) => Ok(None),
+        // the sender is dropped without sending if `f` panics.
+        Err(Either::A(..)) => Err(ErrorKind::Message("worker thread stopped".to_owned()).into()),
+        Err(Either::B((e, _))) => Err(e.into()),
+    }))
+}
+
+/// Wait at most `timeout` for `future` to resolve, giving it back if it doesn't.
+pub fn wait_with_timeout<F>(
+    timer: &Timer,
+    timeout: Duration,
+    future: F,
+) -> Result<::std::result::Result<F::Item, F>>
+where
+    F: Future<Error = Error>,
+{
+    match future.select2(timer.sleep(timeout)).wait() {
+        Ok(Either::A((item, _))) => Ok(Ok(item)),
+        Ok(Either::B((_, future))) => Ok(Err(future)),
+        Err(Either::A((e, _))) => Err(e),
+        Err(Either::B((e, _))) => Err(e.into()),
+    }
+}
+
+/// Run `f` on a separate thread, waiting at most `timeout` for it to complete.
+///
+/// Returns `None` if the timeout expired first.
+pub fn run_with_timeout<T, F>(timer: &Timer, timeout: Duration, f: F) -> Result<Option<T>>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_with_timeout(timer, timeout, f).wait()
+}
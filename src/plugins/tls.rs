@@ -0,0 +1,133 @@
+//! TLS settings of plugins which talk HTTPS, configured through a `tls` table.
+
+use errors::*;
+
+use reqwest;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct TlsConfig {
+    /// PEM encoded CA certificate to trust, in addition to the system roots.
+    ca_file: Option<String>,
+    /// PKCS #12 archive with the client certificate and its key.
+    client_cert: Option<String>,
+    /// Password of the client certificate archive.
+    client_cert_password: Option<String>,
+    /// Accept any server certificate, which should only be used for testing.
+    insecure_skip_verify: Option<bool>,
+}
+
+/// Read a file referenced by the `tls` table, reporting the field it came from.
+///
+/// Relative paths are relative to the directory of the configuration file.
+fn read_file(field: &str, base_dir: &Path, path: &str) -> Result<Vec<u8>> {
+    let path = base_dir.join(path);
+    let mut content = Vec::new();
+
+    File::open(&path).and_then(|mut f| f.read_to_end(&mut content)).map_err(|e| {
+        ErrorKind::InvalidPluginConfig(
+            format!("tls.{}", field),
+            format!("could not read {}: {}", path.display(), e),
+        )
+    })?;
+
+    Ok(content)
+}
+
+fn invalid(field: &str, e: reqwest::Error) -> Error {
+    ErrorKind::InvalidPluginConfig(format!("tls.{}", field), e.to_string()).into()
+}
+
+/// Build a client using the given TLS settings, or the defaults if there are none.
+///
+/// Files are resolved against `base_dir`, the directory of the configuration file. Missing or
+/// invalid certificates fail here, so that they fail the setup of the plugin.
+pub fn client(tls: Option<&TlsConfig>, base_dir: &Path) -> Result<reqwest::Client> {
+    build(builder(tls, base_dir)?)
+}
+
+/// Set up a client builder with the given TLS settings, for plugins which configure more.
+pub fn builder(tls: Option<&TlsConfig>, base_dir: &Path) -> Result<reqwest::ClientBuilder> {
+    let mut builder = reqwest::Client::builder();
+
+    let tls = match tls {
+        Some(tls) => tls,
+        None => return Ok(builder),
+    };
+
+    if let Some(ref path) = tls.ca_file {
+        let pem = read_file("ca_file", base_dir, path)?;
+        let certificate = reqwest::Certificate::from_pem(&pem).map_err(
+            |e| invalid("ca_file", e),
+        )?;
+        builder.add_root_certificate(certificate);
+    }
+
+    if let Some(ref path) = tls.client_cert {
+        let der = read_file("client_cert", base_dir, path)?;
+        let password = tls.client_cert_password.as_ref().map(String::as_str).unwrap_or("");
+        let identity = reqwest::Identity::from_pkcs12_der(&der, password).map_err(|e| {
+            invalid("client_cert", e)
+        })?;
+        builder.identity(identity);
+    } else if tls.client_cert_password.is_some() {
+        return Err(
+            ErrorKind::InvalidPluginConfig(
+                "tls.client_cert_password".to_owned(),
+                "requires client_cert".to_owned(),
+            ).into(),
+        );
+    }
+
+    if tls.insecure_skip_verify.unwrap_or(false) {
+        warn!("tls: server certificates are not verified (insecure_skip_verify)");
+        builder.danger_accept_invalid_certs(true);
+    }
+
+    Ok(builder)
+}
+
+/// Build the client from a builder set up through `builder`.
+pub fn build(mut builder: reqwest::ClientBuilder) -> Result<reqwest::Client> {
+    builder.build().map_err(|e| {
+        ErrorKind::InvalidPluginConfig("tls".to_owned(), e.to_string()).into()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_missing_file() {
+        let tls = TlsConfig {
+            ca_file: Some("/nonexistent/ca.pem".to_owned()),
+            ..TlsConfig::default()
+        };
+
+        match client(Some(&tls), Path::new("/etc/sysmon")) {
+            Err(Error(ErrorKind::InvalidPluginConfig(field, _), _)) => {
+                assert_eq!("tls.ca_file", field)
+            }
+            other => panic!("expected invalid plugin config, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_relative_file() {
+        let tls = TlsConfig {
+            ca_file: Some("certs/ca.pem".to_owned()),
+            ..TlsConfig::default()
+        };
+
+        // resolved next to the configuration file, not in the working directory.
+        match client(Some(&tls), Path::new("/nonexistent/sysmon")) {
+            Err(Error(ErrorKind::InvalidPluginConfig(_, reason), _)) => {
+                assert!(reason.starts_with("could not read /nonexistent/sysmon/certs/ca.pem:"))
+            }
+            other => panic!("expected invalid plugin config, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! Input plugin that reports the time since boot from /proc/uptime.
+
+use metric::*;
+use plugin::*;
+use errors::*;
+use parsers::uptime::*;
+
+use std::fs::File;
+use std::io::Read;
+use std::sync::Arc;
+
+const PROC_UPTIME: &'static str = "/proc/uptime";
+
+#[derive(Debug)]
+struct UptimeInput {}
+
+impl Input for UptimeInput {
+    fn setup(&self, _ctx: PluginContext) -> Result<Box<InputInstance>> {
+        Ok(Box::new(UptimeInputInstance {
+            uptime: Arc::new(key("system").tag("what", "uptime").tag("unit", "s").build()),
+            meta: Arc::new(SampleMeta::new().description("Time since boot").unit("seconds")),
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct UptimeInputInstance {
+    uptime: Arc<MetricId>,
+    meta: Arc<SampleMeta>,
+}
+
+impl InputInstance for UptimeInputInstance {
+    fn poll(&self) -> Result<Samples> {
+        let mut content = String::new();
+
+        File::open(PROC_UPTIME)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .chain_err(|| format!("could not read {}", PROC_UPTIME))?;
+
+        let uptime = parse_uptime(&content).chain_err(
+            || format!("could not parse {}", PROC_UPTIME),
+        )?;
+
+        // uptime only ever increases, until the next boot.
+        let sample = Sample::new(self.uptime.clone(), uptime.uptime)
+            .with_kind(SampleKind::Counter)
+            .with_meta(self.meta.clone());
+
+        Ok(vec![sample])
+    }
+
+    /// Reading `/proc/uptime` doesn't block.
+    fn blocking(&self) -> bool {
+        false
+    }
+}
+
+pub fn input() -> Result<Box<Input>> {
+    Ok(Box::new(UptimeInput {}))
+}
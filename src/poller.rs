@@ -1,45 +1,481 @@
 use errors::*;
+use logger;
+use metric::*;
 use plugin::*;
 use scheduler::Runnable;
 use futures::*;
-use std::sync::Arc;
+use futures::future::Either;
+use futures::stream;
+use futures_cpupool::CpuPool;
+use health::Health;
+use bus::{OnFull, SampleBus};
+use pool::PoolLoad;
+use histograms::{HistogramRule, Histograms};
+use rename::{rename, RenameRule};
+use telemetry::Telemetry;
+use std::panic::{self, AssertUnwindSafe};
+use std::collections::HashMap;
+use std::f64;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio_timer::Timer;
+
+/// Limits the number of samples fed to outputs per second, shared by all pollers.
+///
+/// Up to a second worth of samples can be fed at once, after which the limit replenishes at the
+/// given rate.
+pub struct RateLimit {
+    rate: f64,
+    /// Samples which can currently be fed, and when this was last replenished.
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RateLimit {
+    pub fn new(rate: u64) -> RateLimit {
+        RateLimit {
+            rate: rate as f64,
+            state: Mutex::new((rate as f64, Instant::now())),
+        }
+    }
+
+    /// Take up to `count` samples from the limit, returning how many may be fed.
+    pub fn take(&self, count: usize) -> usize {
+        self.take_at(count, Instant::now())
+    }
+
+    fn take_at(&self, count: usize, now: Instant) -> usize {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            // a poisoned limit shouldn't stop samples from flowing.
+            Err(_) => return count,
+        };
+
+        let (available, last) = *state;
+
+        let elapsed = if now > last {
+            now.duration_since(last)
+        } else {
+            Duration::new(0, 0)
+        };
+
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        let available = (available + secs * self.rate).min(self.rate);
+        let taken = (count as f64).min(available.floor());
+
+        *state = (available - taken, now);
+        taken as usize
+    }
+}
+
+/// What to do with samples whose value is NaN or infinite.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnNonFinite {
+    /// Drop the sample.
+    Drop,
+    /// Replace infinities with the largest finite value of the same sign. NaN has no such value,
+    /// so those samples are still dropped.
+    Clamp,
+}
+
+impl OnNonFinite {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            OnNonFinite::Drop => "dropping",
+            OnNonFinite::Clamp => "clamping",
+        }
+    }
+}
+
+/// Drop or clamp samples with non-finite values.
+///
+/// Returns the samples to feed, and the metric and value of every sample which wasn't finite.
+fn validate(
+    samples: Samples,
+    on_non_finite: OnNonFinite,
+) -> (Samples, Vec<(Arc<MetricId>, f64)>) {
+    if samples.iter().all(|s| s.value.is_finite()) {
+        return (samples, Vec::new());
+    }
+
+    let mut out = Vec::with_capacity(samples.len());
+    let mut invalid = Vec::new();
+
+    for mut sample in samples {
+        if sample.value.is_finite() {
+            out.push(sample);
+            continue;
+        }
+
+        invalid.push((sample.metric_id.clone(), sample.value));
+
+        if on_non_finite == OnNonFinite::Clamp && !sample.value.is_nan() {
+            sample.value = if sample.value > 0f64 { f64::MAX } else { f64::MIN };
+            out.push(sample);
+        }
+    }
+
+    (out, invalid)
+}
+
+/// Previous values of derive samples, used to report them as rates.
+///
+/// Kept by the runtime, so that a reload doesn't lose a poll worth of rates for instances which
+/// keep running.
+pub struct Derivatives {
+    /// The last derive sample, by instance name and metric.
+    previous: Mutex<HashMap<(String, Arc<MetricId>), Sample>>,
+}
+
+impl Derivatives {
+    pub fn new() -> Derivatives {
+        Derivatives { previous: Mutex::new(HashMap::new()) }
+    }
+
+    /// Replace derive samples of the named instance with their rate per second since the last
+    /// poll, as gauges.
+    ///
+    /// A derive sample is dropped if there is nothing to compute a rate from yet, or if its value
+    /// went down since the last poll, which means that the counter was reset.
+    pub fn apply(&self, name: &str, samples: Samples, clock: Clock) -> Samples {
+        if samples.iter().all(|s| s.kind != SampleKind::Derive) {
+            return samples;
+        }
+
+        let mut previous = match self.previous.lock() {
+            Ok(previous) => previous,
+            Err(_) => return samples,
+        };
+
+        let mut out = Vec::with_capacity(samples.len());
+
+        for sample in samples {
+            if sample.kind != SampleKind::Derive {
+                out.push(sample);
+                continue;
+            }
+
+            let key = (name.to_owned(), sample.metric_id.clone());
+
+            let rate = previous.get(&key).and_then(|earlier| {
+                if sample.value < earlier.value {
+                    return None;
+                }
+
+                sample.elapsed_since(earlier, clock).and_then(|elapsed| {
+                    let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+
+                    if secs > 0f64 {
+                        Some((sample.value - earlier.value) / secs)
+                    } else {
+                        None
+                    }
+                })
+            });
+
+            if let Some(rate) = rate {
+                let mut rate_sample = sample.clone();
+                rate_sample.value = rate;
+                rate_sample.kind = SampleKind::Gauge;
+                out.push(rate_sample);
+            }
+
+            previous.insert(key, sample);
+        }
+
+        out
+    }
+
+    /// Forget about instances which are no longer running.
+    pub fn retain(&self, instances: &[String]) {
+        if let Ok(mut previous) = self.previous.lock() {
+            previous.retain(|&(ref name, _), _| instances.contains(name));
+        }
+    }
+}
+
+/// State shared by all polls, built once by the runtime for all of its pollers.
+pub struct PollContext {
+    pub bus: Arc<SampleBus>,
+    pub cpupool: Arc<CpuPool>,
+    pub timer: Arc<Timer>,
+    /// Timeout for instances which do not configure their own.
+    pub poll_timeout: Duration,
+    /// Tags added to every sample, after the tags of the instance.
+    pub tags: Arc<Vec<(String, String)>>,
+    pub rename: Arc<Vec<RenameRule>>,
+    pub health: Arc<Health>,
+    pub telemetry: Arc<Telemetry>,
+    pub rate_limit: Option<Arc<RateLimit>>,
+    pub load: Arc<PoolLoad>,
+    pub derivatives: Arc<Derivatives>,
+    pub histograms: Arc<Histograms>,
+    pub histogram_rules: Arc<Vec<HistogramRule>>,
+    pub clock: Clock,
+    pub reactor: Reactor,
+    pub on_non_finite: OnNonFinite,
+}
 
 pub struct Poller {
-    input: Arc<Vec<Arc<Box<InputInstance>>>>,
-    output: Arc<Vec<Box<OutputInstance>>>,
+    /// Maximum number of instances polled at the same time.
+    max_concurrency: usize,
+    input: Vec<Arc<ConfiguredInput>>,
+    context: Arc<PollContext>,
 }
 
 impl Poller {
     pub fn new(
-        input: Arc<Vec<Arc<Box<InputInstance>>>>,
-        output: Arc<Vec<Box<OutputInstance>>>,
+        input: Vec<Arc<ConfiguredInput>>,
+        max_concurrency: usize,
+        context: Arc<PollContext>,
     ) -> Poller {
         Poller {
+            max_concurrency: max_concurrency,
             input: input,
-            output: output,
+            context: context,
         }
     }
 }
 
-impl Runnable for Poller {
-    fn run(&self) -> Box<Future<Item = (), Error = Error>> {
-        for instance in self.input.iter() {
-            let samples = match instance.poll() {
-                Err(err) => return Box::new(future::err(err)),
-                Ok(s) => s,
+impl Poller {
+    /// Trigger polls of the instances of this poller, outside of its schedule.
+    pub fn trigger(&self) -> PollTrigger {
+        PollTrigger {
+            input: self.input.clone(),
+            context: self.context.clone(),
+        }
+    }
+}
+
+/// Polls instances of a poller outside of its schedule.
+///
+/// Polls triggered this way run next to the scheduled ones, the schedule itself is unaffected.
+#[derive(Clone)]
+pub struct PollTrigger {
+    input: Vec<Arc<ConfiguredInput>>,
+    context: Arc<PollContext>,
+}
+
+impl PollTrigger {
+    /// Poll the named instance, returns `None` if it isn't polled by this poller.
+    pub fn poll_now(&self, name: &str) -> Option<Box<Future<Item = (), Error = Error>>> {
+        self.input.iter().find(|i| i.options.name == name).map(|input| {
+            self.context.poll_input(input.clone())
+        })
+    }
+
+    /// Poll every instance of this poller.
+    pub fn poll_all(&self) -> Box<Future<Item = (), Error = Error>> {
+        let polls: Vec<_> = self.input
+            .iter()
+            .map(|input| self.context.poll_input(input.clone()))
+            .collect();
+
+        Box::new(future::join_all(polls).map(|_| ()))
+    }
+}
+
+impl PollContext {
+    /// Poll a single instance on the pool, and publish the resulting samples to all outputs.
+    ///
+    /// The returned future never fails, errors are logged instead.
+    fn poll_input(&self, input: Arc<ConfiguredInput>) -> Box<Future<Item = (), Error = Error>> {
+        let name = input.options.name.clone();
+
+        // queueing another poll behind one which hasn't even started would only grow the backlog.
+        if self.load.saturated() && self.load.is_inflight(&name) {
+            warn!(
+                "{}: skipping poll, the thread pool is saturated ({} queued tasks) and the \
+                 previous poll has not completed",
+                name,
+                self.load.queued()
+            );
+            return Box::new(future::ok(()));
+        }
+
+        let timeout = input.options.poll_timeout.unwrap_or(self.poll_timeout);
+        let bus = self.bus.clone();
+        let tags = self.tags.clone();
+        let rules = self.rename.clone();
+        let cpupool = self.cpupool.clone();
+        let health = self.health.clone();
+        let telemetry = self.telemetry.clone();
+        let rate_limit = self.rate_limit.clone();
+        let load = self.load.clone();
+        let derivatives = self.derivatives.clone();
+        let histograms = self.histograms.clone();
+        let histogram_rules = self.histogram_rules.clone();
+        let clock = self.clock;
+        let on_non_finite = self.on_non_finite;
+
+        let task = PoolLoad::poll_started(&self.load, &name);
+        telemetry.pool_queued(self.load.queued());
+
+        // with a single-threaded reactor, only instances which block are polled on the pool.
+        let inline = self.reactor == Reactor::Single && !input.instance.blocking();
+        // feeding blocks too while the publisher waits for full output queues.
+        let inline_feed = inline && self.bus.on_full() != OnFull::Block;
+
+        let poll = move || -> Result<_> {
+            let _task = task;
+            let timestamp = SystemTime::now();
+            let instant = Instant::now();
+
+            // a misbehaving plugin must not take the poll loop down with it.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                logger::with_plugin(&input.options.name, || input.instance.poll())
+            }));
+
+            let duration = instant.elapsed();
+
+            let samples = match result {
+                Ok(result) => result?,
+                Err(payload) => {
+                    return Err(
+                        ErrorKind::Message(format!("panicked: {}", panic_message(&payload)))
+                            .into(),
+                    );
+                }
             };
 
-            for sample in samples {
-                for instance in self.output.iter() {
-                    match instance.feed(&sample) {
-                        Err(err) => return Box::new(future::err(err)),
-                        Ok(()) => (),
+            // every sample of a poll is stamped with the time the poll started.
+            let samples = samples
+                .into_iter()
+                .map(|mut sample| {
+                    sample.timestamp = timestamp;
+                    sample.instant = instant;
+                    sample
+                })
+                .collect();
+
+            Ok((input, samples, duration))
+        };
+
+        let poll: Box<Future<Item = _, Error = Error>> = if inline {
+            Box::new(future::lazy(poll))
+        } else {
+            Box::new(self.cpupool.spawn_fn(poll))
+        };
+
+        let sleep = self.timer.sleep(timeout).map_err(Into::<Error>::into);
+
+        // samples from a poll which timed out are discarded together with the poll future.
+        Box::new(poll.select2(sleep).then(move |result| -> Box<Future<Item = (), Error = Error>> {
+            match result {
+                Ok(Either::A(((input, samples, duration), _))) => {
+                    health.success(&name);
+                    telemetry.poll_success(&name, duration);
+
+                    // validated first, so that rates are never computed from them.
+                    let (samples, invalid) = validate(samples, on_non_finite);
+
+                    if let Some(&(ref metric_id, value)) = invalid.first() {
+                        warn!(
+                            "{}: {} sample(s) with non-finite values, {} them (first: {} = {})",
+                            name,
+                            invalid.len(),
+                            on_non_finite.as_str(),
+                            metric_id.key().unwrap_or("<no key>"),
+                            value
+                        );
+                        telemetry.samples_non_finite(&name, invalid.len());
+                    }
+
+                    let samples = derivatives.apply(&name, samples, clock);
+                    let mut samples = histograms.apply(&name, samples, &histogram_rules);
+
+                    let limit = input.options.max_samples_per_poll.unwrap_or(samples.len());
+
+                    if samples.len() > limit {
+                        warn!(
+                            "{}: poll returned {} samples, dropping all but {} \
+                             (max_samples_per_poll)",
+                            name,
+                            samples.len(),
+                            limit
+                        );
+                        telemetry.samples_dropped(&name, samples.len() - limit);
+                        samples.truncate(limit);
+                    }
+
+                    if let Some(ref rate_limit) = rate_limit {
+                        let allowed = rate_limit.take(samples.len());
+
+                        if allowed < samples.len() {
+                            warn!(
+                                "{}: dropping {} samples (max_samples_per_second)",
+                                name,
+                                samples.len() - allowed
+                            );
+                            telemetry.samples_dropped(&name, samples.len() - allowed);
+                            samples.truncate(allowed);
+                        }
+                    }
+
+                    let task = PoolLoad::feed_started(&load);
+                    telemetry.pool_queued(load.queued());
+
+                    let publish = move || -> Result<()> {
+                        let _task = task;
+                        feed(&input, &tags, &rules, &bus, samples);
+                        Ok(())
                     };
+
+                    if inline_feed {
+                        return Box::new(future::lazy(publish));
+                    }
+
+                    // tagging every sample is comparatively expensive, so keep it off the reactor.
+                    return Box::new(cpupool.spawn_fn(publish));
+                }
+                Ok(Either::B(..)) => {
+                    error!("{}: poll timed out after {:?}", name, timeout);
+                    let e = ErrorKind::Message(format!("poll timed out after {:?}", timeout));
+                    health.failure(&name, &e.into());
+                    telemetry.poll_failure(&name);
                 }
+                Err(Either::A((e, _))) => {
+                    error!("{}: poll failed: {}", name, e);
+                    health.failure(&name, &e);
+                    telemetry.poll_failure(&name);
+                }
+                Err(Either::B((e, _))) => error!("{}: poll timer failed: {}", name, e),
             }
-        }
 
-        Box::new(future::ok(()))
+            Box::new(future::ok(()))
+        }))
+    }
+}
+
+/// Tag and rename the samples of an instance and publish them to the outputs it is routed to.
+///
+/// Tags of the sample take precedence over tags of the instance, which take precedence over the
+/// global tags.
+fn feed(
+    input: &ConfiguredInput,
+    tags: &Vec<(String, String)>,
+    rules: &Vec<RenameRule>,
+    bus: &SampleBus,
+    mut samples: Samples,
+) {
+    for sample in samples.iter_mut() {
+        let metric_id = sample.metric_id.with_tags(&input.tags).with_tags(tags);
+        let metric_id = rename(rules, &metric_id).unwrap_or(metric_id);
+        sample.metric_id = Arc::new(metric_id);
+    }
+
+    bus.publish_to(input.options.route.as_ref().map(Vec::as_slice), &samples);
+}
+
+impl Runnable for Poller {
+    fn run(&self) -> Box<Future<Item = (), Error = Error>> {
+        let context = self.context.clone();
+
+        // a failing instance is logged by poll_input, so it never affects the others.
+        let polls = stream::iter_ok::<_, Error>(self.input.clone())
+            .map(move |input| context.poll_input(input))
+            .buffer_unordered(self.max_concurrency);
+
+        Box::new(polls.for_each(|_| Ok(())))
     }
 }
 
@@ -48,3 +484,86 @@ impl Drop for Poller {
         info!("Dropping Poller");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_derivatives() {
+        let derivatives = Derivatives::new();
+        let id = Arc::new(key("net").tag("what", "rx-bytes").build());
+        let gauge = Arc::new(key("net").tag("what", "up").build());
+        let now = Instant::now();
+
+        let sample = |id: &Arc<MetricId>, value: f64, offset: u64, kind: SampleKind| {
+            let mut sample = Sample::new(id.clone(), value).with_kind(kind);
+            sample.instant = now + Duration::new(offset, 0);
+            sample
+        };
+
+        let first = derivatives.apply(
+            "net",
+            vec![
+                sample(&id, 100f64, 0, SampleKind::Derive),
+                sample(&gauge, 1f64, 0, SampleKind::Gauge),
+            ],
+            Clock::Monotonic,
+        );
+
+        // nothing to compute a rate from yet.
+        assert_eq!(1, first.len());
+
+        let second = derivatives.apply(
+            "net",
+            vec![sample(&id, 300f64, 2, SampleKind::Derive)],
+            Clock::Monotonic,
+        );
+
+        assert_eq!(vec![100f64], second.iter().map(|s| s.value).collect::<Vec<_>>());
+        assert_eq!(SampleKind::Gauge, second[0].kind);
+
+        // a reset counter is skipped, the next poll computes from the new value.
+        let reset = derivatives.apply(
+            "net",
+            vec![sample(&id, 10f64, 3, SampleKind::Derive)],
+            Clock::Monotonic,
+        );
+
+        assert!(reset.is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        let id = Arc::new(key("test").build());
+        let samples = || {
+            [1f64, f64::NAN, f64::INFINITY, f64::NEG_INFINITY]
+                .iter()
+                .map(|v| Sample::new(id.clone(), *v))
+                .collect::<Samples>()
+        };
+
+        let values = |samples: Samples| samples.iter().map(|s| s.value).collect::<Vec<_>>();
+
+        let (dropped, invalid) = validate(samples(), OnNonFinite::Drop);
+        assert_eq!(vec![1f64], values(dropped));
+        assert_eq!(3, invalid.len());
+
+        let (clamped, invalid) = validate(samples(), OnNonFinite::Clamp);
+        assert_eq!(vec![1f64, f64::MAX, f64::MIN], values(clamped));
+        assert_eq!(3, invalid.len());
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let limit = RateLimit::new(10);
+        let now = Instant::now();
+
+        assert_eq!(8, limit.take_at(8, now));
+        assert_eq!(2, limit.take_at(8, now));
+        assert_eq!(0, limit.take_at(8, now));
+        assert_eq!(5, limit.take_at(8, now + Duration::from_millis(500)));
+        // never more than a second worth of samples.
+        assert_eq!(10, limit.take_at(20, now + Duration::new(10, 0)));
+    }
+}
@@ -0,0 +1,127 @@
+//! Load of the thread pool, as seen by the pollers.
+//!
+//! Every task spawned by a poller is tracked from the moment it is queued until it completes, so
+//! that a backed up pool can be detected before even more work is queued onto it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Tasks spawned onto the thread pool which haven't completed, and the instances they poll.
+pub struct PoolLoad {
+    /// Number of queued tasks beyond which the pool is saturated.
+    threshold: AtomicUsize,
+    queued: AtomicUsize,
+    /// Number of polls on the pool, by instance name.
+    inflight: Mutex<HashMap<String, usize>>,
+}
+
+impl PoolLoad {
+    pub fn new(threshold: usize) -> PoolLoad {
+        PoolLoad {
+            threshold: AtomicUsize::new(threshold),
+            queued: AtomicUsize::new(0),
+            inflight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Change the threshold, tasks which are already queued remain tracked.
+    pub fn set_threshold(&self, threshold: usize) {
+        self.threshold.store(threshold, Ordering::Relaxed);
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// If more tasks are queued than the threshold.
+    pub fn saturated(&self) -> bool {
+        self.queued() > self.threshold.load(Ordering::Relaxed)
+    }
+
+    /// If a poll of the named instance is queued or running.
+    pub fn is_inflight(&self, name: &str) -> bool {
+        match self.inflight.lock() {
+            Ok(inflight) => inflight.contains_key(name),
+            Err(_) => false,
+        }
+    }
+
+    /// Track a poll of the named instance, until the returned task is dropped.
+    pub fn poll_started(load: &Arc<PoolLoad>, name: &str) -> PoolTask {
+        if let Ok(mut inflight) = load.inflight.lock() {
+            *inflight.entry(name.to_owned()).or_insert(0) += 1;
+        }
+
+        PoolLoad::task_started(load, Some(name.to_owned()))
+    }
+
+    /// Track a task which doesn't poll an instance, until the returned task is dropped.
+    pub fn feed_started(load: &Arc<PoolLoad>) -> PoolTask {
+        PoolLoad::task_started(load, None)
+    }
+
+    fn task_started(load: &Arc<PoolLoad>, name: Option<String>) -> PoolTask {
+        load.queued.fetch_add(1, Ordering::Relaxed);
+
+        PoolTask {
+            load: load.clone(),
+            name: name,
+        }
+    }
+}
+
+/// A tracked task, which is no longer queued once dropped.
+///
+/// Moved into the closure spawned onto the pool, so that it is dropped both when the closure
+/// completes and when it is discarded without running.
+pub struct PoolTask {
+    load: Arc<PoolLoad>,
+    name: Option<String>,
+}
+
+impl Drop for PoolTask {
+    fn drop(&mut self) {
+        if let Some(ref name) = self.name {
+            if let Ok(mut inflight) = self.load.inflight.lock() {
+                let done = match inflight.get_mut(name) {
+                    Some(count) => {
+                        *count -= 1;
+                        *count == 0
+                    }
+                    None => false,
+                };
+
+                if done {
+                    inflight.remove(name);
+                }
+            }
+        }
+
+        self.load.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pool_load() {
+        let load = Arc::new(PoolLoad::new(1));
+
+        let cpu = PoolLoad::poll_started(&load, "cpu");
+        assert!(load.is_inflight("cpu"));
+        assert!(!load.saturated());
+
+        let feed = PoolLoad::feed_started(&load);
+        assert!(load.saturated());
+        assert_eq!(2, load.queued());
+
+        drop(cpu);
+        drop(feed);
+
+        assert!(!load.is_inflight("cpu"));
+        assert_eq!(0, load.queued());
+    }
+}
@@ -0,0 +1,184 @@
+//! Rules which rewrite the key of samples before they are fed to outputs.
+//!
+//! Rules are configured through `[[rename]]` tables, and are tried in order. The first rule that
+//! matches a key is applied, and the remaining rules are skipped.
+
+use errors::*;
+use metric::MetricId;
+use regex::Regex;
+use serde;
+use toml;
+
+#[derive(Deserialize, Debug)]
+struct RenameRuleIn {
+    /// Regular expression the key has to match, every key matches if left out.
+    pattern: Option<String>,
+    /// Replacement of the matched part of the key, which can refer to groups like `$1`.
+    replace: Option<String>,
+    /// Prefix to add to the key, after any replacement.
+    prefix: Option<String>,
+    /// Tag to keep the original key in.
+    original_tag: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenameRule {
+    pattern: Option<Regex>,
+    replace: Option<String>,
+    prefix: Option<String>,
+    original_tag: Option<String>,
+}
+
+impl RenameRule {
+    /// Rename the key if the rule matches it, returning `None` otherwise.
+    fn apply(&self, key: &str) -> Option<String> {
+        let mut renamed = match (self.pattern.as_ref(), self.replace.as_ref()) {
+            (Some(pattern), _) if !pattern.is_match(key) => return None,
+            (Some(pattern), Some(replace)) => pattern.replace(key, replace.as_str()).into_owned(),
+            _ => key.to_owned(),
+        };
+
+        if let Some(ref prefix) = self.prefix {
+            renamed.insert_str(0, prefix);
+        }
+
+        Some(renamed)
+    }
+}
+
+fn decode_rule(value: &toml::Value) -> Result<RenameRule> {
+    let mut decoder = toml::Decoder::new(value.clone());
+    let rule: RenameRuleIn = serde::Deserialize::deserialize(&mut decoder)?;
+
+    if let Some(toml::Value::Table(leftover)) = decoder.toml {
+        if !leftover.is_empty() {
+            let keys: Vec<String> = leftover.keys().cloned().collect();
+            return Err(
+                ErrorKind::Message(format!("unknown key(s): {}", keys.join(", "))).into(),
+            );
+        }
+    }
+
+    let pattern = match rule.pattern {
+        Some(pattern) => {
+            Some(Regex::new(&pattern).map_err(|e| {
+                ErrorKind::ConfigField("pattern".to_owned(), e.to_string())
+            })?)
+        }
+        None => None,
+    };
+
+    if rule.replace.is_some() && pattern.is_none() {
+        return Err(
+            ErrorKind::ConfigField("replace".to_owned(), "requires a pattern".to_owned()).into(),
+        );
+    }
+
+    if rule.replace.is_none() && rule.prefix.is_none() {
+        return Err(
+            ErrorKind::Message("expected at least one of replace or prefix".to_owned()).into(),
+        );
+    }
+
+    Ok(RenameRule {
+        pattern: pattern,
+        replace: rule.replace,
+        prefix: rule.prefix,
+        original_tag: rule.original_tag,
+    })
+}
+
+/// Decode the `[[rename]]` rules of a configuration file.
+pub fn decode_rename(table: &toml::Table, key: &str) -> Result<Vec<RenameRule>> {
+    let values = match table.get(key) {
+        None => return Ok(Vec::new()),
+        Some(&toml::Value::Array(ref values)) => values,
+        Some(_) => {
+            return Err(
+                ErrorKind::ConfigField(key.to_owned(), "must be an array of tables".to_owned())
+                    .into(),
+            );
+        }
+    };
+
+    let mut rules = Vec::new();
+
+    for (index, value) in values.iter().enumerate() {
+        rules.push(decode_rule(value).chain_err(|| {
+            ErrorKind::ConfigSection(format!("{}[{}]", key, index))
+        })?);
+    }
+
+    Ok(rules)
+}
+
+/// Rename a metric using the first matching rule.
+///
+/// Returns `None` if no rule matches, or if the metric has no key.
+pub fn rename(rules: &[RenameRule], metric_id: &MetricId) -> Option<MetricId> {
+    let key = match metric_id.key() {
+        Some(key) => key,
+        None => return None,
+    };
+
+    for rule in rules {
+        if let Some(renamed) = rule.apply(key) {
+            let mut renamed_id = metric_id.with_key(&renamed);
+
+            if let Some(ref tag) = rule.original_tag {
+                renamed_id = renamed_id.with_tags(&[(tag.clone(), key.to_owned())]);
+            }
+
+            return Some(renamed_id);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metric::key;
+
+    fn rules(content: &str) -> Result<Vec<RenameRule>> {
+        let table = toml::Parser::new(content).parse().unwrap();
+        decode_rename(&table, "rename")
+    }
+
+    #[test]
+    fn test_rename() {
+        let rules = rules(
+            "\
+[[rename]]
+pattern = \"^noisy\\\\.(.*)$\"
+replace = \"prod.$1\"
+original_tag = \"original\"
+
+[[rename]]
+prefix = \"prod.\"
+",
+        ).unwrap();
+
+        let id = key("noisy.system").tag("what", "cpu-used").build();
+        let expected = key("prod.system")
+            .tag("what", "cpu-used")
+            .tag("original", "noisy.system")
+            .build();
+        assert_eq!(Some(expected), rename(&rules, &id));
+
+        // only the first matching rule is applied.
+        let id = key("system").build();
+        assert_eq!(Some(key("prod.system").build()), rename(&rules, &id));
+
+        assert_eq!(None, rename(&rules, &MetricId::new().build()));
+    }
+
+    #[test]
+    fn test_decode_rename_errors() {
+        assert!(rules("[[rename]]\nreplace = \"x\"").is_err());
+        assert!(rules("[[rename]]\npattern = \"(\"\nprefix = \"x\"").is_err());
+        assert!(rules("[[rename]]\npattern = \"x\"").is_err());
+        assert!(rules("[[rename]]\nprefx = \"x\"").is_err());
+    }
+}
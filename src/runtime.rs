@@ -0,0 +1,961 @@
+use bus::SampleBus;
+use config::*;
+use dispatcher::Dispatcher;
+use errors::*;
+use futures::*;
+use futures_cpupool::CpuPool;
+use health::{Health, InstanceHealth, StatusLog};
+use heartbeat::Heartbeat;
+use histograms::Histograms;
+use logger;
+use ordering::order_sections;
+use plugin::*;
+use poller::{Derivatives, PollContext, PollTrigger, Poller, RateLimit};
+use pool::PoolLoad;
+use scheduler::*;
+use std::collections::BTreeMap;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use telemetry::*;
+use tokio_core::reactor::Handle;
+use tokio_timer::Timer;
+use toml;
+use updater::Updater;
+
+/// Tear down input instances, once polls and updates in progress have completed.
+///
+/// Polls which are still running after `TEARDOWN_TIMEOUT_SECS` are not waited for any longer.
+pub fn teardown_inputs(inputs: Vec<Arc<ConfiguredInput>>) {
+    let deadline = Instant::now() + Duration::new(TEARDOWN_TIMEOUT_SECS, 0);
+
+    for input in inputs {
+        // polls and updates hold on to the instance while they are running.
+        while Arc::strong_count(&input) > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let name = input.options.name.clone();
+        teardown(&name, move || input.instance.teardown());
+    }
+}
+
+/// Tear down output instances, which should have been flushed for the last time.
+pub fn teardown_outputs(outputs: Vec<Arc<ConfiguredOutput>>) {
+    for output in outputs {
+        let name = output.options.name.clone();
+        teardown(&name, move || output.teardown());
+    }
+}
+
+/// Plugin instances, together with the sections that configured them.
+pub struct Instances {
+    inputs: Vec<(PluginSection, Arc<ConfiguredInput>)>,
+    outputs: Vec<(PluginSection, Arc<ConfiguredOutput>)>,
+}
+
+impl Instances {
+    fn new() -> Instances {
+        Instances {
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn inputs(&self) -> Vec<Arc<ConfiguredInput>> {
+        self.inputs.iter().map(|&(_, ref i)| i.clone()).collect()
+    }
+
+    pub fn outputs(&self) -> Vec<Arc<ConfiguredOutput>> {
+        self.outputs.iter().map(|&(_, ref o)| o.clone()).collect()
+    }
+
+    /// Sections of all instances, inputs first.
+    fn sections(&self) -> Vec<&PluginSection> {
+        let inputs = self.inputs.iter().map(|&(ref s, _)| s);
+        let outputs = self.outputs.iter().map(|&(ref s, _)| s);
+        inputs.chain(outputs).collect()
+    }
+
+    fn find_input(&self, section: &PluginSection) -> Option<Arc<ConfiguredInput>> {
+        self.inputs.iter().find(|&&(ref s, _)| s == section).map(
+            |&(_, ref i)| i.clone(),
+        )
+    }
+
+    fn find_output(&self, section: &PluginSection) -> Option<Arc<ConfiguredOutput>> {
+        self.outputs.iter().find(|&&(ref s, _)| s == section).map(
+            |&(_, ref o)| o.clone(),
+        )
+    }
+}
+
+/// A running instance, as listed through the control socket.
+#[derive(Serialize, Debug)]
+pub struct InstanceDescription {
+    kind: &'static str,
+    id: String,
+    name: String,
+    #[serde(rename = "type")]
+    plugin_type: Option<String>,
+}
+
+impl InstanceDescription {
+    fn new(section: &PluginSection, name: &str) -> InstanceDescription {
+        let plugin_type = match section.table.get("type") {
+            Some(&toml::Value::String(ref plugin_type)) => Some(plugin_type.clone()),
+            _ => None,
+        };
+
+        InstanceDescription {
+            kind: match section.kind {
+                PluginKind::Input => "input",
+                PluginKind::Output => "output",
+            },
+            id: section.id.clone(),
+            name: name.to_owned(),
+            plugin_type: plugin_type,
+        }
+    }
+}
+
+/// How the sections of a reload compare to the running ones.
+///
+/// Sections are identified by their kind and id. An instance is only set up again if its section
+/// changed, unchanged instances keep running with their state.
+#[derive(Debug, Default, PartialEq)]
+pub struct ReloadSummary {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+}
+
+impl ReloadSummary {
+    pub fn diff(running: &[&PluginSection], sections: &[&PluginSection]) -> ReloadSummary {
+        let mut summary = ReloadSummary::default();
+
+        for section in sections {
+            match running.iter().find(|r| r.kind == section.kind && r.id == section.id) {
+                None => summary.added += 1,
+                Some(r) if r.table == section.table => summary.unchanged += 1,
+                Some(_) => summary.changed += 1,
+            }
+        }
+
+        summary.removed = running
+            .iter()
+            .filter(|r| !sections.iter().any(|s| s.kind == r.kind && s.id == r.id))
+            .count();
+
+        summary
+    }
+}
+
+/// Owns the running plugin instances and the schedules that drive them.
+///
+/// Instances can be replaced at runtime through `reload`, which keeps instances whose section is
+/// unchanged running.
+pub struct Runtime {
+    handle: Handle,
+    cpupool: Arc<CpuPool>,
+    timer: Arc<Timer>,
+    plugins: PluginRegistry,
+    instances: Instances,
+    schedules: Vec<ScheduleHandle>,
+    /// Triggers of the running pollers, for polls outside of their schedule.
+    triggers: Vec<PollTrigger>,
+    /// Bus of the running instances.
+    bus: Option<Arc<SampleBus>>,
+    health: Arc<Health>,
+    telemetry: Arc<Telemetry>,
+    /// Kept through reloads, since polls of retired instances might still be queued.
+    load: Arc<PoolLoad>,
+    derivatives: Arc<Derivatives>,
+    /// Histograms of observations, kept across reloads like derivatives.
+    histograms: Arc<Histograms>,
+}
+
+impl Runtime {
+    pub fn new(handle: Handle, cpupool: Arc<CpuPool>, plugins: PluginRegistry) -> Runtime {
+        Runtime {
+            handle: handle,
+            cpupool: cpupool,
+            timer: Arc::new(Timer::default()),
+            plugins: plugins,
+            instances: Instances::new(),
+            schedules: Vec::new(),
+            triggers: Vec::new(),
+            bus: None,
+            health: Arc::new(Health::new()),
+            telemetry: Arc::new(Telemetry::new()),
+            load: Arc::new(PoolLoad::new(0)),
+            derivatives: Arc::new(Derivatives::new()),
+            histograms: Arc::new(Histograms::new()),
+        }
+    }
+
+    /// Set up instances for the given sections.
+    ///
+    /// Running instances whose section is unchanged are reused instead of being set up again.
+    /// Exclusive outputs which are not reused are torn down first, so that their replacements can
+    /// bind the same port.
+    ///
+    /// Failing setups are only retried before the runtime has started, since a reload sets up on
+    /// the reactor thread, which would stop polling and serving while the retries wait.
+    pub fn prepare(&self, config: &Config, sections: Vec<PluginSection>) -> Result<Instances> {
+        self.release_exclusive(&sections);
+
+        let mut partial_context = PartialPluginContext::new(
+            self.cpupool.clone(),
+            self.handle.clone(),
+            config.clock,
+            config.reactor,
+            self.health.clone(),
+            self.timer.clone(),
+            self.telemetry.clone(),
+            config.allow_unknown_keys,
+            config.dry_run,
+        );
+
+        if self.bus.is_some() {
+            partial_context = partial_context.without_retries();
+        }
+
+        let mut instances = Instances::new();
+        // every section is set up, so that all failing sections are reported together.
+        let mut errors = Vec::new();
+
+        for section in order_sections(sections)? {
+            match section.kind {
+                PluginKind::Input => {
+                    let result = match self.instances.find_input(&section) {
+                        Some(input) => Ok(Some(input)),
+                        None => {
+                            setup_input(&section, &self.plugins, &partial_context).map(|input| {
+                                if let Some(ref input) = input {
+                                    self.hand_over_state(&section, input);
+                                }
+
+                                input
+                            })
+                        }
+                    };
+
+                    match skip_failed(config, &section, result) {
+                        Ok(Some(input)) => instances.inputs.push((section, input)),
+                        Ok(None) => {}
+                        Err(e) => errors.push(e),
+                    }
+                }
+                PluginKind::Output => {
+                    let result = match self.instances.find_output(&section) {
+                        Some(output) => Ok(Some(output)),
+                        None => {
+                            let previous = self.previous_output(&section);
+                            let previous = previous.as_ref().map(|p| &**p);
+                            setup_output(&section, &self.plugins, &partial_context, previous)
+                        }
+                    };
+
+                    match skip_failed(config, &section, result) {
+                        Ok(Some(output)) => instances.outputs.push((section, output)),
+                        Ok(None) => {}
+                        Err(e) => errors.push(e),
+                    }
+                }
+            }
+        }
+
+        combine_errors(errors)?;
+
+        if config.skip_failed && instances.inputs.is_empty() && instances.outputs.is_empty() {
+            return Err(
+                ErrorKind::Message("no plugin instances could be set up".to_owned()).into(),
+            );
+        }
+
+        Ok(instances)
+    }
+
+    /// Replace the running instances and reschedule polling, updating, and delivery of batches.
+    ///
+    /// Instances which are no longer part of the set are dropped once their last in-progress
+    /// poll or update has completed.
+    pub fn start(&mut self, config: &Config, instances: Instances) -> Result<()> {
+        self.stop();
+        self.hand_over_spools(&instances);
+
+        let mut inputs = instances.inputs();
+
+        if config.internal_metrics {
+            inputs.push(self.self_input());
+        }
+
+        let outputs = Arc::new(instances.outputs());
+        let bus = self.bus(config, &outputs);
+        self.bus = Some(bus.clone());
+        self.health.track(&inputs, config.health_threshold)?;
+        let names: Vec<String> = inputs.iter().map(|i| i.options.name.clone()).collect();
+        self.telemetry.retain(&names);
+        self.derivatives.retain(&names);
+        self.histograms.retain(&names);
+        // a seeded jitter gives every start the same offsets.
+        let mut jitter = Jitter::new(config.jitter, config.jitter_seed);
+
+        self.load.set_threshold(config.max_queued_tasks());
+        let context = self.poll_context(config, &bus);
+
+        // the boundary is computed on every start, so a reload doesn't inherit the old phase.
+        let now = SystemTime::now();
+
+        for ((interval, align), group) in
+            group_by_interval(&inputs, |i| {
+                (config.poll_interval_for(&i.options), i.options.align)
+            })
+        {
+            debug!(
+                "polling {} instance(s) every {:?}{}",
+                group.len(),
+                interval,
+                if align { ", aligned" } else { "" }
+            );
+            let poller = Poller::new(group, config.max_concurrency(), context.clone());
+            self.triggers.push(poller.trigger());
+
+            let delay = if align {
+                align_delay(now, interval)
+            } else {
+                interval + jitter.offset(interval)
+            };
+
+            self.spawn("poll", delay, interval, config.on_overrun, poller)?;
+        }
+
+        for (interval, group) in
+            group_by_interval(&inputs, |i| config.update_interval_for(&i.options))
+        {
+            debug!("updating {} instance(s) every {:?}", group.len(), interval);
+            let updater = Updater::new(
+                group,
+                self.cpupool.clone(),
+                config.reactor,
+                self.health.clone(),
+                self.telemetry.clone(),
+            );
+            self.spawn("update", interval, interval, config.on_overrun, updater)?;
+        }
+
+        let mut batched: BTreeMap<Duration, Vec<Arc<ConfiguredOutput>>> = BTreeMap::new();
+
+        for output in outputs.iter() {
+            if let Some(flush_interval) = output.options.flush_interval {
+                batched.entry(flush_interval).or_insert_with(Vec::new).push(
+                    output.clone(),
+                );
+            }
+        }
+
+        if let Some(interval) = config.status_interval {
+            let status_log = StatusLog::new(self.health.clone());
+            self.spawn("status", interval, interval, config.on_overrun, status_log)?;
+        }
+
+        if let Some(interval) = config.heartbeat_interval {
+            debug!("feeding a heartbeat to outputs every {:?}", interval);
+            let heartbeat = Heartbeat::new(bus.clone(), self.cpupool.clone(), &config.tags);
+            self.spawn("heartbeat", interval, interval, config.on_overrun, heartbeat)?;
+        }
+
+        for (interval, group) in batched {
+            debug!("delivering batches of {} output(s) every {:?}", group.len(), interval);
+            let dispatcher = Dispatcher::new(group, self.cpupool.clone());
+            self.spawn("deliver", interval, interval, config.on_overrun, dispatcher)?;
+        }
+
+        self.retire_inputs(&instances);
+        self.retire_outputs(&instances);
+        self.instances = instances;
+        Ok(())
+    }
+
+    /// Update and poll every instance once, without scheduling anything, then flush all outputs.
+    ///
+    /// The returned future resolves once the samples of the poll have been delivered.
+    pub fn run_once(
+        &mut self,
+        config: &Config,
+        instances: Instances,
+    ) -> Result<Box<Future<Item = (), Error = Error>>> {
+        self.stop();
+
+        let mut inputs = instances.inputs();
+
+        if config.internal_metrics {
+            inputs.push(self.self_input());
+        }
+
+        let outputs = instances.outputs();
+        let bus = self.bus(config, &outputs);
+        self.health.track(&inputs, config.health_threshold)?;
+        self.load.set_threshold(config.max_queued_tasks());
+
+        let updater = Updater::new(
+            inputs.clone(),
+            self.cpupool.clone(),
+            config.reactor,
+            self.health.clone(),
+            self.telemetry.clone(),
+        );
+
+        let poller = Poller::new(inputs, config.max_concurrency(), self.poll_context(config, &bus));
+        let cpupool = self.cpupool.clone();
+        self.instances = instances;
+
+        let flush = move |_| {
+            cpupool.spawn_fn(move || {
+                bus.drain_all();
+
+                for output in outputs.iter() {
+                    if let Err(e) = output.flush() {
+                        error!("{}: failed to flush output: {}", output.options.name, e);
+                    }
+
+                    if let Err(e) = output.instance.shutdown() {
+                        error!("{}: failed to shut down output: {}", output.options.name, e);
+                    }
+                }
+
+                teardown_outputs(outputs);
+                Ok(())
+            })
+        };
+
+        Ok(Box::new(updater.run().and_then(move |_| poller.run()).and_then(flush)))
+    }
+
+    /// Update and poll every input once, resolving to the number of inputs which failed or reported
+    /// no samples.
+    ///
+    /// Problems are logged as warnings, and never fail the future. Samples are discarded, since
+    /// inputs are polled again once they are started.
+    pub fn self_test(
+        &self,
+        config: &Config,
+        instances: &Instances,
+    ) -> Box<Future<Item = usize, Error = Error>> {
+        let inputs = instances.inputs();
+        let total = inputs.len();
+        let cpupool = self.cpupool.clone();
+        let timer = self.timer.clone();
+        let poll_timeout = config.poll_timeout;
+
+        let updater = Updater::new(
+            inputs.clone(),
+            self.cpupool.clone(),
+            config.reactor,
+            self.health.clone(),
+            self.telemetry.clone(),
+        );
+
+        // update failures are logged by the updater, and show up in the poll as well.
+        let polls = updater.run().then(move |_| {
+            let polls: Vec<_> = inputs
+                .into_iter()
+                .map(|input| {
+                    let name = input.options.name.clone();
+                    let timeout = input.options.poll_timeout.unwrap_or(poll_timeout);
+
+                    let poll = cpupool.spawn_fn(move || {
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            logger::with_plugin(&input.options.name, || input.instance.poll())
+                        }));
+
+                        match result {
+                            Ok(result) => result,
+                            Err(payload) => Err(
+                                ErrorKind::Message(
+                                    format!("panicked: {}", panic_message(&payload)),
+                                ).into(),
+                            ),
+                        }
+                    });
+
+                    let sleep = timer.sleep(timeout).map_err(Into::<Error>::into);
+
+                    poll.select2(sleep).then(move |result| -> Result<usize> {
+                        let problem = match result {
+                            Ok(future::Either::A((ref samples, _))) if samples.is_empty() => {
+                                "reported no samples".to_owned()
+                            }
+                            Ok(future::Either::A(..)) => return Ok(0),
+                            Ok(future::Either::B(..)) => format!("timed out after {:?}", timeout),
+                            Err(future::Either::A((e, _))) => format!("failed: {}", e),
+                            Err(future::Either::B((e, _))) => format!("failed: {}", e),
+                        };
+
+                        warn!("{}: self-test: {}", name, problem);
+                        Ok(1)
+                    })
+                })
+                .collect();
+
+            future::join_all(polls)
+        });
+
+        Box::new(polls.map(move |problems| {
+            let problems: usize = problems.iter().sum();
+            info!("self-test: {} of {} input(s) failed or reported no samples", problems, total);
+            problems
+        }))
+    }
+
+    /// Set up the given sections and start them, replacing what is currently running.
+    ///
+    /// If any section fails to set up, the currently running instances are left untouched.
+    pub fn reload(&mut self, config: &Config, sections: Vec<PluginSection>) -> Result<()> {
+        let instances = self.prepare(config, sections)?;
+        let summary = ReloadSummary::diff(&self.instances.sections(), &instances.sections());
+
+        info!(
+            "running {} input(s) and {} output(s) ({} added, {} removed, {} changed, {} \
+             unchanged)",
+            instances.inputs.len(),
+            instances.outputs.len(),
+            summary.added,
+            summary.removed,
+            summary.changed,
+            summary.unchanged
+        );
+
+        self.start(config, instances)
+    }
+
+    /// Cancel all schedules.
+    pub fn stop(&mut self) {
+        for schedule in self.schedules.drain(..) {
+            schedule.cancel();
+        }
+
+        self.triggers.clear();
+    }
+
+    /// Cancel all schedules, and stop waiting for outputs which are falling behind.
+    ///
+    /// Polls blocked on a full output queue give up, and drop their samples.
+    pub fn shutdown(&mut self) {
+        self.stop();
+
+        if let Some(bus) = self.bus.take() {
+            bus.close();
+        }
+    }
+
+    /// Poll the named input instance immediately, in addition to its regular polls.
+    ///
+    /// Every input instance is polled if no name is given. These polls don't shift the phase of
+    /// the regular polls, so the next one still happens when it was due.
+    pub fn poll_now(&self, name: Option<&str>) -> Result<()> {
+        let name = match name {
+            Some(name) => name,
+            None => {
+                info!("polling all instances on demand");
+
+                for trigger in self.triggers.iter() {
+                    let poll = trigger.poll_all();
+                    self.handle.spawn(poll.map_err(|e| error!("poll failed: {}", e)));
+                }
+
+                return Ok(());
+            }
+        };
+
+        for trigger in self.triggers.iter() {
+            if let Some(poll) = trigger.poll_now(name) {
+                self.handle.spawn(poll.map_err(|e| error!("poll failed: {}", e)));
+                return Ok(());
+            }
+        }
+
+        Err(ErrorKind::Message(format!("no such input instance: {}", name)).into())
+    }
+
+    /// Running instances, inputs first.
+    pub fn describe(&self) -> Vec<InstanceDescription> {
+        let inputs = self.instances.inputs.iter().map(|&(ref s, ref i)| {
+            InstanceDescription::new(s, &i.options.name)
+        });
+
+        let outputs = self.instances.outputs.iter().map(|&(ref s, ref o)| {
+            InstanceDescription::new(s, &o.options.name)
+        });
+
+        inputs.chain(outputs).collect()
+    }
+
+    /// Health of the named input instance, `None` if no such instance is running.
+    pub fn status(&self, name: &str) -> Result<Option<InstanceHealth>> {
+        Ok(self.health.status()?.remove(name))
+    }
+
+    /// Currently running output instances.
+    pub fn outputs(&self) -> Vec<Arc<ConfiguredOutput>> {
+        self.instances.outputs()
+    }
+
+    /// Take the running instances, so that they can be torn down once the runtime has stopped.
+    pub fn take_instances(&mut self) -> Instances {
+        mem::replace(&mut self.instances, Instances::new())
+    }
+
+    /// Flush and tear down running exclusive outputs which the given sections won't reuse.
+    fn release_exclusive(&self, sections: &[PluginSection]) {
+        for &(ref section, ref output) in self.instances.outputs.iter() {
+            if !output.instance.exclusive() || sections.contains(section) {
+                continue;
+            }
+
+            let name = &output.options.name;
+            info!("{}: tearing down, so that its replacement can be set up", name);
+
+            if let Err(e) = output.flush() {
+                error!("{}: failed to flush retired output: {}", name, e);
+            }
+
+            let output = output.clone();
+            teardown(name, move || output.teardown());
+        }
+    }
+
+    /// Running output with the same id as the section, which a new instance replaces.
+    fn previous_output(&self, section: &PluginSection) -> Option<Arc<ConfiguredOutput>> {
+        self.instances
+            .outputs
+            .iter()
+            .find(|&&(ref s, _)| s.id == section.id)
+            .map(|&(_, ref o)| o.clone())
+    }
+
+    /// Move the spools of replaced outputs to their replacements, so that a spool file is only
+    /// ever used by one output.
+    fn hand_over_spools(&self, instances: &Instances) {
+        for &(ref section, ref output) in instances.outputs.iter() {
+            if let Some(previous) = self.previous_output(section) {
+                if !Arc::ptr_eq(&previous, output) {
+                    previous.hand_over_spool(output);
+                }
+            }
+        }
+    }
+
+    /// Tear down input instances which are not part of the new instances.
+    fn retire_inputs(&self, instances: &Instances) {
+        let retired: Vec<Arc<ConfiguredInput>> = self.instances
+            .inputs
+            .iter()
+            .map(|&(_, ref i)| i.clone())
+            .filter(|i| !instances.inputs.iter().any(|&(_, ref n)| Arc::ptr_eq(i, n)))
+            .collect();
+
+        if retired.is_empty() {
+            return;
+        }
+
+        self.cpupool
+            .spawn_fn(move || {
+                teardown_inputs(retired);
+                Ok::<(), ()>(())
+            })
+            .forget();
+    }
+
+    /// Deliver pending batches of outputs which are not part of the new instances, then tear them
+    /// down.
+    fn retire_outputs(&self, instances: &Instances) {
+        let retired: Vec<Arc<ConfiguredOutput>> = self.instances
+            .outputs
+            .iter()
+            .map(|&(_, ref o)| o.clone())
+            .filter(|o| !instances.outputs.iter().any(|&(_, ref n)| Arc::ptr_eq(o, n)))
+            .collect();
+
+        if retired.is_empty() {
+            return;
+        }
+
+        self.cpupool
+            .spawn_fn(move || {
+                for output in retired.iter() {
+                    if let Err(e) = output.flush() {
+                        error!("{}: failed to flush retired output: {}", output.options.name, e);
+                    }
+                }
+
+                teardown_outputs(retired);
+                Ok::<(), ()>(())
+            })
+            .forget();
+    }
+
+    /// Bus delivering samples to the given outputs.
+    fn bus(&self, config: &Config, outputs: &[Arc<ConfiguredOutput>]) -> Arc<SampleBus> {
+        let mut bus = SampleBus::new(
+            self.cpupool.clone(),
+            config.buffer_size,
+            config.on_full,
+            self.telemetry.clone(),
+        );
+
+        for output in outputs {
+            bus.subscribe(output.clone());
+        }
+
+        Arc::new(bus)
+    }
+
+    /// State shared by the pollers of every group of input instances.
+    ///
+    /// The rate limit is shared too, since it limits all samples fed to outputs.
+    fn poll_context(&self, config: &Config, bus: &Arc<SampleBus>) -> Arc<PollContext> {
+        let rate_limit = config.max_samples_per_second.map(|rate| Arc::new(RateLimit::new(rate)));
+
+        Arc::new(PollContext {
+            bus: bus.clone(),
+            cpupool: self.cpupool.clone(),
+            timer: self.timer.clone(),
+            poll_timeout: config.poll_timeout,
+            tags: Arc::new(config.tags.clone()),
+            rename: Arc::new(config.rename.clone()),
+            health: self.health.clone(),
+            telemetry: self.telemetry.clone(),
+            rate_limit: rate_limit,
+            load: self.load.clone(),
+            derivatives: self.derivatives.clone(),
+            histograms: self.histograms.clone(),
+            histogram_rules: Arc::new(config.histograms.clone()),
+            clock: config.clock,
+            reactor: config.reactor,
+            on_non_finite: config.on_non_finite,
+        })
+    }
+
+    /// Restore the state of the running instance with the same id into its replacement.
+    ///
+    /// Failing to hand over state is logged, since the new instance works without it.
+    fn hand_over_state(&self, section: &PluginSection, input: &ConfiguredInput) {
+        let previous = self.instances.inputs.iter().find(|&&(ref s, _)| {
+            s.id == section.id && s.table.get("type") == section.table.get("type")
+        });
+
+        let previous = match previous {
+            Some(&(_, ref previous)) => previous,
+            None => return,
+        };
+
+        let (saving, restoring) = match (previous.instance.state(), input.instance.state()) {
+            (Some(saving), Some(restoring)) => (saving, restoring),
+            _ => return,
+        };
+
+        let result = saving.save().and_then(|saved| match saved {
+            Some(saved) => restoring.restore(saved),
+            None => Ok(()),
+        });
+
+        match result {
+            Ok(()) => debug!("{}: restored state of the previous instance", section.id),
+            Err(e) => {
+                warn!("{}: failed to restore state of the previous instance: {}", section.id, e)
+            }
+        }
+    }
+
+    /// Input instance which reports internal metrics.
+    fn self_input(&self) -> Arc<ConfiguredInput> {
+        let options = InstanceOptions {
+            name: SELF_INSTANCE.to_owned(),
+            ..InstanceOptions::default()
+        };
+
+        Arc::new(ConfiguredInput {
+            id: SELF_INSTANCE.to_owned(),
+            options: options,
+            tags: vec![("plugin".to_owned(), SELF_INSTANCE.to_owned())],
+            instance: Box::new(SelfInputInstance::new(self.telemetry.clone())),
+        })
+    }
+
+    /// Schedule a task, where `kind` and the interval identify the schedule in internal metrics.
+    fn spawn<R: Runnable + 'static>(
+        &mut self,
+        kind: &str,
+        delay: Duration,
+        interval: Duration,
+        overrun: Overrun,
+        task: R,
+    ) -> Result<()> {
+        let millis = interval.as_secs() * 1000 + interval.subsec_nanos() as u64 / 1_000_000;
+        let name = format!("{}-{}ms", kind, millis);
+
+        let (schedule, task) = schedule(
+            &self.handle,
+            name,
+            delay,
+            interval,
+            overrun,
+            self.telemetry.clone(),
+            task,
+        )?;
+
+        self.handle.spawn(task.map_err(
+            |e| error!("scheduled task failed: {}", e),
+        ));
+
+        self.schedules.push(schedule);
+        Ok(())
+    }
+}
+
+/// Log and discard setup errors if `skip_failed` is enabled.
+fn skip_failed<T>(
+    config: &Config,
+    section: &PluginSection,
+    result: Result<Option<T>>,
+) -> Result<Option<T>> {
+    match result {
+        Err(e) => {
+            if !config.skip_failed {
+                return Err(e);
+            }
+
+            error!("{}: failed to set up, skipping: {}", section.id, e);
+
+            for e in e.iter().skip(1) {
+                error!("  caused by: {}", e);
+            }
+
+            Ok(None)
+        }
+        result => result,
+    }
+}
+
+/// Group input instances by the interval they should be scheduled at.
+fn group_by_interval<K, F>(
+    inputs: &Vec<Arc<ConfiguredInput>>,
+    interval: F,
+) -> BTreeMap<K, Vec<Arc<ConfiguredInput>>>
+where
+    K: Ord,
+    F: Fn(&ConfiguredInput) -> K,
+{
+    let mut groups = BTreeMap::new();
+
+    for input in inputs {
+        groups
+            .entry(interval(input))
+            .or_insert_with(Vec::new)
+            .push(input.clone());
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use plugins::load_plugins;
+    use std::net::TcpListener;
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn test_reload_summary() {
+        let cpu = PluginSection::parse(PluginKind::Input, "cpu", "type = \"cpu\"");
+        let disk = PluginSection::parse(PluginKind::Input, "disk", "type = \"disk\"");
+        let load = PluginSection::parse(PluginKind::Input, "load", "type = \"load\"");
+        let mem = PluginSection::parse(PluginKind::Input, "mem", "type = \"mem\"");
+        let slow_disk =
+            PluginSection::parse(PluginKind::Input, "disk", "type = \"disk\"\ninterval = \"1m\"");
+
+        let summary = ReloadSummary::diff(&[&cpu, &disk, &load], &[&cpu, &slow_disk, &mem]);
+
+        let expected = ReloadSummary {
+            added: 1,
+            removed: 1,
+            changed: 1,
+            unchanged: 1,
+        };
+
+        assert_eq!(expected, summary);
+    }
+
+    #[test]
+    fn test_reload_changed_prometheus() {
+        let core = Core::new().unwrap();
+        let pool = Arc::new(CpuPool::new(1));
+        let mut runtime = Runtime::new(core.handle(), pool, load_plugins());
+        let config = Config::new();
+
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let content = format!("type = \"prometheus\"\naddress = \"127.0.0.1\"\nport = {}", port);
+
+        let prom = PluginSection::parse(PluginKind::Output, "prom", &content);
+        runtime.reload(&config, vec![prom]).unwrap();
+
+        // the changed section binds the same port, without the reactor running in between.
+        let changed = format!("{}\nformat = \"openmetrics\"", content);
+        let prom = PluginSection::parse(PluginKind::Output, "prom", &changed);
+        runtime.reload(&config, vec![prom.clone()]).unwrap();
+
+        // an unchanged section keeps the running instance, and its port.
+        runtime.reload(&config, vec![prom]).unwrap();
+        assert!(TcpListener::bind(("127.0.0.1", port)).is_err());
+    }
+
+    #[test]
+    fn test_reload_removed_listener() {
+        let core = Core::new().unwrap();
+        let pool = Arc::new(CpuPool::new(1));
+        let mut runtime = Runtime::new(core.handle(), pool, load_plugins());
+        let config = Config::new();
+
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let snoop = format!("type = \"snoop\"\nbind = \"127.0.0.1:{}\"", port);
+        let stdout = PluginSection::parse(PluginKind::Output, "stdout", "type = \"stdout\"");
+
+        let snoop = PluginSection::parse(PluginKind::Output, "snoop", &snoop);
+        runtime.reload(&config, vec![snoop, stdout.clone()]).unwrap();
+        assert!(TcpListener::bind(("127.0.0.1", port)).is_err());
+
+        // removing the section releases the port, without waiting for the reactor.
+        runtime.reload(&config, vec![stdout]).unwrap();
+        assert!(TcpListener::bind(("127.0.0.1", port)).is_ok());
+    }
+
+    #[test]
+    fn test_reload_hands_over_spool() {
+        let core = Core::new().unwrap();
+        let pool = Arc::new(CpuPool::new(1));
+        let mut runtime = Runtime::new(core.handle(), pool, load_plugins());
+        let config = Config::new();
+
+        let dir = ::std::env::temp_dir().join("sysmon-runtime-test-spool");
+        let content = format!("type = \"stdout\"\nspool_dir = {:?}", dir.to_string_lossy());
+
+        let stdout = PluginSection::parse(PluginKind::Output, "stdout", &content);
+        runtime.reload(&config, vec![stdout]).unwrap();
+        let previous = runtime.outputs().remove(0);
+        assert!(previous.has_spool());
+
+        let changed = format!("{}\nbatch_size = 10", content);
+        let stdout = PluginSection::parse(PluginKind::Output, "stdout", &changed);
+        runtime.reload(&config, vec![stdout]).unwrap();
+        let output = runtime.outputs().remove(0);
+
+        assert!(!Arc::ptr_eq(&previous, &output));
+        assert!(output.has_spool());
+        assert!(!previous.has_spool());
+    }
+}
@@ -1,6 +1,265 @@
 use errors::*;
 use futures::*;
+use futures::stream::{self, Stream};
+use futures::sync::oneshot;
+use rand::{self, Rng, SeedableRng, XorShiftRng};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use telemetry::Telemetry;
+use tokio_core::reactor::{Handle, Interval, Timeout};
 
 pub trait Runnable {
     fn run(&self) -> Box<Future<Item = (), Error = Error>>;
 }
+
+/// What to do about ticks which were missed, because a run took longer than the interval.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overrun {
+    /// Skip missed ticks, and run again on the next boundary of the interval.
+    Skip,
+    /// Run once for every missed tick, back-to-back, before returning to the interval.
+    CatchUp,
+}
+
+#[derive(PartialEq)]
+enum Event {
+    Tick,
+    Cancel,
+}
+
+/// Handle to a scheduled task, used to cancel it.
+///
+/// Dropping the handle without calling `cancel` leaves the task running.
+pub struct ScheduleHandle {
+    cancel: oneshot::Sender<()>,
+}
+
+impl ScheduleHandle {
+    /// Stop the scheduled task.
+    ///
+    /// A run which is already in progress is allowed to complete, after which the future returned
+    /// by `schedule` resolves.
+    pub fn cancel(self) {
+        // the schedule might already have stopped, in which case there is nothing to cancel.
+        let _ = self.cancel.send(());
+    }
+}
+
+/// Random offsets used to spread out the first run of schedules sharing the same interval.
+pub struct Jitter {
+    /// Largest offset, as a fraction of the interval.
+    fraction: f64,
+    rng: XorShiftRng,
+}
+
+impl Jitter {
+    /// Create a new source of offsets.
+    ///
+    /// Offsets are deterministic if a seed is provided.
+    pub fn new(fraction: f64, seed: Option<u64>) -> Jitter {
+        let rng = match seed {
+            // the constants keep the state from being all zeroes, which xorshift does not allow.
+            Some(seed) => {
+                XorShiftRng::from_seed([seed as u32, (seed >> 32) as u32, 0x9e37_79b9, 0x243f_6a88])
+            }
+            None => rand::weak_rng(),
+        };
+
+        Jitter {
+            fraction: fraction,
+            rng: rng,
+        }
+    }
+
+    /// Pick a random offset in `[0, fraction * interval)`.
+    pub fn offset(&mut self, interval: Duration) -> Duration {
+        if self.fraction <= 0f64 {
+            return Duration::new(0, 0);
+        }
+
+        let nanos = interval.as_secs() as f64 * 1e9 + interval.subsec_nanos() as f64;
+        let nanos = (nanos * self.fraction * self.rng.next_f64()) as u64;
+        Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+    }
+}
+
+/// Time from `now` until the next wall-clock boundary of `interval`.
+///
+/// Boundaries are multiples of the interval since the unix epoch, so a `60s` interval is aligned
+/// to the start of every minute.
+pub fn align_delay(now: SystemTime, interval: Duration) -> Duration {
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or(Duration::new(0, 0));
+
+    let interval_nanos = to_nanos(interval);
+
+    if interval_nanos == 0 {
+        return Duration::new(0, 0);
+    }
+
+    let remainder = to_nanos(since_epoch) % interval_nanos;
+
+    if remainder == 0 {
+        return Duration::new(0, 0);
+    }
+
+    let nanos = interval_nanos - remainder;
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+fn to_nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64
+}
+
+/// Number of ticks which were missed, given how late a tick was observed.
+///
+/// A tick observed less than one interval late is on time, so no ticks were missed.
+pub fn missed_ticks(late: Duration, interval: Duration) -> u64 {
+    let interval = to_nanos(interval);
+
+    if interval == 0 {
+        return 0;
+    }
+
+    to_nanos(late) / interval
+}
+
+/// Schedule the given task to run once after `delay`, and then once every `interval`.
+///
+/// Runs are sequential, so a run which takes longer than the interval causes ticks to be missed,
+/// which are handled according to `overrun`. How late every tick is observed is recorded in
+/// `telemetry` under `name`.
+///
+/// The returned future resolves when the schedule is cancelled through its handle, or fails if the
+/// task fails.
+pub fn schedule<R>(
+    handle: &Handle,
+    name: String,
+    delay: Duration,
+    interval: Duration,
+    overrun: Overrun,
+    telemetry: Arc<Telemetry>,
+    task: R,
+) -> Result<(ScheduleHandle, Box<Future<Item = (), Error = Error>>)>
+where
+    R: Runnable + 'static,
+{
+    let (tx, rx) = oneshot::channel::<()>();
+
+    let cancel = rx.then(|result| -> Box<Future<Item = Event, Error = Error>> {
+        match result {
+            Ok(()) => Box::new(future::ok(Event::Cancel)),
+            // handle was dropped without cancelling, so keep running.
+            Err(_) => Box::new(future::empty()),
+        }
+    });
+
+    let interval_handle = handle.clone();
+
+    // the interval itself skips ticks which were missed, firing on the next boundary.
+    let ticks = Timeout::new(delay, handle)?
+        .and_then(move |_| Interval::new(interval, &interval_handle))
+        .map(|rest| stream::once(Ok(())).chain(rest))
+        .flatten_stream()
+        .map_err(Into::into)
+        .map(|_| Event::Tick);
+
+    let task = Rc::new(task);
+    let mut next = Instant::now() + delay;
+
+    // runs are sequential, so a cancellation is only observed between two runs.
+    let run = ticks
+        .select(cancel.into_stream())
+        .take_while(|event| Ok(*event == Event::Tick))
+        .and_then(move |_| {
+            let now = Instant::now();
+
+            let late = if now > next {
+                now.duration_since(next)
+            } else {
+                Duration::new(0, 0)
+            };
+
+            telemetry.tick(&name, late);
+
+            let missed = missed_ticks(late, interval);
+            next = next + interval * (missed as u32 + 1);
+
+            let runs = match overrun {
+                Overrun::Skip => {
+                    if missed > 0 {
+                        warn!("{}: previous run overran, skipped {} tick(s)", name, missed);
+                    }
+
+                    1
+                }
+                Overrun::CatchUp => {
+                    if missed > 0 {
+                        warn!("{}: previous run overran, catching up {} tick(s)", name, missed);
+                    }
+
+                    missed + 1
+                }
+            };
+
+            let task = task.clone();
+            let telemetry = telemetry.clone();
+            let name = name.clone();
+
+            stream::iter_ok::<_, Error>(0..runs).for_each(move |_| {
+                let started = Instant::now();
+                let telemetry = telemetry.clone();
+                let name = name.clone();
+
+                task.run().map(move |_| telemetry.run_finished(&name, started.elapsed()))
+            })
+        })
+        .for_each(|_| Ok(()));
+
+    Ok((ScheduleHandle { cancel: tx }, Box::new(run)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_jitter_seed() {
+        let interval = Duration::new(10, 0);
+        let mut a = Jitter::new(0.5, Some(42));
+        let mut b = Jitter::new(0.5, Some(42));
+
+        for _ in 0..10 {
+            let offset = a.offset(interval);
+            assert_eq!(offset, b.offset(interval));
+            assert!(offset < Duration::new(5, 0));
+        }
+    }
+
+    #[test]
+    fn test_align_delay() {
+        let minute = Duration::new(60, 0);
+        let at = |secs, nanos| UNIX_EPOCH + Duration::new(secs, nanos);
+
+        assert_eq!(Duration::new(0, 0), align_delay(at(1500000000, 0), minute));
+        assert_eq!(Duration::new(20, 0), align_delay(at(1500000040, 0), minute));
+        assert_eq!(Duration::new(19, 500_000_000), align_delay(at(1500000040, 500_000_000), minute));
+    }
+
+    #[test]
+    fn test_jitter_disabled() {
+        let mut jitter = Jitter::new(0f64, None);
+        assert_eq!(Duration::new(0, 0), jitter.offset(Duration::new(10, 0)));
+    }
+
+    #[test]
+    fn test_missed_ticks() {
+        let interval = Duration::new(10, 0);
+
+        assert_eq!(0, missed_ticks(Duration::new(0, 0), interval));
+        assert_eq!(0, missed_ticks(Duration::new(9, 999_999_999), interval));
+        assert_eq!(1, missed_ticks(Duration::new(10, 0), interval));
+        assert_eq!(3, missed_ticks(Duration::new(35, 0), interval));
+        assert_eq!(0, missed_ticks(Duration::new(35, 0), Duration::new(0, 0)));
+    }
+}
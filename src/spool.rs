@@ -0,0 +1,459 @@
+//! Spooling of samples an output could not deliver to disk.
+//!
+//! Samples which don't fit in the backoff buffer of an output are appended to a file in its
+//! `spool_dir`, one JSON record per line. Once the output recovers, the file is drained before any
+//! newer samples are delivered, and it is truncated whenever it has been drained completely.
+//!
+//! The offset of the first record which hasn't been delivered is kept next to the spool, in a
+//! `.offset` file, so that a spool which wasn't drained completely when sysmon stopped continues
+//! where it left off. Only the samples of a delivery which completed right before sysmon stopped
+//! might be delivered again.
+
+use errors::*;
+use metric::MetricId;
+use plugin::{Sample, SampleKind};
+use serde_json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Largest size of a spool file, unless configured otherwise.
+pub const DEFAULT_MAX_SPOOL_BYTES: u64 = 100 * 1024 * 1024;
+
+/// A single spooled sample.
+#[derive(Serialize, Deserialize, Debug)]
+struct Record {
+    key: Option<String>,
+    tags: Vec<(String, String)>,
+    resource: Vec<(String, String)>,
+    /// Value as a string, since JSON can't represent NaN or infinities.
+    value: String,
+    kind: String,
+    /// Nanoseconds since the unix epoch.
+    timestamp: u64,
+}
+
+impl Record {
+    fn from_sample(sample: &Sample) -> Record {
+        let id = &sample.metric_id;
+
+        Record {
+            key: id.key().map(ToOwned::to_owned),
+            tags: id.tags().to_vec(),
+            resource: id.resource().to_vec(),
+            value: sample.value.to_string(),
+            kind: sample.kind.as_str().to_owned(),
+            timestamp: sample.timestamp_nanos(),
+        }
+    }
+
+    /// Sample of the record, metadata of the metric is not spooled.
+    fn into_sample(self) -> Result<Sample> {
+        let value: f64 = self.value.parse().map_err(|_| {
+            ErrorKind::Message(format!("invalid value: {}", self.value))
+        })?;
+
+        let kind = match self.kind.as_str() {
+            "gauge" => SampleKind::Gauge,
+            "counter" => SampleKind::Counter,
+            "derive" => SampleKind::Derive,
+            "observation" => SampleKind::Observation,
+            "histogram" => SampleKind::Histogram,
+            kind => return Err(ErrorKind::Message(format!("invalid kind: {}", kind)).into()),
+        };
+
+        let mut builder = MetricId::new();
+
+        if let Some(ref key) = self.key {
+            builder = builder.key(key);
+        }
+
+        for &(ref key, ref value) in self.tags.iter() {
+            builder = builder.tag(key, value);
+        }
+
+        for &(ref key, ref value) in self.resource.iter() {
+            builder = builder.resource(key, value);
+        }
+
+        let mut sample = Sample::new(Arc::new(builder.build()), value).with_kind(kind);
+
+        sample.timestamp = UNIX_EPOCH +
+            Duration::new(
+                self.timestamp / 1_000_000_000,
+                (self.timestamp % 1_000_000_000) as u32,
+            );
+
+        Ok(sample)
+    }
+}
+
+/// Name of the spool file of an output.
+///
+/// Bytes which don't belong in a file name are escaped as `_` followed by their hex value, `_`
+/// included, so that every id gets a file of its own.
+fn file_name(id: &str) -> String {
+    let mut name = String::new();
+
+    for b in id.bytes() {
+        match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' => name.push(b as char),
+            b => name.push_str(&format!("_{:02x}", b)),
+        }
+    }
+
+    format!("{}.spool", name)
+}
+
+/// Path of the spool of the output with the given id in `dir`.
+pub fn spool_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(file_name(id))
+}
+
+/// Read the offset saved next to a spool, or `0` if there is none.
+///
+/// An offset which can't be read is logged, the spool is then drained from the start.
+fn load_offset(path: &Path) -> u64 {
+    let mut content = String::new();
+
+    match File::open(path).and_then(|mut f| f.read_to_string(&mut content)) {
+        Ok(_) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return 0,
+        Err(e) => {
+            warn!("{}: could not read offset: {}", path.display(), e);
+            return 0;
+        }
+    }
+
+    match content.trim().parse::<u64>() {
+        Ok(offset) => offset,
+        Err(_) => {
+            warn!("{}: invalid offset: {:?}", path.display(), content);
+            0
+        }
+    }
+}
+
+/// Append-only file of samples waiting to be delivered to an output, oldest first.
+pub struct Spool {
+    path: PathBuf,
+    /// Where `offset` is saved, so that it survives restarts.
+    offset_path: PathBuf,
+    max_bytes: u64,
+    /// Offset of the first record which hasn't been delivered.
+    offset: u64,
+}
+
+impl Spool {
+    /// Open the spool of the output with the given id in `dir`, which is created if needed.
+    ///
+    /// Samples left behind by a previous run are kept, and the ones which weren't delivered yet
+    /// are delivered first.
+    pub fn open(dir: &Path, id: &str, max_bytes: Option<u64>) -> Result<Spool> {
+        fs::create_dir_all(dir).chain_err(|| {
+            format!("could not create spool directory {}", dir.display())
+        })?;
+
+        let path = spool_path(dir, id);
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .chain_err(|| format!("could not open spool {}", path.display()))?;
+
+        let offset_path = path.with_extension("offset");
+        let len = file.metadata()?.len();
+        let mut offset = load_offset(&offset_path);
+
+        // the spool was truncated by someone else.
+        if offset > len {
+            offset = 0;
+        }
+
+        Ok(Spool {
+            path: path,
+            offset_path: offset_path,
+            max_bytes: max_bytes.unwrap_or(DEFAULT_MAX_SPOOL_BYTES),
+            offset: offset,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Change the largest size of the spool, like when it is handed over to another output.
+    pub fn set_max_bytes(&mut self, max_bytes: Option<u64>) {
+        self.max_bytes = max_bytes.unwrap_or(DEFAULT_MAX_SPOOL_BYTES);
+    }
+
+    fn len(&self) -> Result<u64> {
+        Ok(fs::metadata(&self.path)?.len())
+    }
+
+    /// If every spooled sample has been delivered.
+    pub fn is_empty(&self) -> bool {
+        self.len().map(|len| self.offset >= len).unwrap_or(true)
+    }
+
+    /// Append samples, returning how many of the newest ones were dropped since the spool is full.
+    pub fn push(&mut self, samples: &[Sample]) -> Result<usize> {
+        let mut len = self.len()?;
+        let mut buffer = Vec::new();
+
+        for (index, sample) in samples.iter().enumerate() {
+            let mut line = serde_json::to_vec(&Record::from_sample(sample))?;
+            line.push(b'\n');
+
+            let size = len + (buffer.len() + line.len()) as u64;
+
+            if size > self.max_bytes && self.offset > 0 {
+                self.compact()?;
+                len = self.len()?;
+            }
+
+            if len + (buffer.len() + line.len()) as u64 > self.max_bytes {
+                self.append(&buffer)?;
+                return Ok(samples.len() - index);
+            }
+
+            buffer.extend(line);
+        }
+
+        self.append(&buffer)?;
+        Ok(0)
+    }
+
+    fn append(&self, buffer: &[u8]) -> Result<()> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        file.write_all(buffer)?;
+        Ok(())
+    }
+
+    /// Remove delivered records from the start of the file.
+    fn compact(&mut self) -> Result<()> {
+        let tmp = self.path.with_extension("spool.tmp");
+
+        {
+            let mut file = File::open(&self.path)?;
+            file.seek(SeekFrom::Start(self.offset))?;
+            io::copy(&mut file, &mut File::create(&tmp)?)?;
+        }
+
+        // the saved offset is removed first, since it would skip records of the compacted file.
+        let offset = mem::replace(&mut self.offset, 0);
+
+        if let Err(e) = self.save_offset() {
+            self.offset = offset;
+            return Err(e);
+        }
+
+        if let Err(e) = fs::rename(&tmp, &self.path) {
+            self.offset = offset;
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    /// Save the offset, a spool without a saved offset is read from the start.
+    fn save_offset(&self) -> Result<()> {
+        if self.offset == 0 {
+            return match fs::remove_file(&self.offset_path) {
+                Err(ref e) if e.kind() != io::ErrorKind::NotFound => {
+                    Err(format!("could not remove {}: {}", self.offset_path.display(), e).into())
+                }
+                _ => Ok(()),
+            };
+        }
+
+        let tmp = self.path.with_extension("offset.tmp");
+        File::create(&tmp)?.write_all(self.offset.to_string().as_bytes())?;
+        fs::rename(&tmp, &self.offset_path)?;
+        Ok(())
+    }
+
+    /// Read up to `max` of the oldest samples, without removing them.
+    ///
+    /// Returns the samples, and the number of bytes to `consume` once they have been delivered.
+    /// Records which can't be decoded, like one which was partially written when sysmon stopped,
+    /// are skipped.
+    ///
+    /// Samples can be pushed while the ones read are being delivered, since compacting the spool
+    /// only removes records which were consumed before.
+    pub fn read(&self, max: usize) -> Result<(Vec<Sample>, u64)> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        reader.seek(SeekFrom::Start(self.offset))?;
+
+        let mut read = 0u64;
+        let mut samples = Vec::new();
+        let mut line = String::new();
+
+        while samples.len() < max {
+            line.clear();
+
+            let n = reader.read_line(&mut line)?;
+
+            if n == 0 {
+                break;
+            }
+
+            read += n as u64;
+
+            let record = serde_json::from_str::<Record>(&line).map_err(Into::<Error>::into);
+
+            match record.and_then(Record::into_sample) {
+                Ok(sample) => samples.push(sample),
+                Err(e) => warn!("{}: skipping invalid record: {}", self.path.display(), e),
+            }
+        }
+
+        Ok((samples, read))
+    }
+
+    /// Remove the samples returned by `read`, given the number of bytes it returned.
+    ///
+    /// The file is truncated once every sample has been removed.
+    pub fn consume(&mut self, read: u64) -> Result<()> {
+        self.offset += read;
+
+        if self.offset >= self.len()? {
+            File::create(&self.path)?;
+            self.offset = 0;
+        }
+
+        self.save_offset()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metric::key;
+    use std::env;
+
+    fn dir() -> PathBuf {
+        env::temp_dir().join("sysmon-spool-test")
+    }
+
+    fn spool(name: &str, max_bytes: Option<u64>) -> Spool {
+        let path = spool_path(&dir(), name);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("offset"));
+        Spool::open(&dir(), name, max_bytes).unwrap()
+    }
+
+    fn values(samples: &[Sample]) -> Vec<String> {
+        samples.iter().map(|s| s.value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut spool = spool("round-trip", None);
+        let id = Arc::new(key("disk").tag("mount", "/").resource("host", "a").build());
+
+        let samples = vec![
+            Sample::new(id.clone(), 1f64),
+            Sample::new(id.clone(), ::std::f64::NAN).with_kind(SampleKind::Counter),
+            Sample::new(id.clone(), 3f64),
+        ];
+
+        assert!(spool.is_empty());
+        assert_eq!(0, spool.push(&samples).unwrap());
+        assert!(!spool.is_empty());
+
+        let (first, read) = spool.read(2).unwrap();
+        assert_eq!(vec!["1", "NaN"], values(&first));
+        assert_eq!(id, first[0].metric_id);
+        assert_eq!(SampleKind::Counter, first[1].kind);
+        assert_eq!(samples[0].timestamp_nanos(), first[0].timestamp_nanos());
+
+        // reading again without consuming returns the same samples.
+        assert_eq!(vec!["1", "NaN"], values(&spool.read(2).unwrap().0));
+
+        spool.consume(read).unwrap();
+
+        let (rest, read) = spool.read(10).unwrap();
+        assert_eq!(vec!["3"], values(&rest));
+
+        spool.consume(read).unwrap();
+        assert!(spool.is_empty());
+        assert_eq!(0, fs::metadata(spool.path()).unwrap().len());
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let id = Arc::new(key("test").build());
+        let samples: Vec<Sample> = (0..3).map(|v| Sample::new(id.clone(), v as f64)).collect();
+
+        let line = serde_json::to_vec(&Record::from_sample(&samples[0])).unwrap().len() + 1;
+        let mut spool = spool("max-bytes", Some(line as u64 * 2));
+
+        assert_eq!(1, spool.push(&samples).unwrap());
+
+        // delivered records make room once they are compacted away.
+        let (_, read) = spool.read(1).unwrap();
+        spool.consume(read).unwrap();
+        assert_eq!(0, spool.push(&samples[2..]).unwrap());
+
+        assert_eq!(vec!["1", "2"], values(&spool.read(10).unwrap().0));
+    }
+
+    #[test]
+    fn test_push_while_reading() {
+        let id = Arc::new(key("test").build());
+        let samples: Vec<Sample> = (0..4).map(|v| Sample::new(id.clone(), v as f64)).collect();
+
+        let line = serde_json::to_vec(&Record::from_sample(&samples[0])).unwrap().len() + 1;
+        let mut spool = spool("push-while-reading", Some(line as u64 * 3));
+
+        spool.push(&samples[..2]).unwrap();
+        let (_, read) = spool.read(1).unwrap();
+        spool.consume(read).unwrap();
+
+        // compacting while a sample is being delivered keeps it at the start.
+        let (delivering, read) = spool.read(1).unwrap();
+        assert_eq!(0, spool.push(&samples[2..]).unwrap());
+        spool.consume(read).unwrap();
+
+        assert_eq!(vec!["1"], values(&delivering));
+        assert_eq!(vec!["2", "3"], values(&spool.read(10).unwrap().0));
+    }
+
+    #[test]
+    fn test_reopen() {
+        let id = Arc::new(key("test").build());
+        let samples: Vec<Sample> = (0..3).map(|v| Sample::new(id.clone(), v as f64)).collect();
+
+        let mut spool = spool("reopen", None);
+        spool.push(&samples).unwrap();
+
+        let (_, read) = spool.read(1).unwrap();
+        spool.consume(read).unwrap();
+
+        // delivered samples are not read again by the next run.
+        let mut spool = Spool::open(&dir(), "reopen", None).unwrap();
+        let (rest, read) = spool.read(10).unwrap();
+        assert_eq!(vec!["1", "2"], values(&rest));
+
+        spool.consume(read).unwrap();
+        assert!(!spool.path().with_extension("offset").exists());
+        assert!(Spool::open(&dir(), "reopen", None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!("influxdb.spool", file_name("influxdb"));
+        assert_eq!("in_2ecpu.spool", file_name("in.cpu"));
+        assert_eq!("in_5fcpu.spool", file_name("in_cpu"));
+        assert_eq!("a_2fb_5b0_5d.spool", file_name("a/b[0]"));
+    }
+}
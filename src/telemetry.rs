@@ -0,0 +1,360 @@
+//! Metrics about sysmon itself, as recorded by the poller, the updater, and the scheduler.
+//!
+//! With `internal_metrics` enabled, the runtime adds an input instance named `self` which reports
+//! them through the regular outputs.
+
+use errors::*;
+use metric::*;
+use plugin::*;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Name of the input instance reporting internal metrics.
+pub const SELF_INSTANCE: &'static str = "self";
+
+/// Largest number of durations kept until the `self` instance reports them, older ones are
+/// dropped first.
+const MAX_PENDING_OBSERVATIONS: usize = 10000;
+
+/// Counters of a single input instance.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct InstanceTelemetry {
+    successes: u64,
+    failures: u64,
+    /// Samples dropped because of `max_samples_per_poll` or `max_samples_per_second`.
+    dropped: u64,
+    /// Duration of the last successful poll.
+    last_duration: Option<Duration>,
+}
+
+struct State {
+    instances: BTreeMap<String, InstanceTelemetry>,
+    /// How late the last run of every schedule started, keyed by schedule.
+    drift: BTreeMap<String, Duration>,
+    /// Samples dropped because the queue of an output was full, keyed by output.
+    output_drops: BTreeMap<String, u64>,
+    /// Unchanged samples suppressed by `dedup`, keyed by output.
+    output_dedup: BTreeMap<String, u64>,
+    /// Samples with NaN or infinite values, keyed by input instance.
+    non_finite: BTreeMap<String, u64>,
+    /// Durations recorded since the last report, which are aggregated into histograms.
+    observations: VecDeque<(Arc<MetricId>, f64)>,
+}
+
+/// Internal metrics, shared between the runtime and the tasks it schedules.
+pub struct Telemetry {
+    state: Mutex<State>,
+    /// Number of updates which have started, but not completed.
+    updates_in_progress: AtomicUsize,
+    /// Largest number of tasks queued onto the thread pool by pollers.
+    pool_high_water: AtomicUsize,
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000f64 + duration.subsec_nanos() as f64 / 1e6
+}
+
+fn as_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+impl State {
+    /// Record a duration to report as an observation, in seconds.
+    fn observe(&mut self, builder: MetricIdBuilder, duration: Duration) {
+        if self.observations.len() >= MAX_PENDING_OBSERVATIONS {
+            self.observations.pop_front();
+        }
+
+        let metric_id = builder.tag("unit", "s").build();
+        self.observations.push_back((Arc::new(metric_id), as_secs(duration)));
+    }
+}
+
+impl Telemetry {
+    pub fn new() -> Telemetry {
+        Telemetry {
+            state: Mutex::new(State {
+                instances: BTreeMap::new(),
+                drift: BTreeMap::new(),
+                output_drops: BTreeMap::new(),
+                output_dedup: BTreeMap::new(),
+                non_finite: BTreeMap::new(),
+                observations: VecDeque::new(),
+            }),
+            updates_in_progress: AtomicUsize::new(0),
+            pool_high_water: AtomicUsize::new(0),
+        }
+    }
+
+    /// Record a successful poll of the named instance.
+    pub fn poll_success(&self, name: &str, duration: Duration) {
+        self.update(|state| {
+            let instance = state.instances.entry(name.to_owned()).or_insert_with(
+                Default::default,
+            );
+
+            instance.successes += 1;
+            instance.last_duration = Some(duration);
+
+            let latency = key("sysmon").tag("instance", name).tag("what", "poll-latency");
+            state.observe(latency, duration);
+        });
+    }
+
+    /// Record a failed poll of the named instance.
+    pub fn poll_failure(&self, name: &str) {
+        self.update(|state| {
+            let instance = state.instances.entry(name.to_owned()).or_insert_with(
+                Default::default,
+            );
+
+            instance.failures += 1;
+        });
+    }
+
+    /// Record samples of the named instance which were dropped instead of fed to outputs.
+    pub fn samples_dropped(&self, name: &str, count: usize) {
+        self.update(|state| {
+            let instance = state.instances.entry(name.to_owned()).or_insert_with(
+                Default::default,
+            );
+
+            instance.dropped += count as u64;
+        });
+    }
+
+    /// Record samples of the named instance whose value was NaN or infinite.
+    pub fn samples_non_finite(&self, name: &str, count: usize) {
+        self.update(|state| {
+            *state.non_finite.entry(name.to_owned()).or_insert(0) += count as u64;
+        });
+    }
+
+    /// Record samples which were dropped because the queue of the named output was full.
+    pub fn output_samples_dropped(&self, name: &str, count: usize) {
+        self.update(|state| {
+            *state.output_drops.entry(name.to_owned()).or_insert(0) += count as u64;
+        });
+    }
+
+    /// Record unchanged samples which were not delivered to the named output because of `dedup`.
+    pub fn output_samples_deduplicated(&self, name: &str, count: usize) {
+        self.update(|state| {
+            *state.output_dedup.entry(name.to_owned()).or_insert(0) += count as u64;
+        });
+    }
+
+    pub fn update_started(&self) {
+        self.updates_in_progress.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a completed update of the named instance, successful or not.
+    pub fn update_finished(&self, name: &str, duration: Duration) {
+        self.updates_in_progress.fetch_sub(1, Ordering::Relaxed);
+
+        self.update(|state| {
+            let latency = key("sysmon").tag("instance", name).tag("what", "update-latency");
+            state.observe(latency, duration);
+        });
+    }
+
+    /// Record how long a run of the named schedule took, like polling all of its instances.
+    pub fn run_finished(&self, schedule: &str, duration: Duration) {
+        self.update(|state| {
+            let latency = key("sysmon").tag("schedule", schedule).tag("what", "run-latency");
+            state.observe(latency, duration);
+        });
+    }
+
+    /// Record the number of tasks currently queued onto the thread pool.
+    pub fn pool_queued(&self, queued: usize) {
+        let mut high_water = self.pool_high_water.load(Ordering::Relaxed);
+
+        while queued > high_water {
+            let previous =
+                self.pool_high_water.compare_and_swap(high_water, queued, Ordering::Relaxed);
+
+            if previous == high_water {
+                break;
+            }
+
+            high_water = previous;
+        }
+    }
+
+    /// Record how late a run of the named schedule started.
+    pub fn tick(&self, schedule: &str, drift: Duration) {
+        self.update(|state| { state.drift.insert(schedule.to_owned(), drift); });
+    }
+
+    /// Forget about instances and schedules which are no longer running.
+    pub fn retain(&self, instances: &[String]) {
+        self.update(|state| {
+            state.instances.retain(|name, _| instances.contains(name));
+            state.non_finite.retain(|name, _| instances.contains(name));
+            state.drift.clear();
+        });
+    }
+
+    fn update<F>(&self, f: F)
+    where
+        F: FnOnce(&mut State),
+    {
+        match self.state.lock() {
+            Ok(mut state) => f(&mut state),
+            Err(e) => error!("failed to record telemetry: {}", e),
+        }
+    }
+
+    /// Report the current state as samples.
+    ///
+    /// Durations recorded since the last report are included as observations.
+    pub fn samples(&self) -> Result<Samples> {
+        let mut state = self.state.lock()?;
+        let mut samples = Vec::new();
+
+        let sample = |builder: MetricIdBuilder, value: f64| {
+            Sample::new(Arc::new(builder.build()), value)
+        };
+
+        let counter = |builder: MetricIdBuilder, value: f64| {
+            sample(builder, value).with_kind(SampleKind::Counter)
+        };
+
+        for (name, instance) in state.instances.iter() {
+            let base = key("sysmon").tag("instance", name);
+
+            samples.push(counter(
+                base.clone().tag("what", "poll-successes"),
+                instance.successes as f64,
+            ));
+
+            samples.push(counter(
+                base.clone().tag("what", "poll-failures"),
+                instance.failures as f64,
+            ));
+
+            samples.push(counter(
+                base.clone().tag("what", "samples-dropped"),
+                instance.dropped as f64,
+            ));
+
+            if let Some(duration) = instance.last_duration {
+                samples.push(sample(
+                    base.clone().tag("what", "poll-duration").tag("unit", "ms"),
+                    as_millis(duration),
+                ));
+            }
+        }
+
+        for (name, count) in state.non_finite.iter() {
+            samples.push(counter(
+                key("sysmon").tag("instance", name).tag("what", "samples-non-finite"),
+                *count as f64,
+            ));
+        }
+
+        for (schedule, drift) in state.drift.iter() {
+            samples.push(sample(
+                key("sysmon")
+                    .tag("what", "scheduler-drift")
+                    .tag("schedule", schedule)
+                    .tag("unit", "ms"),
+                as_millis(*drift),
+            ));
+        }
+
+        for (output, dropped) in state.output_drops.iter() {
+            samples.push(counter(
+                key("sysmon").tag("what", "output-samples-dropped").tag("output", output),
+                *dropped as f64,
+            ));
+        }
+
+        for (output, suppressed) in state.output_dedup.iter() {
+            samples.push(counter(
+                key("sysmon").tag("what", "output-samples-deduplicated").tag("output", output),
+                *suppressed as f64,
+            ));
+        }
+
+        samples.push(sample(
+            key("sysmon").tag("what", "updates-in-progress"),
+            self.updates_in_progress.load(Ordering::Relaxed) as f64,
+        ));
+
+        samples.push(sample(
+            key("sysmon").tag("what", "pool-queued-high-water"),
+            self.pool_high_water.load(Ordering::Relaxed) as f64,
+        ));
+
+        for (metric_id, value) in state.observations.drain(..) {
+            samples.push(Sample::new(metric_id, value).with_kind(SampleKind::Observation));
+        }
+
+        Ok(samples)
+    }
+}
+
+/// Input instance which reports the internal metrics.
+pub struct SelfInputInstance {
+    telemetry: Arc<Telemetry>,
+}
+
+impl SelfInputInstance {
+    pub fn new(telemetry: Arc<Telemetry>) -> SelfInputInstance {
+        SelfInputInstance { telemetry: telemetry }
+    }
+}
+
+impl fmt::Debug for SelfInputInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SelfInputInstance")
+    }
+}
+
+impl InputInstance for SelfInputInstance {
+    fn poll(&self) -> Result<Samples> {
+        self.telemetry.samples()
+    }
+
+    /// Counters are kept in memory.
+    fn blocking(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_samples() {
+        let telemetry = Telemetry::new();
+
+        telemetry.poll_success("cpu", Duration::from_millis(5));
+        telemetry.poll_failure("cpu");
+        telemetry.poll_failure("disk");
+        telemetry.samples_dropped("disk", 10);
+        telemetry.update_started();
+        telemetry.pool_queued(3);
+        telemetry.pool_queued(2);
+
+        let samples = telemetry.samples().unwrap();
+        let values: Vec<f64> = samples.iter().map(|s| s.value).collect();
+
+        // successes, failures, drops and duration of cpu, the same without duration for disk,
+        // updates, the largest number of queued tasks, and the poll of cpu as an observation.
+        assert_eq!(
+            vec![1f64, 1f64, 0f64, 5f64, 0f64, 1f64, 10f64, 1f64, 3f64, 0.005f64],
+            values
+        );
+        assert_eq!(SampleKind::Observation, samples[9].kind);
+
+        telemetry.retain(&vec!["disk".to_owned()]);
+        assert_eq!(5, telemetry.samples().unwrap().len());
+    }
+}
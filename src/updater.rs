@@ -1,30 +1,45 @@
 use errors::*;
+use logger;
 use futures::*;
-use plugin::InputInstance;
+use futures::future::Either;
+use plugin::{ConfiguredInput, Reactor};
 use scheduler::Runnable;
 use futures_cpupool::CpuPool;
+use health::Health;
+use telemetry::Telemetry;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
 struct InputInstanceState {
     /// Only permit one update at a time.
     in_progress: Arc<AtomicBool>,
-    instance: Arc<Box<InputInstance>>,
+    input: Arc<ConfiguredInput>,
 }
 
 pub struct Updater {
     states: Vec<InputInstanceState>,
     pool: Arc<CpuPool>,
+    reactor: Reactor,
+    health: Arc<Health>,
+    telemetry: Arc<Telemetry>,
 }
 
 impl Updater {
-    pub fn new(input: Arc<Vec<Arc<Box<InputInstance>>>>, pool: Arc<CpuPool>) -> Updater {
+    pub fn new(
+        input: Vec<Arc<ConfiguredInput>>,
+        pool: Arc<CpuPool>,
+        reactor: Reactor,
+        health: Arc<Health>,
+        telemetry: Arc<Telemetry>,
+    ) -> Updater {
         let states: Vec<_> = input
-            .iter()
+            .into_iter()
             .map(|i| {
                 InputInstanceState {
                     in_progress: Arc::new(AtomicBool::new(false)),
-                    instance: i.clone(),
+                    input: i,
                 }
             })
             .collect();
@@ -32,6 +47,9 @@ impl Updater {
         Updater {
             states: states,
             pool: pool,
+            reactor: reactor,
+            health: health,
+            telemetry: telemetry,
         }
     }
 }
@@ -47,13 +65,59 @@ impl Runnable for Updater {
 
                 match should_update {
                     true => {
-                        Box::new(self.pool.spawn(state.instance.update().map(move |_| {
+                        let name = state.input.options.name.clone();
+                        let health = self.health.clone();
+                        let telemetry = self.telemetry.clone();
+                        telemetry.update_started();
+                        let started = Instant::now();
+
+                        // a plugin may panic while setting up its update, not only while running.
+                        let update = panic::catch_unwind(AssertUnwindSafe(|| {
+                            logger::with_plugin(&name, || state.input.instance.update())
+                        }));
+
+                        let update = match update {
+                            Ok(update) => {
+                                Either::A(
+                                    AssertUnwindSafe(logger::scoped(name.clone(), update))
+                                        .catch_unwind(),
+                                )
+                            }
+                            Err(payload) => Either::B(future::err(payload)),
+                        };
+
+                        let update = update.then(move |result| {
                             in_progress.store(false, Ordering::Relaxed);
-                            ()
-                        }))) as Box<Future<Item = (), Error = Error>>
+                            telemetry.update_finished(&name, started.elapsed());
+
+                            match result {
+                                Ok(Ok(())) => {}
+                                Ok(Err(e)) => {
+                                    error!("{}: update failed: {}", name, e);
+                                    health.failure(&name, &e);
+                                }
+                                Err(payload) => {
+                                    let message = panic_message(&payload);
+                                    error!("{}: update panicked: {}", name, message);
+                                    let e = ErrorKind::Message(format!("panicked: {}", message));
+                                    health.failure(&name, &e.into());
+                                }
+                            }
+
+                            Ok(())
+                        });
+
+                        let inline = self.reactor == Reactor::Single &&
+                            !state.input.instance.blocking();
+
+                        if inline {
+                            Box::new(update) as Box<Future<Item = (), Error = Error>>
+                        } else {
+                            Box::new(self.pool.spawn(update))
+                        }
                     }
                     false => {
-                        info!("Update already in progress for: {:?}", state.instance);
+                        info!("Update already in progress for: {}", state.input.options.name);
 
                         Box::new(future::ok(()))
                     }
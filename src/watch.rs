@@ -0,0 +1,109 @@
+use config::ConfigFormat;
+use errors::*;
+use futures::Stream;
+use futures::sync::mpsc;
+use notify::{self, DebouncedEvent, RecursiveMode, Watcher};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc as std_mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Time that has to pass without further changes before a change is reported.
+const DEBOUNCE_MS: u64 = 500;
+
+/// Watch configuration files and directories for changes.
+///
+/// The returned stream emits one item for every burst of changes. Files are watched through their
+/// parent directory, so that editors which replace a file through a rename are also detected.
+pub fn watch_config(
+    files: &Vec<String>,
+    dirs: &Vec<String>,
+    recursive: bool,
+) -> Result<Box<Stream<Item = (), Error = Error>>> {
+    let debounce = Duration::from_millis(DEBOUNCE_MS);
+    let (tx, rx) = std_mpsc::channel();
+
+    let mut watcher = notify::watcher(tx, debounce).map_err(|e| {
+        ErrorKind::Message(format!("failed to set up watcher: {}", e))
+    })?;
+
+    let files: Vec<PathBuf> = files.iter().map(|f| canonicalize(Path::new(f))).collect();
+    let dirs: Vec<PathBuf> = dirs.iter().map(|d| canonicalize(Path::new(d))).collect();
+
+    for file in files.iter() {
+        let parent = file.parent().unwrap_or(Path::new("."));
+        watch_path(&mut watcher, parent, RecursiveMode::NonRecursive)?;
+    }
+
+    let mode = if recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+
+    for dir in dirs.iter() {
+        watch_path(&mut watcher, dir, mode)?;
+    }
+
+    let (out_tx, out_rx) = mpsc::unbounded();
+
+    thread::spawn(move || {
+        // the watcher stops watching when dropped.
+        let _watcher = watcher;
+
+        while let Ok(event) = rx.recv() {
+            if !is_relevant(&event, &files, &dirs) {
+                continue;
+            }
+
+            // coalesce changes to several files (e.g. a checkout) into a single reload.
+            while let Ok(_) = rx.recv_timeout(debounce) {}
+
+            if out_tx.unbounded_send(()).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(Box::new(out_rx.map_err(|_| {
+        ErrorKind::Message("watcher stopped".to_owned()).into()
+    })))
+}
+
+fn watch_path<W: Watcher>(watcher: &mut W, path: &Path, mode: RecursiveMode) -> Result<()> {
+    debug!("watching: {}", path.display());
+
+    watcher.watch(path, mode).map_err(|e| {
+        ErrorKind::Message(format!("failed to watch {}: {}", path.display(), e)).into()
+    })
+}
+
+/// Event paths are absolute, so watched paths have to be as well.
+fn canonicalize(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+/// Check if the event affects any of the configuration files.
+fn is_relevant(event: &DebouncedEvent, files: &Vec<PathBuf>, dirs: &Vec<PathBuf>) -> bool {
+    let path = match *event {
+        DebouncedEvent::Create(ref path) |
+        DebouncedEvent::Write(ref path) |
+        DebouncedEvent::Remove(ref path) |
+        DebouncedEvent::Rename(_, ref path) => path,
+        // events were lost, so anything could have changed.
+        DebouncedEvent::Rescan => return true,
+        DebouncedEvent::Error(ref e, _) => {
+            warn!("watch error: {}", e);
+            return false;
+        }
+        _ => return false,
+    };
+
+    if files.iter().any(|f| f == path) {
+        return true;
+    }
+
+    let is_config = ConfigFormat::from_path(path).is_some();
+    is_config && dirs.iter().any(|d| path.starts_with(d))
+}
@@ -0,0 +1,159 @@
+//! Support for configuration files in YAML.
+//!
+//! Documents are converted into the same tables as TOML files, so they are loaded the same way.
+
+use errors::*;
+use toml;
+use yaml_rust::{Yaml, YamlLoader};
+
+/// Parse a YAML document into a table.
+///
+/// An empty document is an empty table.
+pub fn parse_yaml(content: &str) -> Result<toml::Table> {
+    let documents = YamlLoader::load_from_str(content).map_err(|e| {
+        ErrorKind::YamlParse(e.to_string())
+    })?;
+
+    let mut documents = documents.into_iter();
+
+    let document = match documents.next() {
+        Some(document) => document,
+        None => return Ok(toml::Table::new()),
+    };
+
+    if documents.next().is_some() {
+        return Err(ErrorKind::YamlParse("expected a single document".to_owned()).into());
+    }
+
+    match document {
+        Yaml::Null => Ok(toml::Table::new()),
+        Yaml::Hash(_) => {
+            match convert("", document)? {
+                Some(toml::Value::Table(table)) => Ok(table),
+                _ => Err(ErrorKind::YamlParse("expected a mapping".to_owned()).into()),
+            }
+        }
+        _ => Err(ErrorKind::YamlParse("expected a mapping".to_owned()).into()),
+    }
+}
+
+fn error(path: &str, message: &str) -> Error {
+    let path = if path.is_empty() { "<root>" } else { path };
+    ErrorKind::YamlParse(format!("{}: {}", path, message)).into()
+}
+
+/// Convert a YAML value into a TOML value.
+///
+/// Returns `None` for null values, which TOML has no equivalent of. Keys with a null value are
+/// left out of their table, the same as an omitted key.
+fn convert(path: &str, value: Yaml) -> Result<Option<toml::Value>> {
+    let value = match value {
+        Yaml::Null => return Ok(None),
+        Yaml::Boolean(value) => toml::Value::Boolean(value),
+        Yaml::Integer(value) => toml::Value::Integer(value),
+        Yaml::String(value) => toml::Value::String(value),
+        Yaml::Real(value) => {
+            let parsed = value.parse::<f64>().map_err(
+                |_| error(path, "invalid number"),
+            )?;
+
+            toml::Value::Float(parsed)
+        }
+        Yaml::Array(values) => {
+            let mut out = Vec::new();
+
+            for (index, value) in values.into_iter().enumerate() {
+                let path = format!("{}[{}]", path, index);
+
+                match convert(&path, value)? {
+                    Some(value) => out.push(value),
+                    None => return Err(error(&path, "null is not supported in lists")),
+                }
+            }
+
+            toml::Value::Array(out)
+        }
+        Yaml::Hash(entries) => {
+            let mut table = toml::Table::new();
+
+            for (key, value) in entries {
+                let key = match key {
+                    Yaml::String(key) => key,
+                    Yaml::Integer(key) => key.to_string(),
+                    _ => return Err(error(path, "keys must be strings")),
+                };
+
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+
+                if let Some(value) = convert(&path, value)? {
+                    table.insert(key, value);
+                }
+            }
+
+            toml::Value::Table(table)
+        }
+        Yaml::Alias(_) => return Err(error(path, "aliases are not supported")),
+        Yaml::BadValue => return Err(error(path, "invalid value")),
+    };
+
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_yaml() {
+        let content = "\
+poll_interval: 10s
+threads: 4
+jitter: 0.5
+in:
+  cpu:
+    type: cpu
+    enabled: true
+    name: ~
+  frontend:
+    - type: http
+      url: http://frontend-1
+    - type: http
+      url: http://frontend-2
+";
+
+        let expected = toml::Parser::new(
+            "\
+poll_interval = \"10s\"
+threads = 4
+jitter = 0.5
+
+[in.cpu]
+type = \"cpu\"
+enabled = true
+
+[[in.frontend]]
+type = \"http\"
+url = \"http://frontend-1\"
+
+[[in.frontend]]
+type = \"http\"
+url = \"http://frontend-2\"
+",
+        ).parse()
+            .unwrap();
+
+        assert_eq!(expected, parse_yaml(content).unwrap());
+    }
+
+    #[test]
+    fn test_parse_yaml_errors() {
+        assert_eq!(toml::Table::new(), parse_yaml("").unwrap());
+        assert!(parse_yaml("- a\n- b\n").is_err());
+        assert!(parse_yaml("a: [1, ~]\n").is_err());
+        assert!(parse_yaml("a: [1\n").is_err());
+    }
+}